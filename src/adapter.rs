@@ -0,0 +1,242 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::run::{shell_quote, BuiltinStep, STATS_PREFIX};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildTool {
+    Maven,
+    Gradle,
+}
+
+/// Runs OpenRewrite recipes through the project's own build tool.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct OpenRewrite {
+    pub recipe: String,
+
+    /// Maven coordinates of the recipe modules, e.g. `org.openrewrite.recipe:rewrite-migrate-java:2.5.0`
+    #[serde(default)]
+    pub artifacts: Vec<String>,
+
+    /// Detected from the worktree when not set
+    pub build: Option<BuildTool>,
+}
+
+/// Runs a jscodeshift transform over the worktree.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Jscodeshift {
+    pub transform: String,
+
+    #[serde(default)]
+    pub paths: Vec<String>,
+
+    pub parser: Option<String>,
+    pub extensions: Option<String>,
+
+    #[serde(default)]
+    pub options: Vec<String>,
+}
+
+const MAVEN_PLUGIN: &str = "org.openrewrite.maven:rewrite-maven-plugin:run";
+const GRADLE_PLUGIN: &str = "org.openrewrite:plugin:latest.release";
+
+impl OpenRewrite {
+    fn maven_command(&self) -> String {
+        let mut command = format!(
+            "mvn -B {} {}",
+            MAVEN_PLUGIN,
+            shell_quote(&format!("-Drewrite.activeRecipes={}", self.recipe))
+        );
+        if !self.artifacts.is_empty() {
+            command.push(' ');
+            command.push_str(&shell_quote(&format!(
+                "-Drewrite.recipeArtifactCoordinates={}",
+                self.artifacts.join(",")
+            )));
+        }
+        command
+    }
+
+    fn gradle_command(&self) -> String {
+        // Gradle has no command line equivalent of the Maven plugin, so apply it with an init script
+        let dependencies: String = self
+            .artifacts
+            .iter()
+            .map(|artifact| format!("    dependencies {{ rewrite(\"{}\") }}\n", artifact))
+            .collect();
+        format!(
+            "init_script=$(mktemp)\n\
+             cat > \"$init_script\" <<'MEND_EOF'\n\
+             initscript {{\n    repositories {{ maven {{ url \"https://plugins.gradle.org/m2\" }} }}\n    dependencies {{ classpath(\"{}\") }}\n}}\n\
+             rootProject {{\n    plugins.apply(org.openrewrite.gradle.RewritePlugin)\n{}    rewrite {{ activeRecipe(\"{}\") }}\n    afterEvaluate {{ if (repositories.isEmpty()) {{ repositories {{ mavenCentral() }} }} }}\n}}\n\
+             MEND_EOF\n\
+             if [ -x ./gradlew ]; then gradle_bin=./gradlew; else gradle_bin=gradle; fi\n\
+             \"$gradle_bin\" --init-script \"$init_script\" rewriteRun\n\
+             gradle_status=$?\n\
+             rm -f \"$init_script\"\n\
+             (exit $gradle_status)",
+            GRADLE_PLUGIN, dependencies, self.recipe
+        )
+    }
+}
+
+impl BuiltinStep for OpenRewrite {
+    fn describe(&self) -> String {
+        format!("OpenRewrite {}", self.recipe)
+    }
+
+    fn to_script(&self) -> crate::error::Result<String> {
+        let invocation = match self.build {
+            Some(BuildTool::Maven) => self.maven_command(),
+            Some(BuildTool::Gradle) => self.gradle_command(),
+            None => format!(
+                "if [ -f pom.xml ]; then\n{}\nelse\n{}\nfi",
+                self.maven_command(),
+                self.gradle_command()
+            ),
+        };
+        // Both plugins log one line per changed file
+        Ok(capture_and_count(
+            &invocation,
+            &[("changed", "grep -c 'Changes have been made to'")],
+        ))
+    }
+}
+
+impl Jscodeshift {
+    /// Makes a transform given relative to the config file absolute, as steps run inside the worktree.
+    pub fn resolve_transform(&mut self, config_dir: &Path) {
+        let candidate = config_dir.join(&self.transform);
+        if Path::new(&self.transform).is_relative() && candidate.exists() {
+            if let Ok(absolute) = candidate.canonicalize() {
+                self.transform = absolute.to_string_lossy().to_string();
+            }
+        }
+    }
+}
+
+impl BuiltinStep for Jscodeshift {
+    fn describe(&self) -> String {
+        format!("jscodeshift {}", self.transform)
+    }
+
+    fn to_script(&self) -> crate::error::Result<String> {
+        let mut invocation = "if [ -x ./node_modules/.bin/jscodeshift ]; then jscodeshift_bin=./node_modules/.bin/jscodeshift; else jscodeshift_bin='npx --yes jscodeshift'; fi\n\
+             $jscodeshift_bin --fail-on-error -t "
+            .to_string();
+        invocation.push_str(&shell_quote(&self.transform));
+        if let Some(parser) = &self.parser {
+            invocation.push_str(&format!(" --parser={}", shell_quote(parser)));
+        }
+        if let Some(extensions) = &self.extensions {
+            invocation.push_str(&format!(" --extensions={}", shell_quote(extensions)));
+        }
+        for option in &self.options {
+            invocation.push(' ');
+            invocation.push_str(&shell_quote(option));
+        }
+        if self.paths.is_empty() {
+            invocation.push_str(" .");
+        }
+        for path in &self.paths {
+            invocation.push(' ');
+            invocation.push_str(&shell_quote(path));
+        }
+        // jscodeshift ends with a "Results:" block of `<count> <label>` lines
+        Ok(capture_and_count(
+            &invocation,
+            &[
+                ("changed", "sed -n 's/^\\([0-9][0-9]*\\) ok$/\\1/p'"),
+                ("unmodified", "sed -n 's/^\\([0-9][0-9]*\\) unmodified$/\\1/p'"),
+                ("errors", "sed -n 's/^\\([0-9][0-9]*\\) errors$/\\1/p'"),
+            ],
+        ))
+    }
+}
+
+/// Wraps a tool invocation so its output is kept, counted into stats lines, and its exit status preserved.
+/// A count the tool didn't print is 0.
+fn capture_and_count(invocation: &str, counters: &[(&str, &str)]) -> String {
+    let mut script = format!(
+        "tool_log=$(mktemp)\n{{\n{}\n}} > \"$tool_log\" 2>&1\ntool_status=$?\ncat \"$tool_log\"\n",
+        invocation
+    );
+    for (key, filter) in counters {
+        script.push_str(&format!(
+            "tool_count=$({} \"$tool_log\" | head -n 1)\necho \"{}{}=${{tool_count:-0}}\"\n",
+            filter, STATS_PREFIX, key
+        ));
+    }
+    script.push_str("rm -f \"$tool_log\"\nexit $tool_status\n");
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::adapter::{BuildTool, Jscodeshift, OpenRewrite};
+    use crate::run::{BuiltinStep, StepResponse};
+    use std::process::Command;
+
+    #[test]
+    fn openrewrite_maven_script() {
+        let step = OpenRewrite {
+            recipe: "org.openrewrite.java.migrate.UpgradeToJava17".to_string(),
+            artifacts: vec!["org.openrewrite.recipe:rewrite-migrate-java:2.5.0".to_string()],
+            build: Some(BuildTool::Maven),
+        };
+        insta::assert_snapshot!(step.to_script().unwrap());
+    }
+
+    #[test]
+    fn openrewrite_detects_build_tool() {
+        let step = OpenRewrite {
+            recipe: "org.openrewrite.java.format.AutoFormat".to_string(),
+            artifacts: vec![],
+            build: None,
+        };
+        insta::assert_snapshot!(step.to_script().unwrap());
+    }
+
+    #[test]
+    fn jscodeshift_script() {
+        let step = Jscodeshift {
+            transform: "/codemods/rename.js".to_string(),
+            paths: vec!["src".to_string()],
+            parser: Some("tsx".to_string()),
+            extensions: Some("ts,tsx".to_string()),
+            options: vec!["--from=foo".to_string()],
+        };
+        insta::assert_snapshot!(step.to_script().unwrap());
+    }
+
+    #[test]
+    fn jscodeshift_results_become_stats() {
+        let step = Jscodeshift {
+            transform: "t.js".to_string(),
+            paths: vec![],
+            parser: None,
+            extensions: None,
+            options: vec![],
+        };
+        // Stand in for the real tool with one that prints a results block
+        let script = step.to_script().unwrap().replace(
+            "$jscodeshift_bin",
+            "printf 'Results:\\n1 errors\\n10 unmodified\\n0 skipped\\n5 ok\\n'; false",
+        );
+        let output = Command::new("sh").args(["-c", &script]).output().unwrap();
+        assert!(!output.status.success());
+        let mut step_response = StepResponse::pending();
+        step_response.record_stats(&String::from_utf8_lossy(&output.stdout));
+        assert_eq!(step_response.metadata.get("changed"), Some(&"5".to_string()));
+        assert_eq!(step_response.metadata.get("unmodified"), Some(&"10".to_string()));
+        assert_eq!(step_response.metadata.get("errors"), Some(&"1".to_string()));
+
+        let script = step.to_script().unwrap().replace("$jscodeshift_bin", "printf 'Results:\\n3 ok\\n'");
+        let output = Command::new("sh").args(["-c", &script]).output().unwrap();
+        let mut step_response = StepResponse::pending();
+        step_response.record_stats(&String::from_utf8_lossy(&output.stdout));
+        assert_eq!(step_response.metadata.get("changed"), Some(&"3".to_string()));
+        assert_eq!(step_response.metadata.get("errors"), Some(&"0".to_string()));
+    }
+}
@@ -0,0 +1,70 @@
+use clap::CommandFactory;
+use std::collections::BTreeMap;
+
+use crate::Cli;
+
+/// The config file given with `-f`, `--file` or `--file=`.
+fn file_arg(args: &[String]) -> Option<&str> {
+    args.iter().enumerate().find_map(|(arg_i, arg)| match arg.as_str() {
+        "-f" | "--file" => args.get(arg_i + 1).map(String::as_str),
+        _ => arg
+            .strip_prefix("--file=")
+            .or_else(|| arg.strip_prefix("-f"))
+            .filter(|file| !file.is_empty()),
+    })
+}
+
+/// When the first argument is neither an option nor a subcommand but an alias, replaces it with the alias's arguments.
+/// `load_aliases` gets the config file given with `-f` and returns its `[aliases]`, it's only called for a possible alias.
+pub fn expand_alias<F>(args: Vec<String>, load_aliases: F) -> Vec<String>
+where
+    F: FnOnce(Option<&str>) -> Option<BTreeMap<String, String>>,
+{
+    let Some(name) = args.get(1).filter(|arg| !arg.starts_with('-')) else {
+        return args;
+    };
+    // Subcommands win, an alias can't change what `mend status` means
+    if Cli::command().find_subcommand(name).is_some() {
+        return args;
+    }
+    let Some(expansion) = load_aliases(file_arg(&args)).and_then(|aliases| aliases.get(name).cloned()) else {
+        return args;
+    };
+    let mut expanded = vec![args[0].clone()];
+    expanded.extend(expansion.split_whitespace().map(str::to_string));
+    expanded.extend(args[2..].iter().cloned());
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alias::expand_alias;
+    use crate::Cli;
+    use clap::Parser;
+    use std::collections::BTreeMap;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn aliases_expand_in_place_of_the_subcommand() {
+        let aliases = BTreeMap::from([
+            ("ci".to_string(), "--ci github --keep-going".to_string()),
+            ("status".to_string(), "--dry-run".to_string()),
+        ]);
+        let mut loaded_from = None;
+        let expanded = expand_alias(args("mend ci -f app/mend.toml -j 2"), |file| {
+            loaded_from = file.map(str::to_string);
+            Some(aliases.clone())
+        });
+        assert_eq!(expanded, args("mend --ci github --keep-going -f app/mend.toml -j 2"));
+        assert_eq!(loaded_from.as_deref(), Some("app/mend.toml"));
+        let cli = Cli::try_parse_from(&expanded).unwrap();
+        assert!(cli.continue_on_error);
+
+        assert_eq!(expand_alias(args("mend status"), |_| Some(aliases.clone())), args("mend status"));
+        assert_eq!(expand_alias(args("mend --file=mend.toml"), |_| panic!("no alias to load")), args("mend --file=mend.toml"));
+        assert_eq!(expand_alias(args("mend nightly"), |_| Some(aliases.clone())), args("mend nightly"));
+    }
+}
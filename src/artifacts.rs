@@ -0,0 +1,136 @@
+use anyhow::Context;
+use indicatif::HumanBytes;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::repo::{changed_tracked_sizes, list_staged_new, list_untracked, remove_files};
+
+/// Directories of build output and installed dependencies, which steps adding them rarely mean to commit.
+const ARTIFACT_DIRS: [&str; 7] = ["node_modules/", "target/", "build/", "dist/", "__pycache__/", ".gradle/", ".venv/"];
+/// Step metadata holding how much the step grew the worktree by, e.g. `+1.50 MiB`.
+pub const GROWTH_KEY: &str = "worktree_growth";
+/// How much a step may grow the worktree by before it's warned about, when `[artifacts] warn_mb` isn't set.
+const DEFAULT_WARN_MB: u64 = 10;
+
+/// The `[artifacts]` table, what steps leave behind that shouldn't be committed.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct ArtifactsConfig {
+    /// New files removed before each step's commit, an entry ending in `/` is a directory anywhere in the tree,
+    /// e.g. `node_modules/`, others a file name or path, e.g. `npm-debug.log`
+    #[serde(default)]
+    pub clean: Vec<String>,
+    /// A step growing the worktree by more megabytes than this is warned about
+    pub warn_mb: Option<u64>,
+}
+
+/// What a step is about to commit, measured after the files in `[artifacts] clean` were removed.
+#[derive(Debug, Default, PartialEq)]
+pub struct StepArtifacts {
+    /// How many bytes the worktree grew by, negative when the step removed more than it added
+    pub growth: i64,
+    pub removed: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+/// Whether `path` is under a directory `entry` names, or is the file it names.
+fn matches_entry(path: &str, entry: &str) -> bool {
+    if entry.ends_with('/') {
+        path.starts_with(entry) || path.contains(&format!("/{}", entry))
+    } else {
+        path == entry || path.ends_with(&format!("/{}", entry))
+    }
+}
+
+fn file_size(repo_dir: &Path, path: &str) -> u64 {
+    fs::metadata(repo_dir.join(path)).map_or(0, |metadata| metadata.len())
+}
+
+/// `+1.50 MiB` or `-200 B`.
+pub fn format_growth(growth: i64) -> String {
+    let sign = if growth < 0 { "-" } else { "+" };
+    format!("{}{}", sign, HumanBytes(growth.unsigned_abs()))
+}
+
+/// Removes the step's new files that `config` cleans, then measures the worktree's growth and warns about
+/// artifacts the step's commit would add. Only files the step staged are committed, others just take up space.
+pub fn check_step_artifacts(repo_dir: &Path, config: &ArtifactsConfig) -> anyhow::Result<StepArtifacts> {
+    let mut artifacts = StepArtifacts::default();
+    let is_cleaned = |path: &String| config.clean.iter().any(|entry| matches_entry(path, entry));
+    let (cleaned_untracked, untracked): (Vec<String>, Vec<String>) = list_untracked(repo_dir)?.into_iter().partition(is_cleaned);
+    let (cleaned_staged, staged): (Vec<String>, Vec<String>) = list_staged_new(repo_dir)?.into_iter().partition(is_cleaned);
+    for path in &cleaned_untracked {
+        fs::remove_file(repo_dir.join(path)).with_context(|| format!("Could not remove `{}`", path))?;
+    }
+    remove_files(repo_dir, &cleaned_staged)?;
+    artifacts.removed = cleaned_staged.into_iter().chain(cleaned_untracked).collect();
+    artifacts.removed.sort();
+
+    // Staged new files are among the changes since HEAD, at no size there
+    let mut growth: i64 = untracked.iter().map(|path| file_size(repo_dir, path) as i64).sum();
+    for (path, size_at_head) in changed_tracked_sizes(repo_dir)? {
+        growth += file_size(repo_dir, &path) as i64 - size_at_head as i64;
+    }
+    artifacts.growth = growth;
+    for dir in ARTIFACT_DIRS {
+        let sizes: Vec<u64> = staged.iter().filter(|path| matches_entry(path, dir)).map(|path| file_size(repo_dir, path)).collect();
+        if !sizes.is_empty() {
+            artifacts.warnings.push(format!(
+                "commits {} new files ({}) under {}, list it in [artifacts] clean or .gitignore",
+                sizes.len(),
+                HumanBytes(sizes.iter().sum()),
+                dir
+            ));
+        }
+    }
+    let warn_mb = config.warn_mb.unwrap_or(DEFAULT_WARN_MB);
+    if growth > (warn_mb * 1024 * 1024) as i64 {
+        artifacts.warnings.push(format!("grows the worktree by {}, more than {} MB", HumanBytes(growth as u64), warn_mb));
+    }
+    Ok(artifacts)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::artifacts::{check_step_artifacts, format_growth, ArtifactsConfig};
+    use crate::test_support::git;
+    use std::fs;
+
+    #[test]
+    fn artifacts_are_cleaned_and_warned_about() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let repo_dir = temp_dir.path();
+        git(repo_dir, &["init", "-q"]);
+        fs::write(repo_dir.join("App.java"), "class Foo {}\n").unwrap();
+        git(repo_dir, &["add", "App.java"]);
+        git(repo_dir, &["commit", "-q", "-m", "Foo"]);
+
+        fs::write(repo_dir.join("App.java"), "class Bar {}\n// Renamed\n").unwrap();
+        fs::create_dir_all(repo_dir.join("web/node_modules/left-pad")).unwrap();
+        fs::write(repo_dir.join("web/node_modules/left-pad/index.js"), "x".repeat(2048)).unwrap();
+        fs::create_dir_all(repo_dir.join("build")).unwrap();
+        fs::write(repo_dir.join("build/App.class"), "y".repeat(1000)).unwrap();
+        fs::write(repo_dir.join("npm-debug.log"), "z".repeat(500)).unwrap();
+        fs::create_dir_all(repo_dir.join("dist")).unwrap();
+        fs::write(repo_dir.join("dist/app.js"), "w".repeat(300)).unwrap();
+        git(repo_dir, &["add", "build", "web"]);
+        let config = ArtifactsConfig {
+            clean: vec!["node_modules/".to_string(), "npm-debug.log".to_string()],
+            warn_mb: Some(0),
+        };
+        let artifacts = check_step_artifacts(repo_dir, &config).unwrap();
+        assert_eq!(artifacts.removed, vec!["npm-debug.log", "web/node_modules/left-pad/index.js"]);
+        assert!(!repo_dir.join("web/node_modules/left-pad/index.js").exists());
+        assert!(!repo_dir.join("npm-debug.log").exists());
+        // dist/ isn't staged, so it isn't committed
+        assert_eq!(artifacts.growth, 1000 + 300 + 11);
+        assert_eq!(format_growth(artifacts.growth), "+1.28 KiB");
+        assert_eq!(
+            artifacts.warnings,
+            vec![
+                "commits 1 new files (1000 B) under build/, list it in [artifacts] clean or .gitignore",
+                "grows the worktree by 1.28 KiB, more than 0 MB"
+            ]
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::run::EStatus;
+use crate::schema::to_versioned_json;
+use crate::state::RunState;
+
+const STATUS_FILE: &str = "status.json";
+const BADGE_FILE: &str = "badge.svg";
+
+/// The outcome of the latest run in `.mend/status.json`, for dashboards to pick up.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct RunStatus {
+    pub config: String,
+    pub from_sha: String,
+    /// Every step succeeded or was skipped
+    pub success: bool,
+    /// Where the worktree ended up
+    pub final_sha: Option<String>,
+    /// Seconds since the Unix epoch
+    pub finished_at: u64,
+    pub steps_done: usize,
+    pub steps_failed: usize,
+    pub steps_skipped: usize,
+    /// Steps that didn't run, after a failure
+    pub steps_pending: usize,
+}
+
+impl RunStatus {
+    pub fn new(state: &RunState, final_sha: Option<String>, finished_at: u64) -> Self {
+        let count = |status: EStatus| state.steps.iter().filter(|step| step.status == status).count();
+        let steps_failed = count(EStatus::Failed) + count(EStatus::VerifyFailed);
+        let steps_pending = count(EStatus::Pending);
+        RunStatus {
+            config: state.config.clone(),
+            from_sha: state.from_sha.clone(),
+            success: steps_failed == 0 && steps_pending == 0,
+            final_sha,
+            finished_at,
+            steps_done: count(EStatus::Done),
+            steps_failed,
+            steps_skipped: count(EStatus::Skipped),
+            steps_pending,
+        }
+    }
+}
+
+/// A shields.io style badge saying how many of the steps got through, green when all did.
+pub fn render_badge(status: &RunStatus) -> String {
+    let total = status.steps_done + status.steps_failed + status.steps_skipped + status.steps_pending;
+    let message = format!("{}/{} steps", status.steps_done + status.steps_skipped, total);
+    let color = if status.success { "#4c1" } else { "#e05d44" };
+    // Close enough to Verdana 11px for the short texts of a badge
+    let text_width = |text: &str| text.chars().count() * 7 + 10;
+    let label_width = text_width("mend");
+    let message_width = text_width(&message);
+    let width = label_width + message_width;
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img" aria-label="mend: {message}">
+  <title>mend: {message}</title>
+  <rect width="{label_width}" height="20" fill="#555"/>
+  <rect x="{label_width}" width="{message_width}" height="20" fill="{color}"/>
+  <g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11">
+    <text x="{label_x}" y="14">mend</text>
+    <text x="{message_x}" y="14">{message}</text>
+  </g>
+</svg>
+"##,
+        label_x = label_width / 2,
+        message_x = label_width + message_width / 2,
+    )
+}
+
+/// Writes `status.json`, and `badge.svg` when asked for, into `mend_dir`.
+pub fn write_status(mend_dir: &Path, status: &RunStatus, badge: bool) -> anyhow::Result<()> {
+    let status_path = mend_dir.join(STATUS_FILE);
+    fs::write(&status_path, to_versioned_json(status)?)
+        .with_context(|| format!("Could not write `{}`", status_path.to_string_lossy()))?;
+    if badge {
+        let badge_path = mend_dir.join(BADGE_FILE);
+        fs::write(&badge_path, render_badge(status))
+            .with_context(|| format!("Could not write `{}`", badge_path.to_string_lossy()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::badge::{write_status, RunStatus};
+    use crate::run::EStatus;
+    use crate::state::RunState;
+    use std::fs;
+
+    #[test]
+    fn status_and_badge_describe_the_latest_run() {
+        let planned_steps = vec![
+            ("1".to_string(), "rename a b".to_string()),
+            ("lint".to_string(), "lint".to_string()),
+            ("3".to_string(), "rename e f".to_string()),
+        ];
+        let mut state = RunState::new("mend.toml", "base", &planned_steps);
+        state.steps[0].status = EStatus::Done;
+        state.steps[1].status = EStatus::Skipped;
+        state.steps[2].status = EStatus::Failed;
+        let status = RunStatus::new(&state, Some("abc1234".to_string()), 1700000000);
+        assert!(!status.success);
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        write_status(temp_dir.path(), &status, false).unwrap();
+        assert!(!temp_dir.path().join("badge.svg").exists());
+        write_status(temp_dir.path(), &status, true).unwrap();
+        insta::assert_snapshot!(fs::read_to_string(temp_dir.path().join("status.json")).unwrap());
+        insta::assert_snapshot!(fs::read_to_string(temp_dir.path().join("badge.svg")).unwrap());
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::{bail, Context};
+use std::path::Path;
+
+use crate::{Step, StepConfig};
+
+/// Which of the numbered commits of a batch step with a `chunk_size` a row's step is part of.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Chunk {
+    /// The batch step's instruction, e.g. `rename @renames.csv`
+    pub title: String,
+    /// Counting from 1
+    pub number: usize,
+    pub count: usize,
+}
+
+/// The argument naming a batch file, e.g. `@renames.csv`, and its position among the instruction's words.
+fn batch_file(instruction: &str) -> Option<(usize, &str)> {
+    instruction.split_whitespace().enumerate().find_map(|(word_i, word)| {
+        let file = word.strip_prefix('@')?;
+        (file.ends_with(".csv") || file.ends_with(".tsv")).then_some((word_i, file))
+    })
+}
+
+/// One instruction per row of the batch file, its columns taking the place of the `@file` argument.
+fn expand_instruction(instruction: &str, dir: &Path) -> anyhow::Result<Option<Vec<String>>> {
+    let Some((file_i, file)) = batch_file(instruction) else {
+        return Ok(None);
+    };
+    let words: Vec<&str> = instruction.split_whitespace().collect();
+    let delimiter = if file.ends_with(".tsv") { b'\t' } else { b',' };
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(delimiter)
+        .comment(Some(b'#'))
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_path(dir.join(file))
+        .with_context(|| format!("Could not read `{}`", file))?;
+    let mut instructions = vec![];
+    for record in reader.records() {
+        let record = record.with_context(|| format!("Could not read `{}`", file))?;
+        if record.iter().all(str::is_empty) {
+            continue;
+        }
+        let line = record.position().map_or(0, |position| position.line());
+        for (column_i, value) in record.iter().enumerate() {
+            // Steps split their arguments on whitespace, so such a value would shift the ones after it
+            if value.is_empty() || value.contains(char::is_whitespace) {
+                bail!(
+                    "Line {} of `{}` has an empty value or one with whitespace in column {}",
+                    line,
+                    file,
+                    column_i + 1
+                );
+            }
+        }
+        let expanded: Vec<&str> = words[..file_i]
+            .iter()
+            .copied()
+            .chain(record.iter())
+            .chain(words[file_i + 1..].iter().copied())
+            .collect();
+        instructions.push(expanded.join(" "));
+    }
+    Ok(Some(instructions))
+}
+
+/// Replaces each step with an `@file.csv` or `@file.tsv` argument by a step per row of the file,
+/// found relative to `dir`. Steps with an `id` get `-1`, `-2`.. appended to it, and with a `chunk_size` the
+/// `Chunk` they're committed in.
+pub fn expand_batch_steps(steps: Vec<Step>, dir: &Path) -> anyhow::Result<Vec<Step>> {
+    let mut expanded_steps = vec![];
+    for step in steps {
+        let (instruction, chunk_size) = match &step {
+            Step::Instruction(instruction) => (Some(instruction), None),
+            Step::Structured(step_config) => (step_config.run.as_ref(), step_config.chunk_size),
+        };
+        let Some(instructions) = instruction.map(|instruction| expand_instruction(instruction, dir)).transpose()?.flatten()
+        else {
+            if chunk_size.is_some() {
+                bail!("`chunk_size` needs a step with an `@file.csv` or `@file.tsv` argument");
+            }
+            expanded_steps.push(step);
+            continue;
+        };
+        if chunk_size == Some(0) {
+            bail!("`chunk_size` must be at least 1");
+        }
+        let count = chunk_size.map_or(0, |chunk_size| instructions.len().div_ceil(chunk_size));
+        for (row_i, row_instruction) in instructions.into_iter().enumerate() {
+            expanded_steps.push(match &step {
+                Step::Instruction(_) => Step::Instruction(row_instruction),
+                Step::Structured(step_config) => Step::Structured(Box::new(StepConfig {
+                    id: step_config.id.as_ref().map(|id| format!("{}-{}", id, row_i + 1)),
+                    run: Some(row_instruction),
+                    chunk: chunk_size.map(|chunk_size| Chunk {
+                        title: step_config.run.clone().unwrap_or_default(),
+                        number: row_i / chunk_size + 1,
+                        count,
+                    }),
+                    ..*step_config.clone()
+                })),
+            });
+        }
+    }
+    Ok(expanded_steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::batch::expand_batch_steps;
+    use crate::{Step, StepConfig};
+    use std::fs;
+
+    #[test]
+    fn batch_files_expand_into_a_step_per_row() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::write(temp_dir.path().join("renames.csv"), "# from,to\nFoo, Bar\n\n\"Baz\",Qux\n").unwrap();
+        fs::write(temp_dir.path().join("moves.tsv"), "a.c\tsrc/a.c\n").unwrap();
+        let steps = vec![
+            Step::from("format"),
+            Step::from("rename @renames.csv --force"),
+            Step::Structured(Box::new(StepConfig {
+                id: Some("move".to_string()),
+                run: Some("move @moves.tsv".to_string()),
+                ..Default::default()
+            })),
+            Step::from("notify @team"),
+        ];
+        let expanded = expand_batch_steps(steps, temp_dir.path()).unwrap();
+        assert_eq!(
+            expanded,
+            vec![
+                Step::from("format"),
+                Step::from("rename Foo Bar --force"),
+                Step::from("rename Baz Qux --force"),
+                Step::Structured(Box::new(StepConfig {
+                    id: Some("move-1".to_string()),
+                    run: Some("move a.c src/a.c".to_string()),
+                    ..Default::default()
+                })),
+                Step::from("notify @team"),
+            ]
+        );
+
+        fs::write(temp_dir.path().join("renames.csv"), "Foo,Bar\nBaz,Big Qux\n").unwrap();
+        let err = expand_batch_steps(vec![Step::from("rename @renames.csv")], temp_dir.path()).unwrap_err();
+        assert_eq!(err.to_string(), "Line 2 of `renames.csv` has an empty value or one with whitespace in column 2");
+        assert!(expand_batch_steps(vec![Step::from("rename @missing.csv")], temp_dir.path()).is_err());
+        let chunked = Step::Structured(Box::new(StepConfig {
+            run: Some("move @moves.tsv".to_string()),
+            chunk_size: Some(0),
+            ..Default::default()
+        }));
+        let err = expand_batch_steps(vec![chunked], temp_dir.path()).unwrap_err();
+        assert_eq!(err.to_string(), "`chunk_size` must be at least 1");
+    }
+}
@@ -0,0 +1,301 @@
+use anyhow::{anyhow, bail, Context};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::report::{cast_path, read_run, render_report, run_config_path, ReportFormat, RunRecord};
+use crate::repo::{create_bundle, fetch_bundle};
+use crate::run::run_command_with_output;
+use crate::secrets::{decrypt_file, encrypt_file, is_encrypted};
+use crate::schema::{from_versioned_json, to_versioned_json};
+
+const MANIFEST_FILE: &str = "manifest.json";
+const RECORD_FILE: &str = "run.json";
+const CONFIG_FILE: &str = "config.toml";
+const CAST_FILE: &str = "run.cast";
+const COMMITS_FILE: &str = "commits.bundle";
+const LOGS_DIR: &str = "logs";
+/// The ref the run's commits go by in `commits.bundle`
+const BUNDLE_REF: &str = "refs/mend/bundle";
+
+#[derive(Args, Debug)]
+pub struct BundleArgs {
+    /// Id of a run in `.mend/runs`, the latest run when not given
+    pub run_id: Option<String>,
+
+    /// Archive to write, `mend-run-<id>.tar.gz` by default, `mend-run-<id>.tar.gz.age` when encrypted
+    #[arg(short = 'o', long = "output")]
+    pub output: Option<String>,
+
+    /// Encrypt the archive with age for this recipient, an `age1...` public key, repeat for several
+    #[arg(long = "recipient")]
+    pub recipients: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct UnbundleArgs {
+    /// Archive written by `mend bundle`
+    pub file: String,
+
+    /// age identity file to decrypt an encrypted archive with, else `MEND_AGE_IDENTITY` or `MEND_AGE_IDENTITY_FILE`
+    #[arg(long = "identity")]
+    pub identity: Option<String>,
+
+    /// Keep the unpacked run instead of deleting it once shown, and print where it is
+    #[arg(long = "keep")]
+    pub keep: bool,
+
+    /// Fetch the run's commits into the branch `mend/run-<id>` of `--repo`
+    #[arg(long = "apply")]
+    pub apply: bool,
+
+    /// Repository the commits are fetched into
+    #[arg(long = "repo", default_value = ".")]
+    pub repo: String,
+}
+
+/// What a bundle holds, so `mend unbundle` can tell a bundle it can read from any other archive.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct BundleManifest {
+    pub run_id: String,
+    pub from_sha: String,
+    /// Last commit of the run, none when no step committed
+    pub to_sha: Option<String>,
+    pub mend_version: String,
+    pub files: Vec<String>,
+}
+
+fn archive_dir_name(run_id: &str) -> String {
+    format!("mend-run-{}", run_id)
+}
+
+fn write_file(path: &Path, contents: &str) -> anyhow::Result<()> {
+    fs::write(path, contents).with_context(|| format!("Could not write `{}`", path.to_string_lossy()))
+}
+
+fn run_tool(dir: &Path, program: &str, args: Vec<&str>) -> anyhow::Result<()> {
+    let output = run_command_with_output(dir, program.to_string(), args)?;
+    if !output.status.success() {
+        bail!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+/// Lays out the run's record, config, cast, failed step logs and commits in `dir`, returning what was added.
+fn stage_run(base_repo_dir: &Path, mend_dir: &Path, record: &RunRecord, dir: &Path) -> anyhow::Result<BundleManifest> {
+    let mut files = vec![RECORD_FILE.to_string()];
+    write_file(&dir.join(RECORD_FILE), &to_versioned_json(record)?)?;
+    let config_path = run_config_path(mend_dir, &record.id);
+    if config_path.exists() {
+        fs::copy(&config_path, dir.join(CONFIG_FILE))
+            .with_context(|| format!("Could not read `{}`", config_path.to_string_lossy()))?;
+        files.push(CONFIG_FILE.to_string());
+    }
+    let cast_path = cast_path(mend_dir, &record.id);
+    if cast_path.exists() {
+        fs::copy(&cast_path, dir.join(CAST_FILE))
+            .with_context(|| format!("Could not read `{}`", cast_path.to_string_lossy()))?;
+        files.push(CAST_FILE.to_string());
+    }
+    for step in &record.steps {
+        if let Some(output) = &step.output {
+            fs::create_dir_all(dir.join(LOGS_DIR))?;
+            let log_file = format!("{}/{}.log", LOGS_DIR, step.id);
+            write_file(&dir.join(&log_file), output)?;
+            files.push(log_file);
+        }
+    }
+    let to_sha = record.steps.iter().rev().find_map(|step| step.sha.clone());
+    if let Some(to_sha) = &to_sha {
+        create_bundle(base_repo_dir, &dir.join(COMMITS_FILE), &record.from_sha, to_sha, BUNDLE_REF)
+            .context("Could not bundle the run's commits")?;
+        files.push(COMMITS_FILE.to_string());
+    }
+    let manifest = BundleManifest {
+        run_id: record.id.clone(),
+        from_sha: record.from_sha.clone(),
+        to_sha,
+        mend_version: env!("CARGO_PKG_VERSION").to_string(),
+        files,
+    };
+    write_file(&dir.join(MANIFEST_FILE), &to_versioned_json(&manifest)?)?;
+    Ok(manifest)
+}
+
+/// Packs a recorded run into one archive to hand to someone else, e.g. a recipe's author
+/// looking into a failed step. Returns the archive's path.
+pub fn run_bundle(base_repo_dir: &Path, mend_dir: &Path, args: &BundleArgs) -> anyhow::Result<PathBuf> {
+    let record = read_run(mend_dir, args.run_id.as_deref())?;
+    let staging_dir = env::temp_dir().join(format!("mend-bundle-{}", std::process::id()));
+    let dir_name = archive_dir_name(&record.id);
+    let run_dir = staging_dir.join(&dir_name);
+    fs::create_dir_all(&run_dir).with_context(|| format!("Could not create `{}`", run_dir.to_string_lossy()))?;
+    let result = (|| {
+        stage_run(base_repo_dir, mend_dir, &record, &run_dir)?;
+        let archive_path = match &args.output {
+            Some(output) => PathBuf::from(output),
+            None if args.recipients.is_empty() => PathBuf::from(format!("{}.tar.gz", dir_name)),
+            None => PathBuf::from(format!("{}.tar.gz.age", dir_name)),
+        };
+        let archive_path = env::current_dir()?.join(archive_path);
+        let tar_path = staging_dir.join(format!("{}.tar.gz", dir_name));
+        let tar_path_str = tar_path.to_string_lossy().to_string();
+        run_tool(&staging_dir, "tar", vec!["-czf", &tar_path_str, &dir_name])?;
+        if args.recipients.is_empty() {
+            fs::copy(&tar_path, &archive_path)
+                .with_context(|| format!("Could not write `{}`", archive_path.to_string_lossy()))?;
+        } else {
+            encrypt_file(&args.recipients, &tar_path, &archive_path).context("Could not encrypt the bundle")?;
+        }
+        Ok(archive_path)
+    })();
+    let _ = fs::remove_dir_all(&staging_dir);
+    result
+}
+
+/// Decrypts and unpacks a bundle into `dir`, returning the directory of the run in it and its manifest.
+/// An encrypted bundle is told apart by its content, whatever it was renamed to.
+fn unpack(file: &Path, identity: Option<&str>, dir: &Path) -> anyhow::Result<(PathBuf, BundleManifest)> {
+    let file_str = file.to_string_lossy().to_string();
+    let tar_path = if is_encrypted(file)? {
+        let tar_path = dir.join("bundle.tar.gz");
+        decrypt_file(identity, file, &tar_path).context("Could not decrypt the bundle")?;
+        tar_path
+    } else {
+        file.to_path_buf()
+    };
+    let tar_path_str = tar_path.to_string_lossy().to_string();
+    run_tool(dir, "tar", vec!["-xzf", &tar_path_str])?;
+    let run_dir = fs::read_dir(dir)?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| path.join(MANIFEST_FILE).exists())
+        .ok_or_else(|| anyhow!("`{}` is not a bundle written by mend bundle", file_str))?;
+    let manifest_path = run_dir.join(MANIFEST_FILE);
+    let manifest = from_versioned_json(&manifest_path, &fs::read_to_string(&manifest_path)?, &[])?;
+    Ok((run_dir, manifest))
+}
+
+/// Shows the run in a bundle, and with `--apply` fetches its commits into a branch.
+pub fn run_unbundle(args: &UnbundleArgs) -> anyhow::Result<()> {
+    let file = env::current_dir()?.join(&args.file);
+    let dir = env::temp_dir().join(format!("mend-unbundle-{}", std::process::id()));
+    fs::create_dir_all(&dir).with_context(|| format!("Could not create `{}`", dir.to_string_lossy()))?;
+    let result = show_bundle(&file, args, &dir);
+    if !args.keep {
+        let _ = fs::remove_dir_all(&dir);
+    }
+    result
+}
+
+fn show_bundle(file: &Path, args: &UnbundleArgs, dir: &Path) -> anyhow::Result<()> {
+    let (run_dir, manifest) = unpack(file, args.identity.as_deref(), dir)?;
+    let record_path = run_dir.join(RECORD_FILE);
+    let record: RunRecord = from_versioned_json(&record_path, &fs::read_to_string(&record_path)?, &[])?;
+    print!("{}", render_report(&record, ReportFormat::Console));
+    if args.keep {
+        println!("Unpacked into {}, with {}", run_dir.to_string_lossy(), manifest.files.join(", "));
+    }
+    if args.apply {
+        let Some(to_sha) = &manifest.to_sha else {
+            bail!("The run in the bundle made no commits, nothing to apply");
+        };
+        let branch = format!("mend/run-{}", manifest.run_id);
+        let repo_dir = env::current_dir()?.join(&args.repo);
+        fetch_bundle(&repo_dir, &run_dir.join(COMMITS_FILE), BUNDLE_REF, &branch)
+            .with_context(|| format!("Could not fetch the run's commits, does the repository have {}?", manifest.from_sha))?;
+        println!("Fetched the run's commits up to {} into branch {}", to_sha, branch);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bundle::{run_bundle, run_unbundle, BundleArgs, UnbundleArgs};
+    use crate::report::{write_run, RunRecord};
+    use crate::run::{RunSummary, StepCommit, StepRequest, StepResponse};
+    use crate::test_support::git;
+    use age::secrecy::ExposeSecret;
+    use age::x25519;
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn bundled_run_is_unpacked_and_applied_elsewhere() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let repo_dir = temp_dir.path().join("repo");
+        fs::create_dir(&repo_dir).unwrap();
+        git(&repo_dir, &["init", "-q"]);
+        git(&repo_dir, &["-c", "user.name=mend", "-c", "user.email=mend@example.com", "commit", "-q", "--allow-empty", "-m", "base"]);
+        let from_sha = git(&repo_dir, &["rev-parse", "--short", "HEAD"]);
+        git(&repo_dir, &["clone", "-q", ".", "../elsewhere"]);
+        fs::write(repo_dir.join("a.c"), "int a;\n").unwrap();
+        git(&repo_dir, &["add", "a.c"]);
+        git(&repo_dir, &["-c", "user.name=mend", "-c", "user.email=mend@example.com", "commit", "-q", "-m", "Step 1"]);
+        let step_sha = git(&repo_dir, &["rev-parse", "--short", "HEAD"]);
+
+        let steps = vec![("1".to_string(), "rename a b".to_string()), ("lint".to_string(), "lint".to_string())];
+        let mut record = RunRecord::new("1700000000".to_string(), "mend.toml", &from_sha, &steps);
+        let mut failed_response = StepResponse::pending();
+        failed_response.output = Some("a.c:1: warning".to_string());
+        record.record_summary(&RunSummary {
+            failed_steps: vec![1],
+            failures: vec![(StepRequest { id: "lint".to_string(), ..Default::default() }, failed_response)],
+            commits: vec![StepCommit { id: "1".to_string(), step: 1, sha: step_sha.clone(), ..Default::default() }],
+            ..Default::default()
+        });
+        let mend_dir = repo_dir.join(".mend");
+        write_run(&mend_dir, &record).unwrap();
+        fs::write(mend_dir.join("runs/1700000000.toml"), "steps = [\"rename a b\", \"lint\"]\n").unwrap();
+
+        let archive = temp_dir.path().join("failed-lint.tar.gz");
+        let args = BundleArgs {
+            run_id: None,
+            output: Some(archive.to_string_lossy().to_string()),
+            recipients: vec![],
+        };
+        assert_eq!(run_bundle(&repo_dir, &mend_dir, &args).unwrap(), archive);
+        assert!(git(&repo_dir, &["for-each-ref", "refs/mend"]).is_empty());
+
+        run_unbundle(&UnbundleArgs {
+            file: archive.to_string_lossy().to_string(),
+            identity: None,
+            keep: false,
+            apply: true,
+            repo: temp_dir.path().join("elsewhere").to_string_lossy().to_string(),
+        })
+        .unwrap();
+        let applied_sha = git(&temp_dir.path().join("elsewhere"), &["rev-parse", "--short", "mend/run-1700000000"]);
+        assert_eq!(applied_sha, step_sha);
+        assert!(!env::temp_dir().join(format!("mend-unbundle-{}", std::process::id())).exists());
+
+        // Encrypted, and recognized as such under any name
+        let identity = x25519::Identity::generate();
+        let identity_file = temp_dir.path().join("key.txt");
+        fs::write(&identity_file, identity.to_string().expose_secret()).unwrap();
+        let encrypted = temp_dir.path().join("for-the-author.bundle");
+        let args = BundleArgs {
+            run_id: None,
+            output: Some(encrypted.to_string_lossy().to_string()),
+            recipients: vec![identity.to_public().to_string()],
+        };
+        run_bundle(&repo_dir, &mend_dir, &args).unwrap();
+        assert!(!fs::read(&encrypted).unwrap().starts_with(&[0x1f, 0x8b]));
+        let unbundle = |identity: Option<&Path>| {
+            run_unbundle(&UnbundleArgs {
+                file: encrypted.to_string_lossy().to_string(),
+                identity: identity.map(|identity| identity.to_string_lossy().to_string()),
+                keep: false,
+                apply: false,
+                repo: ".".to_string(),
+            })
+        };
+        let other_identity_file = temp_dir.path().join("other-key.txt");
+        fs::write(&other_identity_file, x25519::Identity::generate().to_string().expose_secret()).unwrap();
+        assert!(unbundle(Some(&other_identity_file)).is_err());
+        unbundle(Some(&identity_file)).unwrap();
+    }
+}
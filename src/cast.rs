@@ -0,0 +1,130 @@
+use anyhow::Context;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::process::Output;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::run::Executor;
+
+/// Writes an asciinema v2 cast, one event per line so a run that stops early still leaves a playable file.
+pub struct CastWriter {
+    file: File,
+    started: Instant,
+}
+
+impl CastWriter {
+    pub fn create(path: &Path, title: &str) -> anyhow::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Could not create `{}`", parent.to_string_lossy()))?;
+        }
+        let mut file =
+            File::create(path).with_context(|| format!("Could not create `{}`", path.to_string_lossy()))?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        let header = json!({"version": 2, "width": 120, "height": 40, "timestamp": timestamp, "title": title});
+        writeln!(file, "{}", header)?;
+        Ok(CastWriter {
+            file,
+            started: Instant::now(),
+        })
+    }
+
+    fn write_at(&mut self, seconds: f64, text: &str) {
+        // Terminals need carriage returns, scripts only print newlines
+        let data = text.replace("\r\n", "\n").replace('\n', "\r\n");
+        let event = json!([(seconds * 1000.0).round() / 1000.0, "o", data]);
+        if let Err(err) = writeln!(self.file, "{}", event) {
+            eprintln!("Could not record output: {}", err);
+        }
+    }
+
+    pub fn write(&mut self, text: &str) {
+        let seconds = self.started.elapsed().as_secs_f64();
+        self.write_at(seconds, text);
+    }
+}
+
+/// Passes scripts on to `inner`, recording each one with its output when there is a cast.
+pub struct CastExecutor<E: Executor> {
+    inner: E,
+    cast: Option<CastWriter>,
+}
+
+impl<E: Executor> CastExecutor<E> {
+    pub fn new(inner: E, cast: Option<CastWriter>) -> Self {
+        CastExecutor { inner, cast }
+    }
+}
+
+impl<E: Executor> Executor for CastExecutor<E> {
+    fn run_script(
+        &mut self,
+        cwd: &Path,
+        script: &str,
+        env: &BTreeMap<String, String>,
+        timeout: Option<Duration>,
+        on_line: &mut dyn FnMut(&str),
+    ) -> crate::error::Result<Output> {
+        if let Some(cast) = &mut self.cast {
+            cast.write(&format!("$ {}\n", script.trim_end().replace('\n', "\n> ")));
+        }
+        let output = self.inner.run_script(cwd, script, env, timeout, on_line);
+        if let (Some(cast), Ok(output)) = (&mut self.cast, &output) {
+            let text = output_text(output);
+            if !text.is_empty() {
+                cast.write(&text);
+            }
+        }
+        output
+    }
+
+    /// Scripts of steps running alongside others aren't recorded
+    fn worker(&self) -> Option<Box<dyn Executor + Send>> {
+        self.inner.worker()
+    }
+}
+
+fn output_text(output: &Output) -> String {
+    let mut text = String::from_utf8_lossy(&output.stdout).to_string();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    if !output.status.success() {
+        text.push_str(&format!("[{}]\n", output.status));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cast::{CastExecutor, CastWriter};
+    use crate::run::{Executor, ShellExecutor};
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn cast_records_scripts_and_output() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cast_path = temp_dir.path().join("runs").join("1.cast");
+        let cast = CastWriter::create(&cast_path, "mend run").unwrap();
+        let mut executor = CastExecutor::new(ShellExecutor::default(), Some(cast));
+        executor
+            .run_script(Path::new("."), "echo one\necho two", &BTreeMap::new(), None, &mut |_| {})
+            .unwrap();
+        drop(executor);
+        let lines: Vec<serde_json::Value> = fs::read_to_string(&cast_path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[0]["version"], 2);
+        assert_eq!(lines[1][1], "o");
+        assert_eq!(lines[1][2], "$ echo one\r\n> echo two\r\n");
+        assert_eq!(lines[2][2], "one\r\ntwo\r\n");
+    }
+}
@@ -0,0 +1,275 @@
+use anyhow::Context;
+use clap::Args;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::include::cache_name;
+use crate::lock::read_lock;
+use crate::repo::{clone_bare, fetch_branches, fetch_commit, has_commit, remove_worktree, set_remote_url, worker_worktrees, MEND_DIR};
+
+/// Overrides where clones of remote repos are kept, `$XDG_CACHE_HOME/mend/clones` or `~/.cache/mend/clones` by default.
+pub const CLONE_CACHE_ENV: &str = "MEND_CLONE_CACHE";
+/// Under a remote repo's cache dir, the one clone that talks to the remote.
+const MIRROR_DIR: &str = "mirror.git";
+/// Under a remote repo's cache dir, a clone per config borrowing the mirror's objects.
+const CHECKOUTS_DIR: &str = "checkouts";
+/// In a checkout, when a run last used it, seconds since the Unix epoch.
+const LAST_USED_FILE: &str = "last-used";
+const DEFAULT_MAX_AGE_DAYS: u64 = 30;
+
+#[derive(Args, Debug)]
+pub struct GcArgs {
+    /// Remove checkouts no run used for this many days
+    #[arg(long = "max-age-days", default_value_t = DEFAULT_MAX_AGE_DAYS)]
+    pub max_age_days: u64,
+}
+
+/// Whether a `from.repo` is a URL to clone rather than a local checkout,
+/// e.g. `https://github.com/org/app.git` or `git@github.com:org/app.git`.
+pub fn is_remote(repo: &str) -> bool {
+    if repo.contains("://") {
+        return true;
+    }
+    // scp-like syntax, a user and host before the colon
+    repo.split_once(':')
+        .is_some_and(|(host, _)| host.contains('@') && !host.contains('/'))
+}
+
+pub fn clone_cache_dir() -> PathBuf {
+    if let Some(dir) = env::var_os(CLONE_CACHE_ENV) {
+        return PathBuf::from(dir);
+    }
+    let cache_home = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(shellexpand::tilde("~/.cache").as_ref()),
+    };
+    cache_home.join("mend").join("clones")
+}
+
+/// The clone a config works on for the remote repo `url`. Each config gets its own,
+/// so runs of different configs don't share a worktree or wait for each other's lock.
+pub fn checkout_dir(cache_dir: &Path, url: &str, config_path: &Path) -> PathBuf {
+    let config_path = fs::canonicalize(config_path).unwrap_or(config_path.to_path_buf());
+    let key = format!("{:x}", Sha256::digest(config_path.to_string_lossy().as_bytes()));
+    cache_dir.join(cache_name(url)).join(CHECKOUTS_DIR).join(&key[..12])
+}
+
+/// A sha stays put, a branch or tag can move on the remote.
+fn is_pinned(rev: &str) -> bool {
+    rev.len() >= 7 && rev.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn now_secs(now: SystemTime) -> u64 {
+    now.duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Makes sure the checkout at `checkout_dir` exists and has `rev`. Only the mirror shared by all checkouts of `url`
+/// fetches from the remote, and only when it lacks `rev` or `rev` can move. The checkout borrows the mirror's objects
+/// through git's alternates, so another config on the same remote costs next to nothing on disk or the network.
+pub fn ensure_checkout(url: &str, rev: &str, checkout_dir: &Path) -> anyhow::Result<()> {
+    let repo_cache_dir = checkout_dir
+        .parent()
+        .and_then(Path::parent)
+        .with_context(|| format!("`{}` is not in a clone cache", checkout_dir.to_string_lossy()))?;
+    let mirror_dir = repo_cache_dir.join(MIRROR_DIR);
+    let mirror_str = mirror_dir.to_string_lossy().to_string();
+    if !mirror_dir.exists() {
+        fs::create_dir_all(repo_cache_dir)
+            .with_context(|| format!("Could not create `{}`", repo_cache_dir.to_string_lossy()))?;
+        eprintln!("Cloning {} into {}", url, mirror_str);
+        clone_bare(url, &mirror_dir, false).with_context(|| format!("Could not clone `{}`", url))?;
+    } else if !is_pinned(rev) || !has_commit(&mirror_dir, rev) {
+        fetch_branches(&mirror_dir, url).with_context(|| format!("Could not fetch `{}`", url))?;
+    }
+    if !has_commit(&mirror_dir, rev) {
+        fetch_commit(&mirror_dir, url, rev).with_context(|| format!("`{}` has no commit `{}`", url, rev))?;
+    }
+
+    if !checkout_dir.exists() {
+        fs::create_dir_all(repo_cache_dir.join(CHECKOUTS_DIR))
+            .with_context(|| format!("Could not create `{}`", repo_cache_dir.to_string_lossy()))?;
+        clone_bare(&mirror_str, checkout_dir, true)?;
+        // Pushes from the checkout go to the remote, not the cache
+        set_remote_url(checkout_dir, "origin", url)?;
+    } else if !is_pinned(rev) {
+        fetch_branches(checkout_dir, &mirror_str)?;
+    }
+    let last_used_path = checkout_dir.join(LAST_USED_FILE);
+    fs::write(&last_used_path, now_secs(SystemTime::now()).to_string())
+        .with_context(|| format!("Could not write `{}`", last_used_path.to_string_lossy()))
+}
+
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| entries.flatten().map(|entry| entry.path()).filter(|path| path.is_dir()).collect())
+        .unwrap_or_default();
+    dirs.sort();
+    dirs
+}
+
+/// Removes the checkouts no run used within `max_age`, and a remote's mirror along with its last checkout.
+/// Checkouts with a run going are kept however old. Returns what was removed, or would be with `dry_run`.
+pub fn collect_garbage(cache_dir: &Path, max_age: Duration, now: SystemTime, dry_run: bool) -> anyhow::Result<Vec<PathBuf>> {
+    let mut removed = vec![];
+    for repo_cache_dir in subdirs(cache_dir) {
+        let checkouts = subdirs(&repo_cache_dir.join(CHECKOUTS_DIR));
+        let mut kept = 0;
+        for checkout_dir in checkouts {
+            let last_used: u64 = fs::read_to_string(checkout_dir.join(LAST_USED_FILE))
+                .ok()
+                .and_then(|contents| contents.trim().parse().ok())
+                .unwrap_or_default();
+            let running = read_lock(&checkout_dir.join(MEND_DIR)).is_some_and(|lock| lock.is_alive());
+            if running || now_secs(now).saturating_sub(last_used) <= max_age.as_secs() {
+                kept += 1;
+                continue;
+            }
+            if !dry_run {
+                fs::remove_dir_all(&checkout_dir)
+                    .with_context(|| format!("Could not remove `{}`", checkout_dir.to_string_lossy()))?;
+            }
+            removed.push(checkout_dir);
+        }
+        if kept == 0 {
+            // The mirror is only kept for its checkouts, which borrow its objects
+            if !dry_run {
+                fs::remove_dir_all(&repo_cache_dir)
+                    .with_context(|| format!("Could not remove `{}`", repo_cache_dir.to_string_lossy()))?;
+            }
+            removed.push(repo_cache_dir.join(MIRROR_DIR));
+        }
+    }
+    Ok(removed)
+}
+
+/// Removes the worktrees of parallel steps a killed run left in `base_repo_dir`, unless a run is going there.
+/// Returns what was removed, or would be with `dry_run`.
+pub fn prune_worker_worktrees(base_repo_dir: &Path, dry_run: bool) -> anyhow::Result<Vec<PathBuf>> {
+    if read_lock(&base_repo_dir.join(MEND_DIR)).is_some_and(|lock| lock.is_alive()) {
+        return Ok(vec![]);
+    }
+    let worker_dirs = worker_worktrees(base_repo_dir);
+    if !dry_run {
+        for worker_dir in &worker_dirs {
+            remove_worktree(worker_dir)
+                .with_context(|| format!("Could not remove the worktree `{}`", worker_dir.to_string_lossy()))?;
+        }
+    }
+    Ok(worker_dirs)
+}
+
+/// With `base_repo_dir`, the repo of the config in use, also prunes the worktrees its killed runs left behind.
+pub fn run_gc(args: &GcArgs, dry_run: bool, base_repo_dir: Option<&Path>) -> anyhow::Result<()> {
+    let cache_dir = clone_cache_dir();
+    let max_age = Duration::from_secs(args.max_age_days * 24 * 60 * 60);
+    let removed = collect_garbage(&cache_dir, max_age, SystemTime::now(), dry_run)?;
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    for path in &removed {
+        println!("{} {}", verb, path.to_string_lossy());
+    }
+    if removed.is_empty() {
+        println!("Nothing in {} is older than {} days", cache_dir.to_string_lossy(), args.max_age_days);
+    }
+    if let Some(base_repo_dir) = base_repo_dir {
+        for path in prune_worker_worktrees(base_repo_dir, dry_run)? {
+            println!("{} {}", verb, path.to_string_lossy());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clone_cache::{checkout_dir, collect_garbage, ensure_checkout, is_remote, prune_worker_worktrees, LAST_USED_FILE};
+    use crate::lock::acquire_lock;
+    use crate::repo::{add_worker_worktree, ensure_worktree, worker_worktrees, GitRepo, Repo};
+    use crate::test_support::git;
+    use std::fs;
+    use std::path::Path;
+    use std::process::Command;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn remote_repos_are_told_from_paths() {
+        assert!(is_remote("https://github.com/craftvscruft/mend.git"));
+        assert!(is_remote("git@github.com:craftvscruft/mend.git"));
+        assert!(is_remote("file:///srv/git/mend.git"));
+        assert!(!is_remote("~/src/mend"));
+        assert!(!is_remote("C:\\src\\mend"));
+        assert!(!is_remote("../mend"));
+    }
+
+    #[test]
+    fn configs_share_one_mirror_of_a_remote() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let remote_dir = temp_dir.path().join("remote");
+        fs::create_dir(&remote_dir).unwrap();
+        git(&remote_dir, &["init", "-q", "-b", "main"]);
+        fs::write(remote_dir.join("App.java"), "class Foo {}\n").unwrap();
+        git(&remote_dir, &["add", "App.java"]);
+        git(&remote_dir, &["commit", "-q", "-m", "Foo"]);
+        let url = format!("file://{}", remote_dir.to_string_lossy());
+        let cache_dir = temp_dir.path().join("clones");
+
+        let first_dir = checkout_dir(&cache_dir, &url, Path::new("first/mend.toml"));
+        let second_dir = checkout_dir(&cache_dir, &url, Path::new("second/mend.toml"));
+        assert_ne!(first_dir, second_dir);
+        assert_eq!(first_dir.parent(), second_dir.parent());
+        ensure_checkout(&url, "main", &first_dir).unwrap();
+        // A commit pushed after the first clone is fetched by the mirror
+        fs::write(remote_dir.join("App.java"), "class Bar {}\n").unwrap();
+        git(&remote_dir, &["commit", "-q", "-am", "Bar"]);
+        let sha = GitRepo { repo_dir: remote_dir.clone() }.current_short_sha().unwrap();
+        ensure_checkout(&url, &sha, &second_dir).unwrap();
+
+        let worktree_dir = ensure_worktree(&second_dir, ".mend/worktree2", &sha).unwrap();
+        assert_eq!(fs::read_to_string(worktree_dir.join("App.java")).unwrap(), "class Bar {}\n");
+        let alternates = fs::read_to_string(second_dir.join("objects/info/alternates")).unwrap();
+        assert!(alternates.contains("mirror.git"));
+
+        let now = SystemTime::now();
+        let long_ago = now.duration_since(UNIX_EPOCH).unwrap().as_secs() - 40 * 24 * 60 * 60;
+        fs::write(first_dir.join(LAST_USED_FILE), long_ago.to_string()).unwrap();
+        let max_age = Duration::from_secs(30 * 24 * 60 * 60);
+        assert_eq!(collect_garbage(&cache_dir, max_age, now, false).unwrap(), vec![first_dir.clone()]);
+        assert!(!first_dir.exists());
+        assert!(second_dir.exists());
+        // Once its last checkout is stale the mirror goes too
+        let later = now + Duration::from_secs(31 * 24 * 60 * 60);
+        let mirror_dir = first_dir.parent().unwrap().with_file_name("mirror.git");
+        assert_eq!(collect_garbage(&cache_dir, max_age, later, true).unwrap(), vec![second_dir.clone(), mirror_dir.clone()]);
+        assert!(mirror_dir.exists());
+        collect_garbage(&cache_dir, max_age, later, false).unwrap();
+        assert!(!mirror_dir.exists());
+    }
+
+    #[test]
+    fn worker_worktrees_of_a_killed_run_are_pruned() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let repo_dir = temp_dir.path().join("repo");
+        fs::create_dir(&repo_dir).unwrap();
+        git(&repo_dir, &["init", "-q", "-b", "main"]);
+        fs::write(repo_dir.join("App.java"), "class Foo {}\n").unwrap();
+        git(&repo_dir, &["add", "App.java"]);
+        git(&repo_dir, &["commit", "-q", "-m", "Foo"]);
+        let sha = GitRepo { repo_dir: repo_dir.clone() }.current_short_sha().unwrap();
+        let worktree_dir = ensure_worktree(&repo_dir, ".mend/worktree2", &sha).unwrap();
+        let worker_dir = add_worker_worktree(&worktree_dir, 1).unwrap();
+        assert_eq!(worker_worktrees(&repo_dir), vec![worker_dir.clone()]);
+
+        // A run going still needs them
+        let lock = acquire_lock(&repo_dir.join(".mend"), "mend.toml", 2).unwrap();
+        assert!(prune_worker_worktrees(&repo_dir, false).unwrap().is_empty());
+        drop(lock);
+        assert_eq!(prune_worker_worktrees(&repo_dir, true).unwrap(), vec![worker_dir.clone()]);
+        assert!(worker_dir.exists());
+        assert_eq!(prune_worker_worktrees(&repo_dir, false).unwrap(), vec![worker_dir.clone()]);
+        assert!(!worker_dir.exists());
+        assert!(worktree_dir.exists());
+        let worktrees = Command::new("git").current_dir(&repo_dir).args(["worktree", "list"]).output().unwrap();
+        assert!(!String::from_utf8_lossy(&worktrees.stdout).contains("worktree2-step-2"));
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::Context;
+use regex::Regex;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+/// Where GitHub and GitLab look for the file, the first one found is used.
+pub const CODEOWNERS_PATHS: &[&str] = &[".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS", ".gitlab/CODEOWNERS"];
+/// Under the base repo, where the branch of each group of owners is put together.
+pub const OWNERS_WORKTREE_DIR: &str = ".mend/worktree-owners";
+/// Names the branch of the commits touching only files nobody owns.
+const UNOWNED: &str = "unowned";
+
+/// The rules of a CODEOWNERS file, the last one matching a path giving its owners.
+pub struct CodeOwners {
+    rules: Vec<(Regex, Vec<String>)>,
+}
+
+/// Commits of the run whose files have the same owners, which get a branch and a pull request of their own.
+#[derive(Debug, PartialEq)]
+pub struct OwnerGroup {
+    /// Empty for files nobody owns
+    pub owners: Vec<String>,
+    /// The steps whose commits are in the group, counting from 1, in the order they ran
+    pub steps: Vec<usize>,
+}
+
+/// A CODEOWNERS pattern as a regex over paths relative to the repo's root, gitignore style: a pattern with a slash
+/// other than at its end is anchored at the root, one naming a directory matches everything in it.
+fn pattern_regex(pattern: &str) -> anyhow::Result<Regex> {
+    let trimmed = pattern.trim_end_matches('/');
+    let anchored = trimmed.contains('/');
+    let mut rest = trimmed.trim_start_matches('/');
+    let mut regex = String::from(if anchored { "^" } else { "^(.*/)?" });
+    while let Some(c) = rest.chars().next() {
+        let (part, len) = if rest.starts_with("**/") {
+            ("(.*/)?".to_string(), 3)
+        } else if rest.starts_with("**") {
+            (".*".to_string(), 2)
+        } else {
+            match c {
+                '*' => ("[^/]*".to_string(), 1),
+                '?' => ("[^/]".to_string(), 1),
+                _ => (regex::escape(&c.to_string()), c.len_utf8()),
+            }
+        };
+        regex.push_str(&part);
+        rest = &rest[len..];
+    }
+    regex.push_str("(/.*)?$");
+    Regex::new(&regex).with_context(|| format!("Invalid CODEOWNERS pattern `{}`", pattern))
+}
+
+impl CodeOwners {
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut rules = vec![];
+        for line in text.lines() {
+            let line = line.split(" #").next().unwrap_or_default().trim();
+            // GitLab's `[Section]` headers only group the rules under them
+            if line.is_empty() || line.starts_with('#') || line.starts_with('[') || line.starts_with("^[") {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let pattern = words.next().unwrap_or_default();
+            rules.push((pattern_regex(pattern)?, words.map(str::to_string).collect()));
+        }
+        Ok(CodeOwners { rules })
+    }
+
+    /// The CODEOWNERS file of the checkout at `repo_dir`, None when it has none.
+    pub fn read(repo_dir: &Path) -> anyhow::Result<Option<Self>> {
+        let Some(path) = CODEOWNERS_PATHS.iter().map(|path| repo_dir.join(path)).find(|path| path.is_file()) else {
+            return Ok(None);
+        };
+        let text = fs::read_to_string(&path).with_context(|| format!("Could not read `{}`", path.to_string_lossy()))?;
+        Ok(Some(CodeOwners::parse(&text)?))
+    }
+
+    /// The owners of `path`, empty when no rule matches it or the last one that does names nobody.
+    pub fn owners(&self, path: &str) -> &[String] {
+        self.rules
+            .iter()
+            .rev()
+            .find(|(regex, _)| regex.is_match(path))
+            .map_or(&[], |(_, owners)| owners.as_slice())
+    }
+}
+
+/// Groups the `commits`, each a step counting from 1 with the files its commit touches, by the owners of those files.
+/// A commit touching files of several owners goes to the group of all of them, so it's reviewed once, by everyone.
+pub fn group_by_owners(code_owners: &CodeOwners, commits: &[(usize, Vec<String>)]) -> Vec<OwnerGroup> {
+    let mut groups: Vec<OwnerGroup> = vec![];
+    for (step, files) in commits {
+        let owners: Vec<String> = files
+            .iter()
+            .flat_map(|file| code_owners.owners(file))
+            .cloned()
+            .collect::<BTreeSet<String>>()
+            .into_iter()
+            .collect();
+        match groups.iter_mut().find(|group| group.owners == owners) {
+            Some(group) => group.steps.push(*step),
+            None => groups.push(OwnerGroup { owners, steps: vec![*step] }),
+        }
+    }
+    groups
+}
+
+/// The run's `branch` with the group's owners added, e.g. `mend/java17-org-payments` for `@org/payments`.
+pub fn branch_name(branch: &str, owners: &[String]) -> String {
+    let names = if owners.is_empty() { vec![UNOWNED.to_string()] } else { owners.to_vec() };
+    let suffix = names
+        .iter()
+        .flat_map(|owner| owner.split(|c: char| !c.is_ascii_alphanumeric()))
+        .filter(|word| !word.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<_>>()
+        .join("-");
+    format!("{}-{}", branch, suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codeowners::{branch_name, group_by_owners, CodeOwners, OwnerGroup};
+
+    const CODEOWNERS: &str = "# Default
+*       @org/platform
+*.md    @org/docs # Prose
+/src/payments/ @org/payments @alice
+docs/**/api.md @org/api
+
+[Generated]
+/gen/
+";
+
+    #[test]
+    fn the_last_matching_rule_names_the_owners() {
+        let code_owners = CodeOwners::parse(CODEOWNERS).unwrap();
+        assert_eq!(code_owners.owners("build.gradle"), ["@org/platform"]);
+        assert_eq!(code_owners.owners("src/app/README.md"), ["@org/docs"]);
+        assert_eq!(code_owners.owners("src/payments/Invoice.java"), ["@org/payments", "@alice"]);
+        assert_eq!(code_owners.owners("lib/src/payments/Invoice.java"), ["@org/platform"]);
+        assert_eq!(code_owners.owners("docs/v2/rest/api.md"), ["@org/api"]);
+        assert!(code_owners.owners("gen/Schema.java").is_empty());
+    }
+
+    #[test]
+    fn commits_are_grouped_by_the_owners_of_their_files() {
+        let code_owners = CodeOwners::parse(CODEOWNERS).unwrap();
+        let commits = vec![
+            (1, vec!["src/payments/Invoice.java".to_string()]),
+            (2, vec!["build.gradle".to_string(), "gen/Schema.java".to_string()]),
+            (3, vec!["src/payments/Refund.java".to_string()]),
+            (4, vec!["gen/Schema.java".to_string()]),
+            (5, vec!["src/payments/Refund.java".to_string(), "README.md".to_string()]),
+        ];
+        assert_eq!(
+            group_by_owners(&code_owners, &commits),
+            vec![
+                OwnerGroup { owners: vec!["@alice".to_string(), "@org/payments".to_string()], steps: vec![1, 3] },
+                OwnerGroup { owners: vec!["@org/platform".to_string()], steps: vec![2] },
+                OwnerGroup { owners: vec![], steps: vec![4] },
+                OwnerGroup {
+                    owners: vec!["@alice".to_string(), "@org/docs".to_string(), "@org/payments".to_string()],
+                    steps: vec![5]
+                },
+            ]
+        );
+        assert_eq!(branch_name("mend/java17", &["@alice".to_string(), "@org/payments".to_string()]), "mend/java17-alice-org-payments");
+        assert_eq!(branch_name("mend/java17", &[]), "mend/java17-unowned");
+    }
+}
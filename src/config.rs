@@ -1,64 +1,307 @@
-use crate::Mend;
-use anyhow::{anyhow, Context};
+use crate::batch::expand_batch_steps;
+use crate::clone_cache::is_remote;
+use crate::error::{MendError, Result};
+use crate::include::{read_include, INCLUDE_CACHE_DIR};
+use crate::progress::progress_regex;
+use crate::repo::MEND_DIR;
+use crate::run::{bind_params, parse_timeout};
+use crate::when::Condition;
+use crate::{Mend, Step};
+use anyhow::Context;
 use std::collections::BTreeMap;
 use std::fs;
-use std::path::Path;
+use std::io;
+use std::path::{Path, PathBuf};
 
-pub fn load_mend(file: &Path) -> anyhow::Result<Mend> {
+/// Whether `file` is a YAML config rather than TOML, going by its extension.
+pub fn is_yaml(file: &Path) -> bool {
+    matches!(
+        file.extension().and_then(|extension| extension.to_str()),
+        Some("yaml" | "yml")
+    )
+}
+
+/// Parses a config or include file, as YAML or TOML depending on its extension.
+pub fn parse_mend(file: &Path, contents: &str) -> Result<Mend> {
+    let config_error = |line, cause| MendError::Config {
+        file: file.to_path_buf(),
+        line,
+        cause,
+    };
+    if is_yaml(file) {
+        serde_yaml::from_str(contents).map_err(|err| {
+            let line = err.location().map(|location| location.line());
+            config_error(line, err.into())
+        })
+    } else {
+        toml::from_str(contents).map_err(|err| {
+            let line = err.span().map(|span| contents[..span.start].matches('\n').count() + 1);
+            config_error(line, err.into())
+        })
+    }
+}
+
+/// `-f -` reads the config from stdin, its includes are relative to the current directory.
+pub const STDIN_CONFIG: &str = "-";
+
+fn invalid(message: String) -> MendError {
+    MendError::Validation(message)
+}
+
+pub fn load_mend(file: &Path) -> Result<Mend> {
+    let contents = if file == Path::new(STDIN_CONFIG) {
+        io::read_to_string(io::stdin()).context("Could not read the config from stdin")?
+    } else {
+        fs::read_to_string(file).with_context(|| format!("Could not read file `{}`", file.to_string_lossy()))?
+    };
+    load_mend_contents(file, &contents)
+}
+
+/// Like `load_mend` with the main config's `contents` given, e.g. an editor's unsaved buffer.
+pub fn load_mend_contents(file: &Path, contents: &str) -> Result<Mend> {
     let file_str = file.to_str().unwrap_or_default();
     let parent_dir = &file.parent().unwrap_or(Path::new(""));
 
-    let contents =
-        fs::read_to_string(&file).with_context(|| format!("Could not read file `{}`", file_str))?;
-
-    let main_mend: Mend = toml::from_str(&contents)
-        .with_context(|| format!("Unable to load data from `{}`", file_str))?;
+    let mut main_mend = parse_mend(file, contents)?;
+    read_run_files(&mut main_mend, file)?;
 
     let mut merged_mend: Mend = Mend {
         from: None,
         include: Vec::new(),
         env: BTreeMap::new(),
+        secrets: BTreeMap::new(),
         recipes: BTreeMap::new(),
         hooks: BTreeMap::new(),
         steps: Vec::new(),
+        verify: None,
+        matrix: BTreeMap::new(),
+        verify_baseline: None,
+        gates: None,
+        git: None,
+        commit: None,
+        committers: BTreeMap::new(),
+        phases: Vec::new(),
+        heartbeat: None,
+        shell: None,
+        telemetry: None,
+        metrics: None,
+        keep_going: None,
+        on_failure: None,
+        badge: None,
+        notify: None,
+        forge: None,
+        logs: None,
+        artifacts: None,
+        output: None,
+        publish: None,
+        lint_rules: vec![],
+        corpus: vec![],
+        aliases: BTreeMap::new(),
+        timeout: None,
+        run_env: BTreeMap::new(),
     };
-    for include_file in &main_mend.include {
-        let include_contents =
-            fs::read_to_string(parent_dir.join(include_file)).with_context(|| {
+    // Remote includes are cached with the run state of the repo the config works on,
+    // next to the config when that repo is remote and not cloned yet
+    let cache_dir = match &main_mend.from {
+        Some(from) if !is_remote(&from.repo) => crate::base_repo_dir(from, file),
+        _ => parent_dir.to_path_buf(),
+    }
+    .join(MEND_DIR)
+    .join(INCLUDE_CACHE_DIR);
+    let mut chain = vec![fs::canonicalize(file).unwrap_or(file.to_path_buf())];
+    merge_includes(&mut merged_mend, file, &main_mend, &cache_dir, &mut chain)?;
+    crate::extend_mend(&mut merged_mend, main_mend);
+    if let Some(Err(err)) = merged_mend.timeout.as_deref().map(parse_timeout) {
+        return Err(invalid(format!("`timeout` in `{}`: {:#}", file_str, err)));
+    }
+    if let Some(metrics) = &merged_mend.metrics {
+        if metrics.textfile.is_none() && metrics.pushgateway.is_none() {
+            return Err(invalid(format!("`[metrics]` in `{}` needs a `textfile` or a `pushgateway`", file_str)));
+        }
+    }
+    if let Some(from) = &merged_mend.from {
+        match (from.sha.is_empty(), from.git_ref.is_some()) {
+            (true, false) => return Err(invalid(format!("`from` in `{}` needs a `sha` or a `ref`", file_str))),
+            (false, true) => return Err(invalid(format!("`from` in `{}` has both a `sha` and a `ref`, keep one", file_str))),
+            _ => {}
+        }
+    }
+    let has_branch = merged_mend.output.as_ref().is_some_and(|output| output.branch.is_some());
+    if merged_mend.forge.as_ref().is_some_and(|forge| forge.pull_request.is_some()) && !has_branch {
+        return Err(invalid(format!("`[forge.pull_request]` in `{}` needs an `[output] branch` to push", file_str)));
+    }
+    if merged_mend.publish.is_some() && !has_branch {
+        return Err(invalid(format!("`[publish]` in `{}` needs an `[output] branch` to push", file_str)));
+    }
+    for (recipe_name, recipe) in &merged_mend.recipes {
+        if let Some(Err(err)) = recipe.timeout.as_deref().map(parse_timeout) {
+            return Err(invalid(format!("Recipe `{}` in `{}`: {:#}", recipe_name, file_str, err)));
+        }
+        if let Some(Err(err)) = recipe.progress_regex.as_deref().map(progress_regex) {
+            return Err(invalid(format!("Recipe `{}` in `{}` has an invalid progress_regex: {:#}", recipe_name, file_str, err)));
+        }
+    }
+    for recipe_entry in merged_mend.recipes.values_mut() {
+        // This allows users to specify either single "tag" or multiple "tags".
+        // Probably should be handled on the deserialization side
+        if let Some(tag) = recipe_entry.tag.take() {
+            recipe_entry.tags.push(tag);
+            recipe_entry.tag = None
+        }
+    }
+    let expand = |steps| expand_batch_steps(steps, parent_dir).map_err(|err| invalid(format!("Steps in `{}`: {:#}", file_str, err)));
+    merged_mend.steps = expand(std::mem::take(&mut merged_mend.steps))?;
+    for phase in merged_mend.phases.iter_mut() {
+        phase.steps = expand(std::mem::take(&mut phase.steps))?;
+    }
+    // Phase steps run after the top level ones, keep a single list and remember where each phase is
+    for phase in merged_mend.phases.iter_mut() {
+        phase.first_step = merged_mend.steps.len();
+        phase.step_count = phase.steps.len();
+        merged_mend.steps.append(&mut phase.steps);
+    }
+    let mut step_ids: Vec<String> = vec![];
+    for (i, step) in merged_mend.steps.iter().enumerate() {
+        let id = step.id(i);
+        if step_ids.contains(&id) {
+            return Err(invalid(format!("Step {} in `{}` reuses the id `{}`", i + 1, file_str, id)));
+        }
+        if let Some(target) = step.fixup() {
+            if !step_ids.contains(target) {
+                return Err(invalid(format!(
+                    "Step {} in `{}` is a fixup of `{}`, which is not the id of an earlier step",
+                    i + 1,
+                    file_str,
+                    target
+                )));
+            }
+        }
+        if let Some(need) = step.needs().into_iter().flatten().find(|need| !step_ids.contains(need)) {
+            return Err(invalid(format!(
+                "Step {} in `{}` needs `{}`, which is not the id of an earlier step",
+                i + 1,
+                file_str,
+                need
+            )));
+        }
+        if let Some(role) = step.committer_role().filter(|role| !merged_mend.committers.contains_key(*role)) {
+            return Err(invalid(format!("Step {} in `{}` commits as `{}`, which is not in [committers]", i + 1, file_str, role)));
+        }
+        if let Some(when) = step.when() {
+            if let Err(err) = Condition::parse(when) {
+                return Err(invalid(format!("Step {} in `{}` has an invalid `when`: {:#}", i + 1, file_str, err)));
+            }
+        }
+        step_ids.push(id);
+    }
+    for (i, step) in merged_mend.steps.iter().enumerate() {
+        let instruction = match step {
+            Step::Instruction(instruction) => Some(instruction),
+            Step::Structured(step_config) => step_config.run.as_ref(),
+        };
+        let words: Vec<&str> = instruction.map_or(vec![], |instruction| instruction.split_whitespace().collect());
+        if let Some((recipe_name, args)) = words.split_first() {
+            match merged_mend.recipes.get(*recipe_name) {
+                Some(recipe) if !recipe.params.is_empty() => {
+                    if let Err(err) = bind_params(recipe_name, &recipe.params, args) {
+                        return Err(invalid(format!("Step {} in `{}`: {}", i + 1, file_str, err)));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    for (i, step) in merged_mend.steps.iter_mut().enumerate() {
+        if let Step::Structured(step_config) = step {
+            if step_config.kind_count() != 1 {
+                return Err(invalid(format!(
+                    "Step {} in `{}` needs exactly one of `run`, `edit`, `openrewrite` or `jscodeshift`",
+                    i + 1,
+                    file_str
+                )));
+            }
+            if let Some(Err(err)) = step_config.timeout.as_deref().map(parse_timeout) {
+                return Err(invalid(format!("Step {} in `{}`: {:#}", i + 1, file_str, err)));
+            }
+            if let Some(jscodeshift) = &mut step_config.jscodeshift {
+                jscodeshift.resolve_transform(parent_dir);
+            }
+        }
+    }
+    Ok(merged_mend)
+}
+
+/// Merges what `mend` includes into `merged_mend`, includes of includes first.
+/// `chain` holds the canonical paths of the files including this one, to catch cycles.
+fn merge_includes(
+    merged_mend: &mut Mend,
+    file: &Path,
+    mend: &Mend,
+    cache_dir: &Path,
+    chain: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let file_str = file.to_str().unwrap_or_default();
+    let parent_dir = file.parent().unwrap_or(Path::new(""));
+    for include_file in &mend.include {
+        let (include_path, include_contents) =
+            read_include(parent_dir, cache_dir, include_file).with_context(|| {
                 format!(
                     "Could not read include file `{}` included from `{}`",
                     &include_file, file_str
                 )
             })?;
-        let include_mend: Mend = toml::from_str(&include_contents)
-            .with_context(|| format!("Unable to load data from `{}`", &include_file))?;
-        if !include_mend.steps.is_empty() {
-            return Err(anyhow!(
-                "We only allow includes 1 level deep, sorry. Please restructure `{}`",
+        let canonical_path = fs::canonicalize(&include_path).unwrap_or(include_path.clone());
+        if chain.contains(&canonical_path) {
+            let cycle: Vec<String> = chain
+                .iter()
+                .chain([&canonical_path])
+                .map(|path| path.to_string_lossy().to_string())
+                .collect();
+            return Err(invalid(format!("Includes form a cycle: {}", cycle.join(" -> "))));
+        }
+        let mut include_mend = parse_mend(&include_path, &include_contents)?;
+        read_run_files(&mut include_mend, &include_path)?;
+        if !include_mend.steps.is_empty() || !include_mend.phases.is_empty() {
+            return Err(invalid(format!(
+                "Only the main config can have steps, please move those of `{}` there",
                 &include_file
-            ));
+            )));
         }
-        crate::extend_mend(&mut merged_mend, include_mend);
+        chain.push(canonical_path);
+        merge_includes(merged_mend, &include_path, &include_mend, cache_dir, chain)?;
+        chain.pop();
+        crate::extend_mend(merged_mend, include_mend);
     }
-    crate::extend_mend(&mut merged_mend, main_mend);
-    for recipe_entry in merged_mend.recipes.values_mut() {
-        // This allows users to specify either single "tag" or multiple "tags".
-        // Probably should be handled on the deserialization side
-        match recipe_entry.tag.take() {
-            Some(tag) => {
-                recipe_entry.tags.push(tag);
-                recipe_entry.tag = None
-            }
-            None => {}
+    Ok(())
+}
+
+/// Reads the `run` of each recipe with a `run_file`, which becomes the path of the script.
+fn read_run_files(mend: &mut Mend, file: &Path) -> Result<()> {
+    let file_str = file.to_str().unwrap_or_default();
+    let parent_dir = file.parent().unwrap_or(Path::new(""));
+    for (recipe_name, recipe) in mend.recipes.iter_mut() {
+        let Some(run_file) = &recipe.run_file else {
+            continue;
+        };
+        if !recipe.run.is_empty() {
+            return Err(invalid(format!("Recipe `{}` in `{}` has both `run` and `run_file`", recipe_name, file_str)));
         }
+        let path = parent_dir.join(run_file);
+        recipe.run = fs::read_to_string(&path)
+            .with_context(|| format!("Could not read the run_file of recipe `{}` in `{}`", recipe_name, file_str))?;
+        recipe.run_file = Some(path.to_string_lossy().to_string());
     }
-    Ok(merged_mend)
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use crate::config::load_mend;
-    use std::path::PathBuf;
+    use crate::error::MendError;
+    use crate::run::{create_run_status_from_mend, plan_squash_groups};
+    use crate::select::StepSelection;
+    use std::fs;
+    use std::path::{Path, PathBuf};
 
     fn path_from_manifest(rel_path: &str) -> PathBuf {
         let mut toml_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -72,4 +315,139 @@ mod tests {
         let loaded = load_mend(toml_path.as_path());
         insta::assert_yaml_snapshot!(loaded.expect("Failed loading"));
     }
+
+    #[test]
+    fn includes_are_resolved_recursively_until_a_cycle() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, contents: &str| fs::write(temp_dir.path().join(name), contents).unwrap();
+        write("mend.toml", "include = [\"packs/cargo.toml\"]\nsteps = [\"clippy_fix\"]\n");
+        write("base.toml", "[recipes.fmt]\nrun = \"cargo fmt\"\n");
+        fs::create_dir(temp_dir.path().join("packs")).unwrap();
+        write("packs/cargo.toml", "include = [\"../base.toml\"]\n[recipes.clippy_fix]\nrun = \"cargo clippy --fix\"\n");
+        let mend = load_mend(&temp_dir.path().join("mend.toml")).unwrap();
+        assert_eq!(mend.recipes.keys().collect::<Vec<_>>(), vec!["clippy_fix", "fmt"]);
+
+        write("base.toml", "include = [\"packs/cargo.toml\"]\n");
+        let message = format!("{:#}", load_mend(&temp_dir.path().join("mend.toml")).unwrap_err());
+        assert!(message.starts_with("Includes form a cycle: "));
+        let cargo_path = fs::canonicalize(temp_dir.path().join("packs/cargo.toml")).unwrap();
+        assert!(message.contains("mend.toml -> "));
+        assert!(message.ends_with(&format!("base.toml -> {}", cargo_path.to_string_lossy())));
+    }
+
+    #[test]
+    fn yaml_config_with_mixed_includes_loads_like_toml() {
+        let yaml = load_mend(path_from_manifest("tests/data/yaml/mend.yml").as_path()).unwrap();
+        let mut toml = load_mend(path_from_manifest("examples/mend.toml").as_path()).unwrap();
+        toml.include = yaml.include.clone();
+        assert_eq!(yaml, toml);
+    }
+
+    #[test]
+    fn phases_are_flattened_into_steps() {
+        let mend = load_mend(path_from_manifest("tests/data/phases.toml").as_path()).unwrap();
+        assert_eq!(mend.steps.len(), 4);
+        assert!(mend.phases.iter().all(|phase| phase.steps.is_empty()));
+        let step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
+        insta::assert_yaml_snapshot!(plan_squash_groups(&mend, &step_requests));
+    }
+
+    #[test]
+    fn fixup_must_target_earlier_step() {
+        let result = load_mend(path_from_manifest("tests/data/fixup-unknown.toml").as_path());
+        let message = format!("{:#}", result.err().unwrap());
+        assert!(message.contains("is a fixup of `format`, which is not the id of an earlier step"));
+    }
+
+    #[test]
+    fn needs_must_name_earlier_steps() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("mend.toml");
+        fs::write(&path, "[[steps]]\nid = \"fmt\"\nrun = \"cargo fmt\"\n[[steps]]\nrun = \"cargo fix\"\nneeds = [\"fmt\", \"lint\"]\n").unwrap();
+        let message = format!("{:#}", load_mend(&path).unwrap_err());
+        assert!(message.contains("Step 2 in "), "{}", message);
+        assert!(message.contains("needs `lint`, which is not the id of an earlier step"), "{}", message);
+    }
+
+    #[test]
+    fn invalid_when_is_reported_at_load() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("mend.toml");
+        fs::write(&path, "[[steps]]\nrun = \"cargo fmt\"\nwhen = \"env.CI = 'true'\"\n").unwrap();
+        let message = format!("{:#}", load_mend(&path).unwrap_err());
+        assert!(message.starts_with("Step 1 in "), "{}", message);
+        assert!(message.contains("has an invalid `when`"), "{}", message);
+    }
+
+    #[test]
+    fn committer_role_must_name_a_committer() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("mend.toml");
+        fs::write(&path, "[committers.bot]\nname = \"mend-bot\"\nemail = \"bot@example.com\"\n[[steps]]\nrun = \"cargo fmt\"\ncommitter_role = \"bot\"\n[[steps]]\nrun = \"cargo fix\"\ncommitter_role = \"release\"\n").unwrap();
+        let message = format!("{:#}", load_mend(&path).unwrap_err());
+        assert!(message.starts_with("Step 2 in "), "{}", message);
+        assert!(message.contains("commits as `release`, which is not in [committers]"), "{}", message);
+    }
+
+    #[test]
+    fn from_needs_a_sha_or_a_ref() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("mend.toml");
+        fs::write(&path, "from = { repo = \"~/app\", ref = \"origin/main\", fetch = true }\n").unwrap();
+        assert!(load_mend(&path).is_ok());
+        fs::write(&path, "from = { repo = \"~/app\" }\n").unwrap();
+        assert!(format!("{:#}", load_mend(&path).unwrap_err()).contains("needs a `sha` or a `ref`"));
+        fs::write(&path, "from = { repo = \"~/app\", sha = \"2d62d13\", ref = \"v1.2\" }\n").unwrap();
+        assert!(format!("{:#}", load_mend(&path).unwrap_err()).contains("has both a `sha` and a `ref`"));
+    }
+
+    #[test]
+    fn batch_steps_are_committed_in_chunks() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::write(temp_dir.path().join("renames.csv"), "Foo,Bar\nBaz,Qux\nOld,New\n").unwrap();
+        let path = temp_dir.path().join("mend.toml");
+        fs::write(&path, "[[steps]]\nrun = \"format\"\n[[steps]]\nrun = \"rename @renames.csv\"\nchunk_size = 2\n").unwrap();
+        let mend = load_mend(&path).unwrap();
+        assert_eq!(mend.steps.len(), 4);
+        let step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
+        insta::assert_yaml_snapshot!(plan_squash_groups(&mend, &step_requests));
+
+        fs::write(&path, "[[steps]]\nrun = \"rename Foo Bar\"\nchunk_size = 2\n").unwrap();
+        let message = format!("{:#}", load_mend(&path).unwrap_err());
+        assert!(message.contains("`chunk_size` needs a step with an `@file.csv` or `@file.tsv` argument"), "{}", message);
+    }
+
+    #[test]
+    fn recipes_can_be_read_from_run_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::create_dir(temp_dir.path().join("recipes")).unwrap();
+        fs::write(temp_dir.path().join("recipes/rename.sh"), "sed -i s/$1/$2/ App.java\n").unwrap();
+        let path = temp_dir.path().join("mend.toml");
+        fs::write(&path, "steps = [\"rename Foo Bar\"]\n[recipes.rename]\nrun_file = \"recipes/rename.sh\"\n").unwrap();
+        let mend = load_mend(&path).unwrap();
+        assert_eq!(mend.recipes["rename"].run, "sed -i s/$1/$2/ App.java\n");
+        assert_eq!(mend.recipes["rename"].run_file.as_deref().map(Path::new), Some(temp_dir.path().join("recipes/rename.sh").as_path()));
+
+        fs::write(&path, "[recipes.rename]\nrun = \"rename-tool\"\nrun_file = \"recipes/rename.sh\"\n").unwrap();
+        let message = format!("{:#}", load_mend(&path).unwrap_err());
+        assert!(message.contains("has both `run` and `run_file`"), "{}", message);
+    }
+
+    #[test]
+    fn errors_can_be_told_apart() {
+        let fixup_error = load_mend(path_from_manifest("tests/data/fixup-unknown.toml").as_path()).unwrap_err();
+        assert!(matches!(fixup_error, MendError::Validation(_)));
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("mend.toml");
+        fs::write(&path, "steps = [\"format\"]\n\n[recipes\n").unwrap();
+        let parse_error = load_mend(&path).unwrap_err();
+        match &parse_error {
+            MendError::Config { file, line, .. } => {
+                assert_eq!(file, &path);
+                assert_eq!(*line, Some(3));
+            }
+            _ => panic!("Expected a config error, got {:#}", parse_error),
+        }
+    }
 }
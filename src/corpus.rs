@@ -0,0 +1,188 @@
+use anyhow::Context;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use crate::progress::Notify;
+use crate::repo::{ensure_worktree, remove_worktree, GitRepo, Repo, MEND_DIR};
+use crate::run::{run_all_steps, EStatus, Executor, RunOptions, RunSummary, StepRequest, StepResponse};
+
+/// A `[[corpus]]` entry, a sample repository `mend verify-recipes` tries the recipes on.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct CorpusRepo {
+    /// A local checkout or a URL, cloned into the clone cache like `from.repo`
+    pub repo: String,
+    /// The commit, branch or tag each step starts from
+    pub sha: String,
+    /// Invocations of the recipes to try on the repo, each on its own from `sha`
+    #[serde(default)]
+    pub steps: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct VerifyRecipesArgs {
+    /// Only try these recipes, comma separated
+    #[arg(long = "recipes", value_delimiter = ',')]
+    pub recipes: Vec<String>,
+
+    /// Fail when a recipe succeeds on fewer than this percentage of its samples
+    #[arg(long = "min-success", default_value_t = 100)]
+    pub min_success: u8,
+
+    /// Where each sample's diff is written
+    #[arg(long = "diffs-dir", default_value = "mend-verify-recipes")]
+    pub diffs_dir: PathBuf,
+
+    /// Skip the verify commands, for a quicker look at which recipes apply
+    #[arg(long = "no-verify")]
+    pub no_verify: bool,
+}
+
+/// How a recipe fared on one sample.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SampleResult {
+    pub repo: String,
+    pub recipe: String,
+    pub run: String,
+    pub status: EStatus,
+}
+
+/// Samples run unattended, what they did is reported once each is over.
+pub struct QuietNotifier;
+
+impl Notify for QuietNotifier {
+    fn notify(&mut self, _i: usize, _run: &str, _status: &EStatus, _sha: &Option<String>, _inc: bool) {}
+    fn notify_done(&self, _summary: &RunSummary) {}
+    fn notify_failure(&self, _failed_request: &StepRequest, _failed_response: &StepResponse) {}
+}
+
+/// The recipe a step invokes.
+pub fn recipe_name(step_request: &StepRequest) -> &str {
+    step_request.run.split_whitespace().next().unwrap_or_default()
+}
+
+/// Runs the one step on `sha` in a throwaway worktree of `base_repo_dir`, returning how it ended and what it changed.
+pub fn verify_sample<E: Executor>(
+    base_repo_dir: &Path,
+    sha: &str,
+    step_request: StepRequest,
+    executor: &mut E,
+    options: &RunOptions,
+) -> anyhow::Result<(EStatus, String)> {
+    let work_dir = ensure_worktree(base_repo_dir, &format!("{}/verify-recipes", MEND_DIR), sha)
+        .with_context(|| format!("Could not check out `{}`", sha))?;
+    let mut repo = GitRepo { repo_dir: work_dir.clone() };
+    let from_sha = repo.current_short_sha()?;
+    let options = RunOptions {
+        continue_on_error: true,
+        env: options.env.clone(),
+        ..Default::default()
+    };
+    let status = match run_all_steps(vec![step_request], &mut QuietNotifier, &mut repo, executor, &options) {
+        Ok(summary) if !summary.skipped_steps.is_empty() => EStatus::Skipped,
+        Ok(summary) => summary.failures.first().map_or(EStatus::Done, |(_, step_response)| step_response.status),
+        Err(failure) => failure.1.status,
+    };
+    let diff = if status == EStatus::Done { repo.diff(&from_sha)? } else { String::new() };
+    // The commits stay behind unreferenced, git collects them eventually
+    remove_worktree(&work_dir)?;
+    Ok((status, diff))
+}
+
+/// For each recipe, how many of its samples it succeeded on out of those it ran on. Skipped samples don't count.
+pub fn success_rates(results: &[SampleResult]) -> BTreeMap<String, (usize, usize)> {
+    let mut rates: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    for result in results.iter().filter(|result| result.status != EStatus::Skipped) {
+        let (succeeded, total) = rates.entry(result.recipe.clone()).or_default();
+        *total += 1;
+        if result.status == EStatus::Done {
+            *succeeded += 1;
+        }
+    }
+    rates
+}
+
+/// Rounded down, a recipe without samples counts as succeeding.
+pub fn percentage(succeeded: usize, total: usize) -> usize {
+    (succeeded * 100).checked_div(total).unwrap_or(100)
+}
+
+/// A table of each recipe's success rate, followed by the samples it failed on.
+pub fn render_success_rates(results: &[SampleResult]) -> String {
+    let rates = success_rates(results);
+    let width = rates.keys().map(|recipe| recipe.chars().count()).max().unwrap_or_default().max("Recipe".len());
+    let mut text = format!("{:<width$}  Succeeded\n", "Recipe", width = width);
+    for (recipe, (succeeded, total)) in &rates {
+        let _ = writeln!(
+            text,
+            "{:<width$}  {} of {} ({}%)",
+            recipe,
+            succeeded,
+            total,
+            percentage(*succeeded, *total),
+            width = width
+        );
+    }
+    let failed: Vec<&SampleResult> = results.iter().filter(|result| result.status.is_failed()).collect();
+    if !failed.is_empty() {
+        let _ = writeln!(text, "\nFailed:");
+        for result in failed {
+            let verify = if result.status == EStatus::VerifyFailed { " (verify)" } else { "" };
+            let _ = writeln!(text, "  {} on {}{}", result.run, result.repo, verify);
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::corpus::{render_success_rates, verify_sample, SampleResult};
+    use crate::run::{EStatus, RunOptions, ShellExecutor, StepRequest};
+    use crate::test_support::git;
+    use std::fs;
+
+    #[test]
+    fn samples_run_in_throwaway_worktrees() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let repo_dir = temp_dir.path();
+        git(repo_dir, &["init", "-q", "-b", "main"]);
+        fs::write(repo_dir.join("App.java"), "class Foo {}\n").unwrap();
+        git(repo_dir, &["add", "App.java"]);
+        git(repo_dir, &["commit", "-q", "-m", "Foo"]);
+        let step = |script: &str| StepRequest {
+            run: "rename Foo Bar".to_string(),
+            run_resolved: vec![script.to_string()],
+            commit_msg: "rename Foo Bar".to_string(),
+            ..Default::default()
+        };
+        let mut executor = ShellExecutor::default();
+        let (status, diff) =
+            verify_sample(repo_dir, "main", step("sed -i.bak s/Foo/Bar/ App.java && rm App.java.bak"), &mut executor, &RunOptions::default()).unwrap();
+        assert_eq!(status, EStatus::Done);
+        assert!(diff.contains("-class Foo {}\n+class Bar {}"));
+        let (status, diff) = verify_sample(repo_dir, "main", step("grep -q Baz App.java"), &mut executor, &RunOptions::default()).unwrap();
+        assert_eq!(status, EStatus::Failed);
+        assert!(diff.is_empty());
+        // Nothing is left behind
+        assert!(!repo_dir.join(".mend/verify-recipes").exists());
+        assert_eq!(fs::read_to_string(repo_dir.join("App.java")).unwrap(), "class Foo {}\n");
+
+        let result = |repo: &str, run: &str, status: EStatus| SampleResult {
+            repo: repo.to_string(),
+            recipe: run.split_whitespace().next().unwrap().to_string(),
+            run: run.to_string(),
+            status,
+        };
+        let results = vec![
+            result("org/app", "rename Foo Bar", EStatus::Done),
+            result("org/app", "format", EStatus::Done),
+            result("org/lib", "rename Baz Qux", EStatus::Failed),
+            result("org/lib", "format", EStatus::VerifyFailed),
+            result("org/cli", "rename Cli Tool", EStatus::Done),
+            result("org/cli", "format", EStatus::Skipped),
+        ];
+        insta::assert_snapshot!(render_success_rates(&results));
+    }
+}
@@ -0,0 +1,286 @@
+use anyhow::{bail, Context};
+use clap::{Args, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::lock::read_lock;
+use crate::logs::{status_label, step_log_path};
+use crate::progress::Notify;
+use crate::run::{EStatus, RunSummary, StepRequest, StepResponse};
+
+/// Under `.mend`, what `mend ctl` asks of the run going on.
+const CONTROL_FILE: &str = "control.json";
+/// Under `.mend`, the output of the steps `mend ctl tail` follows, a file per step named like their logs.
+const TAIL_DIR: &str = "tail";
+/// Ends a step's tail file, followed by its status.
+const TAIL_END: &str = "mend:tail-end ";
+/// How often the run looks for new requests and `mend ctl tail` for new output.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Args, Debug)]
+pub struct CtlArgs {
+    #[command(subcommand)]
+    pub command: CtlCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CtlCommand {
+    /// Change how much the run going on shows, without restarting it
+    Verbosity { level: Verbosity },
+    /// Print what a step of the run going on prints, as it's printed, until the step ends
+    Tail {
+        /// The step's id, or its number counting from 1
+        step_id: String,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Verbosity {
+    /// Progress only
+    Normal,
+    /// The lines scripts print too, like --verbose
+    Debug,
+}
+
+/// Written by `mend ctl` to `.mend/control.json`, read by the run as it goes.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ControlRequests {
+    pub verbosity: Option<Verbosity>,
+    /// Steps whose output is copied to `.mend/tail`, counting from 1
+    #[serde(default)]
+    pub tail: Vec<usize>,
+}
+
+fn read_requests(path: &Path) -> Option<ControlRequests> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+fn is_running(mend_dir: &Path) -> bool {
+    read_lock(mend_dir).is_some_and(|lock| lock.is_alive())
+}
+
+/// Changes the requests of the run going on, written to another file first so the run never reads half of them.
+fn update_requests(mend_dir: &Path, update: impl FnOnce(&mut ControlRequests)) -> anyhow::Result<()> {
+    if !is_running(mend_dir) {
+        bail!("No mend run is going in `{}`", mend_dir.to_string_lossy());
+    }
+    let path = mend_dir.join(CONTROL_FILE);
+    let mut requests = read_requests(&path).unwrap_or_default();
+    update(&mut requests);
+    let partial_path = path.with_extension("json.partial");
+    fs::write(&partial_path, serde_json::to_string(&requests)?)
+        .with_context(|| format!("Could not write `{}`", partial_path.to_string_lossy()))?;
+    fs::rename(&partial_path, &path).with_context(|| format!("Could not write `{}`", path.to_string_lossy()))
+}
+
+pub fn set_verbosity(mend_dir: &Path, verbosity: Verbosity) -> anyhow::Result<()> {
+    update_requests(mend_dir, |requests| requests.verbosity = Some(verbosity))?;
+    eprintln!("Asked the run for {} output", if verbosity == Verbosity::Debug { "debug" } else { "normal" });
+    Ok(())
+}
+
+/// Prints the step's output to `out` as the run copies it to the step's tail file, until the step or the run ends.
+pub fn tail<W: Write>(mend_dir: &Path, step_i: usize, out: &mut W, interval: Duration) -> anyhow::Result<()> {
+    update_requests(mend_dir, |requests| {
+        if !requests.tail.contains(&(step_i + 1)) {
+            requests.tail.push(step_i + 1);
+        }
+    })?;
+    let path = step_log_path(&mend_dir.join(TAIL_DIR), step_i);
+    let mut printed = 0;
+    loop {
+        // Checked before reading, so what the run wrote before it ended is still printed
+        let running = is_running(mend_dir);
+        let text = fs::read_to_string(&path).unwrap_or_default();
+        // A line still being written is printed once it's whole
+        let complete = text.rfind('\n').map_or(0, |end| end + 1);
+        for line in text.get(printed..complete).unwrap_or_default().lines() {
+            if let Some(status) = line.strip_prefix(TAIL_END) {
+                writeln!(out, "Step {} {}", step_i + 1, status)?;
+                return Ok(());
+            }
+            writeln!(out, "{}", line)?;
+        }
+        printed = printed.max(complete);
+        out.flush()?;
+        if !running {
+            bail!("The run ended before step {} did", step_i + 1);
+        }
+        thread::sleep(interval);
+    }
+}
+
+/// Passes everything on to `inner` and, every `POLL_INTERVAL` at most, does what `mend ctl` asked for since.
+pub struct ControlNotifier<N: Notify> {
+    inner: N,
+    path: PathBuf,
+    tail_dir: PathBuf,
+    interval: Duration,
+    last_checked: Option<Instant>,
+    modified: Option<SystemTime>,
+    requests: ControlRequests,
+    /// Status of the steps that ended, for tails asked for once they had
+    finished: BTreeMap<usize, EStatus>,
+}
+
+impl<N: Notify> ControlNotifier<N> {
+    /// Requests and tails left behind by an earlier run are removed.
+    pub fn new(inner: N, mend_dir: &Path) -> Self {
+        let path = mend_dir.join(CONTROL_FILE);
+        let tail_dir = mend_dir.join(TAIL_DIR);
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_dir_all(&tail_dir);
+        ControlNotifier {
+            inner,
+            path,
+            tail_dir,
+            interval: POLL_INTERVAL,
+            last_checked: None,
+            modified: None,
+            requests: ControlRequests::default(),
+            finished: BTreeMap::new(),
+        }
+    }
+
+    fn check_requests(&mut self) {
+        if self.last_checked.is_some_and(|last_checked| last_checked.elapsed() < self.interval) {
+            return;
+        }
+        self.last_checked = Some(Instant::now());
+        let modified = fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok();
+        if modified.is_none() || modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+        let Some(requests) = read_requests(&self.path) else {
+            return;
+        };
+        if let Some(verbosity) = requests.verbosity.filter(|verbosity| Some(*verbosity) != self.requests.verbosity) {
+            self.inner.set_verbose(verbosity == Verbosity::Debug);
+        }
+        for step in requests.tail.iter().filter(|step| !self.requests.tail.contains(step)) {
+            if let Some(status) = step.checked_sub(1).and_then(|step_i| self.finished.get(&step_i)) {
+                self.append_tail(step - 1, &format!("{}{}", TAIL_END, status_label(status)));
+            }
+        }
+        self.requests = requests;
+    }
+
+    fn append_tail(&self, i: usize, line: &str) {
+        let path = step_log_path(&self.tail_dir, i);
+        let appended = fs::create_dir_all(&self.tail_dir)
+            .and_then(|_| OpenOptions::new().create(true).append(true).open(&path))
+            .and_then(|mut file| writeln!(file, "{}", line));
+        if let Err(err) = appended {
+            eprintln!("Could not write `{}`: {}", path.to_string_lossy(), err);
+        }
+    }
+
+    fn is_tailed(&self, i: usize) -> bool {
+        self.requests.tail.contains(&(i + 1))
+    }
+}
+
+impl<N: Notify> Notify for ControlNotifier<N> {
+    fn notify(&mut self, i: usize, run: &str, status: &EStatus, sha: &Option<String>, inc: bool) {
+        self.check_requests();
+        if matches!(status, EStatus::Done | EStatus::Failed | EStatus::VerifyFailed | EStatus::Skipped) {
+            self.finished.insert(i, *status);
+            if self.is_tailed(i) {
+                self.append_tail(i, &format!("{}{}", TAIL_END, status_label(status)));
+            }
+        }
+        self.inner.notify(i, run, status, sha, inc)
+    }
+
+    fn notify_done(&self, summary: &RunSummary) {
+        self.inner.notify_done(summary)
+    }
+
+    fn notify_failure(&self, failed_request: &StepRequest, failed_response: &StepResponse) {
+        self.inner.notify_failure(failed_request, failed_response)
+    }
+
+    fn notify_output(&mut self, i: usize, line: &str) {
+        self.check_requests();
+        if self.is_tailed(i) {
+            self.append_tail(i, line);
+        }
+        self.inner.notify_output(i, line)
+    }
+
+    fn set_verbose(&mut self, verbose: bool) {
+        self.inner.set_verbose(verbose)
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        self.inner.suspend(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ctl::{tail, ControlNotifier, Verbosity, CONTROL_FILE};
+    use crate::lock::acquire_lock;
+    use crate::progress::Notify;
+    use crate::run::{EStatus, RunSummary, StepRequest, StepResponse};
+    use std::fs;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct VerbosityNotifier {
+        verbose: Vec<bool>,
+        lines: Vec<String>,
+    }
+
+    impl Notify for VerbosityNotifier {
+        fn notify(&mut self, _i: usize, _run: &str, _status: &EStatus, _sha: &Option<String>, _inc: bool) {}
+        fn notify_done(&self, _summary: &RunSummary) {}
+        fn notify_failure(&self, _failed_request: &StepRequest, _failed_response: &StepResponse) {}
+        fn notify_output(&mut self, _i: usize, line: &str) {
+            self.lines.push(line.to_string());
+        }
+        fn set_verbose(&mut self, verbose: bool) {
+            self.verbose.push(verbose);
+        }
+    }
+
+    #[test]
+    fn ctl_changes_verbosity_and_tails_steps_of_the_running_run() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mend_dir = temp_dir.path();
+        assert!(super::set_verbosity(mend_dir, Verbosity::Debug).is_err());
+        let _lock = acquire_lock(mend_dir, "mend.toml", 3).unwrap();
+        let mut notifier = ControlNotifier::new(VerbosityNotifier::default(), mend_dir);
+        notifier.interval = Duration::ZERO;
+        notifier.notify(0, "echo first", &EStatus::Done, &None, true);
+
+        super::set_verbosity(mend_dir, Verbosity::Debug).unwrap();
+        notifier.notify_output(1, "compiling 1 of 2");
+        let tail_dir = mend_dir.join("tail");
+        assert!(!tail_dir.exists());
+        // Followed from here on, with the first step ended already
+        let mut requests: super::ControlRequests = serde_json::from_str(&fs::read_to_string(mend_dir.join(CONTROL_FILE)).unwrap()).unwrap();
+        requests.tail = vec![1, 2];
+        fs::write(mend_dir.join(CONTROL_FILE), serde_json::to_string(&requests).unwrap()).unwrap();
+        // The file's modification time may not have moved on
+        notifier.modified = None;
+        notifier.notify_output(1, "compiling 2 of 2");
+        notifier.notify(1, "cargo build", &EStatus::VerifyFailed, &None, true);
+        assert_eq!(notifier.inner.verbose, vec![true]);
+        assert_eq!(notifier.inner.lines, vec!["compiling 1 of 2", "compiling 2 of 2"]);
+
+        let mut out = vec![];
+        tail(mend_dir, 0, &mut out, Duration::ZERO).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Step 1 done\n");
+        let mut out = vec![];
+        tail(mend_dir, 1, &mut out, Duration::ZERO).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "compiling 2 of 2\nStep 2 verify failed\n");
+    }
+}
@@ -0,0 +1,219 @@
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use crate::{Mend, Step};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ProjectKind {
+    Cargo,
+    Maven,
+    Gradle,
+    Npm,
+    Go,
+}
+
+/// Recognizes build systems from marker files at the root of `dir`.
+pub fn detect_projects(dir: &Path) -> Vec<ProjectKind> {
+    let mut kinds = vec![];
+    if dir.join("Cargo.toml").exists() {
+        kinds.push(ProjectKind::Cargo);
+    }
+    if dir.join("pom.xml").exists() {
+        kinds.push(ProjectKind::Maven);
+    }
+    if dir.join("build.gradle").exists() || dir.join("build.gradle.kts").exists() {
+        kinds.push(ProjectKind::Gradle);
+    }
+    if dir.join("package.json").exists() {
+        kinds.push(ProjectKind::Npm);
+    }
+    if dir.join("go.mod").exists() {
+        kinds.push(ProjectKind::Go);
+    }
+    kinds
+}
+
+impl ProjectKind {
+    pub fn verify_command(&self, dir: &Path) -> Option<String> {
+        match self {
+            ProjectKind::Cargo => Some("cargo check".to_string()),
+            ProjectKind::Maven => Some(if dir.join("mvnw").exists() {
+                "./mvnw -B test".to_string()
+            } else {
+                "mvn -B test".to_string()
+            }),
+            ProjectKind::Gradle => Some(if dir.join("gradlew").exists() {
+                "./gradlew test".to_string()
+            } else {
+                "gradle test".to_string()
+            }),
+            ProjectKind::Npm => npm_verify_command(dir),
+            ProjectKind::Go => Some("go test ./...".to_string()),
+        }
+    }
+}
+
+fn npm_verify_command(dir: &Path) -> Option<String> {
+    // Only when there is a test script, `npm test` fails on a fresh `npm init` project
+    let package_json: Value = serde_json::from_str(&fs::read_to_string(dir.join("package.json")).ok()?).ok()?;
+    package_json.get("scripts")?.get("test")?;
+    let runner = if dir.join("pnpm-lock.yaml").exists() {
+        "pnpm"
+    } else if dir.join("yarn.lock").exists() {
+        "yarn"
+    } else {
+        "npm"
+    };
+    Some(format!("{} test", runner))
+}
+
+/// The verify command to use when the config doesn't specify one, if any project is recognized.
+pub fn default_verify_command(dir: &Path) -> Option<String> {
+    let commands: Vec<String> = detect_projects(dir)
+        .iter()
+        .filter_map(|kind| kind.verify_command(dir))
+        .collect();
+    if commands.is_empty() {
+        None
+    } else {
+        Some(commands.join(" && "))
+    }
+}
+
+const LANGUAGE_EXTENSIONS: &[(&str, &[&str])] = &[
+    ("c", &["c", "h"]),
+    ("cpp", &["cc", "cpp", "cxx", "hh", "hpp", "hxx"]),
+    ("csharp", &["cs"]),
+    ("go", &["go"]),
+    ("java", &["java"]),
+    ("javascript", &["js", "jsx", "mjs", "cjs"]),
+    ("kotlin", &["kt", "kts"]),
+    ("php", &["php"]),
+    ("python", &["py"]),
+    ("ruby", &["rb"]),
+    ("rust", &["rs"]),
+    ("scala", &["scala"]),
+    ("shell", &["sh", "bash"]),
+    ("swift", &["swift"]),
+    ("typescript", &["ts", "tsx"]),
+];
+
+/// Languages with at least one file among `files`, recognized by extension.
+pub fn detect_languages(files: &[String]) -> BTreeSet<String> {
+    files
+        .iter()
+        .filter_map(|file| Path::new(file).extension()?.to_str())
+        .filter_map(|extension| {
+            let extension = extension.to_ascii_lowercase();
+            LANGUAGE_EXTENSIONS
+                .iter()
+                .find(|(_, extensions)| extensions.contains(&extension.as_str()))
+                .map(|(language, _)| language.to_string())
+        })
+        .collect()
+}
+
+/// Steps using a recipe for languages of which no file is present, likely a config meant for another repo.
+pub fn language_warnings(mend: &Mend, present: &BTreeSet<String>) -> Vec<String> {
+    let mut warnings = vec![];
+    for (step_i, step) in mend.steps.iter().enumerate() {
+        let instruction = match step {
+            Step::Instruction(instruction) => instruction,
+            Step::Structured(step_config) => match &step_config.run {
+                Some(run) => run,
+                None => continue,
+            },
+        };
+        let recipe_name = instruction.split_whitespace().next().unwrap_or_default();
+        if let Some(recipe) = mend.recipes.get(recipe_name) {
+            let targets_present = recipe
+                .languages
+                .iter()
+                .any(|language| present.contains(&language.to_ascii_lowercase()));
+            if !recipe.languages.is_empty() && !targets_present {
+                warnings.push(format!(
+                    "step {} `{}` uses recipe `{}` for {}, but no such files are in the worktree",
+                    step_i + 1,
+                    instruction.trim(),
+                    recipe_name,
+                    recipe.languages.join(", ")
+                ));
+            }
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detect::{default_verify_command, detect_languages, detect_projects, language_warnings, ProjectKind};
+    use crate::{Mend, Recipe};
+    use std::fs;
+
+    #[test]
+    fn detects_nothing_in_empty_dir() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        assert!(detect_projects(temp_dir.path()).is_empty());
+        assert_eq!(default_verify_command(temp_dir.path()), None);
+    }
+
+    #[test]
+    fn detects_gradle_wrapper_and_npm_tests() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::write(temp_dir.path().join("build.gradle.kts"), "").unwrap();
+        fs::write(temp_dir.path().join("gradlew"), "").unwrap();
+        fs::write(temp_dir.path().join("package.json"), r#"{"scripts": {"test": "jest"}}"#).unwrap();
+        fs::write(temp_dir.path().join("yarn.lock"), "").unwrap();
+        assert_eq!(
+            detect_projects(temp_dir.path()),
+            vec![ProjectKind::Gradle, ProjectKind::Npm]
+        );
+        assert_eq!(
+            default_verify_command(temp_dir.path()),
+            Some("./gradlew test && yarn test".to_string())
+        );
+    }
+
+    #[test]
+    fn skips_npm_without_test_script() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::write(temp_dir.path().join("package.json"), r#"{"name": "x"}"#).unwrap();
+        fs::write(temp_dir.path().join("Cargo.toml"), "").unwrap();
+        assert_eq!(
+            default_verify_command(temp_dir.path()),
+            Some("cargo check".to_string())
+        );
+    }
+
+    #[test]
+    fn warns_about_recipes_for_absent_languages() {
+        let files = vec![
+            "src/Main.java".to_string(),
+            "build.gradle.kts".to_string(),
+            "README".to_string(),
+        ];
+        let present = detect_languages(&files);
+        assert_eq!(present.iter().collect::<Vec<_>>(), vec!["java", "kotlin"]);
+        let mut mend: Mend = toml::from_str(r#"steps = ["to_kotlin Main", "to_ts app", "echo hi"]"#).unwrap();
+        mend.recipes.insert(
+            "to_kotlin".to_string(),
+            Recipe {
+                languages: vec!["Java".to_string()],
+                ..Default::default()
+            },
+        );
+        mend.recipes.insert(
+            "to_ts".to_string(),
+            Recipe {
+                languages: vec!["javascript".to_string()],
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            language_warnings(&mend, &present),
+            vec!["step 2 `to_ts app` uses recipe `to_ts` for javascript, but no such files are in the worktree".to_string()]
+        );
+    }
+}
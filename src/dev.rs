@@ -0,0 +1,149 @@
+use anyhow::{anyhow, Context};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::corpus::{recipe_name, QuietNotifier};
+use crate::repo::{ensure_worktree, GitRepo, Repo, MEND_DIR};
+use crate::run::{run_all_steps, EStatus, Executor, RunOptions, StepRequest};
+use crate::Mend;
+
+/// How often the watched files are looked at.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+/// How one run of the step went.
+#[derive(Debug, PartialEq)]
+pub struct DevRun {
+    pub status: EStatus,
+    /// What the step printed when it failed
+    pub output: String,
+    /// What it changed when it succeeded
+    pub diff: String,
+}
+
+/// The index of the step `step_id` names, its id or its number counting from 1.
+pub fn step_index(step_requests: &[StepRequest], step_id: &str) -> anyhow::Result<usize> {
+    step_requests
+        .iter()
+        .position(|step_request| step_request.id == step_id)
+        .or_else(|| step_id.parse::<usize>().ok().filter(|number| (1..=step_requests.len()).contains(number)).map(|number| number - 1))
+        .ok_or_else(|| anyhow!("No step with id or number `{}`", step_id))
+}
+
+/// The config and the `run_file` of the step's recipe, whose changes re-run the step.
+pub fn watched_files(mend: &Mend, config_path: &Path, step_request: &StepRequest) -> Vec<PathBuf> {
+    let mut files = vec![config_path.to_path_buf()];
+    if let Some(recipe) = mend.recipes.get(recipe_name(step_request)) {
+        files.extend(recipe.run_file.as_ref().map(PathBuf::from));
+    }
+    files
+}
+
+fn modified_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files.iter().map(|file| fs::metadata(file).and_then(|metadata| metadata.modified()).ok()).collect()
+}
+
+/// Blocks until one of `files` is written, removed or created, returning the first that changed.
+pub fn wait_for_change(files: &[PathBuf], interval: Duration) -> PathBuf {
+    let before = modified_times(files);
+    loop {
+        thread::sleep(interval);
+        let now = modified_times(files);
+        if let Some(changed) = before.iter().zip(&now).position(|(before, now)| before != now) {
+            return files[changed].clone();
+        }
+    }
+}
+
+/// Runs the step on `sha` in the worktree `.mend/dev`, set up again each time so every run starts from the same
+/// files. The worktree is left as the step left it, to be looked at.
+pub fn run_dev_step<E: Executor>(
+    base_repo_dir: &Path,
+    sha: &str,
+    step_request: StepRequest,
+    executor: &mut E,
+    options: &RunOptions,
+) -> anyhow::Result<DevRun> {
+    let work_dir = ensure_worktree(base_repo_dir, &format!("{}/dev", MEND_DIR), sha)
+        .with_context(|| format!("Could not check out `{}`", sha))?;
+    let mut repo = GitRepo { repo_dir: work_dir };
+    let from_sha = repo.current_short_sha()?;
+    let options = RunOptions {
+        env: options.env.clone(),
+        ..Default::default()
+    };
+    let (status, output) = match run_all_steps(vec![step_request], &mut QuietNotifier, &mut repo, executor, &options) {
+        Ok(summary) if !summary.skipped_steps.is_empty() => (EStatus::Skipped, String::new()),
+        Ok(_) => (EStatus::Done, String::new()),
+        Err(failure) => (failure.1.status, failure.1.output.unwrap_or_default()),
+    };
+    let diff = if status == EStatus::Done { repo.diff(&from_sha)? } else { String::new() };
+    Ok(DevRun { status, output, diff })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dev::{run_dev_step, step_index, wait_for_change, watched_files};
+    use crate::run::{create_run_status_from_mend, EStatus, RunOptions, ShellExecutor};
+    use crate::select::StepSelection;
+    use crate::Mend;
+    use crate::test_support::git;
+    use std::fs;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn dev_reruns_the_step_from_the_same_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let repo_dir = temp_dir.path();
+        git(repo_dir, &["init", "-q", "-b", "main"]);
+        fs::write(repo_dir.join("App.java"), "class Foo {}\n").unwrap();
+        git(repo_dir, &["add", "App.java"]);
+        git(repo_dir, &["commit", "-q", "-m", "Foo"]);
+        let run_file = repo_dir.join("rename.sh");
+        let mut mend: Mend = toml::from_str(
+            r#"
+steps = ["echo first", { id = "rename", run = "rename Foo Bar" }]
+
+[recipes.rename]
+run = "sed -i.bak s/$1/$2/ App.java && rm App.java.bak"
+"#,
+        )
+        .unwrap();
+        mend.recipes.get_mut("rename").unwrap().run_file = Some(run_file.to_string_lossy().to_string());
+        let step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
+        assert_eq!(step_index(&step_requests, "rename").unwrap(), 1);
+        assert_eq!(step_index(&step_requests, "1").unwrap(), 0);
+        assert!(step_index(&step_requests, "3").is_err());
+        let config_path = repo_dir.join("mend.toml");
+        assert_eq!(watched_files(&mend, &config_path, &step_requests[1]), vec![config_path.clone(), run_file.clone()]);
+        assert_eq!(watched_files(&mend, &config_path, &step_requests[0]), vec![config_path]);
+
+        let mut executor = ShellExecutor::default();
+        let rename_step = || {
+            let mut step_request = create_run_status_from_mend(&mend, &StepSelection::default()).swap_remove(1);
+            // ShellExecutor::default runs sh, which doesn't know the `function` the recipe becomes
+            step_request.run_resolved = vec!["sed -i.bak s/Foo/Bar/ App.java && rm App.java.bak".to_string()];
+            step_request
+        };
+        for _ in 0..2 {
+            let dev_run = run_dev_step(repo_dir, "main", rename_step(), &mut executor, &RunOptions::default()).unwrap();
+            assert_eq!(dev_run.status, EStatus::Done, "{}", dev_run.output);
+            assert!(dev_run.diff.contains("-class Foo {}\n+class Bar {}"), "{}", dev_run.diff);
+        }
+        let mut failing = rename_step();
+        failing.run_resolved = vec!["echo 'no Baz here'; grep -q Baz App.java".to_string()];
+        let dev_run = run_dev_step(repo_dir, "main", failing, &mut executor, &RunOptions::default()).unwrap();
+        assert_eq!(dev_run.status, EStatus::Failed);
+        assert!(dev_run.output.contains("no Baz here"), "{}", dev_run.output);
+
+        let watched = vec![run_file.clone()];
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            fs::write(run_file, "sed -i s/$1/$2/ App.java\n").unwrap();
+        });
+        assert_eq!(wait_for_change(&watched, Duration::from_millis(10)), watched[0]);
+        writer.join().unwrap();
+    }
+}
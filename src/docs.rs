@@ -0,0 +1,109 @@
+use std::fmt::Write;
+
+use crate::{Mend, Recipe};
+
+/// Highest `$N` used in a script, for recipes that don't name their arguments.
+fn positional_count(script: &str) -> usize {
+    let chars: Vec<char> = script.chars().collect();
+    chars
+        .windows(2)
+        .filter(|pair| pair[0] == '$')
+        .filter_map(|pair| pair[1].to_digit(10))
+        .max()
+        .unwrap_or_default() as usize
+}
+
+pub fn usage(name: &str, recipe: &Recipe) -> String {
+    let mut usage = name.to_string();
+    if recipe.params.is_empty() {
+        for arg_i in 1..=positional_count(&recipe.run) {
+            let _ = write!(usage, " <{}>", arg_i);
+        }
+    } else {
+        for param in &recipe.params {
+            let _ = match param.split_once('=') {
+                Some((name, default)) => write!(usage, " [{}={}]", name, default),
+                None => write!(usage, " {}=<{}>", param, param),
+            };
+        }
+    }
+    usage
+}
+
+fn code_list(items: &[String]) -> String {
+    items
+        .iter()
+        .map(|item| format!("`{}`", item))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Markdown section on one recipe, also shown when hovering its name in an editor.
+pub fn render_recipe(name: &str, recipe: &Recipe) -> String {
+    let mut text = format!("## {}\n\n", name);
+    if let Some(description) = &recipe.description {
+        let _ = writeln!(text, "{}\n", description.trim());
+    }
+    let _ = writeln!(text, "Usage: `{}`\n", usage(name, recipe));
+    for (label, items) in [
+        ("Tags", &recipe.tags),
+        ("Requires", &recipe.requires),
+        ("Languages", &recipe.languages),
+        ("Locks", &recipe.locks),
+    ] {
+        if !items.is_empty() {
+            let _ = writeln!(text, "- {}: {}", label, code_list(items));
+        }
+    }
+    if let Some(interpreter) = &recipe.interpreter {
+        let _ = writeln!(text, "- Interpreter: `{}`", interpreter);
+    }
+    if let Some(commit_template) = &recipe.commit_template {
+        let _ = writeln!(text, "- Commit message: `{}`", commit_template);
+    }
+    if !recipe.examples.is_empty() {
+        let _ = writeln!(text, "\nExamples:\n\n```");
+        for example in &recipe.examples {
+            let _ = writeln!(text, "{}", example);
+        }
+        let _ = writeln!(text, "```");
+    }
+    text
+}
+
+/// Markdown catalog of the recipes in a merged config, in name order.
+pub fn render_docs(mend: &Mend) -> String {
+    let mut text = String::from("# Recipes\n");
+    for (name, recipe) in &mend.recipes {
+        let _ = write!(text, "\n{}", render_recipe(name, recipe));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::docs::render_docs;
+    use crate::Mend;
+
+    #[test]
+    fn docs_cover_every_recipe() {
+        let mend: Mend = toml::from_str(
+            r#"
+[recipes.rename]
+run = "untangler rename $1 $2 -w -f $DEFAULT_FILE"
+commit_template = "R - Rename $1 to $2"
+description = "Renames a symbol everywhere it is used."
+params = ["old", "new", "scope=all"]
+requires = ["untangler"]
+languages = ["c"]
+examples = ["rename main_loop run_loop"]
+
+[recipes.format]
+run = "clang-format -i $1"
+tags = ["binary_identical"]
+"#,
+        )
+        .unwrap();
+        insta::assert_snapshot!(render_docs(&mend));
+    }
+}
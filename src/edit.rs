@@ -0,0 +1,502 @@
+use anyhow::{anyhow, bail, Context};
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+use serde_json::ser::PrettyFormatter;
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+use toml_edit::{Document, InlineTable, Item, Table, TableLike};
+
+use crate::run::{shell_quote, BuiltinStep, STATS_PREFIX};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum EditOp {
+    Set,
+    Remove,
+    Append,
+}
+
+impl EditOp {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EditOp::Set => "set",
+            EditOp::Remove => "remove",
+            EditOp::Append => "append",
+        }
+    }
+}
+
+/// A declarative edit of a structured file, as written in a step.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Edit {
+    pub file: String,
+    pub op: EditOp,
+    pub path: String,
+    pub value: Option<toml::Value>,
+}
+
+impl BuiltinStep for Edit {
+    fn describe(&self) -> String {
+        format!("Edit {}: {} {}", self.file, self.op.as_str(), self.path)
+    }
+
+    /// The edit runs as `mend edit` so it goes through the executor and hooks like any other script.
+    fn to_script(&self) -> crate::error::Result<String> {
+        let mut script = format!(
+            "\"$MEND_BIN\" edit --stats {} {} {}",
+            self.op.as_str(),
+            shell_quote(&self.file),
+            shell_quote(&self.path)
+        );
+        if let Some(value) = &self.value {
+            let json = serde_json::to_string(value)
+                .with_context(|| format!("Cannot convert value for {}", self.describe()))?;
+            script.push(' ');
+            script.push_str(&shell_quote(&json));
+        }
+        script.push('\n');
+        Ok(script)
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct EditArgs {
+    #[arg(value_enum)]
+    pub op: EditOp,
+    /// JSON, YAML or TOML file to edit
+    pub file: String,
+    /// Path inside the file, e.g. `.scripts.test` or `.items[0]`
+    pub path: String,
+    /// Value as a JSON literal, anything else is taken as a plain string
+    pub value: Option<String>,
+    /// Print whether the path matched and the file changed as a `mend:stats` line, as edit steps do
+    #[arg(long = "stats")]
+    pub stats: bool,
+}
+
+pub fn run_edit(args: &EditArgs) -> anyhow::Result<()> {
+    let value = args
+        .value
+        .as_ref()
+        .map(|text| serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.clone())));
+    let file = Path::new(&args.file);
+    let matched = path_matches(file, &args.path)?;
+    let changed = apply_edit(file, args.op, &args.path, value)?;
+    if args.stats {
+        println!("{}matched={} changed={}", STATS_PREFIX, u8::from(matched), u8::from(changed));
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Debug, PartialEq)]
+enum Format {
+    Json,
+    Yaml,
+    Toml,
+}
+
+fn detect_format(file: &Path) -> anyhow::Result<Format> {
+    match file.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => Ok(Format::Json),
+        Some("yaml") | Some("yml") => Ok(Format::Yaml),
+        Some("toml") => Ok(Format::Toml),
+        _ => bail!(
+            "Cannot tell the format of `{}`, expected a .json, .yaml, .yml or .toml extension",
+            file.to_string_lossy()
+        ),
+    }
+}
+
+/// Applies one edit to the file in place, returning whether its contents changed.
+/// TOML keeps comments and layout, JSON keeps key order and indentation,
+/// YAML keeps key order but comments are lost.
+pub fn apply_edit(
+    file: &Path,
+    op: EditOp,
+    path: &str,
+    value: Option<Value>,
+) -> anyhow::Result<bool> {
+    let file_str = file.to_string_lossy();
+    let format = detect_format(file)?;
+    let segments = parse_path(path)?;
+    if op != EditOp::Remove && value.is_none() {
+        bail!("Edit `{}` of {} needs a value", op.as_str(), path);
+    }
+    let contents =
+        fs::read_to_string(file).with_context(|| format!("Could not read file `{}`", file_str))?;
+    let edited = match format {
+        Format::Json => {
+            let mut root: Value = serde_json::from_str(&contents)
+                .with_context(|| format!("Unable to load data from `{}`", file_str))?;
+            let before = root.clone();
+            edit_value(&mut root, &segments, op, value)?;
+            if root == before {
+                return Ok(false);
+            }
+            write_json(&root, &contents)?
+        }
+        Format::Yaml => {
+            let mut root: Value = serde_yaml::from_str(&contents)
+                .with_context(|| format!("Unable to load data from `{}`", file_str))?;
+            let before = root.clone();
+            edit_value(&mut root, &segments, op, value)?;
+            if root == before {
+                return Ok(false);
+            }
+            serde_yaml::to_string(&root)?
+        }
+        Format::Toml => {
+            let mut doc: Document = contents
+                .parse()
+                .with_context(|| format!("Unable to load data from `{}`", file_str))?;
+            edit_toml(&mut doc, &segments, op, value.as_ref())?;
+            doc.to_string()
+        }
+    };
+    if edited == contents {
+        return Ok(false);
+    }
+    fs::write(file, edited).with_context(|| format!("Could not write file `{}`", file_str))?;
+    Ok(true)
+}
+
+/// Whether `path` names a value in the file as it is.
+fn path_matches(file: &Path, path: &str) -> anyhow::Result<bool> {
+    let file_str = file.to_string_lossy();
+    let contents =
+        fs::read_to_string(file).with_context(|| format!("Could not read file `{}`", file_str))?;
+    let root: Value = match detect_format(file)? {
+        Format::Json => serde_json::from_str(&contents).map_err(anyhow::Error::from),
+        Format::Yaml => serde_yaml::from_str(&contents).map_err(anyhow::Error::from),
+        Format::Toml => toml::from_str(&contents).map_err(anyhow::Error::from),
+    }
+    .with_context(|| format!("Unable to load data from `{}`", file_str))?;
+    let matched = parse_path(path)?.iter().try_fold(&root, |node, segment| match segment {
+        Segment::Key(key) => node.get(key),
+        Segment::Index(index) => node.get(index),
+    });
+    Ok(matched.is_some())
+}
+
+fn parse_path(path: &str) -> anyhow::Result<Vec<Segment>> {
+    let trimmed = path.strip_prefix('.').unwrap_or(path);
+    let mut segments = vec![];
+    if trimmed.is_empty() {
+        bail!("Edit path must not be empty");
+    }
+    for part in trimmed.split('.') {
+        let (key, mut rest) = match part.find('[') {
+            Some(bracket) => part.split_at(bracket),
+            None => (part, ""),
+        };
+        if !key.is_empty() {
+            segments.push(Segment::Key(key.to_string()));
+        } else if rest.is_empty() {
+            bail!("Empty key in edit path `{}`", path);
+        }
+        while !rest.is_empty() {
+            let close = rest
+                .find(']')
+                .ok_or_else(|| anyhow!("Unclosed `[` in edit path `{}`", path))?;
+            let index = rest[1..close]
+                .parse::<usize>()
+                .with_context(|| format!("Bad index in edit path `{}`", path))?;
+            segments.push(Segment::Index(index));
+            rest = &rest[close + 1..];
+            if !rest.is_empty() && !rest.starts_with('[') {
+                bail!("Unexpected `{}` in edit path `{}`", rest, path);
+            }
+        }
+    }
+    Ok(segments)
+}
+
+fn edit_value(
+    root: &mut Value,
+    segments: &[Segment],
+    op: EditOp,
+    value: Option<Value>,
+) -> anyhow::Result<()> {
+    let (last, parents) = segments.split_last().expect("path is never empty");
+    let mut node = root;
+    for segment in parents {
+        let child = match segment {
+            Segment::Key(key) => {
+                if node.is_null() && op != EditOp::Remove {
+                    *node = Value::Object(Map::new());
+                }
+                let object = node
+                    .as_object_mut()
+                    .ok_or_else(|| anyhow!("Expected an object at `{}`", key))?;
+                if op == EditOp::Remove {
+                    object.get_mut(key)
+                } else {
+                    Some(object.entry(key.as_str()).or_insert(Value::Null))
+                }
+            }
+            Segment::Index(index) => node
+                .as_array_mut()
+                .ok_or_else(|| anyhow!("Expected an array at index {}", index))?
+                .get_mut(*index),
+        };
+        node = match child {
+            Some(child) => child,
+            None if op == EditOp::Remove => return Ok(()),
+            None => bail!("No element to descend into at {:?}", segment),
+        };
+    }
+    match (op, last) {
+        (EditOp::Set, Segment::Key(key)) => {
+            if node.is_null() {
+                *node = Value::Object(Map::new());
+            }
+            node.as_object_mut()
+                .ok_or_else(|| anyhow!("Expected an object to set `{}`", key))?
+                .insert(key.to_string(), value.unwrap_or_default());
+        }
+        (EditOp::Set, Segment::Index(index)) => {
+            let array = node
+                .as_array_mut()
+                .ok_or_else(|| anyhow!("Expected an array to set index {}", index))?;
+            if *index < array.len() {
+                array[*index] = value.unwrap_or_default();
+            } else if *index == array.len() {
+                array.push(value.unwrap_or_default());
+            } else {
+                bail!("Index {} is out of bounds", index);
+            }
+        }
+        (EditOp::Remove, Segment::Key(key)) => {
+            if let Some(object) = node.as_object_mut() {
+                object.shift_remove(key);
+            }
+        }
+        (EditOp::Remove, Segment::Index(index)) => {
+            if let Some(array) = node.as_array_mut() {
+                if *index < array.len() {
+                    array.remove(*index);
+                }
+            }
+        }
+        (EditOp::Append, _) => {
+            let target = match last {
+                Segment::Key(key) => {
+                    if node.is_null() {
+                        *node = Value::Object(Map::new());
+                    }
+                    node.as_object_mut()
+                        .ok_or_else(|| anyhow!("Expected an object to append to `{}`", key))?
+                        .entry(key.as_str())
+                        .or_insert(Value::Array(vec![]))
+                }
+                Segment::Index(index) => node
+                    .as_array_mut()
+                    .and_then(|array| array.get_mut(*index))
+                    .ok_or_else(|| anyhow!("No element at index {} to append to", index))?,
+            };
+            if target.is_null() {
+                *target = Value::Array(vec![]);
+            }
+            target
+                .as_array_mut()
+                .ok_or_else(|| anyhow!("Can only append to an array"))?
+                .push(value.unwrap_or_default());
+        }
+    }
+    Ok(())
+}
+
+fn write_json(root: &Value, original: &str) -> anyhow::Result<String> {
+    let indent = detect_indent(original);
+    let mut buf = Vec::new();
+    let formatter = PrettyFormatter::with_indent(indent.as_bytes());
+    let mut serializer = serde_json::Serializer::with_formatter(&mut buf, formatter);
+    root.serialize(&mut serializer)?;
+    let mut text = String::from_utf8(buf)?;
+    if original.ends_with('\n') {
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+fn detect_indent(text: &str) -> String {
+    text.lines()
+        .skip(1)
+        .map(|line| {
+            line.chars()
+                .take_while(|c| *c == ' ' || *c == '\t')
+                .collect::<String>()
+        })
+        .find(|indent| !indent.is_empty())
+        .unwrap_or_else(|| "  ".to_string())
+}
+
+fn edit_toml(
+    doc: &mut Document,
+    segments: &[Segment],
+    op: EditOp,
+    value: Option<&Value>,
+) -> anyhow::Result<()> {
+    let mut keys = vec![];
+    for segment in segments {
+        match segment {
+            Segment::Key(key) => keys.push(key.as_str()),
+            Segment::Index(_) => bail!("Array indices in edit paths are not supported for TOML"),
+        }
+    }
+    let (last, parents) = keys.split_last().expect("path is never empty");
+    let mut table: &mut dyn TableLike = doc.as_table_mut();
+    for key in parents {
+        if table.get(key).is_none() {
+            if op == EditOp::Remove {
+                return Ok(());
+            }
+            let mut new_table = Table::new();
+            new_table.set_implicit(true);
+            table.insert(key, Item::Table(new_table));
+        }
+        table = table
+            .get_mut(key)
+            .and_then(Item::as_table_like_mut)
+            .ok_or_else(|| anyhow!("Expected a table at `{}`", key))?;
+    }
+    match op {
+        EditOp::Set => {
+            let mut new_value = toml_value_from_json(value.unwrap_or(&Value::Null))?;
+            if let Some(old_value) = table.get(last).and_then(Item::as_value) {
+                *new_value.decor_mut() = old_value.decor().clone();
+            }
+            table.insert(last, Item::Value(new_value));
+        }
+        EditOp::Remove => {
+            table.remove(last);
+        }
+        EditOp::Append => {
+            let new_value = toml_value_from_json(value.unwrap_or(&Value::Null))?;
+            match table.get_mut(last) {
+                Some(item) => item
+                    .as_array_mut()
+                    .ok_or_else(|| anyhow!("Can only append to an array"))?
+                    .push(new_value),
+                None => {
+                    let mut array = toml_edit::Array::new();
+                    array.push(new_value);
+                    table.insert(last, Item::Value(array.into()));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn toml_value_from_json(value: &Value) -> anyhow::Result<toml_edit::Value> {
+    Ok(match value {
+        Value::Null => bail!("TOML has no null value"),
+        Value::Bool(b) => (*b).into(),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => i.into(),
+            None => n.as_f64().unwrap_or_default().into(),
+        },
+        Value::String(s) => s.as_str().into(),
+        Value::Array(items) => {
+            let mut array = toml_edit::Array::new();
+            for item in items {
+                array.push(toml_value_from_json(item)?);
+            }
+            array.into()
+        }
+        Value::Object(map) => {
+            let mut inline = InlineTable::new();
+            for (key, item) in map {
+                inline.insert(key, toml_value_from_json(item)?);
+            }
+            inline.into()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::edit::{apply_edit, parse_path, path_matches, EditOp, Segment};
+    use serde_json::json;
+    use std::fs;
+
+    #[test]
+    fn parse_path_with_indices() {
+        assert_eq!(
+            parse_path(".scripts.items[0][2]").unwrap(),
+            vec![
+                Segment::Key("scripts".to_string()),
+                Segment::Key("items".to_string()),
+                Segment::Index(0),
+                Segment::Index(2),
+            ]
+        );
+        assert!(parse_path(".").is_err());
+        assert!(parse_path("a..b").is_err());
+        assert!(parse_path("a[x]").is_err());
+    }
+
+    #[test]
+    fn edit_json_keeps_order_and_indent() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("package.json");
+        fs::write(&file, "{\n    \"name\": \"x\",\n    \"scripts\": {\n        \"build\": \"tsc\"\n    }\n}\n").unwrap();
+
+        assert!(apply_edit(&file, EditOp::Set, ".scripts.test", Some(json!("jest"))).unwrap());
+        assert!(apply_edit(&file, EditOp::Append, ".files", Some(json!("dist"))).unwrap());
+        assert!(apply_edit(&file, EditOp::Remove, ".name", None).unwrap());
+        assert!(!apply_edit(&file, EditOp::Remove, ".missing.key", None).unwrap());
+        insta::assert_snapshot!(fs::read_to_string(&file).unwrap());
+    }
+
+    #[test]
+    fn edit_path_matches_only_what_the_file_has() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("Cargo.toml");
+        fs::write(&file, "[package]\nname = \"x\"\nauthors = [\"a\"]\n").unwrap();
+        assert!(path_matches(&file, ".package.name").unwrap());
+        assert!(path_matches(&file, ".package.authors[0]").unwrap());
+        assert!(!path_matches(&file, ".package.authors[1]").unwrap());
+        assert!(!path_matches(&file, ".dependencies.serde").unwrap());
+    }
+
+    #[test]
+    fn edit_yaml() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("ci.yml");
+        fs::write(&file, "jobs:\n  build:\n    steps:\n      - run: make\n").unwrap();
+
+        assert!(apply_edit(&file, EditOp::Set, ".jobs.build.steps[0].run", Some(json!("make all"))).unwrap());
+        assert!(apply_edit(&file, EditOp::Append, ".jobs.build.steps", Some(json!({"run": "make test"}))).unwrap());
+        insta::assert_snapshot!(fs::read_to_string(&file).unwrap());
+    }
+
+    #[test]
+    fn edit_toml_keeps_comments() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("Cargo.toml");
+        fs::write(&file, "[package]\nname = \"x\" # keep me\nversion = \"0.1.0\"\n\n# Deps\n[dependencies]\nserde = { version = \"1\" }\n").unwrap();
+
+        assert!(apply_edit(&file, EditOp::Set, "package.name", Some(json!("y"))).unwrap());
+        assert!(apply_edit(&file, EditOp::Append, "dependencies.serde.features", Some(json!("derive"))).unwrap());
+        assert!(apply_edit(&file, EditOp::Remove, "package.version", None).unwrap());
+        assert!(apply_edit(&file, EditOp::Set, "package.authors[0]", Some(json!("me"))).is_err());
+        insta::assert_snapshot!(fs::read_to_string(&file).unwrap());
+    }
+
+    #[test]
+    fn edit_rejects_unknown_format() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("notes.txt");
+        fs::write(&file, "hello").unwrap();
+        assert!(apply_edit(&file, EditOp::Set, "a", Some(json!(1))).is_err());
+    }
+}
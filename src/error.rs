@@ -0,0 +1,164 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// What `config`, `repo` and `run` return on failure. The CLI turns it into an `anyhow::Error` with `?`,
+/// where it can still be matched with `downcast_ref::<MendError>()`, and prints it like any other.
+/// What caused it, e.g. the TOML parser's error, follows in the error's chain.
+pub enum MendError {
+    /// A config or include file that doesn't parse, `line` counting from 1 when the parser knows it
+    Config { file: PathBuf, line: Option<usize>, cause: anyhow::Error },
+    /// A git command that failed, with what it printed
+    Git { command: String, output: String },
+    /// A lock file another git process kept holding, with that process when it could be found
+    GitLock { lock_path: PathBuf, holder: Option<String> },
+    /// A program that could not be found or started
+    Exec { program: String, cause: anyhow::Error },
+    /// A config that parses but describes steps mend can't run
+    Validation(String),
+    /// Anything else going wrong on the way, e.g. a file that can't be read, shown as the error it wraps
+    Other(anyhow::Error),
+}
+
+pub type Result<T, E = MendError> = std::result::Result<T, E>;
+
+impl MendError {
+    fn causes(&self) -> impl Iterator<Item = &(dyn std::error::Error + 'static)> {
+        std::iter::successors(std::error::Error::source(self), |cause| cause.source())
+    }
+}
+
+impl fmt::Display for MendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MendError::Config { file, line: None, .. } => {
+                write!(f, "Unable to load data from `{}`", file.to_string_lossy())
+            }
+            MendError::Config { file, line: Some(line), .. } => {
+                write!(f, "Unable to load data from `{}` at line {}", file.to_string_lossy(), line)
+            }
+            MendError::Git { command, output } => write!(f, "Failed to run {}, output:\n{}", command, output),
+            MendError::GitLock { lock_path, holder: Some(holder) } => write!(
+                f,
+                "`{}` is held by {}, wait for it to finish or stop it and run again",
+                lock_path.to_string_lossy(),
+                holder
+            ),
+            MendError::GitLock { lock_path, holder: None } => write!(
+                f,
+                "`{}` exists but no running process was found holding it, a git command that crashed likely left it behind. \
+                 Remove it once no git command is running in the repo",
+                lock_path.to_string_lossy()
+            ),
+            MendError::Exec { program, .. } => write!(f, "Could not run command {}", program),
+            MendError::Validation(message) => write!(f, "{}", message),
+            MendError::Other(err) => write!(f, "{}", err),
+        }?;
+        // `{:#}` goes on with the causes like anyhow does, the CLI printing errors that way
+        if f.alternate() {
+            self.causes().try_for_each(|cause| write!(f, ": {}", cause))?;
+        }
+        Ok(())
+    }
+}
+
+/// Like anyhow's, the error followed by what caused it.
+impl fmt::Debug for MendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let MendError::Other(err) = self {
+            return fmt::Debug::fmt(err, f);
+        }
+        write!(f, "{}", self)?;
+        let causes: Vec<_> = self.causes().collect();
+        match causes.as_slice() {
+            [] => Ok(()),
+            [cause] => write!(f, "\n\nCaused by:\n    {}", cause),
+            causes => {
+                write!(f, "\n\nCaused by:")?;
+                causes.iter().enumerate().try_for_each(|(i, cause)| write!(f, "\n    {}: {}", i, cause))
+            }
+        }
+    }
+}
+
+impl std::error::Error for MendError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MendError::Config { cause, .. } | MendError::Exec { cause, .. } => Some(cause.as_ref()),
+            // `Other` shows as the error it wraps, whose own cause comes next
+            MendError::Other(err) => err.source(),
+            _ => None,
+        }
+    }
+}
+
+/// Keeps a `MendError` that only went through `anyhow` as it was, any other error becomes `Other`.
+/// One wrapped in context stays `Other` too, so the context is still shown.
+impl From<anyhow::Error> for MendError {
+    fn from(err: anyhow::Error) -> Self {
+        if err.chain().next().is_some_and(|outermost| outermost.is::<MendError>()) {
+            err.downcast().unwrap_or_else(MendError::Other)
+        } else {
+            MendError::Other(err)
+        }
+    }
+}
+
+impl From<std::io::Error> for MendError {
+    fn from(err: std::io::Error) -> Self {
+        MendError::Other(err.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::MendError;
+    use anyhow::{anyhow, Context};
+    use std::path::PathBuf;
+
+    fn every_variant() -> Vec<MendError> {
+        vec![
+            MendError::Config { file: PathBuf::from("mend.toml"), line: Some(3), cause: anyhow!("expected `]`") },
+            MendError::Git { command: "git add a.txt".to_string(), output: "fatal: not a git repository".to_string() },
+            MendError::GitLock { lock_path: PathBuf::from(".git/index.lock"), holder: None },
+            MendError::Exec { program: "no-such-tool".to_string(), cause: anyhow!("cannot find binary path") },
+            MendError::Validation("Step 2 reuses the id `format`".to_string()),
+            MendError::Other(anyhow!("No such file or directory").context("Could not read file `mend.toml`")),
+        ]
+    }
+
+    #[test]
+    fn variants_survive_context_wrapping() {
+        for (err, expected) in every_variant().into_iter().zip(every_variant()) {
+            let message = format!("{:#}", expected);
+            let wrapped: anyhow::Error = Err::<(), _>(err).context("Could not start the run").unwrap_err();
+            let found = wrapped.downcast_ref::<MendError>().expect("MendError behind the context");
+            assert_eq!(std::mem::discriminant(found), std::mem::discriminant(&expected));
+            assert_eq!(format!("{:#}", wrapped), format!("Could not start the run: {}", message));
+        }
+    }
+
+    #[test]
+    fn causes_are_shown_with_the_error() {
+        let [config, _, _, exec, validation, other] = every_variant().try_into().unwrap();
+        assert_eq!(format!("{:#}", config), "Unable to load data from `mend.toml` at line 3: expected `]`");
+        assert_eq!(format!("{:#}", exec), "Could not run command no-such-tool: cannot find binary path");
+        assert_eq!(format!("{:#}", validation), "Step 2 reuses the id `format`");
+        assert_eq!(format!("{:#}", other), "Could not read file `mend.toml`: No such file or directory");
+        assert_eq!(
+            format!("{:?}", config),
+            "Unable to load data from `mend.toml` at line 3\n\nCaused by:\n    expected `]`"
+        );
+    }
+
+    #[test]
+    fn converting_from_anyhow_keeps_the_variant_unless_context_was_added() {
+        let through_anyhow = MendError::from(anyhow::Error::from(MendError::Validation("No steps".to_string())));
+        assert!(matches!(through_anyhow, MendError::Validation(message) if message == "No steps"));
+
+        let with_context = MendError::from(anyhow::Error::from(MendError::Validation("No steps".to_string())).context("In `mend.toml`"));
+        assert!(matches!(with_context, MendError::Other(_)));
+        assert_eq!(format!("{:#}", with_context), "In `mend.toml`: No steps");
+
+        assert!(matches!(MendError::from(anyhow!("Disk full")), MendError::Other(_)));
+    }
+}
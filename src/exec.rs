@@ -0,0 +1,79 @@
+use clap::Args;
+use toml::value::{Table, Value};
+
+/// Stands in for the config file of `mend exec`, so includes and the followup config go to the current directory.
+pub const EXEC_CONFIG: &str = "mend-exec.toml";
+
+#[derive(Args, Debug)]
+pub struct ExecArgs {
+    /// Commit the run starts from
+    #[arg(long = "from-sha")]
+    pub from_sha: String,
+
+    /// Repository to run in
+    #[arg(long = "repo", default_value = ".")]
+    pub repo: String,
+
+    /// A step to run, as it would be written in `steps`, repeat for several
+    #[arg(long = "step", required = true)]
+    pub steps: Vec<String>,
+
+    /// Where the recipes come from, anything `include` takes. A bare name like `java` means `java.toml`
+    #[arg(long = "recipe-pack")]
+    pub recipe_packs: Vec<String>,
+}
+
+fn include_for_pack(pack: &str) -> String {
+    if !pack.contains(['/', '.', ':']) {
+        format!("{}.toml", pack)
+    } else {
+        pack.to_string()
+    }
+}
+
+/// The config `mend exec` runs, in TOML so it is loaded and checked like any other.
+pub fn exec_config(args: &ExecArgs) -> anyhow::Result<String> {
+    let mut from = Table::new();
+    from.insert("repo".to_string(), Value::String(args.repo.clone()));
+    from.insert("sha".to_string(), Value::String(args.from_sha.clone()));
+    let mut config = Table::new();
+    config.insert("from".to_string(), Value::Table(from));
+    config.insert(
+        "include".to_string(),
+        Value::Array(args.recipe_packs.iter().map(|pack| Value::String(include_for_pack(pack))).collect()),
+    );
+    config.insert(
+        "steps".to_string(),
+        Value::Array(args.steps.iter().cloned().map(Value::String).collect()),
+    );
+    Ok(toml::to_string(&config)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::load_mend_contents;
+    use crate::exec::{exec_config, ExecArgs, EXEC_CONFIG};
+    use std::fs;
+
+    #[test]
+    fn exec_config_includes_recipe_packs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::write(temp_dir.path().join("java.toml"), "[recipes.rename]\nrun = \"echo $1 $2\"\n").unwrap();
+        let args = ExecArgs {
+            from_sha: "abc1234".to_string(),
+            repo: ".".to_string(),
+            steps: vec!["rename Foo \"Bar Baz\"".to_string()],
+            recipe_packs: vec!["java".to_string()],
+        };
+        let config = exec_config(&args).unwrap();
+        assert!(config.contains("include = [\"java.toml\"]"), "{}", config);
+        let mend = load_mend_contents(&temp_dir.path().join(EXEC_CONFIG), &config).unwrap();
+        insta::assert_yaml_snapshot!(mend);
+
+        let remote = ExecArgs {
+            recipe_packs: vec!["https://example.com/java".to_string(), "packs/java.toml".to_string()],
+            ..args
+        };
+        assert!(exec_config(&remote).unwrap().contains("include = [\"https://example.com/java\", \"packs/java.toml\"]"));
+    }
+}
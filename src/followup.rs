@@ -0,0 +1,110 @@
+use anyhow::Context;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{Mend, From};
+
+pub const FOLLOWUP_FILE: &str = "mend-followup.toml";
+
+/// A config that retries only the failed steps, starting from the sha the run ended on.
+pub fn followup_mend(mend: &Mend, failed_steps: &[usize], from_sha: &str) -> Mend {
+    let mut followup = mend.clone();
+    // Includes are already merged in, keeping them would bring their steps back
+    followup.include = vec![];
+    // Their steps are in `steps` already and only the failed ones are kept
+    followup.phases = vec![];
+    // The follow-up starts where this run ended, not wherever a `ref` has moved to since
+    followup.from = mend.from.as_ref().map(|from| From {
+        sha: from_sha.to_string(),
+        repo: from.repo.clone(),
+        git_ref: None,
+        fetch: false,
+    });
+    followup.steps = failed_steps
+        .iter()
+        .filter_map(|step_i| mend.steps.get(*step_i).cloned())
+        .collect();
+    followup
+}
+
+/// Writes the followup config next to `config_path` and returns where it went.
+pub fn write_followup(
+    mend: &Mend,
+    failed_steps: &[usize],
+    from_sha: &str,
+    config_path: &Path,
+) -> anyhow::Result<PathBuf> {
+    let followup = followup_mend(mend, failed_steps, from_sha);
+    let path = config_path.with_file_name(FOLLOWUP_FILE);
+    let text = toml::to_string(&followup).context("Could not serialize followup config")?;
+    fs::write(&path, text)
+        .with_context(|| format!("Could not write `{}`", path.to_string_lossy()))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::followup::followup_mend;
+    use crate::{From, Mend, Recipe, Step, StepConfig};
+
+    #[test]
+    fn followup_keeps_only_failed_steps() {
+        let mut mend = Mend {
+            from: Some(From {
+                sha: "43a3a253".to_string(),
+                repo: "~/dev/project".to_string(),
+                git_ref: None,
+                fetch: false,
+            }),
+            include: vec!["mend-recipes.toml".to_string()],
+            env: Default::default(),
+            secrets: Default::default(),
+            recipes: Default::default(),
+            hooks: Default::default(),
+            steps: vec![
+                Step::from("rename a b"),
+                Step::from("rename c d"),
+                Step::Structured(Box::new(StepConfig {
+                    run: Some("rename e f".to_string()),
+                    fallback: Some("sed_rename $1 $2".to_string()),
+                    ..Default::default()
+                })),
+            ],
+            verify: None,
+            matrix: Default::default(),
+            verify_baseline: None,
+            gates: None,
+            git: None,
+            commit: None,
+            committers: Default::default(),
+            phases: vec![],
+            heartbeat: None,
+            shell: None,
+            telemetry: None,
+            metrics: None,
+            keep_going: None,
+            on_failure: None,
+            badge: None,
+            notify: None,
+            forge: None,
+            logs: None,
+            artifacts: None,
+            output: None,
+            publish: None,
+            lint_rules: vec![],
+            corpus: vec![],
+            aliases: Default::default(),
+            timeout: None,
+            run_env: Default::default(),
+        };
+        mend.recipes.insert(
+            "rename".to_string(),
+            Recipe {
+                run: "rename_symbol $1 $2".to_string(),
+                ..Default::default()
+            },
+        );
+        let followup = followup_mend(&mend, &[1, 2], "abc1234");
+        insta::assert_snapshot!(toml::to_string(&followup).unwrap());
+    }
+}
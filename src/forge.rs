@@ -0,0 +1,503 @@
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt::Write as _;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use indicatif::HumanDuration;
+
+use crate::repo::GitRepo;
+use crate::run::{StepRequest, StepResponse};
+
+/// Step metadata holding the URL of the issue opened for the failed step.
+pub const ISSUE_KEY: &str = "issue";
+/// How long the forge gets to answer each request.
+const FORGE_TIMEOUT_SECS: &str = "30";
+const DEFAULT_OUTPUT_LINES: usize = 30;
+const DEFAULT_PULL_REQUEST_TITLE: &str = "mend: $config";
+const DEFAULT_OWNERS_PULL_REQUEST_TITLE: &str = "mend: $config for $owners";
+const DEFAULT_REMOTE: &str = "origin";
+const DEFAULT_ISSUE_TITLE: &str = "mend: step $step failed: $run";
+const DEFAULT_ISSUE_BODY: &str = "Step $step (`$id`) of mend run $run_id failed:
+
+```
+$run
+```
+
+The end of its output:
+
+```
+$output
+```
+
+To retry it once it's fixed:
+
+```
+$resume
+```
+";
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ForgeType {
+    #[default]
+    Github,
+    Gitlab,
+}
+
+/// The `[forge]` table, where the repo is hosted and how mend gets at its API.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct ForgeConfig {
+    #[serde(rename = "type", default)]
+    pub forge_type: ForgeType,
+    /// `owner/repo` on GitHub, the project's path on GitLab, e.g. `group/app`
+    pub project: String,
+    /// `$VAR` is taken from the environment so it needn't be committed, e.g. `$GITHUB_TOKEN`
+    pub token: String,
+    /// For self-hosted forges, `https://api.github.com` or `https://gitlab.com/api/v4` by default
+    pub api_url: Option<String>,
+    /// Opens an issue for each step a run leaves failed
+    pub issues: Option<IssuesConfig>,
+    /// Pushes the `[output] branch` and opens a pull request, a merge request on GitLab, when a run succeeds
+    pub pull_request: Option<PullRequestConfig>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct PullRequestConfig {
+    /// Branch the pull request is opened against, e.g. `main`
+    pub base: String,
+    /// Where the run's branch is pushed with git's own credentials, `origin` by default
+    pub remote: Option<String>,
+    /// `$config`, the config file's name without its extension, and `$run_id` are filled in, `mend: $config` by default.
+    /// Split pull requests know `$owners` too and are titled `mend: $config for $owners` by default
+    pub title: Option<String>,
+    #[serde(default)]
+    pub draft: bool,
+    /// Comments on the pull request for each step that made a commit, with its summary and, when it had warnings,
+    /// the end of its log
+    #[serde(default)]
+    pub step_comments: bool,
+    /// Opens a pull request per group of owners in CODEOWNERS instead of one, each with the commits touching their
+    /// files on a branch of its own
+    #[serde(default)]
+    pub split_by_owner: bool,
+}
+
+impl PullRequestConfig {
+    pub fn remote(&self) -> &str {
+        self.remote.as_deref().unwrap_or(DEFAULT_REMOTE)
+    }
+}
+
+/// A step of the run, for the pull request's body.
+pub struct PullRequestStep {
+    /// Counting from 1
+    pub step: usize,
+    /// The first line of its commit message, or the step itself when it made no commit
+    pub summary: String,
+    /// None for skipped steps and those whose change is part of another's commit
+    pub sha: Option<String>,
+    pub duration: Option<Duration>,
+    /// The `issue` metadata of the step, linked in the body
+    pub issue: Option<String>,
+    /// The `warning` metadata of the step, one per line
+    pub warnings: Option<String>,
+    /// What the step printed, from its log, for the comment about a step with warnings
+    pub output: Option<String>,
+}
+
+/// The pull request or merge request a run opened.
+pub struct OpenedPullRequest {
+    /// Where it's shown
+    pub url: String,
+    /// Its number on GitHub, its `iid` on GitLab
+    pub number: u64,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct IssuesConfig {
+    /// `$step`, `$id`, `$run`, `$run_id`, `$output` and `$resume` are filled in, `mend: step $step failed: $run` by default
+    pub title: Option<String>,
+    /// Knows the same variables as `title`, by default the step, the end of its output and how to retry it
+    pub body: Option<String>,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// How many of the failed step's last output lines go in `$output`, 30 by default
+    pub output_lines: Option<usize>,
+}
+
+/// A step the run left failed, for the issue opened about it.
+pub struct FailedStep<'a> {
+    /// Counting from 1
+    pub step: usize,
+    pub request: &'a StepRequest,
+    pub response: &'a StepResponse,
+}
+
+fn expand_env(value: &str) -> String {
+    shellexpand::env(value).map(|expanded| expanded.to_string()).unwrap_or_else(|_| value.to_string())
+}
+
+fn api_url(forge: &ForgeConfig) -> String {
+    let default = match forge.forge_type {
+        ForgeType::Github => "https://api.github.com",
+        ForgeType::Gitlab => "https://gitlab.com/api/v4",
+    };
+    forge.api_url.as_deref().unwrap_or(default).trim_end_matches('/').to_string()
+}
+
+/// GitLab takes the project's path in place of its id with the slashes escaped.
+fn encode_project(project: &str) -> String {
+    project.replace('%', "%25").replace('/', "%2F")
+}
+
+fn render(template: &str, vars: &[(&str, String)]) -> String {
+    shellexpand::env_with_context_no_errors(template, |name: &str| {
+        vars.iter().find(|(var, _)| *var == name).map(|(_, value)| value.clone())
+    })
+    .to_string()
+}
+
+/// The URL, headers and JSON payload that open the issue about `failed`, `resume` says how to retry it.
+pub fn issue_request(forge: &ForgeConfig, issues: &IssuesConfig, failed: &FailedStep, run_id: &str, resume: &str) -> (String, Vec<String>, Value) {
+    let output = failed.response.output.as_deref().unwrap_or_default();
+    let lines: Vec<&str> = output.lines().collect();
+    let shown = &lines[lines.len().saturating_sub(issues.output_lines.unwrap_or(DEFAULT_OUTPUT_LINES))..];
+    let vars = [
+        ("step", failed.step.to_string()),
+        ("id", failed.request.id.clone()),
+        ("run", failed.request.run.clone()),
+        ("run_id", run_id.to_string()),
+        ("output", shown.join("\n")),
+        ("resume", resume.to_string()),
+    ];
+    let title = render(issues.title.as_deref().unwrap_or(DEFAULT_ISSUE_TITLE), &vars);
+    let body = render(issues.body.as_deref().unwrap_or(DEFAULT_ISSUE_BODY), &vars);
+    let token = expand_env(&forge.token);
+    match forge.forge_type {
+        ForgeType::Github => (
+            format!("{}/repos/{}/issues", api_url(forge), forge.project),
+            vec![format!("Authorization: Bearer {}", token), "Accept: application/vnd.github+json".to_string()],
+            json!({"title": title, "body": body, "labels": issues.labels}),
+        ),
+        ForgeType::Gitlab => (
+            format!("{}/projects/{}/issues", api_url(forge), encode_project(&forge.project)),
+            vec![format!("PRIVATE-TOKEN: {}", token)],
+            json!({"title": title, "description": body, "labels": issues.labels.join(",")}),
+        ),
+    }
+}
+
+/// Markdown listing the steps with their commits, how long each took and the issues they name.
+pub fn pull_request_body(config: &str, from_sha: &str, steps: &[PullRequestStep]) -> String {
+    let mut body = format!("Made by [mend](https://github.com/craftvscruft/mend) running `{}` on {}.\n\n", config, from_sha);
+    body.push_str("| Step | Commit | Change | Took |\n| --- | --- | --- | --- |\n");
+    for step in steps {
+        let _ = writeln!(
+            body,
+            "| {} | {} | {} | {} |",
+            step.step,
+            step.sha.as_deref().unwrap_or("-"),
+            step.summary.replace('|', "\\|"),
+            step.duration.map_or("-".to_string(), |duration| HumanDuration(duration).to_string())
+        );
+    }
+    let issues: Vec<String> = steps
+        .iter()
+        .filter_map(|step| step.issue.as_ref().map(|issue| format!("- Step {}: {}", step.step, issue)))
+        .collect();
+    if !issues.is_empty() {
+        let _ = write!(body, "\nIssues:\n{}\n", issues.join("\n"));
+    }
+    body
+}
+
+/// The URL, headers and JSON payload that open the pull request of `branch`.
+pub fn pull_request_request(
+    forge: &ForgeConfig,
+    pull_request: &PullRequestConfig,
+    branch: &str,
+    vars: &[(&str, String)],
+    body: &str,
+) -> (String, Vec<String>, Value) {
+    let default_title = match vars.iter().any(|(var, _)| *var == "owners") {
+        true => DEFAULT_OWNERS_PULL_REQUEST_TITLE,
+        false => DEFAULT_PULL_REQUEST_TITLE,
+    };
+    let title = render(pull_request.title.as_deref().unwrap_or(default_title), vars);
+    let token = expand_env(&forge.token);
+    match forge.forge_type {
+        ForgeType::Github => (
+            format!("{}/repos/{}/pulls", api_url(forge), forge.project),
+            vec![format!("Authorization: Bearer {}", token), "Accept: application/vnd.github+json".to_string()],
+            json!({"title": title, "head": branch, "base": pull_request.base, "body": body, "draft": pull_request.draft}),
+        ),
+        ForgeType::Gitlab => (
+            format!("{}/projects/{}/merge_requests", api_url(forge), encode_project(&forge.project)),
+            vec![format!("PRIVATE-TOKEN: {}", token)],
+            json!({
+                // Draft merge requests are told apart by their title
+                "title": if pull_request.draft { format!("Draft: {}", title) } else { title },
+                "source_branch": branch,
+                "target_branch": pull_request.base,
+                "description": body,
+            }),
+        ),
+    }
+}
+
+/// Pushes the run's `branch` from `repo` to `remote` and opens its pull request or merge request.
+pub fn open_pull_request(
+    forge: &ForgeConfig,
+    pull_request: &PullRequestConfig,
+    repo: &GitRepo,
+    remote: &str,
+    branch: &str,
+    vars: &[(&str, String)],
+    body: &str,
+) -> anyhow::Result<OpenedPullRequest> {
+    let (url, headers, payload) = pull_request_request(forge, pull_request, branch, vars, body);
+    repo.push(remote, branch).with_context(|| format!("Could not push `{}`", branch))?;
+    let created = post(&url, &headers, &payload).context("Could not open the pull request")?;
+    let number_key = match forge.forge_type {
+        ForgeType::Github => "number",
+        ForgeType::Gitlab => "iid",
+    };
+    Ok(OpenedPullRequest {
+        url: created_url(forge.forge_type, &created)?,
+        number: created[number_key].as_u64().ok_or_else(|| anyhow!("The pull request the forge opened has no `{}`", number_key))?,
+    })
+}
+
+/// Markdown about a step of the pull request: its commit and summary, then its warnings and the end of its output.
+pub fn step_comment(step: &PullRequestStep) -> String {
+    let mut comment = format!("**Step {}** {}: {}\n", step.step, step.sha.as_deref().unwrap_or("-"), step.summary);
+    if let Some(warnings) = &step.warnings {
+        comment.push_str("\nWarnings:\n");
+        for warning in warnings.lines() {
+            let _ = writeln!(comment, "- {}", warning);
+        }
+        if let Some(output) = &step.output {
+            let lines: Vec<&str> = output.lines().collect();
+            let shown = &lines[lines.len().saturating_sub(DEFAULT_OUTPUT_LINES)..];
+            let _ = write!(comment, "\nThe end of its output:\n\n```\n{}\n```\n", shown.join("\n"));
+        }
+    }
+    comment
+}
+
+/// The URL, headers and JSON payload that comment `body` on the pull request numbered `number`.
+pub fn comment_request(forge: &ForgeConfig, number: u64, body: &str) -> (String, Vec<String>, Value) {
+    let token = expand_env(&forge.token);
+    match forge.forge_type {
+        // Pull requests take their conversation's comments as issues do
+        ForgeType::Github => (
+            format!("{}/repos/{}/issues/{}/comments", api_url(forge), forge.project, number),
+            vec![format!("Authorization: Bearer {}", token), "Accept: application/vnd.github+json".to_string()],
+            json!({"body": body}),
+        ),
+        ForgeType::Gitlab => (
+            format!("{}/projects/{}/merge_requests/{}/notes", api_url(forge), encode_project(&forge.project), number),
+            vec![format!("PRIVATE-TOKEN: {}", token)],
+            json!({"body": body}),
+        ),
+    }
+}
+
+/// Comments on `opened` about each step that made a commit. A comment that can't be posted is reported and the other
+/// steps are tried, the pull request being open already.
+pub fn comment_steps(forge: &ForgeConfig, opened: &OpenedPullRequest, steps: &[PullRequestStep]) {
+    for step in steps.iter().filter(|step| step.sha.is_some()) {
+        let (url, headers, payload) = comment_request(forge, opened.number, &step_comment(step));
+        if let Err(err) = post(&url, &headers, &payload) {
+            eprintln!("Could not comment on {} about step {}: {:#}", opened.url, step.step, err);
+        }
+    }
+}
+
+/// POSTs `payload` and returns the JSON the forge answers with.
+fn post(url: &str, headers: &[String], payload: &Value) -> anyhow::Result<Value> {
+    let mut args = vec!["-fsS", "--max-time", FORGE_TIMEOUT_SECS, "-X", "POST", "-H", "Content-Type: application/json"];
+    for header in headers {
+        args.extend(["-H", header.as_str()]);
+    }
+    args.extend(["--data-binary", "@-", url]);
+    let mut child = Command::new("curl")
+        .args(&args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Talking to the forge needs curl")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(payload.to_string().as_bytes());
+    }
+    let output = child.wait_with_output().context("Could not run curl")?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    serde_json::from_slice(&output.stdout).with_context(|| format!("`{}` did not answer with JSON", url))
+}
+
+/// Where the issue or pull request the forge created is shown.
+fn created_url(forge_type: ForgeType, created: &Value) -> anyhow::Result<String> {
+    let key = match forge_type {
+        ForgeType::Github => "html_url",
+        ForgeType::Gitlab => "web_url",
+    };
+    created[key].as_str().map(str::to_string).ok_or_else(|| anyhow!("What the forge created has no `{}`", key))
+}
+
+/// Opens an issue for each failed step and returns the step's id and the issue's URL for those that were opened.
+/// A forge that can't be reached doesn't fail the run, it's reported and the other steps are tried.
+pub fn open_failure_issues(forge: &ForgeConfig, failed_steps: &[FailedStep], run_id: &str, resume: &str) -> Vec<(String, String)> {
+    let Some(issues) = &forge.issues else {
+        return vec![];
+    };
+    let mut opened = vec![];
+    for failed in failed_steps {
+        let (url, headers, payload) = issue_request(forge, issues, failed, run_id, resume);
+        match post(&url, &headers, &payload).and_then(|created| created_url(forge.forge_type, &created)) {
+            Ok(issue) => {
+                eprintln!("Opened {} for step {}", issue, failed.request.id);
+                opened.push((failed.request.id.clone(), issue));
+            }
+            Err(err) => eprintln!("Could not open an issue for step {}: {:#}", failed.request.id, err),
+        }
+    }
+    opened
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::forge::{
+        comment_request, created_url, issue_request, pull_request_body, pull_request_request, step_comment, FailedStep, ForgeConfig,
+        ForgeType, IssuesConfig, PullRequestConfig, PullRequestStep,
+    };
+    use crate::run::{StepRequest, StepResponse};
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn issues_are_templated_for_each_forge() {
+        let request = StepRequest {
+            id: "lint".to_string(),
+            run: "eslint --fix".to_string(),
+            ..Default::default()
+        };
+        let mut response = StepResponse::pending();
+        response.output = Some("Running\neslint --fix\nsrc/app.js: 1 problem\nsrc/lib.js: 2 problems".to_string());
+        let failed = FailedStep { step: 2, request: &request, response: &response };
+        let issues = IssuesConfig {
+            labels: vec!["mend".to_string(), "migration".to_string()],
+            output_lines: Some(2),
+            ..Default::default()
+        };
+        let mut forge = ForgeConfig {
+            forge_type: ForgeType::Github,
+            project: "craftvscruft/app".to_string(),
+            token: "secret".to_string(),
+            api_url: None,
+            issues: Some(issues.clone()),
+            pull_request: None,
+        };
+        let github = issue_request(&forge, &issues, &failed, "1700000000", "mend -f mend-followup.toml");
+        forge.forge_type = ForgeType::Gitlab;
+        forge.api_url = Some("https://git.example.com/api/v4/".to_string());
+        forge.project = "platform/tools/app".to_string();
+        let custom = IssuesConfig {
+            title: Some("[$id] needs a hand".to_string()),
+            ..issues
+        };
+        let gitlab = issue_request(&forge, &custom, &failed, "1700000000", "mend resume");
+        insta::assert_yaml_snapshot!((github, gitlab));
+
+        assert_eq!(created_url(ForgeType::Github, &json!({"html_url": "https://github.com/o/r/issues/7"})).unwrap(), "https://github.com/o/r/issues/7");
+        assert!(created_url(ForgeType::Gitlab, &json!({"message": "401 Unauthorized"})).is_err());
+    }
+
+    #[test]
+    fn pull_requests_list_the_steps() {
+        let steps = vec![
+            PullRequestStep {
+                step: 1,
+                summary: "Rename Foo to Bar".to_string(),
+                sha: Some("abc1234".to_string()),
+                duration: Some(Duration::from_secs(3)),
+                issue: None,
+                warnings: None,
+                output: None,
+            },
+            PullRequestStep {
+                step: 2,
+                summary: "sed -i 's/a|b/c/' App.java".to_string(),
+                sha: None,
+                duration: None,
+                issue: Some("https://github.com/craftvscruft/app/issues/7".to_string()),
+                warnings: None,
+                output: None,
+            },
+        ];
+        let body = pull_request_body("migrations/java17.toml", "2d62d13", &steps);
+        let forge = ForgeConfig {
+            forge_type: ForgeType::Github,
+            project: "craftvscruft/app".to_string(),
+            token: "secret".to_string(),
+            ..Default::default()
+        };
+        let pull_request = PullRequestConfig {
+            base: "main".to_string(),
+            draft: true,
+            ..Default::default()
+        };
+        let vars = [("config", "java17".to_string()), ("run_id", "1700000000".to_string())];
+        let github = pull_request_request(&forge, &pull_request, "mend/2026-10-16-java17", &vars, &body);
+        let gitlab_forge = ForgeConfig {
+            forge_type: ForgeType::Gitlab,
+            project: "platform/tools/app".to_string(),
+            api_url: Some("https://git.example.com/api/v4".to_string()),
+            ..forge
+        };
+        let gitlab = pull_request_request(&gitlab_forge, &pull_request, "mend/2026-10-16-java17", &vars, "Made by mend");
+        insta::assert_yaml_snapshot!((github, gitlab));
+
+        let owner_vars = [vars[0].clone(), vars[1].clone(), ("owners", "@org/payments".to_string())];
+        let (_, _, split) = pull_request_request(&gitlab_forge, &pull_request, "mend/2026-10-16-java17-org-payments", &owner_vars, "");
+        assert_eq!(split["title"], "Draft: mend: java17 for @org/payments");
+    }
+
+    #[test]
+    fn steps_are_commented_with_their_warnings() {
+        let mut step = PullRequestStep {
+            step: 3,
+            summary: "Upgrade to Java 17".to_string(),
+            sha: Some("abc1234".to_string()),
+            duration: None,
+            issue: None,
+            warnings: None,
+            output: Some("Running\nopenrewrite\n".to_string() + &"Changed App.java\n".repeat(40) + "2 files could not be parsed"),
+        };
+        let quiet = step_comment(&step);
+        assert_eq!(quiet, "**Step 3** abc1234: Upgrade to Java 17\n");
+        step.warnings = Some("Lombok annotations left as they were\n2 files could not be parsed".to_string());
+        let warned = step_comment(&step);
+        assert!(!warned.contains("Running"), "{}", warned);
+
+        let forge = ForgeConfig {
+            forge_type: ForgeType::Github,
+            project: "craftvscruft/app".to_string(),
+            token: "secret".to_string(),
+            ..Default::default()
+        };
+        let github = comment_request(&forge, 12, &warned);
+        let gitlab_forge = ForgeConfig {
+            forge_type: ForgeType::Gitlab,
+            project: "platform/tools/app".to_string(),
+            ..forge
+        };
+        let gitlab = comment_request(&gitlab_forge, 4, &quiet);
+        insta::assert_yaml_snapshot!((github, gitlab));
+    }
+}
@@ -0,0 +1,149 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::repo::GitRepo;
+use crate::run::Executor;
+
+/// Conditions the finished run must meet before its result may be published.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Gates {
+    /// Added plus removed lines across the whole run
+    pub max_changed_lines: Option<usize>,
+    pub max_changed_files: Option<usize>,
+
+    /// Runs the verify command once more at the final sha
+    #[serde(default)]
+    pub verify: bool,
+
+    /// Paths no step may touch, an entry ending in `/` covers a whole directory
+    #[serde(default)]
+    pub protected_paths: Vec<String>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct DiffStats {
+    pub changed_lines: usize,
+    pub files: Vec<String>,
+}
+
+impl DiffStats {
+    /// Parses `git diff --numstat`, where binary files show `-` for their line counts.
+    pub fn from_numstat(numstat: &str) -> Self {
+        let mut stats = DiffStats::default();
+        for line in numstat.lines() {
+            let mut fields = line.splitn(3, '\t');
+            if let (Some(added), Some(removed), Some(path)) = (fields.next(), fields.next(), fields.next()) {
+                stats.changed_lines += added.parse::<usize>().unwrap_or_default();
+                stats.changed_lines += removed.parse::<usize>().unwrap_or_default();
+                stats.files.push(path.to_string());
+            }
+        }
+        stats
+    }
+}
+
+pub fn is_protected(path: &str, protected: &str) -> bool {
+    if protected.ends_with('/') {
+        path.starts_with(protected)
+    } else {
+        path == protected
+    }
+}
+
+/// Describes each violated gate, empty when the run may be published.
+/// `verify_passed` is None when verification wasn't possible.
+pub fn evaluate_gates(gates: &Gates, stats: &DiffStats, verify_passed: Option<bool>) -> Vec<String> {
+    let mut failures = vec![];
+    if let Some(max_lines) = gates.max_changed_lines {
+        if stats.changed_lines > max_lines {
+            failures.push(format!(
+                "max_changed_lines: {} lines changed, at most {} allowed",
+                stats.changed_lines, max_lines
+            ));
+        }
+    }
+    if let Some(max_files) = gates.max_changed_files {
+        if stats.files.len() > max_files {
+            failures.push(format!(
+                "max_changed_files: {} files changed, at most {} allowed",
+                stats.files.len(),
+                max_files
+            ));
+        }
+    }
+    if gates.verify {
+        match verify_passed {
+            Some(true) => {}
+            Some(false) => failures.push("verify: failed at the final sha".to_string()),
+            None => failures.push("verify: no verify command configured or detected".to_string()),
+        }
+    }
+    for path in &stats.files {
+        if let Some(protected) = gates
+            .protected_paths
+            .iter()
+            .find(|protected| is_protected(path, protected))
+        {
+            failures.push(format!("protected_paths: {} touched, matches `{}`", path, protected));
+        }
+    }
+    failures
+}
+
+/// Checks the gates against everything the run changed in `worktree_repo` since `from_sha`.
+pub fn check_gates<E: Executor>(
+    gates: &Gates,
+    worktree_repo: &GitRepo,
+    from_sha: &str,
+    verify: Option<&str>,
+    env: &BTreeMap<String, String>,
+    executor: &mut E,
+) -> anyhow::Result<Vec<String>> {
+    let stats = DiffStats::from_numstat(&worktree_repo.diff_numstat(from_sha)?);
+    let verify_passed = match verify {
+        Some(verify) if gates.verify => Some(
+            executor
+                .run_script(&worktree_repo.repo_dir, verify, env, None, &mut |_| {})?
+                .status
+                .success(),
+        ),
+        _ => None,
+    };
+    Ok(evaluate_gates(gates, &stats, verify_passed))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gates::{evaluate_gates, DiffStats, Gates};
+
+    #[test]
+    fn numstat_counts_lines_and_files() {
+        let stats = DiffStats::from_numstat("3\t1\tsrc/main.c\n-\t-\tlogo.png\n10\t0\t.github/workflows/ci.yml\n");
+        assert_eq!(stats.changed_lines, 14);
+        assert_eq!(stats.files, vec!["src/main.c", "logo.png", ".github/workflows/ci.yml"]);
+    }
+
+    #[test]
+    fn passing_gates_report_nothing() {
+        let gates = Gates {
+            max_changed_lines: Some(100),
+            max_changed_files: Some(3),
+            verify: true,
+            protected_paths: vec![".github/".to_string(), "LICENSE".to_string()],
+        };
+        let stats = DiffStats::from_numstat("3\t1\tsrc/main.c\n1\t1\tLICENSE.md\n");
+        assert!(evaluate_gates(&gates, &stats, Some(true)).is_empty());
+    }
+
+    #[test]
+    fn failing_gates_are_each_reported() {
+        let gates = Gates {
+            max_changed_lines: Some(10),
+            max_changed_files: Some(1),
+            verify: true,
+            protected_paths: vec![".github/".to_string()],
+        };
+        let stats = DiffStats::from_numstat("3\t1\tsrc/main.c\n10\t0\t.github/workflows/ci.yml\n");
+        insta::assert_yaml_snapshot!(evaluate_gates(&gates, &stats, None));
+    }
+}
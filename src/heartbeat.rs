@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::progress::Notify;
+use crate::run::{EStatus, RunSummary, StepRequest, StepResponse};
+
+const HEARTBEAT_FILE: &str = "heartbeat.json";
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct HeartbeatConfig {
+    /// How often `.mend/heartbeat.json` is rewritten while the run goes on
+    pub minutes: u64,
+}
+
+/// Written periodically so monitors can tell a long run is still alive.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Heartbeat {
+    /// Seconds since the Unix epoch
+    pub time: u64,
+    /// The running step counting from 1, 0 before the first one starts
+    pub step: usize,
+    pub run: String,
+    pub completed_steps: usize,
+    pub total_steps: usize,
+    pub percent: usize,
+    /// Extrapolated from the time the completed steps took
+    pub eta_seconds: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct RunProgress {
+    step: usize,
+    run: String,
+    completed_steps: usize,
+    total_steps: usize,
+}
+
+fn create_heartbeat(progress: &RunProgress, elapsed: Duration, time: u64) -> Heartbeat {
+    let percent = (progress.completed_steps * 100)
+        .checked_div(progress.total_steps)
+        .unwrap_or(100);
+    let eta_seconds = if progress.completed_steps == 0 {
+        None
+    } else {
+        let remaining = progress.total_steps.saturating_sub(progress.completed_steps) as u64;
+        Some(elapsed.as_secs() * remaining / progress.completed_steps as u64)
+    };
+    Heartbeat {
+        time,
+        step: progress.step,
+        run: progress.run.clone(),
+        completed_steps: progress.completed_steps,
+        total_steps: progress.total_steps,
+        percent,
+        eta_seconds,
+    }
+}
+
+fn write_heartbeat(path: &Path, progress: &Mutex<RunProgress>, started: Instant) {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let heartbeat = match progress.lock() {
+        Ok(progress) => create_heartbeat(&progress, started.elapsed(), time),
+        Err(_) => return,
+    };
+    if let Ok(json) = serde_json::to_string_pretty(&heartbeat) {
+        if let Err(err) = fs::write(path, json) {
+            eprintln!("Could not write heartbeat to `{}`: {}", path.to_string_lossy(), err);
+        }
+    }
+}
+
+/// Passes everything on to `inner` and keeps track of where the run is for a heartbeat thread.
+pub struct HeartbeatNotifier<N: Notify> {
+    inner: N,
+    progress: Arc<Mutex<RunProgress>>,
+    stop: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+impl<N: Notify> HeartbeatNotifier<N> {
+    /// Without an interval no thread is started and `inner` is used as it is.
+    pub fn new(inner: N, total_steps: usize, mend_dir: &Path, interval: Option<Duration>) -> Self {
+        let progress = Arc::new(Mutex::new(RunProgress {
+            total_steps,
+            ..Default::default()
+        }));
+        let stop = interval.map(|interval| {
+            let (sender, receiver) = channel();
+            let path: PathBuf = mend_dir.join(HEARTBEAT_FILE);
+            let thread_progress = progress.clone();
+            let started = Instant::now();
+            let handle = std::thread::spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+                    write_heartbeat(&path, &thread_progress, started);
+                }
+            });
+            (sender, handle)
+        });
+        HeartbeatNotifier {
+            inner,
+            progress,
+            stop,
+        }
+    }
+}
+
+impl<N: Notify> Drop for HeartbeatNotifier<N> {
+    fn drop(&mut self) {
+        if let Some((sender, handle)) = self.stop.take() {
+            let _ = sender.send(());
+            let _ = handle.join();
+        }
+    }
+}
+
+impl<N: Notify> Notify for HeartbeatNotifier<N> {
+    fn notify(&mut self, i: usize, run: &str, status: &EStatus, sha: &Option<String>, inc: bool) {
+        if let Ok(mut progress) = self.progress.lock() {
+            progress.step = i + 1;
+            progress.run = run.to_string();
+            let completed = match status {
+                EStatus::Done | EStatus::Failed | EStatus::VerifyFailed | EStatus::Skipped => i + 1,
+                _ => i,
+            };
+            progress.completed_steps = progress.completed_steps.max(completed);
+        }
+        self.inner.notify(i, run, status, sha, inc)
+    }
+
+    fn notify_done(&self, summary: &RunSummary) {
+        self.inner.notify_done(summary)
+    }
+
+    fn notify_output(&mut self, i: usize, line: &str) {
+        self.inner.notify_output(i, line)
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        self.inner.suspend(f)
+    }
+
+    fn notify_failure(&self, failed_request: &StepRequest, failed_response: &StepResponse) {
+        self.inner.notify_failure(failed_request, failed_response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::heartbeat::{create_heartbeat, Heartbeat, HeartbeatNotifier, RunProgress, HEARTBEAT_FILE};
+    use crate::progress::Notify;
+    use crate::run::EStatus;
+    use crate::test_support::SilentNotifier;
+    use std::fs;
+    use std::time::Duration;
+
+    #[test]
+    fn heartbeat_estimates_remaining_time() {
+        let progress = RunProgress {
+            step: 3,
+            run: "rename a b".to_string(),
+            completed_steps: 2,
+            total_steps: 8,
+        };
+        let heartbeat = create_heartbeat(&progress, Duration::from_secs(100), 1000);
+        assert_eq!(heartbeat.percent, 25);
+        assert_eq!(heartbeat.eta_seconds, Some(300));
+        let not_started = create_heartbeat(&RunProgress::default(), Duration::from_secs(5), 1000);
+        assert_eq!(not_started.eta_seconds, None);
+    }
+
+    #[test]
+    fn heartbeat_file_follows_the_run() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut notifier = HeartbeatNotifier::new(SilentNotifier, 4, temp_dir.path(), Some(Duration::from_millis(10)));
+        notifier.notify(0, "first", &EStatus::Done, &None, true);
+        notifier.notify(1, "second", &EStatus::Running, &None, true);
+        std::thread::sleep(Duration::from_millis(100));
+        drop(notifier);
+        let heartbeat: Heartbeat =
+            serde_json::from_str(&fs::read_to_string(temp_dir.path().join(HEARTBEAT_FILE)).unwrap()).unwrap();
+        assert_eq!((heartbeat.step, heartbeat.completed_steps, heartbeat.percent), (2, 1, 25));
+        assert_eq!(heartbeat.run, "second");
+    }
+}
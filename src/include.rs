@@ -0,0 +1,179 @@
+use anyhow::{bail, Context};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::repo::clone_shallow;
+use crate::run::run_command_with_output;
+
+/// Where remote includes are kept once fetched, under `.mend`.
+pub const INCLUDE_CACHE_DIR: &str = "cache/includes";
+
+/// An `include` entry, a path relative to the including config or a shared recipe pack.
+#[derive(Debug, PartialEq)]
+pub enum IncludeSource<'a> {
+    Local(&'a str),
+    /// `https://example.com/recipes.toml`
+    Url(&'a str),
+    /// `git+https://github.com/org/recipes.git//cargo.toml`, the repository and the file in it
+    Git { repo: &'a str, file: &'a str },
+}
+
+impl<'a> IncludeSource<'a> {
+    pub fn parse(include: &'a str) -> anyhow::Result<Self> {
+        if let Some(git_url) = include.strip_prefix("git+") {
+            let scheme_end = git_url.find("://").map_or(0, |i| i + 3);
+            if git_url.starts_with("http://") {
+                bail!("Include `{}` would be fetched over plain http, use https", include);
+            }
+            match git_url[scheme_end..].find("//") {
+                Some(i) => {
+                    let file = &git_url[scheme_end + i + 2..];
+                    // The file is joined to the clone, it can't point outside of it
+                    if Path::new(file).components().any(|component| !matches!(component, Component::Normal(_) | Component::CurDir)) {
+                        bail!("Include `{}` names a file outside of the repository", include);
+                    }
+                    Ok(IncludeSource::Git { repo: &git_url[..scheme_end + i], file })
+                }
+                None => bail!(
+                    "Include `{}` needs the file in the repository after `//`, e.g. `{}//recipes.toml`",
+                    include,
+                    include
+                ),
+            }
+        } else if include.starts_with("https://") {
+            Ok(IncludeSource::Url(include))
+        } else if include.starts_with("http://") {
+            bail!("Include `{}` would be fetched over plain http, use https", include)
+        } else {
+            Ok(IncludeSource::Local(include))
+        }
+    }
+
+    pub fn is_remote(&self) -> bool {
+        !matches!(self, IncludeSource::Local(_))
+    }
+}
+
+/// A file or directory name for a URL, keeping its extension. URLs that only differ in the characters
+/// replaced in the name are told apart by the hash of the URL in front.
+pub fn cache_name(url: &str) -> String {
+    let hash = format!("{:x}", Sha256::digest(url.as_bytes()));
+    let readable: String = url
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+    format!("{}-{}", &hash[..12], readable)
+}
+
+/// The path of an include and its contents, fetching remote ones into `cache_dir` the first time.
+pub fn read_include(parent_dir: &Path, cache_dir: &Path, include: &str) -> anyhow::Result<(PathBuf, String)> {
+    let path = match IncludeSource::parse(include)? {
+        IncludeSource::Local(path) => parent_dir.join(path),
+        IncludeSource::Url(url) => {
+            let path = cache_dir.join(cache_name(url));
+            if !path.exists() {
+                fetch_url(url, &path)?;
+            }
+            path
+        }
+        IncludeSource::Git { repo, file } => {
+            let clone_dir = cache_dir.join(cache_name(repo));
+            if !clone_dir.exists() {
+                fs::create_dir_all(cache_dir)
+                    .with_context(|| format!("Could not create `{}`", cache_dir.to_string_lossy()))?;
+                clone_shallow(repo, &clone_dir).with_context(|| format!("Could not fetch include `{}`", include))?;
+            }
+            clone_dir.join(file)
+        }
+    };
+    let contents = fs::read_to_string(&path)?;
+    Ok((path, contents))
+}
+
+fn fetch_url(url: &str, path: &Path) -> anyhow::Result<()> {
+    let cache_dir = path.parent().unwrap_or(Path::new(""));
+    fs::create_dir_all(cache_dir)
+        .with_context(|| format!("Could not create `{}`", cache_dir.to_string_lossy()))?;
+    // Download next to the cached file, so an interrupted fetch never leaves half a config behind
+    let partial_path = path.with_extension("partial");
+    let partial_str = partial_path.to_string_lossy().to_string();
+    let output = run_command_with_output(cache_dir, "curl".to_string(), vec!["-fsSL", "-o", &partial_str, url])
+        .with_context(|| format!("Fetching include `{}` needs curl", url))?;
+    if !output.status.success() {
+        let _ = fs::remove_file(&partial_path);
+        bail!(
+            "Could not fetch include `{}`:\n{}",
+            url,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    fs::rename(&partial_path, path).with_context(|| format!("Could not write `{}`", path.to_string_lossy()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::load_mend;
+    use crate::include::{cache_name, IncludeSource, INCLUDE_CACHE_DIR};
+    use crate::repo::{GitRepo, Repo};
+    use std::fs;
+    use std::process::Command;
+
+    #[test]
+    fn include_sources_are_parsed() {
+        assert_eq!(IncludeSource::parse("recipes.toml").unwrap(), IncludeSource::Local("recipes.toml"));
+        assert_eq!(
+            IncludeSource::parse("https://example.com/recipes.toml").unwrap(),
+            IncludeSource::Url("https://example.com/recipes.toml")
+        );
+        assert_eq!(
+            IncludeSource::parse("git+https://github.com/org/recipes.git//cargo.toml").unwrap(),
+            IncludeSource::Git {
+                repo: "https://github.com/org/recipes.git",
+                file: "cargo.toml"
+            }
+        );
+        assert!(IncludeSource::parse("git+https://github.com/org/recipes.git").is_err());
+        assert!(IncludeSource::parse("http://example.com/recipes.toml").is_err());
+        assert!(IncludeSource::parse("git+http://github.com/org/recipes.git//cargo.toml").is_err());
+        assert!(IncludeSource::parse("git+https://github.com/org/recipes.git//../../.ssh/config").is_err());
+        assert!(IncludeSource::parse("git+https://github.com/org/recipes.git///etc/passwd").is_err());
+    }
+
+    #[test]
+    fn cache_names_of_different_urls_differ() {
+        let a = cache_name("https://example.com/a/b.toml");
+        assert!(a.ends_with("https___example.com_a_b.toml"), "{}", a);
+        assert_ne!(a, cache_name("https://example.com/a_b.toml"));
+    }
+
+    #[test]
+    fn git_includes_are_cloned_once() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let pack_dir = temp_dir.path().join("pack");
+        fs::create_dir(&pack_dir).unwrap();
+        Command::new("git").arg("init").arg("-q").current_dir(&pack_dir).output().unwrap();
+        fs::write(pack_dir.join("cargo.toml"), "[recipes.clippy_fix]\nrun = \"cargo clippy --fix\"\n").unwrap();
+        Command::new("git").args(["add", "cargo.toml"]).current_dir(&pack_dir).output().unwrap();
+        GitRepo {
+            repo_dir: pack_dir.clone(),
+        }
+        .commit_all("Add cargo recipes")
+        .unwrap();
+
+        let config_dir = temp_dir.path().join("config");
+        fs::create_dir(&config_dir).unwrap();
+        let config_path = config_dir.join("mend.toml");
+        fs::write(
+            &config_path,
+            format!("include = [\"git+file://{}//cargo.toml\"]\n", pack_dir.to_string_lossy()),
+        )
+        .unwrap();
+        assert!(load_mend(&config_path).unwrap().recipes.contains_key("clippy_fix"));
+
+        // Later loads read the cache, even when the pack is gone
+        fs::remove_dir_all(&pack_dir).unwrap();
+        assert!(load_mend(&config_path).unwrap().recipes.contains_key("clippy_fix"));
+        assert!(config_dir.join(".mend").join(INCLUDE_CACHE_DIR).exists());
+    }
+}
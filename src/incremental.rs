@@ -0,0 +1,112 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::repo::Repo;
+use crate::run::StepRequest;
+use crate::schema::{from_versioned_json, to_versioned_json};
+
+/// Where the last successful execution of steps with `inputs` is remembered, under `.mend`.
+pub const STEP_CACHE_FILE: &str = "cache/steps.json";
+
+/// What the files of a step's `inputs` and `outputs` looked like when it last succeeded.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CachedStep {
+    pub inputs: String,
+    pub outputs: String,
+}
+
+/// Steps by a digest of their scripts, so changing a recipe or its arguments runs the step again.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StepCache {
+    #[serde(default)]
+    pub steps: BTreeMap<String, CachedStep>,
+}
+
+fn digest(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+fn step_key(step_request: &StepRequest) -> String {
+    digest(&step_request.run_resolved.join("\n"))
+}
+
+/// The step's inputs and outputs as they are now in `repo`.
+pub fn current_state<R: Repo>(repo: &R, step_request: &StepRequest) -> anyhow::Result<CachedStep> {
+    Ok(CachedStep {
+        inputs: digest(&repo.file_hashes(&step_request.inputs)?),
+        outputs: digest(&repo.file_hashes(&step_request.outputs)?),
+    })
+}
+
+impl StepCache {
+    /// An unreadable cache only means steps run again.
+    pub fn read(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| from_versioned_json(path, &contents, &[]).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("Could not create `{}`", dir.to_string_lossy()))?;
+        }
+        fs::write(path, to_versioned_json(self)?).with_context(|| format!("Could not write `{}`", path.to_string_lossy()))
+    }
+
+    /// True when the step's inputs and outputs are as its last successful execution left them.
+    pub fn is_fresh(&self, step_request: &StepRequest, state: &CachedStep) -> bool {
+        self.steps.get(&step_key(step_request)) == Some(state)
+    }
+
+    pub fn record(&mut self, step_request: &StepRequest, state: CachedStep) {
+        self.steps.insert(step_key(step_request), state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::incremental::{current_state, StepCache};
+    use crate::repo::{GitRepo, Repo};
+    use crate::run::StepRequest;
+    use std::fs;
+    use std::process::Command;
+
+    #[test]
+    fn steps_are_fresh_until_their_files_change() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let repo_dir = temp_dir.path().to_path_buf();
+        Command::new("git").arg("init").arg("-q").current_dir(&repo_dir).output().unwrap();
+        fs::create_dir(repo_dir.join("spec")).unwrap();
+        fs::write(repo_dir.join("spec/api.yaml"), "openapi: 3.0.0\n").unwrap();
+        fs::write(repo_dir.join("api.json"), "{}\n").unwrap();
+        let mut repo = GitRepo { repo_dir: repo_dir.clone() };
+        Command::new("git").args(["add", "-A"]).current_dir(&repo_dir).output().unwrap();
+        repo.commit_all("Add spec").unwrap();
+
+        let step_request = StepRequest {
+            run_resolved: vec!["generate_api".to_string()],
+            inputs: vec!["spec/**/*.yaml".to_string()],
+            outputs: vec!["api.json".to_string()],
+            ..Default::default()
+        };
+        let mut cache = StepCache::default();
+        cache.record(&step_request, current_state(&repo, &step_request).unwrap());
+        let cache_path = temp_dir.path().join(".mend/cache/steps.json");
+        cache.write(&cache_path).unwrap();
+        let cache = StepCache::read(&cache_path);
+        assert!(cache.is_fresh(&step_request, &current_state(&repo, &step_request).unwrap()));
+
+        let changed_script = StepRequest { run_resolved: vec!["generate_api --v2".to_string()], ..step_request };
+        assert!(!cache.is_fresh(&changed_script, &current_state(&repo, &changed_script).unwrap()));
+
+        let step_request = StepRequest { run_resolved: vec!["generate_api".to_string()], ..changed_script };
+        fs::write(repo_dir.join("spec/api.yaml"), "openapi: 3.1.0\n").unwrap();
+        repo.commit_all("Bump spec").unwrap();
+        assert!(!cache.is_fresh(&step_request, &current_state(&repo, &step_request).unwrap()));
+    }
+}
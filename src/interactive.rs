@@ -0,0 +1,134 @@
+use anyhow::{bail, Context};
+use std::env;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::process::Command;
+
+use crate::run::StepRequest;
+use crate::secrets::without_identity;
+
+/// Written between the scripts of a step when they're edited together, splitting them again afterwards.
+const SCRIPT_SEPARATOR: &str = "# ---- mend: next script ----";
+
+/// What `--interactive` was told to do with a step.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Approval {
+    Run,
+    Skip,
+    /// Stop the run before the step, `mend resume` picks it up there
+    Quit,
+}
+
+/// Shows the step's scripts and commit message on `out` and reads what to do from `input` until it's told to run,
+/// skip or quit. Edited scripts replace the step's own in `step_request`, only for this run, `edit` gets the scripts
+/// as one text and returns them changed.
+pub fn ask<I: BufRead, O: Write>(
+    input: &mut I,
+    out: &mut O,
+    step_i: usize,
+    step_count: usize,
+    step_request: &mut StepRequest,
+    edit: &mut dyn FnMut(&str) -> anyhow::Result<String>,
+) -> anyhow::Result<Approval> {
+    loop {
+        writeln!(out, "Step {} of {}: {}", step_i + 1, step_count, step_request.run)?;
+        for script in &step_request.run_resolved {
+            writeln!(out, "  Script:")?;
+            for line in script.lines() {
+                writeln!(out, "    {}", line)?;
+            }
+        }
+        writeln!(out, "  Commit message:")?;
+        for line in step_request.commit_msg.lines() {
+            writeln!(out, "    {}", line)?;
+        }
+        write!(out, "[r]un / [s]kip / [e]dit / [q]uit? ")?;
+        out.flush()?;
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            // Nobody left to answer
+            return Ok(Approval::Quit);
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "r" | "run" => return Ok(Approval::Run),
+            "s" | "skip" => return Ok(Approval::Skip),
+            "q" | "quit" => return Ok(Approval::Quit),
+            "e" | "edit" => {
+                let edited = edit(&step_request.run_resolved.join(&format!("\n{}\n", SCRIPT_SEPARATOR)))?;
+                step_request.run_resolved = edited
+                    .split(&format!("\n{}\n", SCRIPT_SEPARATOR))
+                    .map(|script| script.trim_end().to_string())
+                    .filter(|script| !script.trim().is_empty())
+                    .collect();
+            }
+            other => writeln!(out, "`{}` is not one of r, s, e or q", other)?,
+        }
+    }
+}
+
+/// Opens `text` in `$VISUAL` or `$EDITOR`, `vi` when neither is set, and returns it once the editor exits.
+pub fn edit_in_editor(text: &str) -> anyhow::Result<String> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let path = env::temp_dir().join(format!("mend-step-{}.sh", std::process::id()));
+    fs::write(&path, format!("{}\n", text)).with_context(|| format!("Could not write `{}`", path.to_string_lossy()))?;
+    // Like git, the editor may come with arguments, e.g. `code --wait`
+    let status = without_identity(&mut Command::new("sh"))
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(&editor)
+        .arg(&path)
+        .status()
+        .with_context(|| format!("Could not run the editor `{}`", editor))?;
+    let edited = fs::read_to_string(&path).with_context(|| format!("Could not read `{}`", path.to_string_lossy()));
+    let _ = fs::remove_file(&path);
+    if !status.success() {
+        bail!("The editor `{}` exited with {}, the scripts are unchanged", editor, status);
+    }
+    edited
+}
+
+/// Asks on the terminal, an editor that fails leaves the scripts as they were and asks again.
+pub fn ask_on_terminal(step_i: usize, step_count: usize, step_request: &mut StepRequest) -> Approval {
+    let mut input = std::io::stdin().lock();
+    let mut out = std::io::stderr();
+    let mut edit = |text: &str| {
+        edit_in_editor(text).or_else(|err| {
+            eprintln!("{:#}", err);
+            Ok(text.to_string())
+        })
+    };
+    ask(&mut input, &mut out, step_i, step_count, step_request, &mut edit).unwrap_or_else(|err| {
+        eprintln!("Could not ask about step {}: {:#}", step_i + 1, err);
+        Approval::Quit
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interactive::{ask, Approval};
+    use crate::run::StepRequest;
+
+    #[test]
+    fn steps_are_shown_and_edited_before_they_run() {
+        let mut step_request = StepRequest {
+            id: "1".to_string(),
+            run: "rename Foo Bar".to_string(),
+            run_resolved: vec!["./setup.sh".to_string(), "sed -i s/Foo/Bar/g App.java".to_string()],
+            commit_msg: "r - rename Foo Bar".to_string(),
+            ..Default::default()
+        };
+        let mut input = "x\ne\nr\n".as_bytes();
+        let mut out = vec![];
+        let mut edit = |text: &str| Ok(text.replace("sed -i", "sed -i.bak"));
+        let approval = ask(&mut input, &mut out, 0, 3, &mut step_request, &mut edit).unwrap();
+        assert_eq!(approval, Approval::Run);
+        assert_eq!(step_request.run_resolved, vec!["./setup.sh", "sed -i.bak s/Foo/Bar/g App.java"]);
+        insta::assert_snapshot!(String::from_utf8(out).unwrap());
+
+        let mut out = vec![];
+        assert_eq!(ask(&mut "s\n".as_bytes(), &mut out, 0, 3, &mut step_request, &mut edit).unwrap(), Approval::Skip);
+        assert_eq!(ask(&mut "".as_bytes(), &mut out, 0, 3, &mut step_request, &mut edit).unwrap(), Approval::Quit);
+    }
+}
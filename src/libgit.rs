@@ -0,0 +1,551 @@
+use anyhow::{bail, Context};
+use git2::build::CheckoutBuilder;
+use git2::{Commit, Diff, ErrorCode, IndexAddOption, Oid, Patch, Repository, ResetType, Signature, Sort, StatusOptions, WorktreeAddOptions, WorktreePruneOptions};
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::Result;
+use crate::repo::{common_git_dir, lock_wait, wait_for_locks, worker_worktree_dir, GitRepo, Identity, Repo};
+
+/// The prefix git gives the message of `git commit --fixup`, which autosquash looks for.
+const FIXUP_PREFIX: &str = "fixup! ";
+
+fn open(repo_dir: &Path) -> anyhow::Result<Repository> {
+    Repository::open(repo_dir).with_context(|| format!("`{}` is not a git repository", repo_dir.to_string_lossy()))
+}
+
+/// Opens the repository at `repo_dir` and calls `f` with it, again while another process holds a lock it needs.
+/// libgit2 takes its locks before changing anything, so the retries are safe.
+fn with_repo<T>(repo_dir: &Path, mut f: impl FnMut(&Repository) -> anyhow::Result<T>) -> Result<T> {
+    wait_for_locks(lock_wait(), || {
+        let repo = open(repo_dir)?;
+        match f(&repo) {
+            Err(err) => match err.downcast_ref::<git2::Error>().and_then(contended_lock) {
+                Some(lock_path) => Ok(Err(lock_path)),
+                None => Err(err.into()),
+            },
+            Ok(done) => Ok(Ok(done)),
+        }
+    })
+}
+
+/// The lock file in libgit2's `failed to create locked file '<path>': ...`.
+fn contended_lock(err: &git2::Error) -> Option<PathBuf> {
+    if err.code() != ErrorCode::Locked {
+        return None;
+    }
+    let message = err.message();
+    let start = message.find('\'')? + 1;
+    let end = message[start..].find('\'')?;
+    Some(PathBuf::from(&message[start..start + end]))
+}
+
+fn find_commit<'r>(repo: &'r Repository, rev: &str) -> anyhow::Result<Commit<'r>> {
+    repo.revparse_single(rev)
+        .and_then(|object| object.peel_to_commit())
+        .with_context(|| format!("No commit `{}`", rev))
+}
+
+/// The commit HEAD is on, None before the first commit.
+fn head_commit(repo: &Repository) -> anyhow::Result<Option<Commit<'_>>> {
+    match repo.head() {
+        Ok(head) => Ok(Some(head.peel_to_commit()?)),
+        Err(err) if err.code() == ErrorCode::UnbornBranch => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn short_sha(repo: &Repository, oid: Oid) -> anyhow::Result<String> {
+    let short_id = repo.find_object(oid, None)?.short_id()?;
+    Ok(short_id.as_str().unwrap_or_default().to_string())
+}
+
+/// `identity` when given, otherwise the user from git's config.
+fn signature(repo: &Repository, identity: Option<&Identity>) -> anyhow::Result<Signature<'static>> {
+    match identity {
+        Some(identity) => Signature::now(&identity.name, &identity.email)
+            .with_context(|| format!("Invalid committer `{} <{}>`", identity.name, identity.email)),
+        None => repo.signature().context("No user to commit as, set git's `user.name` and `user.email`"),
+    }
+}
+
+/// Like `git commit --all`, stages the changes to tracked files and commits them with what was staged already.
+fn commit_tracked(repo: &Repository, message: &str, identity: Option<&Identity>) -> anyhow::Result<Oid> {
+    let mut index = repo.index()?;
+    index.update_all(["*"], None)?;
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let head = head_commit(repo)?;
+    let unchanged = match &head {
+        Some(head) => head.tree_id() == tree.id(),
+        None => index.is_empty(),
+    };
+    if unchanged {
+        bail!("Nothing to commit in `{}`", repo.workdir().unwrap_or(repo.path()).to_string_lossy());
+    }
+    let signature = signature(repo, identity)?;
+    let parents: Vec<&Commit> = head.iter().collect();
+    Ok(repo.commit(Some("HEAD"), &signature, &signature, &git2::message_prettify(message, None)?, &tree, &parents)?)
+}
+
+/// Fails when tracked files were changed and not committed, which moving HEAD with a hard reset would lose.
+fn ensure_clean(repo: &Repository) -> anyhow::Result<()> {
+    let statuses = repo.statuses(Some(StatusOptions::new().include_untracked(false)))?;
+    if !statuses.is_empty() {
+        bail!("`{}` has uncommitted changes", repo.workdir().unwrap_or(repo.path()).to_string_lossy());
+    }
+    Ok(())
+}
+
+/// The commits on HEAD since `sha`, oldest first.
+fn commits_since<'r>(repo: &'r Repository, sha: &str) -> anyhow::Result<Vec<Commit<'r>>> {
+    let mut walk = repo.revwalk()?;
+    walk.push_head()?;
+    walk.hide(find_commit(repo, sha)?.id())?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    walk.map(|oid| Ok(repo.find_commit(oid?)?)).collect()
+}
+
+/// The commits since `base` in the order autosquash leaves them, each with the fixups folded into it.
+/// A fixup names its target by subject or sha, like git's autosquash, and stays where it is without one.
+fn fixup_groups(commits: Vec<Commit<'_>>) -> Vec<(Commit<'_>, Vec<Commit<'_>>)> {
+    let mut groups: Vec<(Commit, Vec<Commit>)> = vec![];
+    for commit in commits {
+        let summary = commit.summary().unwrap_or_default().to_string();
+        let mut target = summary.as_str();
+        while let Some(rest) = target.strip_prefix(FIXUP_PREFIX) {
+            target = rest;
+        }
+        let group = (target.len() < summary.len())
+            .then(|| {
+                groups.iter_mut().find(|(picked, _)| {
+                    let picked_summary = picked.summary().unwrap_or_default();
+                    picked_summary == target
+                        || (!target.is_empty() && picked_summary.starts_with(target))
+                        || (target.len() >= 4 && picked.id().to_string().starts_with(target))
+                })
+            })
+            .flatten();
+        match group {
+            Some((_, fixups)) => fixups.push(commit),
+            None => groups.push((commit, vec![])),
+        }
+    }
+    groups
+}
+
+/// Applies the change `commit` made to its first parent on top of `onto`'s tree, failing if it conflicts.
+fn apply_onto(repo: &Repository, commit: &Commit, onto: Oid) -> anyhow::Result<Oid> {
+    let parent_tree = match commit.parent(0) {
+        Ok(parent) => parent.tree()?,
+        Err(_) => repo.find_tree(repo.treebuilder(None)?.write()?)?,
+    };
+    let mut index = repo.merge_trees(&parent_tree, &repo.find_tree(onto)?, &commit.tree()?, None)?;
+    if index.has_conflicts() {
+        bail!("Applying {} conflicts", short_sha(repo, commit.id())?);
+    }
+    Ok(index.write_tree_to(repo)?)
+}
+
+/// Matches paths like git's `:(glob)` pathspecs, `*` staying within a directory and `**/` going into any,
+/// a directory matching everything in it.
+fn glob_regex(glob: &str) -> anyhow::Result<Regex> {
+    let mut pattern = String::from("^");
+    let mut rest = glob;
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("**/") {
+            pattern.push_str("(.*/)?");
+            rest = after;
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("**") {
+            pattern.push_str(".*");
+            rest = after;
+            continue;
+        }
+        match c {
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            _ => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    pattern.push_str("(/.*)?$");
+    Regex::new(&pattern).with_context(|| format!("Invalid glob `{}`", glob))
+}
+
+impl Repo for GitRepo {
+    fn dir(&self) -> &Path {
+        &self.repo_dir
+    }
+
+    fn commit_all(&mut self, message: &str) -> Result<()> {
+        with_repo(&self.repo_dir, |repo| commit_tracked(repo, message, None)).map(|_| ())
+    }
+
+    fn commit_all_as(&mut self, message: &str, identity: &Identity) -> Result<()> {
+        with_repo(&self.repo_dir, |repo| commit_tracked(repo, message, Some(identity))).map(|_| ())
+    }
+
+    fn reset_hard(&mut self) -> Result<()> {
+        with_repo(&self.repo_dir, |repo| {
+            let head = repo.head()?.peel_to_commit()?;
+            Ok(repo.reset(head.as_object(), ResetType::Hard, None)?)
+        })
+    }
+
+    fn current_short_sha(&self) -> Result<String> {
+        Ok(with_repo(&self.repo_dir, |repo| short_sha(repo, repo.head()?.peel_to_commit()?.id())).context("Could not get sha")?)
+    }
+
+    fn save_changes_to_branch(&mut self, branch: &str, message: &str) -> Result<bool> {
+        with_repo(&self.repo_dir, |repo| {
+            let mut index = repo.index()?;
+            index.add_all(["*"], IndexAddOption::DEFAULT, None)?;
+            index.update_all(["*"], None)?;
+            index.write()?;
+            let tree = repo.find_tree(index.write_tree()?)?;
+            let head = repo.head()?.peel_to_commit()?;
+            if head.tree_id() == tree.id() {
+                return Ok(false);
+            }
+            let signature = signature(repo, None)?;
+            let commit = repo.find_commit(repo.commit(None, &signature, &signature, message, &tree, &[&head])?)?;
+            repo.branch(branch, &commit, true).with_context(|| format!("Could not save changes to `{}`", branch))?;
+            Ok(true)
+        })
+    }
+
+    fn squash_since(&mut self, sha: &str, message: &str) -> Result<bool> {
+        with_repo(&self.repo_dir, |repo| {
+            if commits_since(repo, sha)?.is_empty() {
+                return Ok(false);
+            }
+            let head = repo.head()?.peel_to_commit()?;
+            let base = find_commit(repo, sha)?;
+            let signature = signature(repo, None)?;
+            let message = git2::message_prettify(message, None)?;
+            let squashed = repo.commit(None, &signature, &signature, &message, &head.tree()?, &[&base])?;
+            // Like `git reset --soft`, what's staged and in the worktree is left alone
+            repo.reset(repo.find_commit(squashed)?.as_object(), ResetType::Soft, None)?;
+            Ok(true)
+        })
+    }
+
+    fn commits_since(&self, sha: &str) -> Result<Vec<String>> {
+        with_repo(&self.repo_dir, |repo| {
+            commits_since(repo, sha)?.iter().map(|commit| short_sha(repo, commit.id())).collect()
+        })
+    }
+
+    fn commit_fixup(&mut self, sha: &str) -> Result<()> {
+        with_repo(&self.repo_dir, |repo| {
+            let target = find_commit(repo, sha)?;
+            let message = format!("{}{}", FIXUP_PREFIX, target.summary().unwrap_or_default());
+            commit_tracked(repo, &message, None)
+        })
+        .map(|_| ())
+    }
+
+    fn autosquash_since(&mut self, sha: &str) -> Result<()> {
+        with_repo(&self.repo_dir, |repo| {
+            ensure_clean(repo)?;
+            let base = find_commit(repo, sha)?;
+            let committer = signature(repo, None)?;
+            // Replayed in memory, nothing changes until the whole history could be
+            let mut parent = base.clone();
+            let mut rewritten = false;
+            for (commit, fixups) in fixup_groups(commits_since(repo, sha)?) {
+                if fixups.is_empty() && !rewritten && commit.parent_ids().eq([parent.id()]) {
+                    parent = commit;
+                    continue;
+                }
+                let mut tree = apply_onto(repo, &commit, parent.tree_id())
+                    .with_context(|| format!("Could not autosquash since {}", sha))?;
+                for fixup in &fixups {
+                    tree = apply_onto(repo, fixup, tree).with_context(|| format!("Could not autosquash since {}", sha))?;
+                }
+                let message = commit.message().unwrap_or_default();
+                let squashed = repo.commit(None, &commit.author(), &committer, message, &repo.find_tree(tree)?, &[&parent])?;
+                parent = repo.find_commit(squashed)?;
+                rewritten = true;
+            }
+            if rewritten {
+                repo.reset(parent.as_object(), ResetType::Hard, None)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn file_hashes(&self, globs: &[String]) -> Result<String> {
+        if globs.is_empty() {
+            return Ok(String::new());
+        }
+        let patterns = globs.iter().map(|glob| glob_regex(glob)).collect::<anyhow::Result<Vec<_>>>()?;
+        with_repo(&self.repo_dir, |repo| {
+            Ok(repo
+                .index()?
+                .iter()
+                .filter_map(|entry| {
+                    let path = String::from_utf8_lossy(&entry.path).to_string();
+                    patterns.iter().any(|pattern| pattern.is_match(&path)).then(|| format!("{} {}\n", entry.id, path))
+                })
+                .collect())
+        })
+    }
+
+    fn cherry_pick(&mut self, sha: &str, identity: Option<&Identity>) -> Result<()> {
+        with_repo(&self.repo_dir, |repo| {
+            let commit = find_commit(repo, sha)?;
+            let head = repo.head()?.peel_to_commit()?;
+            let tree = apply_onto(repo, &commit, head.tree_id()).with_context(|| format!("Could not cherry-pick {}", sha))?;
+            let tree = repo.find_tree(tree)?;
+            // Fails before anything is written when it would overwrite uncommitted changes
+            repo.checkout_tree(tree.as_object(), Some(CheckoutBuilder::new().safe()))?;
+            // The author is kept from `sha`, only the committer changes
+            let committer = signature(repo, identity)?;
+            let message = commit.message().unwrap_or_default();
+            repo.commit(Some("HEAD"), &commit.author(), &committer, message, &tree, &[&head])?;
+            Ok(())
+        })
+    }
+}
+
+/// `<added>\t<deleted>\t<path>` per file like `git diff --numstat`, `-` for the counts of binary files.
+/// Renames aren't looked for, a moved file is deleted and added.
+fn numstat(diff: &Diff) -> anyhow::Result<String> {
+    let mut numstat = String::new();
+    for (delta_i, delta) in diff.deltas().enumerate() {
+        let path = delta.new_file().path().or(delta.old_file().path()).unwrap_or(Path::new(""));
+        let counts = match Patch::from_diff(diff, delta_i)? {
+            Some(patch) if !delta.flags().is_binary() => {
+                let (_, added, deleted) = patch.line_stats()?;
+                format!("{}\t{}", added, deleted)
+            }
+            _ => "-\t-".to_string(),
+        };
+        numstat.push_str(&format!("{}\t{}\n", counts, path.to_string_lossy()));
+    }
+    Ok(numstat)
+}
+
+impl GitRepo {
+    /// Number of commits on HEAD that are not reachable from `sha`.
+    pub fn count_commits_since(&self, sha: &str) -> Result<usize> {
+        with_repo(&self.repo_dir, |repo| Ok(commits_since(repo, sha)?.len()))
+    }
+
+    /// `git diff --numstat` of HEAD against `sha`.
+    pub fn diff_numstat(&self, sha: &str) -> Result<String> {
+        with_repo(&self.repo_dir, |repo| {
+            let from = find_commit(repo, sha)?.tree()?;
+            let head = repo.head()?.peel_to_commit()?.tree()?;
+            numstat(&repo.diff_tree_to_tree(Some(&from), Some(&head), None)?)
+        })
+    }
+
+    /// `git diff --numstat` of just the commit `sha`.
+    pub fn commit_numstat(&self, sha: &str) -> Result<String> {
+        with_repo(&self.repo_dir, |repo| {
+            let commit = find_commit(repo, sha)?;
+            let parent = commit.parents().next().map(|parent| parent.tree()).transpose()?;
+            numstat(&repo.diff_tree_to_tree(parent.as_ref(), Some(&commit.tree()?), None)?)
+        })
+    }
+
+    /// The branch checked out, None on a detached HEAD.
+    pub fn current_branch(&self) -> Result<Option<String>> {
+        with_repo(&self.repo_dir, |repo| {
+            // Before the first commit HEAD names a branch that doesn't exist yet
+            let head = repo.find_reference("HEAD")?;
+            Ok(head.symbolic_target().and_then(|target| target.strip_prefix("refs/heads/")).map(str::to_string))
+        })
+    }
+
+    pub fn commit_message(&self, sha: &str) -> Result<String> {
+        with_repo(&self.repo_dir, |repo| Ok(find_commit(repo, sha)?.message().unwrap_or_default().to_string()))
+    }
+
+    /// The short sha of the commit `git_ref` points to, e.g. `origin/main` or a tag.
+    pub fn resolve_ref(&self, git_ref: &str) -> Result<String> {
+        with_repo(&self.repo_dir, |repo| short_sha(repo, find_commit(repo, git_ref)?.id()))
+    }
+}
+
+/// Paths of the files tracked at HEAD, relative to `repo_dir`.
+pub fn list_files(repo_dir: &Path) -> Result<Vec<String>> {
+    with_repo(repo_dir, |repo| Ok(repo.index()?.iter().map(|entry| String::from_utf8_lossy(&entry.path).to_string()).collect()))
+}
+
+/// A worktree next to the one at `repo_dir` and detached at its HEAD, for a step running alongside others.
+/// One left behind by a run that was killed is replaced.
+pub fn add_worker_worktree(repo_dir: &Path, step_i: usize) -> Result<PathBuf> {
+    let sha = with_repo(repo_dir, |repo| Ok(repo.head()?.peel_to_commit()?.id().to_string()))?;
+    Ok(add_worktree_at(repo_dir, &worker_worktree_dir(repo_dir, step_i), &sha, None, false)?)
+}
+
+pub fn remove_worktree(work_dir: &Path) -> Result<()> {
+    let repo = open(&common_git_dir(work_dir)?)?;
+    Ok(remove_worktree_at(&repo, work_dir)?)
+}
+
+/// Removes the worktree registered at `work_dir`, if any, along with its files. An empty directory is removed too,
+/// git adding worktrees there as well.
+fn remove_worktree_at(repo: &Repository, work_dir: &Path) -> anyhow::Result<()> {
+    if work_dir.read_dir()?.next().is_none() {
+        return Ok(fs::remove_dir(work_dir)?);
+    }
+    let work_dir = work_dir.canonicalize()?;
+    for name in repo.worktrees()?.iter().flatten() {
+        let worktree = repo.find_worktree(name)?;
+        if worktree.path().canonicalize().is_ok_and(|path| path == work_dir) {
+            worktree.prune(Some(WorktreePruneOptions::new().valid(true).locked(true).working_tree(true)))?;
+            if work_dir.exists() {
+                fs::remove_dir_all(&work_dir)
+                    .with_context(|| format!("Could not remove `{}`", work_dir.to_string_lossy()))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The name git keeps the worktree's files under, its directory's name, numbered when another worktree has it.
+fn worktree_name(repo: &Repository, work_dir: &Path) -> anyhow::Result<String> {
+    let taken = repo.worktrees()?;
+    let taken: Vec<&str> = taken.iter().flatten().collect();
+    let base = work_dir.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let mut name = base.clone();
+    let mut number = 1;
+    while taken.contains(&name.as_str()) {
+        name = format!("{}{}", base, number);
+        number += 1;
+    }
+    Ok(name)
+}
+
+/// Adds a worktree at `work_dir_relative` on `branch` or detached at `sha`, replacing what's there.
+/// Works against the shared repository, so this works when repo_dir is itself a linked worktree or a submodule.
+pub fn add_worktree(repo_dir: &Path, work_dir_relative: &str, sha: &str, branch: Option<&str>, replace_branch: bool) -> anyhow::Result<PathBuf> {
+    add_worktree_at(repo_dir, &repo_dir.join(work_dir_relative), sha, branch, replace_branch)
+}
+
+fn add_worktree_at(repo_dir: &Path, work_dir: &Path, sha: &str, branch: Option<&str>, replace_branch: bool) -> anyhow::Result<PathBuf> {
+    let repo = open(repo_dir)?;
+    if work_dir.exists() {
+        remove_worktree_at(&repo, work_dir)?;
+    }
+    let commit = find_commit(&repo, sha)?;
+    let name = worktree_name(&repo, work_dir)?;
+    // libgit2 checks a worktree out on a branch, a detached one starts on a branch deleted right after
+    let head_branch = branch.map_or_else(|| format!("mend-worktree-{}", name), str::to_string);
+    let replace_branch = replace_branch || branch.is_none();
+    let head = repo
+        .branch(&head_branch, &commit, replace_branch)
+        .with_context(|| format!("Could not create branch `{}` at {}", head_branch, sha))?;
+    if let Some(parent) = work_dir.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Could not create `{}`", parent.to_string_lossy()))?;
+    }
+    let mut options = WorktreeAddOptions::new();
+    options.reference(Some(head.get()));
+    let worktree = repo
+        .worktree(&name, work_dir, Some(&options))
+        .with_context(|| format!("Could not add worktree `{}` at {}", work_dir.to_string_lossy(), sha))?;
+    if branch.is_none() {
+        Repository::open_from_worktree(&worktree)?.set_head_detached(commit.id())?;
+        head.into_reference().delete()?;
+    }
+    Ok(work_dir.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libgit::{glob_regex, list_files};
+    use crate::repo::{add_worker_worktree, ensure_worktree, remove_worktree, GitRepo, Identity, Repo};
+    use crate::test_support::git;
+
+    #[test]
+    fn commits_from_a_worker_are_cherry_picked_as_the_committer() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let repo_dir = temp_dir.path();
+        git(repo_dir, &["init", "-q"]);
+        std::fs::write(repo_dir.join("a.txt"), "a\n").unwrap();
+        git(repo_dir, &["add", "a.txt"]);
+        let mut base = GitRepo { repo_dir: repo_dir.to_path_buf() };
+        base.commit_all("Initial").unwrap();
+        assert!(base.commit_all("Nothing").is_err());
+        let sha = base.current_short_sha().unwrap();
+        let mut main = GitRepo { repo_dir: ensure_worktree(repo_dir, ".mend/worktree2", &sha).unwrap() };
+        let mut worker = GitRepo { repo_dir: add_worker_worktree(&main.repo_dir, 0).unwrap() };
+        let author = Identity { name: "Step Author".to_string(), email: "author@example.com".to_string() };
+        std::fs::write(worker.repo_dir.join("a.txt"), "b\n").unwrap();
+        worker.commit_all_as("Change a", &author).unwrap();
+
+        let committer = Identity { name: "Mend".to_string(), email: "mend@example.com".to_string() };
+        main.cherry_pick(&worker.current_short_sha().unwrap(), Some(&committer)).unwrap();
+        assert_eq!(std::fs::read_to_string(main.repo_dir.join("a.txt")).unwrap(), "b\n");
+        assert_eq!(git(&main.repo_dir, &["status", "--porcelain"]), "");
+        assert_eq!(
+            git(&main.repo_dir, &["log", "-1", "--format=%an|%cn|%s"]),
+            "Step Author|Mend|Change a"
+        );
+        assert_eq!(main.commits_since(&sha).unwrap().len(), 1);
+
+        // Conflicting with the worker's change, the cherry-pick changes nothing
+        std::fs::write(main.repo_dir.join("a.txt"), "c\n").unwrap();
+        main.commit_all("Change a again").unwrap();
+        let head = main.current_short_sha().unwrap();
+        std::fs::write(worker.repo_dir.join("a.txt"), "d\n").unwrap();
+        worker.commit_all("Change a differently").unwrap();
+        assert!(main.cherry_pick(&worker.current_short_sha().unwrap(), None).is_err());
+        assert_eq!(main.current_short_sha().unwrap(), head);
+        assert_eq!(git(&main.repo_dir, &["status", "--porcelain"]), "");
+    }
+
+    #[test]
+    fn history_is_read_like_git_reads_it() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let repo_dir = temp_dir.path();
+        git(repo_dir, &["init", "-q", "-b", "main"]);
+        let mut repo = GitRepo { repo_dir: repo_dir.to_path_buf() };
+        assert_eq!(repo.current_branch().unwrap(), Some("main".to_string()));
+        std::fs::write(repo_dir.join("a.txt"), "a\nb\n").unwrap();
+        git(repo_dir, &["add", "a.txt"]);
+        repo.commit_all("Initial").unwrap();
+        let base = repo.current_short_sha().unwrap();
+        std::fs::write(repo_dir.join("a.txt"), "a\nc\nd\n").unwrap();
+        std::fs::write(repo_dir.join("b.bin"), [0u8, 1, 2]).unwrap();
+        git(repo_dir, &["add", "a.txt", "b.bin"]);
+        repo.commit_all("Change").unwrap();
+        let head = repo.current_short_sha().unwrap();
+
+        assert_eq!(repo.count_commits_since(&base).unwrap(), 1);
+        assert_eq!(repo.diff_numstat(&base).unwrap().trim(), git(repo_dir, &["diff", "--numstat", &base, "HEAD"]));
+        assert_eq!(repo.commit_numstat(&base).unwrap().trim(), git(repo_dir, &["show", "--numstat", "--format=", &base]));
+        assert_eq!(repo.commit_numstat(&head).unwrap(), "2\t1\ta.txt\n-\t-\tb.bin\n");
+        assert_eq!(repo.resolve_ref("main").unwrap(), head);
+        assert_eq!(repo.commit_message(&head).unwrap(), git(repo_dir, &["log", "-1", "--format=%B", &head]) + "\n");
+        assert_eq!(list_files(repo_dir).unwrap(), vec!["a.txt", "b.bin"]);
+        assert!(repo.resolve_ref("no-such-ref").is_err());
+
+        let worker_dir = add_worker_worktree(repo_dir, 0).unwrap();
+        assert_eq!(GitRepo { repo_dir: worker_dir.clone() }.current_branch().unwrap(), None);
+        remove_worktree(&worker_dir).unwrap();
+        assert!(!worker_dir.exists());
+        assert_eq!(git(repo_dir, &["worktree", "list", "--porcelain"]).matches("worktree ").count(), 1);
+    }
+
+    #[test]
+    fn globs_match_like_git_pathspecs() {
+        let matches = |glob: &str, path: &str| glob_regex(glob).unwrap().is_match(path);
+        assert!(matches("spec/**/*.yaml", "spec/api.yaml"));
+        assert!(matches("spec/**/*.yaml", "spec/v1/api.yaml"));
+        assert!(!matches("spec/*.yaml", "spec/v1/api.yaml"));
+        assert!(matches("src", "src/main.rs"));
+        assert!(!matches("src", "src2/main.rs"));
+        assert!(matches("*.toml", "Cargo.toml"));
+        assert!(!matches("*.toml", "Cargo.lock"));
+        assert!(matches("a?c.txt", "abc.txt"));
+        assert!(matches("docs/**", "docs/guide/intro.md"));
+    }
+}
@@ -0,0 +1,253 @@
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const LOCK_FILE: &str = "lock";
+/// How long a lock file that can't be read is taken to be still being written by the run that created it.
+const LOCK_WRITE_GRACE: Duration = Duration::from_secs(2);
+
+/// Written to `.mend/lock` in the base repo for as long as a run is going.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct RunLock {
+    pub pid: u32,
+    /// Seconds since the Unix epoch
+    pub started: u64,
+    pub config: String,
+    pub total_steps: usize,
+}
+
+impl RunLock {
+    pub fn is_alive(&self) -> bool {
+        if self.pid == std::process::id() {
+            return true;
+        }
+        Command::new("kill")
+            .args(["-0", &self.pid.to_string()])
+            .output()
+            .map(|output| output.status.success())
+            .unwrap_or(false)
+    }
+
+    /// Signals the run's process group so its step scripts stop too, or just the run when we share its group.
+    fn signal(&self, signal: &str) -> anyhow::Result<()> {
+        let target = match (process_group(self.pid), process_group(std::process::id())) {
+            (Some(group), Some(own_group)) if group != own_group => format!("-{}", group),
+            _ => self.pid.to_string(),
+        };
+        let output = Command::new("kill")
+            .args([&format!("-{}", signal), "--", &target])
+            .output()
+            .context("Could not run kill")?;
+        if !output.status.success() && self.is_alive() {
+            bail!(
+                "Could not send {} to mend run {}: {}",
+                signal,
+                self.pid,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+
+    fn wait_for_exit(&self, timeout: Duration) -> bool {
+        let started = Instant::now();
+        while self.is_alive() {
+            if started.elapsed() > timeout {
+                return false;
+            }
+            sleep(Duration::from_millis(100));
+        }
+        true
+    }
+}
+
+fn process_group(pid: u32) -> Option<u32> {
+    let output = Command::new("ps")
+        .args(["-o", "pgid=", "-p", &pid.to_string()])
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
+
+/// Removes the lock file when the run ends, including on panic.
+pub struct LockGuard {
+    path: PathBuf,
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+pub fn read_lock(mend_dir: &Path) -> Option<RunLock> {
+    let contents = fs::read_to_string(mend_dir.join(LOCK_FILE)).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Takes the lock for a run of `config`, failing while another run holds it. A lock left by a run that's gone is
+/// replaced. The lock file is only ever created afresh, so of two runs starting at once only one gets it.
+pub fn acquire_lock(mend_dir: &Path, config: &str, total_steps: usize) -> anyhow::Result<LockGuard> {
+    fs::create_dir_all(mend_dir)
+        .with_context(|| format!("Could not create `{}`", mend_dir.to_string_lossy()))?;
+    let lock = RunLock {
+        pid: std::process::id(),
+        started: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default(),
+        config: config.to_string(),
+        total_steps,
+    };
+    let contents = serde_json::to_string_pretty(&lock)?;
+    let path = mend_dir.join(LOCK_FILE);
+    let started = Instant::now();
+    loop {
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                let guard = LockGuard { path };
+                file.write_all(contents.as_bytes())
+                    .with_context(|| format!("Could not write lock file `{}`", guard.path.to_string_lossy()))?;
+                return Ok(guard);
+            }
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {}
+            Err(err) => {
+                return Err(err).with_context(|| format!("Could not create lock file `{}`", path.to_string_lossy()))
+            }
+        }
+        let held = fs::read_to_string(&path).unwrap_or_default();
+        match serde_json::from_str::<RunLock>(&held) {
+            Ok(existing) if existing.is_alive() => bail!(
+                "Another mend run (pid {}) is using {}, wait for it to finish or stop it first",
+                existing.pid,
+                mend_dir.to_string_lossy()
+            ),
+            // Just created by a run that hasn't written it yet, or removed since
+            Err(_) if started.elapsed() < LOCK_WRITE_GRACE => {
+                sleep(Duration::from_millis(50));
+                continue;
+            }
+            _ => {}
+        }
+        // The holder is gone. Another run replacing its lock at the same time may have done so already
+        if fs::read_to_string(&path).is_ok_and(|current| current == held) {
+            if let Err(err) = fs::remove_file(&path) {
+                if err.kind() != ErrorKind::NotFound {
+                    return Err(err).with_context(|| format!("Could not remove stale lock file `{}`", path.to_string_lossy()));
+                }
+            }
+        }
+    }
+}
+
+/// Stops the run holding the lock, asking nicely for `grace` before killing it, and releases the lock.
+/// Returns the stopped run, or None when the lock was left by a run that is already gone.
+pub fn kill_run(mend_dir: &Path, grace: Duration) -> anyhow::Result<Option<RunLock>> {
+    let lock = read_lock(mend_dir).ok_or_else(|| anyhow!("No mend run in progress"))?;
+    if lock.pid == std::process::id() {
+        bail!("Refusing to stop the current process");
+    }
+    let stopped = if lock.is_alive() {
+        lock.signal("TERM")?;
+        if !lock.wait_for_exit(grace) {
+            lock.signal("KILL")?;
+            if !lock.wait_for_exit(grace) {
+                bail!("mend run {} is still running after SIGKILL", lock.pid);
+            }
+        }
+        Some(lock)
+    } else {
+        None
+    };
+    let path = mend_dir.join(LOCK_FILE);
+    fs::remove_file(&path).with_context(|| format!("Could not remove lock file `{}`", path.to_string_lossy()))?;
+    Ok(stopped)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lock::{acquire_lock, kill_run, read_lock, RunLock};
+    use std::fs;
+    use std::process::Command;
+    use std::sync::Barrier;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn lock_is_exclusive_and_released_on_drop() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mend_dir = temp_dir.path().join(".mend");
+        let guard = acquire_lock(&mend_dir, "mend.toml", 3).unwrap();
+        let lock = read_lock(&mend_dir).unwrap();
+        assert_eq!(lock.pid, std::process::id());
+        assert_eq!(lock.total_steps, 3);
+        assert!(acquire_lock(&mend_dir, "mend.toml", 3).is_err());
+        drop(guard);
+        assert_eq!(read_lock(&mend_dir), None);
+    }
+
+    #[test]
+    fn stale_lock_is_replaced() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let stale = RunLock {
+            pid: u32::MAX - 1,
+            started: 0,
+            config: "old.toml".to_string(),
+            total_steps: 1,
+        };
+        assert!(!stale.is_alive());
+        fs::write(temp_dir.path().join("lock"), serde_json::to_string(&stale).unwrap()).unwrap();
+        let _guard = acquire_lock(temp_dir.path(), "mend.toml", 2).unwrap();
+        assert_eq!(read_lock(temp_dir.path()).unwrap().config, "mend.toml");
+    }
+
+    #[test]
+    fn kill_run_stops_the_process_and_releases_the_lock() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        let run = RunLock {
+            pid: child.id(),
+            started: 0,
+            config: "mend.toml".to_string(),
+            total_steps: 1,
+        };
+        fs::write(temp_dir.path().join("lock"), serde_json::to_string(&run).unwrap()).unwrap();
+        // Reap the child as soon as it exits so it doesn't linger as a zombie
+        let waiter = std::thread::spawn(move || child.wait().unwrap());
+        let stopped = kill_run(temp_dir.path(), Duration::from_secs(5)).unwrap();
+        assert_eq!(stopped, Some(run));
+        assert!(!waiter.join().unwrap().success());
+        assert_eq!(read_lock(temp_dir.path()), None);
+        assert!(kill_run(temp_dir.path(), Duration::from_secs(5)).is_err());
+    }
+
+    #[test]
+    fn only_one_of_runs_starting_at_once_gets_the_lock() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let stale = RunLock {
+            pid: u32::MAX - 1,
+            started: 0,
+            config: "old.toml".to_string(),
+            total_steps: 1,
+        };
+        fs::write(temp_dir.path().join("lock"), serde_json::to_string(&stale).unwrap()).unwrap();
+        let barrier = Barrier::new(8);
+        let guards: Vec<_> = thread::scope(|scope| {
+            let attempts: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        acquire_lock(temp_dir.path(), "mend.toml", 2)
+                    })
+                })
+                .collect();
+            attempts.into_iter().map(|attempt| attempt.join().unwrap()).collect()
+        });
+        assert_eq!(guards.iter().filter(|guard| guard.is_ok()).count(), 1);
+    }
+}
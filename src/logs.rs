@@ -0,0 +1,107 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::artifacts::GROWTH_KEY;
+use crate::run::{EStatus, StepRequest, StepResponse};
+
+/// Under `.mend`, a directory per run holding a log per step.
+pub const LOGS_DIR: &str = "logs";
+/// How many runs keep their logs when `[logs] keep_runs` isn't set.
+const DEFAULT_KEEP_RUNS: usize = 20;
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct LogsConfig {
+    /// The logs of this many of the latest runs are kept, older ones are removed when a run ends
+    pub keep_runs: Option<usize>,
+}
+
+pub fn run_log_dir(mend_dir: &Path, run_id: &str) -> PathBuf {
+    mend_dir.join(LOGS_DIR).join(run_id)
+}
+
+/// `step-NN.log`, numbered from 1 and padded so the logs sort in order.
+pub fn step_log_path(log_dir: &Path, step_i: usize) -> PathBuf {
+    log_dir.join(format!("step-{:02}.log", step_i + 1))
+}
+
+/// How a step's log and `mend ctl tail` name its status.
+pub fn status_label(status: &EStatus) -> &'static str {
+    match status {
+        EStatus::Done => "done",
+        EStatus::Failed => "failed",
+        EStatus::VerifyFailed => "verify failed",
+        EStatus::Skipped => "skipped",
+        EStatus::Pending | EStatus::Running => "not finished",
+    }
+}
+
+/// Writes the step's scripts and everything they printed, whatever the console showed of it.
+pub fn write_step_log(log_dir: &Path, step_i: usize, step_request: &StepRequest, step_response: &StepResponse) -> anyhow::Result<()> {
+    fs::create_dir_all(log_dir).with_context(|| format!("Could not create `{}`", log_dir.to_string_lossy()))?;
+    let status = status_label(&step_response.status);
+    let mut text = format!("Step {} [{}]: {}\nStatus: {}\n", step_i + 1, step_request.id, step_request.run, status);
+    if let Some(sha) = &step_response.sha {
+        text.push_str(&format!("Commit: {}\n", sha));
+    }
+    if let Some(growth) = step_response.metadata.get(GROWTH_KEY) {
+        text.push_str(&format!("Worktree growth: {}\n", growth));
+    }
+    text.push('\n');
+    text.push_str(step_response.output.as_deref().unwrap_or_default());
+    let path = step_log_path(log_dir, step_i);
+    fs::write(&path, text).with_context(|| format!("Could not write `{}`", path.to_string_lossy()))
+}
+
+/// Removes the logs of all but the latest `keep_runs` runs. Run ids are timestamps, so they sort by age.
+pub fn prune_logs(mend_dir: &Path, config: Option<&LogsConfig>) -> anyhow::Result<()> {
+    let keep_runs = config.and_then(|config| config.keep_runs).unwrap_or(DEFAULT_KEEP_RUNS);
+    let logs_dir = mend_dir.join(LOGS_DIR);
+    let Ok(entries) = fs::read_dir(&logs_dir) else {
+        return Ok(());
+    };
+    let mut runs: Vec<(u64, PathBuf)> = entries
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| Some((entry.file_name().to_str()?.parse().ok()?, entry.path())))
+        .collect();
+    runs.sort();
+    for (_, dir) in runs.iter().rev().skip(keep_runs) {
+        fs::remove_dir_all(dir).with_context(|| format!("Could not remove old logs `{}`", dir.to_string_lossy()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logs::{prune_logs, run_log_dir, step_log_path, write_step_log, LogsConfig};
+    use crate::run::{EStatus, StepRequest, StepResponse};
+    use std::fs;
+
+    #[test]
+    fn step_logs_are_written_per_run_and_pruned() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mend_dir = temp_dir.path();
+        let step_request = StepRequest {
+            id: "rename".to_string(),
+            run: "rename Foo Bar".to_string(),
+            ..Default::default()
+        };
+        let mut step_response = StepResponse::pending();
+        step_response.status = EStatus::Failed;
+        step_response.push_output_str("Running\nrename-cli Foo Bar\n");
+        step_response.push_output_str("Foo not found");
+        for run_id in ["1700000100", "1700000200", "1700000300"] {
+            write_step_log(&run_log_dir(mend_dir, run_id), 0, &step_request, &step_response).unwrap();
+        }
+        let log_path = step_log_path(&run_log_dir(mend_dir, "1700000300"), 0);
+        assert!(log_path.ends_with("logs/1700000300/step-01.log"));
+        insta::assert_snapshot!(fs::read_to_string(&log_path).unwrap());
+
+        prune_logs(mend_dir, Some(&LogsConfig { keep_runs: Some(2) })).unwrap();
+        assert!(!run_log_dir(mend_dir, "1700000100").exists());
+        assert!(run_log_dir(mend_dir, "1700000200").exists());
+        assert!(run_log_dir(mend_dir, "1700000300").exists());
+    }
+}
@@ -0,0 +1,334 @@
+use anyhow::Context;
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+    PublishDiagnostics,
+};
+use lsp_types::request::{Completion, GotoDefinition, HoverRequest, Request as _};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionParams, CompletionResponse, Diagnostic,
+    DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, GotoDefinitionParams, GotoDefinitionResponse, Hover,
+    HoverContents, HoverParams, HoverProviderCapability, Location, MarkupContent, MarkupKind,
+    OneOf, Position, PublishDiagnosticsParams, Range, ServerCapabilities,
+    TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{is_yaml, load_mend_contents, parse_mend};
+use crate::docs::{render_recipe, usage};
+use crate::validate::find_problems;
+use crate::Mend;
+
+/// Open configs by URI, with their text as the editor has it.
+#[derive(Default)]
+struct Documents {
+    texts: BTreeMap<Url, String>,
+}
+
+impl Documents {
+    /// The merged config of an open document, or why it can't be loaded.
+    fn load(&self, uri: &Url) -> anyhow::Result<(PathBuf, Mend)> {
+        let path = uri
+            .to_file_path()
+            .map_err(|_| anyhow::anyhow!("Only configs on disk are supported, not {}", uri))?;
+        let text = self.texts.get(uri).map(String::as_str).unwrap_or_default();
+        let mend = load_mend_contents(&path, text)?;
+        Ok((path, mend))
+    }
+}
+
+/// The word at `position`, made of the characters a recipe name can have.
+fn word_at(text: &str, position: Position) -> Option<(String, usize)> {
+    let line = text.lines().nth(position.line as usize)?;
+    let chars: Vec<char> = line.chars().collect();
+    let is_word = |c: &char| c.is_ascii_alphanumeric() || *c == '_' || *c == '-';
+    let cursor = (position.character as usize).min(chars.len());
+    let start = chars[..cursor]
+        .iter()
+        .rposition(|c| !is_word(c))
+        .map_or(0, |i| i + 1);
+    let end = chars[cursor..]
+        .iter()
+        .position(|c| !is_word(c))
+        .map_or(chars.len(), |i| cursor + i);
+    let word: String = chars[start..end].iter().collect();
+    (!word.is_empty()).then_some((word, start))
+}
+
+/// Whether the word at `start` is the first one of a string or list item, where steps name their recipe.
+fn is_instruction_start(text: &str, line: u32, start: usize) -> bool {
+    let Some(line) = text.lines().nth(line as usize) else {
+        return false;
+    };
+    let before: String = line.chars().take(start).collect();
+    let before = before.trim_end_matches(' ');
+    before.ends_with('"') || before.ends_with('\'') || before.trim_start() == "-"
+}
+
+fn diagnostic(range: Range, message: String) -> Diagnostic {
+    Diagnostic {
+        range,
+        severity: Some(DiagnosticSeverity::WARNING),
+        source: Some("mend".to_string()),
+        message,
+        ..Default::default()
+    }
+}
+
+/// Puts a problem on the first line mentioning its first `quoted` name, the top of the file otherwise.
+fn problem_range(text: &str, problem: &str) -> Range {
+    let name = problem.split('`').nth(1).filter(|name| !name.is_empty());
+    let line = name
+        .and_then(|name| text.lines().position(|line| line.contains(name)))
+        .unwrap_or_default() as u32;
+    let length = text.lines().nth(line as usize).map_or(0, |line| line.chars().count()) as u32;
+    Range::new(Position::new(line, 0), Position::new(line, length))
+}
+
+fn diagnostics(documents: &Documents, uri: &Url) -> Vec<Diagnostic> {
+    let text = documents.texts.get(uri).map(String::as_str).unwrap_or_default();
+    match documents.load(uri) {
+        Ok((_, mend)) => find_problems(&mend)
+            .into_iter()
+            .map(|problem| diagnostic(problem_range(text, &problem), problem))
+            .collect(),
+        Err(err) => {
+            let mut diagnostic = diagnostic(Range::default(), format!("{:#}", err));
+            diagnostic.severity = Some(DiagnosticSeverity::ERROR);
+            vec![diagnostic]
+        }
+    }
+}
+
+fn completions(documents: &Documents, uri: &Url, position: Position) -> Vec<CompletionItem> {
+    let text = documents.texts.get(uri).map(String::as_str).unwrap_or_default();
+    let start = word_at(text, position).map_or(position.character as usize, |(_, start)| start);
+    if !is_instruction_start(text, position.line, start) {
+        return vec![];
+    }
+    let Ok((_, mend)) = documents.load(uri) else {
+        return vec![];
+    };
+    mend.recipes
+        .iter()
+        .map(|(name, recipe)| CompletionItem {
+            label: name.clone(),
+            kind: Some(CompletionItemKind::FUNCTION),
+            detail: Some(usage(name, recipe)),
+            documentation: recipe.description.clone().map(lsp_types::Documentation::String),
+            ..Default::default()
+        })
+        .collect()
+}
+
+fn hover(documents: &Documents, uri: &Url, position: Position) -> Option<Hover> {
+    let text = documents.texts.get(uri)?;
+    let (word, _) = word_at(text, position)?;
+    let (_, mend) = documents.load(uri).ok()?;
+    let recipe = mend.recipes.get(&word)?;
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: render_recipe(&word, recipe),
+        }),
+        range: None,
+    })
+}
+
+/// Line defining recipe `name` in a TOML or YAML config text.
+fn recipe_line(text: &str, name: &str, yaml: bool) -> Option<u32> {
+    let mut in_recipes = false;
+    for (line_i, line) in text.lines().enumerate() {
+        let trimmed = line.trim();
+        if yaml {
+            if !line.starts_with(' ') && !trimmed.is_empty() {
+                in_recipes = trimmed == "recipes:";
+            } else if in_recipes && trimmed.strip_suffix(':') == Some(name) {
+                return Some(line_i as u32);
+            }
+        } else if trimmed.starts_with(&format!("[recipes.{}]", name))
+            || trimmed.starts_with(&format!("[recipes.\"{}\"]", name))
+        {
+            return Some(line_i as u32);
+        }
+    }
+    None
+}
+
+/// Where recipe `name` is defined, the main config taking precedence over includes like when merging.
+fn definition(path: &Path, text: &str, name: &str) -> Option<Location> {
+    let mut files = vec![(path.to_path_buf(), text.to_string())];
+    if let Ok(main_mend) = parse_mend(path, text) {
+        let parent_dir = path.parent().unwrap_or(Path::new(""));
+        for include in main_mend.include.iter().rev() {
+            let include_path = parent_dir.join(include);
+            if let Ok(include_text) = fs::read_to_string(&include_path) {
+                files.push((include_path, include_text));
+            }
+        }
+    }
+    files.into_iter().find_map(|(file, file_text)| {
+        let line = recipe_line(&file_text, name, is_yaml(&file))?;
+        let uri = Url::from_file_path(fs::canonicalize(&file).unwrap_or(file)).ok()?;
+        Some(Location::new(uri, Range::new(Position::new(line, 0), Position::new(line, 0))))
+    })
+}
+
+fn goto_definition(documents: &Documents, uri: &Url, position: Position) -> Option<Location> {
+    let text = documents.texts.get(uri)?;
+    let (word, _) = word_at(text, position)?;
+    definition(&uri.to_file_path().ok()?, text, &word)
+}
+
+fn handle_request(documents: &Documents, request: Request) -> anyhow::Result<Response> {
+    let result = match request.method.as_str() {
+        Completion::METHOD => {
+            let params: CompletionParams = serde_json::from_value(request.params)?;
+            let position = params.text_document_position;
+            let items = completions(documents, &position.text_document.uri, position.position);
+            serde_json::to_value(CompletionResponse::Array(items))?
+        }
+        HoverRequest::METHOD => {
+            let params: HoverParams = serde_json::from_value(request.params)?;
+            let position = params.text_document_position_params;
+            serde_json::to_value(hover(documents, &position.text_document.uri, position.position))?
+        }
+        GotoDefinition::METHOD => {
+            let params: GotoDefinitionParams = serde_json::from_value(request.params)?;
+            let position = params.text_document_position_params;
+            let location = goto_definition(documents, &position.text_document.uri, position.position);
+            serde_json::to_value(location.map(GotoDefinitionResponse::Scalar))?
+        }
+        _ => {
+            return Ok(Response::new_err(
+                request.id,
+                lsp_server::ErrorCode::MethodNotFound as i32,
+                format!("mend doesn't handle {}", request.method),
+            ))
+        }
+    };
+    Ok(Response::new_ok(request.id, result))
+}
+
+/// Updates the open documents, returning the one whose diagnostics need publishing.
+fn handle_notification(documents: &mut Documents, notification: Notification) -> anyhow::Result<Option<Url>> {
+    match notification.method.as_str() {
+        DidOpenTextDocument::METHOD => {
+            let params: DidOpenTextDocumentParams = serde_json::from_value(notification.params)?;
+            let uri = params.text_document.uri;
+            documents.texts.insert(uri.clone(), params.text_document.text);
+            Ok(Some(uri))
+        }
+        DidChangeTextDocument::METHOD => {
+            let params: DidChangeTextDocumentParams = serde_json::from_value(notification.params)?;
+            let uri = params.text_document.uri;
+            // Full sync, the last change has the whole text
+            if let Some(change) = params.content_changes.into_iter().last() {
+                documents.texts.insert(uri.clone(), change.text);
+            }
+            Ok(Some(uri))
+        }
+        DidCloseTextDocument::METHOD => {
+            let params: DidCloseTextDocumentParams = serde_json::from_value(notification.params)?;
+            documents.texts.remove(&params.text_document.uri);
+            Ok(None)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Serves the language server protocol on stdin and stdout until the editor shuts it down.
+pub fn run_lsp() -> anyhow::Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        completion_provider: Some(Default::default()),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    };
+    connection
+        .initialize(serde_json::to_value(capabilities)?)
+        .context("Language server initialization failed")?;
+    let mut documents = Documents::default();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                let response = handle_request(&documents, request)?;
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(notification) => {
+                if let Some(uri) = handle_notification(&mut documents, notification)? {
+                    let params = PublishDiagnosticsParams::new(uri.clone(), diagnostics(&documents, &uri), None);
+                    let notification = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+                    connection.sender.send(Message::Notification(notification))?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lsp::{completions, definition, diagnostics, is_instruction_start, word_at, Documents};
+    use lsp_types::{Position, Url};
+    use std::fs;
+
+    const RECIPES: &str = "[recipes.rename]\nrun = \"sed -i s/$1/$2/g *.c\"\ndescription = \"Renames a symbol\"\n";
+
+    #[test]
+    fn recipe_names_complete_at_the_start_of_steps() {
+        let text = "steps = [\"ren\", \"rename a b\"]\n";
+        let (word, start) = word_at(text, Position::new(0, 13)).unwrap();
+        assert_eq!(word, "ren");
+        assert!(is_instruction_start(text, 0, start));
+        let (_, start) = word_at(text, Position::new(0, 25)).unwrap();
+        assert!(!is_instruction_start(text, 0, start));
+        assert!(is_instruction_start("steps:\n  - ren\n", 1, 4));
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("mend.toml");
+        let uri = Url::from_file_path(&path).unwrap();
+        let mut documents = Documents::default();
+        documents.texts.insert(uri.clone(), format!("{}{}", text, RECIPES));
+        let items = completions(&documents, &uri, Position::new(0, 13));
+        assert_eq!(items[0].label, "rename");
+        assert_eq!(items[0].detail.as_deref(), Some("rename <1> <2>"));
+    }
+
+    #[test]
+    fn definitions_are_found_in_includes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::write(temp_dir.path().join("recipes.toml"), format!("# Shared\n{}", RECIPES)).unwrap();
+        let path = temp_dir.path().join("mend.yaml");
+        let text = "include: [recipes.toml]\nsteps:\n  - rename a b\n";
+        let location = definition(&path, text, "rename").unwrap();
+        assert!(location.uri.path().ends_with("recipes.toml"));
+        assert_eq!(location.range.start.line, 1);
+        assert!(definition(&path, text, "format").is_none());
+    }
+
+    #[test]
+    fn problems_are_published_on_their_line() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let uri = Url::from_file_path(temp_dir.path().join("mend.toml")).unwrap();
+        let mut documents = Documents::default();
+        let text = "from = { repo = \".\", sha = \"HEAD\" }\nsteps = [\"not-a-mend-command-anywhere\"]\n";
+        documents.texts.insert(uri.clone(), text.to_string());
+        let found = diagnostics(&documents, &uri);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].range.start.line, 1);
+        documents.texts.insert(uri.clone(), "steps = [".to_string());
+        assert!(diagnostics(&documents, &uri)[0].message.contains("Unable to load data"));
+    }
+}
@@ -1,30 +1,245 @@
-use anyhow::bail;
-use clap::Parser;
+use anyhow::{anyhow, bail, Context};
+use clap::{Parser, Subcommand};
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::env;
+use std::fs;
 use std::fmt::Debug;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::progress::{create_console_notifier, Notify};
-use crate::repo::{ensure_worktree, GitRepo};
-use crate::run::{create_run_status_from_mend, ShellExecutor};
+use crate::adapter::{Jscodeshift, OpenRewrite};
+use crate::artifacts::ArtifactsConfig;
+use crate::badge::{write_status, RunStatus};
+use crate::batch::Chunk;
+use crate::bundle::{BundleArgs, UnbundleArgs};
+use crate::cast::{CastExecutor, CastWriter};
+use crate::clone_cache::GcArgs;
+use crate::codeowners::{CodeOwners, CODEOWNERS_PATHS, OWNERS_WORKTREE_DIR};
+use crate::corpus::{CorpusRepo, SampleResult, VerifyRecipesArgs};
+use crate::ctl::{ControlNotifier, CtlArgs, CtlCommand};
+use crate::edit::{Edit, EditArgs};
+use crate::exec::{ExecArgs, EXEC_CONFIG};
+use crate::followup::FOLLOWUP_FILE;
+use crate::forge::{FailedStep, ForgeConfig, PullRequestStep, ISSUE_KEY};
+use crate::gates::{check_gates, DiffStats, Gates};
+use crate::heartbeat::{HeartbeatConfig, HeartbeatNotifier};
+use crate::incremental::STEP_CACHE_FILE;
+use crate::detect::{default_verify_command, detect_languages, language_warnings};
+use crate::notify::{ExecNotifier, NotifyConfig, SlackNotifier, WebhookNotifier};
+use crate::plan::PlanFormat;
+use crate::optimize::OptimizeArgs;
+use crate::policy::{load_policy, step_changes, Policy};
+use crate::progress::{create_notifier, CiSystem, Notify, ProgressOutput};
+use crate::lock::acquire_lock;
+use crate::logs::{prune_logs, run_log_dir, step_log_path, LogsConfig};
+use crate::metrics::{publish_metrics, render_metrics, MetricsConfig};
+use crate::report::{ReportArgs, RunRecord};
+use crate::output::{OutputConfig, PublishConfig};
+use crate::repo::{configure_git, ensure_worktree, ensure_worktree_on_branch, list_files, remove_worktree, GitConfig, GitRepo, Identity, Repo, MEND_DIR, WORKTREE_DIR};
+use crate::select::StepSelection;
+use crate::shell::ShellDialect;
+use crate::simulate::SimulateArgs;
+use crate::state::{read_state, RunState, StateNotifier};
+use crate::trace::{export_trace, TelemetryConfig, Trace, TraceExecutor, TraceNotifier};
+use crate::run::{create_run_status_from_mend, plan_squash_groups, OnFailure, RunOptions, ShellExecutor, DEFAULT_SHELLS};
+use crate::update::SelfUpdateArgs;
 
+mod adapter;
+mod alias;
+mod artifacts;
+mod badge;
+mod bundle;
+mod batch;
+mod cast;
+mod clone_cache;
+mod codeowners;
 mod config;
+mod corpus;
+mod ctl;
+mod detect;
+mod dev;
+mod docs;
+mod edit;
+mod error;
+mod exec;
+mod followup;
+mod forge;
+mod gates;
+mod heartbeat;
+mod include;
+mod incremental;
+mod interactive;
+#[cfg(not(feature = "git-cli"))]
+mod libgit;
+mod lock;
+mod logs;
+mod lsp;
+mod matrix;
+mod metrics;
+mod notify;
+mod optimize;
+mod output;
+mod ownership;
+mod plan;
+mod policy;
 mod progress;
+mod prune;
 mod repo;
+mod report;
+mod revert;
 mod run;
+mod schema;
+mod secrets;
+mod select;
+mod shell;
+mod simulate;
+mod state;
+mod status;
+#[cfg(test)]
+mod test_support;
+mod trace;
+mod tui;
+mod update;
+mod validate;
+mod when;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
+    /// Config file, `-` reads it from stdin
     #[arg(short = 'f', long = "file")]
     pub file: Option<String>,
 
+    /// Print the resolved steps, their scripts, hooks and commit messages instead of running them
     #[arg(long = "dry-run")]
     pub dry_run: bool,
+
+    /// How --dry-run prints the steps
+    #[arg(long = "format", value_enum, default_value = "text")]
+    pub format: PlanFormat,
+
+    /// Keep going after a step fails and write the failed steps to mend-followup.toml
+    #[arg(long = "keep-going", visible_alias = "continue-on-error")]
+    pub continue_on_error: bool,
+
+    /// With --keep-going, keep each failed step's changes on a mend/failed-<n> branch
+    #[arg(long = "quarantine")]
+    pub quarantine: bool,
+
+    /// How many steps with `needs` may run at once, the number of CPUs by default
+    #[arg(short = 'j', long = "jobs")]
+    pub jobs: Option<usize>,
+
+    /// What happens to a failed step before it's reset, `shell` opens a shell in the worktree to look at it
+    #[arg(long = "on-failure", value_enum)]
+    pub on_failure: Option<OnFailure>,
+
+    /// Before each step show its scripts and commit message and ask whether to run, skip or edit it, or quit
+    #[arg(short = 'i', long = "interactive", conflicts_with_all = ["tui", "jobs"])]
+    pub interactive: bool,
+
+    /// How progress is shown, `json` writes one event per line to stdout for wrappers and CI to parse,
+    /// `simple` a plain sentence per step change for screen readers
+    #[arg(long = "output", visible_alias = "progress", value_enum, default_value = "human")]
+    pub output: ProgressOutput,
+
+    /// Full-screen view of the steps and the selected one's output, same as `--output tui`
+    #[arg(long = "tui", conflicts_with = "output")]
+    pub tui: bool,
+
+    /// Show progress the way this CI system presents it, picked by itself on GitHub Actions
+    #[arg(long = "ci", value_enum, conflicts_with_all = ["output", "tui"])]
+    pub ci: Option<CiSystem>,
+
+    /// Organization policy the run must comply with, `$MEND_POLICY` when not given
+    #[arg(long = "policy")]
+    pub policy: Option<String>,
+
+    /// Show the lines the running steps' scripts print as they come
+    #[arg(short = 'v', long = "verbose")]
+    pub verbose: bool,
+
+    /// Record the scripts and their output as an asciinema cast in .mend/runs/<id>.cast
+    #[arg(long = "record")]
+    pub record: bool,
+
+    /// Run and verify the steps but leave their changes uncommitted, each step's patch is kept in .mend/runs/<id>/patches
+    #[arg(long = "no-commit")]
+    pub no_commit: bool,
+
+    /// Run only these steps, comma separated step numbers, ranges like `5-7`, ids or recipe names
+    #[arg(long = "only", value_delimiter = ',')]
+    pub only: Vec<String>,
+
+    /// Leave these steps out, given like --only
+    #[arg(long = "skip", value_delimiter = ',')]
+    pub skip: Vec<String>,
+
+    /// Start at this step, counting from 1, on top of the commit the last run made before it
+    #[arg(long = "from-step", conflicts_with = "from_id")]
+    pub from_step: Option<usize>,
+
+    /// Start at the step with this id, like --from-step
+    #[arg(long = "from-id")]
+    pub from_id: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Commands {
+    /// Set, remove or append a value in a JSON, YAML or TOML file
+    Edit(EditArgs),
+    /// Show whether a run is going, its worktrees and how far it got
+    Status,
+    /// Revert one step's commit in the worktree and verify the result
+    Revert {
+        /// The step's id, or its number counting from 1
+        step_id: String,
+    },
+    /// Stop the run in progress and its step scripts, leaving the worktree at its last step commit
+    Kill,
+    /// Continue the last run from its first step that isn't done, in the same worktree
+    Resume,
+    /// Render the report of a recorded run again, from `.mend/runs` only
+    Report(ReportArgs),
+    /// Pack a recorded run's report, config, logs and commits into one archive to share
+    Bundle(BundleArgs),
+    /// Show the run in an archive written by `mend bundle`, --apply fetches its commits
+    Unbundle(UnbundleArgs),
+    /// Remove recipes no step uses and hook rules that never run, --dry-run only lists them
+    PruneRecipes,
+    /// Suggest an order of the steps that runs more of them alongside each other, from their needs and the last run's timings
+    Optimize(OptimizeArgs),
+    /// Check the merged config for problems without running anything
+    Validate,
+    /// Print Markdown documentation of every recipe in the merged config
+    Docs,
+    /// Experimental: serve completion, hover, go-to-definition and diagnostics for configs to an editor
+    Lsp,
+    /// Run steps given on the command line, without a config file
+    Exec(ExecArgs),
+    /// Run the steps on several bases in throwaway worktrees and show which steps fail where
+    Simulate(SimulateArgs),
+    /// Replace this binary with the latest GitHub release after checking its checksum and signature
+    SelfUpdate(SelfUpdateArgs),
+    /// Remove cached clones of remote repos that no run used for a while, --dry-run only lists them
+    Gc(GcArgs),
+    /// Try the recipes on the `[[corpus]]` sample repos in throwaway worktrees and report how often each succeeds
+    VerifyRecipes(VerifyRecipesArgs),
+    /// Run one step from where a run would start it, again whenever its recipe's `run_file` or the config changes
+    Dev {
+        /// The step's id, or its number counting from 1
+        step_id: String,
+    },
+    /// Change how much the run going on shows, or follow a step's output, without restarting it
+    Ctl(CtlArgs),
 }
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Mend {
     from: Option<From>,
 
@@ -34,6 +249,11 @@ pub struct Mend {
     #[serde(default)]
     env: BTreeMap<String, String>,
 
+    /// Variables for every step's scripts, each armored age ciphertext decrypted at the start of the run
+    /// with the identity in `MEND_AGE_IDENTITY` or the file `MEND_AGE_IDENTITY_FILE` names
+    #[serde(default)]
+    secrets: BTreeMap<String, String>,
+
     #[serde(default)]
     recipes: BTreeMap<String, Recipe>,
 
@@ -41,26 +261,386 @@ pub struct Mend {
     hooks: BTreeMap<String, Vec<Hook>>,
 
     #[serde(default)]
-    steps: Vec<String>,
+    steps: Vec<Step>,
+
+    verify: Option<Verify>,
+
+    /// Values of variables to run each step's `verify` with, once for every combination of them
+    #[serde(default)]
+    matrix: BTreeMap<String, Vec<String>>,
+
+    /// Run the verify command on `from.sha` before the first step, failing the run when it's broken already
+    verify_baseline: Option<bool>,
+
+    /// Checked after a successful run, before anything is published
+    gates: Option<Gates>,
+
+    git: Option<GitConfig>,
+
+    commit: Option<CommitConfig>,
+
+    /// Identities steps' commits are made as, by the name a step's `committer_role` gives
+    #[serde(default)]
+    committers: BTreeMap<String, Identity>,
+
+    /// Named groups of steps, run after `steps`
+    #[serde(default)]
+    phases: Vec<Phase>,
+
+    /// Periodic progress reports for unattended runs
+    heartbeat: Option<HeartbeatConfig>,
+
+    shell: Option<ShellConfig>,
+
+    /// Where the run's OpenTelemetry spans are exported
+    telemetry: Option<TelemetryConfig>,
+
+    /// Default for the `timeout` of recipes and steps, e.g. `30m`
+    timeout: Option<String>,
+
+    /// Where the run's Prometheus metrics go when it ends
+    metrics: Option<MetricsConfig>,
+
+    /// Run later steps after one fails, like `--keep-going`
+    keep_going: Option<bool>,
+
+    /// What happens to a failed step before it's reset, like `--on-failure`
+    on_failure: Option<OnFailure>,
+
+    /// Write `.mend/badge.svg` with the result of each run, next to `.mend/status.json`
+    badge: Option<bool>,
+
+    /// Where else progress and the end of the run are reported
+    notify: Option<NotifyConfig>,
+
+    /// Where the repo is hosted, for opening issues about failed steps
+    forge: Option<ForgeConfig>,
+
+    /// How long the step logs under `.mend/logs` are kept
+    logs: Option<LogsConfig>,
+
+    /// Build artifacts removed before each step's commit, and how much growth is warned about
+    artifacts: Option<ArtifactsConfig>,
+
+    /// Where the run's commits end up, e.g. on a branch of their own
+    output: Option<OutputConfig>,
+
+    /// Another repository the `[output] branch` is pushed to when a run succeeds, rather than `from.repo`
+    publish: Option<PublishConfig>,
+
+    /// Commands `mend validate` also checks the config with, e.g. house rules of an organization. Each gets the merged
+    /// config as JSON on stdin and prints a JSON array of diagnostics, a relative path is found from the config's directory
+    #[serde(default)]
+    lint_rules: Vec<String>,
+
+    /// Sample repos `mend verify-recipes` tries the recipes on
+    #[serde(default)]
+    corpus: Vec<CorpusRepo>,
+
+    /// Names for common invocations, `mend <name>` runs mend with the arguments the name stands for
+    #[serde(default)]
+    aliases: BTreeMap<String, String>,
+
+    /// The variables mend itself gives every step, like `MEND_BIN`, filled in when the run starts
+    #[serde(skip)]
+    run_env: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum CommitMode {
+    /// One commit per step
+    #[default]
+    Step,
+    /// Steps of each phase squashed into one commit when the phase ends
+    Phase,
+    /// One commit for the whole run
+    Squash,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct CommitConfig {
+    #[serde(default)]
+    mode: CommitMode,
+
+    /// Message of the single commit in squash mode
+    squash_template: Option<String>,
+
+    /// Process environment variables templates may use besides `[env]` keys and `MEND_*`
+    #[serde(default)]
+    allow_env: Vec<String>,
+
+    /// Added to the end of every commit message, e.g. `Refactor-Tool: mend`, with variables expanded like in templates
+    #[serde(default)]
+    trailers: Vec<String>,
+
+    /// Also add `Mend-Step` and `Mend-Recipe` trailers to each step's commit, so a run can be reconstructed from history
+    #[serde(default)]
+    step_trailers: bool,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Phase {
+    name: String,
+
+    /// Message of the phase's commit in phase mode, `$phase` is the name
+    commit_template: Option<String>,
+
+    #[serde(default)]
+    steps: Vec<Step>,
+
+    /// `before_step` and `after_step` hooks of the phase's steps only, run before the top level ones
+    #[serde(default)]
+    hooks: BTreeMap<String, Vec<Hook>>,
+
+    /// Where the steps went in `Mend::steps` once loaded
+    #[serde(skip)]
+    first_step: usize,
+    #[serde(skip)]
+    step_count: usize,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct ShellConfig {
+    /// Shells tried in order to run scripts, e.g. `["bash", "busybox sh"]`, the first one installed is used
+    #[serde(default)]
+    candidates: Vec<String>,
+    /// How recipes are wrapped for the shell: `posix`, `powershell` or `cmd`, guessed from the shell's name when not set
+    dialect: Option<ShellDialect>,
+    /// After each script, hand files owned by another user or not writable by their owner back to the
+    /// worktree's owner, for shells that run scripts in a container as root
+    #[serde(default)]
+    normalize_ownership: bool,
+}
+
+/// Checks run after each step's scripts and before its commit.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Verify {
+    /// Detected from the project type in the worktree when not set. Also accepted as `fast`
+    #[serde(alias = "fast")]
+    run: Option<String>,
+
+    /// The full suite, run in place of `run` by the last step of each phase, every `full_every` steps and the last step
+    full: Option<String>,
+
+    /// Also run `full` after every this many steps
+    full_every: Option<usize>,
+}
+
+/// A step is either a plain instruction string or a table for the other step types.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Step {
+    Instruction(String),
+    Structured(Box<StepConfig>),
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct StepConfig {
+    /// Names the step for `fixup` and other commands, otherwise its number is used
+    id: Option<String>,
+    /// Id of an earlier step this step's commit is folded into at the end of the run
+    fixup: Option<String>,
+    /// Ids of the earlier steps this step builds on. It may then run alongside the steps before it that it
+    /// doesn't need, each in a worktree of its own, their commits applied in order afterwards
+    needs: Option<Vec<String>>,
+    run: Option<String>,
+    /// Instruction run after a reset when this step fails, `$1`.. are the step's arguments
+    fallback: Option<String>,
+    /// Replaces the recipe's `expected_exit_codes`
+    expected_exit_codes: Option<Vec<i32>>,
+    /// Condition checked before the step runs, e.g. `env.CI == 'true'`, the step is skipped when it doesn't hold
+    when: Option<String>,
+    /// Variables set for this step's scripts only, on top of `[env]`
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    /// Replaces the recipe's `timeout`
+    timeout: Option<String>,
+    /// Names of shared resources, e.g. a schema registry, the step changes. Steps holding the same
+    /// lock never run at the same time, even with `needs`
+    #[serde(default)]
+    locks: Vec<String>,
+    /// The `[committers]` entry the step's commit is made as, e.g. a bot for generated code
+    committer_role: Option<String>,
+    /// With an `@file.csv` argument, the steps its rows become are committed this many at a time, the commits
+    /// numbered, rather than one by one
+    chunk_size: Option<usize>,
+    /// The commit of a step expanded from a batch file with a `chunk_size`
+    #[serde(skip)]
+    chunk: Option<Chunk>,
+    edit: Option<Edit>,
+    openrewrite: Option<OpenRewrite>,
+    jscodeshift: Option<Jscodeshift>,
+}
+
+impl StepConfig {
+    fn builtin(&self) -> Option<&dyn run::BuiltinStep> {
+        if let Some(edit) = &self.edit {
+            Some(edit)
+        } else if let Some(openrewrite) = &self.openrewrite {
+            Some(openrewrite)
+        } else if let Some(jscodeshift) = &self.jscodeshift {
+            Some(jscodeshift)
+        } else {
+            None
+        }
+    }
+
+    fn kind_count(&self) -> usize {
+        [
+            self.run.is_some(),
+            self.edit.is_some(),
+            self.openrewrite.is_some(),
+            self.jscodeshift.is_some(),
+        ]
+        .iter()
+        .filter(|is_set| **is_set)
+        .count()
+    }
+}
+
+impl Step {
+    /// The configured id, or the step's number counting from 1.
+    fn id(&self, step_i: usize) -> String {
+        match self {
+            Step::Structured(step_config) if step_config.id.is_some() => {
+                step_config.id.clone().unwrap_or_default()
+            }
+            _ => (step_i + 1).to_string(),
+        }
+    }
+
+    fn fixup(&self) -> Option<&String> {
+        match self {
+            Step::Structured(step_config) => step_config.fixup.as_ref(),
+            Step::Instruction(_) => None,
+        }
+    }
+
+    fn needs(&self) -> Option<&Vec<String>> {
+        match self {
+            Step::Structured(step_config) => step_config.needs.as_ref(),
+            Step::Instruction(_) => None,
+        }
+    }
+
+    fn when(&self) -> Option<&String> {
+        match self {
+            Step::Structured(step_config) => step_config.when.as_ref(),
+            Step::Instruction(_) => None,
+        }
+    }
+
+    fn committer_role(&self) -> Option<&String> {
+        match self {
+            Step::Structured(step_config) => step_config.committer_role.as_ref(),
+            Step::Instruction(_) => None,
+        }
+    }
+
+    fn chunk(&self) -> Option<&Chunk> {
+        match self {
+            Step::Structured(step_config) => step_config.chunk.as_ref(),
+            Step::Instruction(_) => None,
+        }
+    }
+
+    fn env(&self) -> Option<&BTreeMap<String, String>> {
+        match self {
+            Step::Structured(step_config) => Some(&step_config.env),
+            Step::Instruction(_) => None,
+        }
+    }
+}
+
+impl std::convert::From<&str> for Step {
+    fn from(instruction: &str) -> Self {
+        Step::Instruction(instruction.to_string())
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct From {
+    /// Filled in from `ref` at the start of a run when the config names a ref instead
+    #[serde(default)]
     sha: String,
     repo: String,
+    /// Branch or tag the run starts from, e.g. `origin/main`, resolved to a sha when the run starts
+    #[serde(rename = "ref")]
+    git_ref: Option<String>,
+    /// Fetch before resolving `ref`, so it's where the remote has it now
+    #[serde(default)]
+    fetch: bool,
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Recipe {
+    #[serde(default)]
     run: String,
+
+    /// Script `run` is read from instead, relative to the file declaring the recipe, e.g. `recipes/rename.sh`.
+    /// `mend dev` re-runs its step whenever it changes
+    run_file: Option<String>,
+
     commit_template: Option<String>,
     tag: Option<String>,
 
     #[serde(default)]
     tags: Vec<String>,
+
+    /// Replaces the `[verify]` command for this recipe, an empty string skips verification
+    verify: Option<String>,
+
+    /// Default fallback for steps using this recipe
+    fallback: Option<String>,
+
+    /// Exit codes that count as success, for tools that exit 1 when they changed something
+    expected_exit_codes: Option<Vec<i32>>,
+
+    /// Languages the recipe changes, e.g. `["java", "kotlin"]`, checked against the worktree's files
+    #[serde(default)]
+    languages: Vec<String>,
+
+    /// What the recipe does, for `mend docs`
+    description: Option<String>,
+
+    /// Named parameters, `name` or `name=default`, that steps pass as `name=value` or in order
+    #[serde(default)]
+    params: Vec<String>,
+
+    /// Programs the recipe needs installed
+    #[serde(default)]
+    requires: Vec<String>,
+
+    /// Steps showing how to use the recipe
+    #[serde(default)]
+    examples: Vec<String>,
+
+    /// Globs of the files the recipe reads, steps are skipped while these and `outputs` are as their last run left them
+    #[serde(default)]
+    inputs: Vec<String>,
+
+    /// Globs of the files the recipe writes
+    #[serde(default)]
+    outputs: Vec<String>,
+
+    /// How long steps using this recipe may run before they are killed, e.g. `10m`
+    timeout: Option<String>,
+
+    /// Shared resources steps using this recipe change, added to the step's own `locks`
+    #[serde(default)]
+    locks: Vec<String>,
+
+    /// Matches the progress lines the recipe's tool prints, e.g. `(?<current>\d+)/(?<total>\d+) files`, filling in the
+    /// step's progress bar. Without `current` and `total` groups its first two groups are taken
+    progress_regex: Option<String>,
+
+    /// Program `run` is written for, e.g. `python3`, `node` or `ruby`, fed the body on stdin instead of
+    /// the shell running it. It gets the step's arguments, and `params` as environment variables
+    interpreter: Option<String>,
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Hook {
     run: Option<String>,
     when_tag: Option<String>,
@@ -68,7 +648,15 @@ pub struct Hook {
 }
 
 fn main() {
-    match run(&Cli::parse()) {
+    let args = alias::expand_alias(env::args().collect(), |file| {
+        let config_path = file.map_or_else(|| default_config_path().ok(), |file| Some(Path::new(file)))?;
+        // Reading the config from stdin here would leave nothing for the run
+        if config_path == Path::new(config::STDIN_CONFIG) {
+            return None;
+        }
+        config::load_mend(config_path).ok().map(|mend| mend.aliases)
+    });
+    match run(&Cli::parse_from(args)) {
         Ok(_) => {
             std::process::exit(0);
         }
@@ -79,45 +667,533 @@ fn main() {
     }
 }
 
-fn drive(mend: &Mend) {
-    let from = mend
-        .from
+/// With `resume`, continues the run it describes in the existing worktree instead of starting over.
+fn drive(mut mend: Mend, config_path: &Path, mut options: RunOptions, resume: Option<RunState>, flags: RunFlags) -> anyhow::Result<()> {
+    let mut from = mend.from.clone().ok_or_else(|| anyhow!("No from declared in config"))?;
+    let base_repo_dir = base_repo_dir(&from, config_path);
+    configure_git(mend.git.clone().unwrap_or_default());
+    if resume.is_none() && clone_cache::is_remote(&from.repo) {
+        clone_cache::ensure_checkout(&from.repo, from.rev(), &base_repo_dir)?;
+    }
+    match &resume {
+        Some(state) => pin_from_to_state(&mut from, state),
+        // --from-step builds on the last run, from where it started
+        None if flags.from_step.is_some() => {}
+        None => resolve_from(&mut from, &base_repo_dir)?,
+    }
+    // Before anything is set up, a missing key shouldn't leave a half started run behind
+    let shell = provide_secrets(&mend, &mut options, shell_executor(&mend)?)?;
+    provide_mend_bin(&mut mend, &mut options);
+    flags.selection.check(&create_run_status_from_mend(&mend, &flags.selection))?;
+    let started = Instant::now();
+    use_shell_dialect(&mut mend, &shell);
+    // Held until the run ends so a second run can't replace the worktree under us
+    let _lock = acquire_lock(
+        &base_repo_dir.join(MEND_DIR),
+        &config_path.to_string_lossy(),
+        mend.steps.len(),
+    )?;
+
+    // With --from-step, the steps before it and the commit to start from, as the last run left them
+    let restart = match (&resume, &flags.from_step) {
+        (None, Some(start_step)) => {
+            let planned_steps: Vec<(String, String)> = create_run_status_from_mend(&mend, &StepSelection::default())
+                .into_iter()
+                .map(|step_request| (step_request.id, step_request.run))
+                .collect();
+            let first_step = start_step.index(&planned_steps)?;
+            let state = state::read_state(&base_repo_dir.join(MEND_DIR))
+                .context("--from-step builds on the commits of the last run")?;
+            pin_from_to_state(&mut from, &state);
+            let worktree_dir = base_repo_dir.join(WORKTREE_DIR);
+            let worktree_repo = GitRepo { repo_dir: worktree_dir.clone() };
+            let start_sha = state.start_point(&from.sha, &planned_steps, first_step, Some(&worktree_repo).filter(|_| worktree_dir.exists()))?;
+            Some((first_step, state, start_sha))
+        }
+        _ => None,
+    };
+    let worktree_dir = if resume.is_some() {
+        base_repo_dir.join(WORKTREE_DIR)
+    } else {
+        let start_sha = restart.as_ref().map_or(&from.sha, |(_, _, start_sha)| start_sha);
+        let started_secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        match output::branch_name(&mend, config_path, &from.sha, started_secs) {
+            Some(branch) => {
+                eprintln!("Committing on the branch {}", branch);
+                // --from-step rebuilds the branch of the last run
+                ensure_worktree_on_branch(base_repo_dir.as_path(), WORKTREE_DIR, start_sha, &branch, restart.is_some())
+                    .with_context(|| format!("Could not create mend's worktree on the branch `{}`, delete it or change [output] branch", branch))?
+            }
+            None => ensure_worktree(base_repo_dir.as_path(), WORKTREE_DIR, start_sha)
+                .with_context(|| format!("Could not create mend's worktree in `{}`", base_repo_dir.to_string_lossy()))?,
+        }
+    };
+    if !worktree_dir.exists() {
+        eprintln!(
+            "Worktree dir {} doesn't exist",
+            worktree_dir.to_string_lossy()
+        );
+    }
+    fill_verify_command(&mut mend, &worktree_dir);
+    // Only a fresh run starts from `from.sha`
+    if mend.verify_baseline.unwrap_or_default() && resume.is_none() && restart.is_none() {
+        match mend.verify.as_ref().and_then(|verify| verify.run.as_deref()) {
+            Some(verify) => {
+                eprintln!("Verifying the baseline {} with `{}`", from.sha, verify);
+                run::verify_baseline(&mut shell.clone(), &worktree_dir, &from.sha, verify, &verify_env(&mend, &options))?;
+            }
+            None => eprintln!("Warning: verify_baseline is set but there's no verify command to run"),
+        }
+    }
+    match list_files(&worktree_dir) {
+        Ok(files) => {
+            for warning in language_warnings(&mend, &detect_languages(&files)) {
+                eprintln!("Warning: {}", warning);
+            }
+        }
+        Err(err) => eprintln!("Could not check recipe languages: {:#}", err),
+    }
+    let mut step_requests = create_run_status_from_mend(&mend, &flags.selection);
+    run::check_committers(&mend)?;
+    if let Some(policy) = &flags.policy {
+        let violations = policy.check_plan(&plan::plan_steps(&mend))?;
+        for violation in &violations {
+            eprintln!("Policy violated: {}", violation);
+        }
+        if !violations.is_empty() {
+            bail!("Refusing to start, the plan violates the policy");
+        }
+    }
+    options.continue_on_error |= mend.keep_going.unwrap_or_default();
+    options.on_failure = options.on_failure.or(mend.on_failure);
+    options.matrix = matrix::variants(&mend.matrix);
+    if options.on_failure == Some(OnFailure::Shell) {
+        // Only a step running in the worktree can be looked at, one at a time
+        options.max_parallel_steps = 1;
+    }
+    if options.max_parallel_steps > 1 {
+        match run::lock_overlapping_steps(&GitRepo { repo_dir: worktree_dir.clone() }, &mut step_requests) {
+            Ok(notes) => {
+                for note in notes {
+                    eprintln!("{}", note);
+                }
+            }
+            Err(err) => {
+                eprintln!("Could not tell which steps touch the same files, running them one at a time: {:#}", err);
+                options.max_parallel_steps = 1;
+            }
+        }
+    }
+    options.squash_groups = plan_squash_groups(&mend, &step_requests);
+    let run_id = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let planned_steps: Vec<(String, String)> = step_requests
+        .iter()
+        .map(|step_request| (step_request.id.clone(), step_request.run.clone()))
+        .collect();
+    let mut run_record = RunRecord::new(run_id.to_string(), &config_path.to_string_lossy(), &from.sha, &planned_steps);
+    if let Err(err) = report::write_run_config(&base_repo_dir.join(MEND_DIR), &run_id.to_string(), &mend) {
+        eprintln!("Could not keep the run's config: {:#}", err);
+    }
+    let cast = if flags.record {
+        let title = format!("mend {}", config_path.to_string_lossy());
+        Some(CastWriter::create(&report::cast_path(&base_repo_dir.join(MEND_DIR), &run_id.to_string()), &title)?)
+    } else {
+        None
+    };
+    let trace = mend
+        .telemetry
         .as_ref()
-        .expect("No from declared in config")
-        .clone();
-    let step_requests = create_run_status_from_mend(mend);
-    let mut notifier = create_console_notifier(&step_requests);
-    // repo could be remote but for now assume a local checkout
-    let repo_dir_raw = Path::new(&from.repo);
-    // Multiple concurrent runs will stomp on each other. Choose unique dir?
-    let base_repo_dir = expand_path(repo_dir_raw);
-
-    if let Ok(worktree_dir) = ensure_worktree(base_repo_dir.as_path(), ".mend/worktree2", &from.sha)
-    {
-        if !worktree_dir.exists() {
-            eprintln!(
-                "Worktree dir {} doesn't exist",
-                worktree_dir.to_string_lossy()
-            );
+        .map(|_| Rc::new(RefCell::new(Trace::start(&config_path.to_string_lossy(), &from.sha, &run_id.to_string()))));
+    let mut executor = TraceExecutor::new(CastExecutor::new(shell, cast), trace.clone());
+    let run_state = match resume {
+        Some(state) => {
+            options.first_step = state.resume_point(&from.sha, &planned_steps)?;
+            let expected_sha = state.last_sha_before(options.first_step);
+            let head_sha = GitRepo { repo_dir: worktree_dir.clone() }.current_short_sha()?;
+            if !head_sha.starts_with(expected_sha) && !expected_sha.starts_with(&head_sha) {
+                bail!(
+                    "The worktree is at {} but step {} left it at {}, start a new run instead",
+                    head_sha,
+                    options.first_step,
+                    expected_sha
+                );
+            }
+            options.resumed_commits = state.commits_before(options.first_step);
+            eprintln!("Resuming at step {} of {}", options.first_step + 1, planned_steps.len());
+            state
         }
-        let mut worktree_repo = GitRepo {
-            repo_dir: worktree_dir,
-        };
-        for (key, value) in &mend.env {
-            let expanded = shellexpand::env(value).unwrap();
-            env::set_var(key, expanded.as_ref());
+        None => {
+            if let Some((first_step, state, start_sha)) = &restart {
+                options.first_step = *first_step;
+                options.resumed_commits = state.commits_before(*first_step);
+                eprintln!("Starting at step {} of {} from {}", first_step + 1, planned_steps.len(), start_sha);
+            }
+            RunState::new(&config_path.to_string_lossy(), &from.sha, &planned_steps)
         }
+    };
+    let heartbeat_interval = mend
+        .heartbeat
+        .as_ref()
+        .map(|heartbeat| Duration::from_secs(heartbeat.minutes.max(1) * 60));
+    let mut notifier = TraceNotifier::new(
+        HeartbeatNotifier::new(
+            SlackNotifier::new(
+                WebhookNotifier::new(
+                    ExecNotifier::new(
+                        StateNotifier::new(
+                            ControlNotifier::new(create_notifier(flags.output, flags.verbose, &step_requests), &base_repo_dir.join(MEND_DIR)),
+                            &base_repo_dir.join(MEND_DIR),
+                            run_state,
+                        ),
+                        mend.notify.as_ref().and_then(|notify| notify.exec.as_ref()),
+                        config_path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new(".")),
+                    ),
+                    mend.notify.as_ref().and_then(|notify| notify.webhook.as_ref()),
+                    step_requests.len(),
+                ),
+                mend.notify.as_ref().and_then(|notify| notify.slack.as_ref()),
+            ),
+            step_requests.len(),
+            &base_repo_dir.join(MEND_DIR),
+            heartbeat_interval,
+        ),
+        trace.clone(),
+    );
+    let mut worktree_repo = GitRepo {
+        repo_dir: worktree_dir,
+    };
+    options.step_cache = Some(base_repo_dir.join(MEND_DIR).join(STEP_CACHE_FILE));
+    let log_dir = run_log_dir(&base_repo_dir.join(MEND_DIR), &run_id.to_string());
+    options.log_dir = Some(log_dir.clone());
+    options.artifacts = Some(mend.artifacts.clone().unwrap_or_default());
 
-        let mut executor = ShellExecutor {};
-        match run::run_all_steps(step_requests, &mut notifier, &mut worktree_repo, &mut executor) {
-            Ok(_) => {
-                notifier.notify_done()
+    let outcome = run::run_all_steps(step_requests, &mut notifier, &mut worktree_repo, &mut executor, &options);
+    match &outcome {
+        Ok(summary) => notifier.notify_done(summary),
+        Err(failure) => notifier.notify_failure(&failure.0, &failure.1),
+    }
+    // The full-screen view stays up until it's closed, what's printed from here lands on the normal screen
+    drop(notifier);
+    if flags.no_commit {
+        // The steps committed as usual so fixups, squashing and parallel steps work, their commits are undone here
+        let patch_dir = report::patch_dir(&base_repo_dir.join(MEND_DIR), &run_id.to_string());
+        let patches = worktree_repo.format_patches(&from.sha, &patch_dir)?;
+        worktree_repo.reset_mixed(&from.sha)?;
+        eprintln!(
+            "Nothing committed, {} step patches are in {}, apply them with `git am` or run again without --no-commit",
+            patches,
+            patch_dir.to_string_lossy()
+        );
+    }
+    if let (Some(telemetry), Some(trace)) = (&mend.telemetry, &trace) {
+        let ok = outcome.as_ref().is_ok_and(|summary| summary.failed_steps.is_empty());
+        export_trace(telemetry, &mut trace.borrow_mut(), ok);
+    }
+    let finished_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    // The state notifier kept each step's status and duration
+    match read_state(&base_repo_dir.join(MEND_DIR)) {
+        Ok(state) => {
+            let status = RunStatus::new(&state, worktree_repo.current_short_sha().ok(), finished_at);
+            if let Err(err) = write_status(&base_repo_dir.join(MEND_DIR), &status, mend.badge.unwrap_or_default()) {
+                eprintln!("{:#}", err);
+            }
+            if let Some(metrics) = &mend.metrics {
+                let diff = DiffStats::from_numstat(&worktree_repo.diff_numstat(&from.sha).unwrap_or_default());
+                publish_metrics(metrics, &render_metrics(&state, &diff, started.elapsed(), finished_at));
+            }
+        }
+        Err(err) => eprintln!("Could not record the run's status: {:#}", err),
+    }
+    if let Err(err) = prune_logs(&base_repo_dir.join(MEND_DIR), mend.logs.as_ref()) {
+        eprintln!("{:#}", err);
+    }
+    match outcome {
+        Ok(summary) => {
+            if !flags.no_commit {
+                revert::write_commits(&base_repo_dir.join(MEND_DIR), &summary.commits)?;
+            }
+            run_record.record_summary(&summary);
+            if !summary.failed_steps.is_empty() {
+                let failed_steps: Vec<FailedStep> = summary
+                    .failed_steps
+                    .iter()
+                    .zip(&summary.failures)
+                    .map(|(step_i, (request, response))| FailedStep { step: step_i + 1, request, response })
+                    .collect();
+                let resume = format!("mend -f {}", config_path.with_file_name(FOLLOWUP_FILE).to_string_lossy());
+                open_issues(&mend, &mut run_record, &failed_steps, &resume);
+            }
+            report::write_run(&base_repo_dir.join(MEND_DIR), &run_record)?;
+            if !summary.failed_steps.is_empty() {
+                let followup_sha = worktree_repo.current_short_sha()?;
+                let followup_path = followup::write_followup(&mend, &summary.failed_steps, &followup_sha, config_path)?;
+                eprintln!(
+                    "{} failed steps written to {}, run it to retry them on top of {}",
+                    summary.failed_steps.len(),
+                    followup_path.to_string_lossy(),
+                    followup_sha
+                );
+                for (step_i, (step_request, _)) in summary.failed_steps.iter().zip(&summary.failures) {
+                    eprintln!(
+                        "Failed step {}: {}, log in {}",
+                        step_request.id,
+                        step_request.run,
+                        step_log_path(&log_dir, *step_i).to_string_lossy()
+                    );
+                }
+                // Nothing is published from a run with failed steps, so the gates aren't checked
+                bail!("{} of {} steps failed", summary.failed_steps.len(), planned_steps.len());
             }
-            Err((step_request, step_response)) => {
-                notifier.notify_failure(&step_request, &step_response)
+            if let Some(policy) = &flags.policy {
+                let mut violations = vec![];
+                for change in step_changes(&worktree_repo, &summary.commits)? {
+                    violations.extend(policy.check_step(&change)?);
+                }
+                for violation in &violations {
+                    eprintln!("Policy violated: {}", violation);
+                }
+                if !violations.is_empty() {
+                    bail!("Refusing to publish, the steps violate the policy");
+                }
+            }
+            if let Some(gates) = &mend.gates {
+                let verify = mend.verify.as_ref().and_then(|verify| verify.run.as_deref());
+                let failures = check_gates(gates, &worktree_repo, &from.sha, verify, &verify_env(&mend, &options), &mut executor)?;
+                for failure in &failures {
+                    eprintln!("Gate failed: {}", failure);
+                }
+                if !failures.is_empty() {
+                    bail!("Refusing to publish, {} of the configured gates failed", failures.len());
+                }
+            }
+            if !flags.no_commit {
+                publish(&mend, config_path, &base_repo_dir, &worktree_repo, &run_record)?;
+            }
+        }
+        Err(failure) => {
+            let (step_request, step_response) = *failure;
+            if let Some(step_i) = planned_steps.iter().position(|(id, _)| *id == step_request.id) {
+                eprintln!("Full log in {}", step_log_path(&log_dir, step_i).to_string_lossy());
+            }
+            run_record.record_stop(&step_request, &step_response);
+            // A run stopped with `--interactive` has no failed step to open an issue about
+            let step_i = planned_steps.iter().position(|(id, _)| *id == step_request.id);
+            if let (Some(step_i), true) = (step_i, step_response.status.is_failed()) {
+                let failed = FailedStep { step: step_i + 1, request: &step_request, response: &step_response };
+                open_issues(&mend, &mut run_record, &[failed], "mend resume");
+            }
+            report::write_run(&base_repo_dir.join(MEND_DIR), &run_record)?;
+            if step_response.status.is_failed() {
+                bail!("Step {} failed", step_request.id);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// What verify runs with outside of a step, as in one: the secrets and `MEND_BIN` of `options` with `[env]` on top.
+fn verify_env(mend: &Mend, options: &RunOptions) -> BTreeMap<String, String> {
+    let mut env = options.env.clone();
+    env.extend(run::resolve_env(mend));
+    env
+}
+
+/// Opens the issues `[forge.issues]` asks for about the failed steps and notes them in the run's record.
+fn open_issues(mend: &Mend, run_record: &mut RunRecord, failed_steps: &[FailedStep], resume: &str) {
+    let Some(forge) = &mend.forge else {
+        return;
+    };
+    for (id, issue) in forge::open_failure_issues(forge, failed_steps, &run_record.id, resume) {
+        run_record.add_step_metadata(&id, ISSUE_KEY, &issue);
+    }
+}
+
+/// Pushes the run's branch to `[publish] remote_url` and opens the pull request `[forge.pull_request]` asks for, each when configured.
+/// With `split_by_owner`, each group of owners in CODEOWNERS gets a branch and a pull request of its own instead.
+fn publish(mend: &Mend, config_path: &Path, base_repo_dir: &Path, worktree_repo: &GitRepo, run_record: &RunRecord) -> anyhow::Result<()> {
+    let mend_dir = &base_repo_dir.join(MEND_DIR);
+    let publish_url = output::publish_url(mend)?;
+    let pull_request = mend.forge.as_ref().and_then(|forge| Some((forge, forge.pull_request.as_ref()?)));
+    if publish_url.is_none() && pull_request.is_none() {
+        return Ok(());
+    }
+    let branch = worktree_repo
+        .current_branch()?
+        .ok_or_else(|| anyhow!("The worktree isn't on a branch to publish, was it created before [output] branch was set?"))?;
+    let Some((forge, pull_request)) = pull_request else {
+        if let Some(url) = publish_url {
+            worktree_repo.push(&url, &branch).with_context(|| format!("Could not publish `{}`", branch))?;
+            eprintln!("Published {}", branch);
+        }
+        return Ok(());
+    };
+    let durations: Vec<Option<u64>> = read_state(mend_dir)
+        .map(|state| state.steps.iter().map(|step| step.duration_ms).collect())
+        .unwrap_or_default();
+    let steps: Vec<PullRequestStep> = run_record
+        .steps
+        .iter()
+        .map(|step| PullRequestStep {
+            step: step.step,
+            summary: step
+                .sha
+                .as_ref()
+                .and_then(|sha| worktree_repo.commit_message(sha).ok())
+                .and_then(|message| message.lines().next().map(str::to_string))
+                .unwrap_or_else(|| step.run.clone()),
+            sha: step.sha.clone(),
+            duration: durations.get(step.step - 1).copied().flatten().map(Duration::from_millis),
+            issue: step.metadata.get(ISSUE_KEY).cloned(),
+            warnings: step.metadata.get("warning").cloned(),
+            // Only shown for steps with warnings
+            output: step
+                .metadata
+                .get("warning")
+                .and_then(|_| fs::read_to_string(step_log_path(&run_log_dir(mend_dir, &run_record.id), step.step - 1)).ok()),
+        })
+        .collect();
+    let vars = vec![
+        ("config", config_path.file_stem().unwrap_or_default().to_string_lossy().to_string()),
+        ("run_id", run_record.id.clone()),
+    ];
+    // A published branch is proposed from the other repository, `[forge] project` says which of the two gets it
+    let remote = publish_url.as_deref().unwrap_or(pull_request.remote());
+    let propose = |branch: &str, vars: &[(&str, String)], steps: &[PullRequestStep]| -> anyhow::Result<()> {
+        let body = forge::pull_request_body(&config_path.to_string_lossy(), &run_record.from_sha, steps);
+        let opened = forge::open_pull_request(forge, pull_request, worktree_repo, remote, branch, vars, &body)?;
+        eprintln!("Opened {}", opened.url);
+        if pull_request.step_comments {
+            forge::comment_steps(forge, &opened, steps);
+        }
+        Ok(())
+    };
+    if !pull_request.split_by_owner {
+        return propose(&branch, &vars, &steps);
+    }
+
+    let code_owners = CodeOwners::read(&worktree_repo.repo_dir)?.ok_or_else(|| {
+        anyhow!("`split_by_owner` needs a CODEOWNERS file, there's none of {}", CODEOWNERS_PATHS.join(", "))
+    })?;
+    let mut commits = vec![];
+    for step in &steps {
+        if let Some(sha) = &step.sha {
+            // `<added>\t<deleted>\t<path>` lines
+            let files = worktree_repo.commit_numstat(sha)?.lines().filter_map(|line| line.splitn(3, '\t').nth(2)).map(str::to_string).collect();
+            commits.push((step.step, files));
+        }
+    }
+    let mut steps: Vec<Option<PullRequestStep>> = steps.into_iter().map(Some).collect();
+    for group in codeowners::group_by_owners(&code_owners, &commits) {
+        let owners = if group.owners.is_empty() { "no owner".to_string() } else { group.owners.join(" ") };
+        let group_branch = codeowners::branch_name(&branch, &group.owners);
+        let mut group_steps: Vec<PullRequestStep> = group.steps.iter().filter_map(|step| steps[step - 1].take()).collect();
+        // The group's commits are picked onto the run's start, and shown with their new shas
+        let work_dir = ensure_worktree_on_branch(base_repo_dir, OWNERS_WORKTREE_DIR, &run_record.from_sha, &group_branch, true)?;
+        let mut group_repo = GitRepo { repo_dir: work_dir.clone() };
+        let picked = group_steps.iter_mut().try_for_each(|step| -> anyhow::Result<()> {
+            group_repo.cherry_pick(step.sha.as_deref().unwrap_or_default(), None)?;
+            step.sha = Some(group_repo.current_short_sha()?);
+            Ok(())
+        });
+        remove_worktree(&work_dir)?;
+        if let Err(err) = picked {
+            eprintln!("Could not put the commits of {} on `{}` without the others', leaving them out: {:#}", owners, group_branch, err);
+            continue;
+        }
+        let mut group_vars = vars.clone();
+        group_vars.push(("owners", owners));
+        propose(&group_branch, &group_vars, &group_steps)?;
+    }
+    Ok(())
+}
+
+fn fill_verify_command(mend: &mut Mend, worktree_dir: &Path) {
+    if let Some(verify) = &mut mend.verify {
+        if verify.run.is_none() {
+            verify.run = default_verify_command(worktree_dir);
+            if verify.run.is_none() {
+                eprintln!("No verify command set and no known project type found, skipping verification");
             }
         }
+    }
+}
+
+fn shell_executor(mend: &Mend) -> anyhow::Result<ShellExecutor> {
+    let candidates: Vec<String> = match &mend.shell {
+        Some(shell) if !shell.candidates.is_empty() => shell.candidates.clone(),
+        _ => DEFAULT_SHELLS.iter().map(|shell| shell.to_string()).collect(),
+    };
+    let mut shell_executor = ShellExecutor::find(&candidates)?;
+    if let Some(dialect) = mend.shell.as_ref().and_then(|shell| shell.dialect) {
+        shell_executor = shell_executor.with_dialect(dialect);
+    }
+    let normalize_ownership = mend.shell.as_ref().is_some_and(|shell| shell.normalize_ownership);
+    Ok(shell_executor.normalizing_ownership(normalize_ownership))
+}
+
+/// `[secrets]` reach the steps decrypted, and what the steps print with their values masked.
+fn provide_secrets(mend: &Mend, options: &mut RunOptions, shell: ShellExecutor) -> anyhow::Result<ShellExecutor> {
+    let secrets = secrets::decrypt_secrets(&mend.secrets)?;
+    let shell = shell.masking(&secrets);
+    options.env.extend(secrets);
+    Ok(shell)
+}
+
+/// Steps are resolved for the shell that was found.
+fn use_shell_dialect(mend: &mut Mend, shell: &ShellExecutor) {
+    mend.shell.get_or_insert_with(Default::default).dialect = Some(shell.dialect());
+}
+
+/// Built-in step types call back into this binary, as `$MEND_BIN` in the steps' environment and commit messages.
+fn provide_mend_bin(mend: &mut Mend, options: &mut RunOptions) {
+    if let Ok(mend_bin) = env::current_exe() {
+        let mend_bin = mend_bin.to_string_lossy().to_string();
+        options.env.insert("MEND_BIN".to_string(), mend_bin.clone());
+        mend.run_env.insert("MEND_BIN".to_string(), mend_bin);
+    }
+}
 
+impl From {
+    /// What to check out of a remote `repo`, the ref itself until it's resolved.
+    fn rev(&self) -> &str {
+        self.git_ref.as_deref().unwrap_or(&self.sha)
+    }
+}
+
+/// With `from.ref`, fills in `from.sha` with the commit the ref points to now, fetched first with `from.fetch`.
+fn resolve_from(from: &mut From, base_repo_dir: &Path) -> anyhow::Result<()> {
+    let Some(git_ref) = &from.git_ref else {
+        return Ok(());
+    };
+    let repo = GitRepo { repo_dir: base_repo_dir.to_path_buf() };
+    // The clone cache fetched already, refs that can move are fetched every time
+    if from.fetch && !clone_cache::is_remote(&from.repo) {
+        repo.fetch_all().with_context(|| format!("Could not fetch before resolving `{}`", git_ref))?;
+    }
+    from.sha = repo.resolve_ref(git_ref).context("Could not resolve `from.ref`")?;
+    eprintln!("Starting from {} at {}", git_ref, from.sha);
+    Ok(())
+}
+
+/// With `from.ref`, the run `state` describes goes on from the sha the ref had when it started, wherever it is now.
+fn pin_from_to_state(from: &mut From, state: &RunState) {
+    if from.git_ref.is_some() {
+        from.sha = state.from_sha.clone();
+    }
+}
+
+/// The local checkout `from.repo` names, or for a remote repo the config's clone of it in the clone cache.
+fn base_repo_dir(from: &From, config_path: &Path) -> PathBuf {
+    if clone_cache::is_remote(&from.repo) {
+        clone_cache::checkout_dir(&clone_cache::clone_cache_dir(), &from.repo, config_path)
+    } else {
+        expand_path(Path::new(&from.repo))
     }
 }
 
@@ -127,40 +1203,454 @@ fn expand_path(repo_dir_raw: &Path) -> PathBuf {
 }
 
 fn run(cli: &Cli) -> anyhow::Result<()> {
+    match &cli.command {
+        Some(Commands::Edit(args)) => edit::run_edit(args),
+        Some(Commands::Status) => {
+            let config_path = config_path(cli)?;
+            let mend = config::load_mend(config_path)?;
+            configure_git(mend.git.clone().unwrap_or_default());
+            let mut from = mend.from.clone().ok_or_else(|| anyhow!("No from declared in config"))?;
+            let base_repo_dir = base_repo_dir(&from, config_path);
+            match state::read_state(&base_repo_dir.join(MEND_DIR)) {
+                Ok(state) => pin_from_to_state(&mut from, &state),
+                Err(_) => resolve_from(&mut from, &base_repo_dir)?,
+            }
+            status::print_status(&base_repo_dir, &from.sha, mend.steps.len())
+        }
+        Some(Commands::Revert { step_id }) => {
+            let config_path = config_path(cli)?;
+            let mut mend = config::load_mend(config_path)?;
+            configure_git(mend.git.clone().unwrap_or_default());
+            let base_repo_dir = base_repo_dir(
+                mend.from
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("No from declared in config"))?,
+                config_path,
+            );
+            fill_verify_command(&mut mend, &base_repo_dir.join(WORKTREE_DIR));
+            let verify = mend.verify.as_ref().and_then(|verify| verify.run.as_deref());
+            revert::revert_step(&base_repo_dir, step_id, verify, &mut shell_executor(&mend)?)
+        }
+        Some(Commands::Kill) => {
+            let config_path = config_path(cli)?;
+            let mend = config::load_mend(config_path)?;
+            configure_git(mend.git.clone().unwrap_or_default());
+            let base_repo_dir = base_repo_dir(
+                mend.from
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("No from declared in config"))?,
+                config_path,
+            );
+            match lock::kill_run(&base_repo_dir.join(MEND_DIR), Duration::from_secs(10))? {
+                Some(stopped) => eprintln!("Stopped mend run {} from {}", stopped.pid, stopped.config),
+                None => eprintln!("Removed the lock of a mend run that was already gone"),
+            }
+            let worktree_dir = base_repo_dir.join(WORKTREE_DIR);
+            if worktree_dir.exists() {
+                // Drop what the interrupted step left behind, the committed steps stay
+                GitRepo { repo_dir: worktree_dir }.reset_hard()?;
+            }
+            Ok(())
+        }
+        Some(Commands::Report(args)) => {
+            let config_path = config_path(cli)?;
+            let mend = config::load_mend(config_path)?;
+            let base_repo_dir = base_repo_dir(
+                mend.from
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("No from declared in config"))?,
+                config_path,
+            );
+            report::run_report(&base_repo_dir.join(MEND_DIR), args)
+        }
+        Some(Commands::Bundle(args)) => {
+            let config_path = config_path(cli)?;
+            let mend = config::load_mend(config_path)?;
+            configure_git(mend.git.clone().unwrap_or_default());
+            let base_repo_dir = base_repo_dir(
+                mend.from
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("No from declared in config"))?,
+                config_path,
+            );
+            let archive_path = bundle::run_bundle(&base_repo_dir, &base_repo_dir.join(MEND_DIR), args)?;
+            println!("Wrote {}", archive_path.to_string_lossy());
+            Ok(())
+        }
+        Some(Commands::Unbundle(args)) => bundle::run_unbundle(args),
+        Some(Commands::PruneRecipes) => prune::run_prune(config_path(cli)?, cli.dry_run),
+        Some(Commands::Optimize(args)) => optimize::run_optimize(config_path(cli)?, run_options(cli).max_parallel_steps, args.apply),
+        Some(Commands::Validate) => validate::run_validate(config_path(cli)?),
+        Some(Commands::Lsp) => lsp::run_lsp(),
+        Some(Commands::SelfUpdate(args)) => update::run_self_update(args),
+        Some(Commands::Gc(args)) => {
+            // Without a config there's only the clone cache to clean up
+            let base_repo_dir = if cli.file.is_some() || default_config_path().is_ok() {
+                let config_path = config_path(cli)?;
+                let mend = config::load_mend(config_path)?;
+                mend.from.as_ref().map(|from| base_repo_dir(from, config_path))
+            } else {
+                None
+            };
+            clone_cache::run_gc(args, cli.dry_run, base_repo_dir.as_deref())
+        }
+        Some(Commands::Exec(args)) => {
+            let config_path = Path::new(EXEC_CONFIG);
+            let mend = config::load_mend_contents(config_path, &exec::exec_config(args)?)?;
+            if cli.dry_run {
+                return print_plan(mend, config_path, cli.format);
+            }
+            drive(mend, config_path, run_options(cli), None, run_flags(cli)?)
+        }
+        Some(Commands::Simulate(args)) => run_simulate(cli, args),
+        Some(Commands::VerifyRecipes(args)) => run_verify_recipes(cli, args),
+        Some(Commands::Dev { step_id }) => run_dev(cli, step_id),
+        Some(Commands::Ctl(args)) => {
+            let config_path = config_path(cli)?;
+            let mend = config::load_mend(config_path)?;
+            let from = mend.from.as_ref().ok_or_else(|| anyhow!("No from declared in config"))?;
+            let mend_dir = base_repo_dir(from, config_path).join(MEND_DIR);
+            match &args.command {
+                CtlCommand::Verbosity { level } => ctl::set_verbosity(&mend_dir, *level),
+                CtlCommand::Tail { step_id } => {
+                    let step_i = dev::step_index(&create_run_status_from_mend(&mend, &StepSelection::default()), step_id)?;
+                    ctl::tail(&mend_dir, step_i, &mut std::io::stdout(), ctl::POLL_INTERVAL)
+                }
+            }
+        }
+        Some(Commands::Docs) => {
+            print!("{}", docs::render_docs(&config::load_mend(config_path(cli)?)?));
+            Ok(())
+        }
+        Some(Commands::Resume) => {
+            let config_path = config_path(cli)?;
+            let mend = config::load_mend(config_path)?;
+            let base_repo_dir = base_repo_dir(
+                mend.from
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("No from declared in config"))?,
+                config_path,
+            );
+            let state = state::read_state(&base_repo_dir.join(MEND_DIR))?;
+            drive(mend, config_path, run_options(cli), Some(state), run_flags(cli)?)
+        }
+        None => run_mend(cli),
+    }
+}
+
+fn config_path(cli: &Cli) -> anyhow::Result<&Path> {
     let config_path = match &cli.file {
         Some(file) => {
             let path = Path::new(file.as_str());
-            if path.exists() {
+            if path.exists() || file == config::STDIN_CONFIG {
                 path
             } else {
                 bail!("Specified file {} doesn't exist", file)
             }
         }
-        None => {
-            let toml_path = Path::new("mend.toml");
-            if toml_path.exists() {
-                toml_path
-            } else {
-                bail!(
-                    "No mend.toml found, please specify one with -f or create one with `mend init`"
-                )
-            }
-        }
+        None => default_config_path()?,
     };
+    Ok(config_path)
+}
+
+/// The config in the current directory, when `-f` isn't given.
+fn default_config_path() -> anyhow::Result<&'static Path> {
+    match ["mend.toml", "mend.yaml", "mend.yml"]
+        .iter()
+        .map(Path::new)
+        .find(|path| path.exists())
+    {
+        Some(path) => Ok(path),
+        None => bail!(
+            "No mend.toml found, please specify one with -f or create one with `mend init`"
+        ),
+    }
+}
+
+fn run_mend(cli: &Cli) -> anyhow::Result<()> {
+    let config_path = config_path(cli)?;
     let merged_mend = config::load_mend(config_path)?;
     if cli.dry_run {
-        eprintln!("Dry run, skipping")
+        print_plan(merged_mend, config_path, cli.format)
     } else {
-        drive(&merged_mend)
+        drive(merged_mend, config_path, run_options(cli), None, run_flags(cli)?)
+    }
+}
+
+fn run_simulate(cli: &Cli, args: &SimulateArgs) -> anyhow::Result<()> {
+    let config_path = config_path(cli)?;
+    let mut mend = config::load_mend(config_path)?;
+    configure_git(mend.git.clone().unwrap_or_default());
+    let base_repo_dir = base_repo_dir(
+        mend.from
+            .as_ref()
+            .ok_or_else(|| anyhow!("No from declared in config"))?,
+        config_path,
+    );
+    let mut executor = shell_executor(&mend)?;
+    use_shell_dialect(&mut mend, &executor);
+    fill_verify_command(&mut mend, &base_repo_dir);
+    let mut options = run_options(cli);
+    provide_mend_bin(&mut mend, &mut options);
+    executor = provide_secrets(&mend, &mut options, executor)?;
+    let selection = step_selection(cli);
+    selection.check(&create_run_status_from_mend(&mend, &selection))?;
+    let mut records = vec![];
+    for (base_i, base) in args.bases.iter().enumerate() {
+        if let Some(from) = mend.from.as_ref().filter(|from| clone_cache::is_remote(&from.repo)) {
+            clone_cache::ensure_checkout(&from.repo, base, &base_repo_dir)?;
+        }
+        eprintln!("Simulating on {}", base);
+        let mut step_requests = create_run_status_from_mend(&mend, &selection);
+        if args.no_verify {
+            for step_request in step_requests.iter_mut() {
+                step_request.verify = None;
+            }
+        }
+        let mut notifier = create_notifier(progress_output(cli), cli.verbose, &step_requests);
+        records.push(simulate::simulate_base(&base_repo_dir, base_i, base, step_requests, &mut notifier, &mut executor, &options)?);
+    }
+    print!("{}", simulate::render_simulation(&records));
+    Ok(())
+}
+
+fn run_verify_recipes(cli: &Cli, args: &VerifyRecipesArgs) -> anyhow::Result<()> {
+    let config_path = config_path(cli)?;
+    let mut mend = config::load_mend(config_path)?;
+    if mend.corpus.is_empty() {
+        bail!("No [[corpus]] repos in the config to try the recipes on");
+    }
+    configure_git(mend.git.clone().unwrap_or_default());
+    let mut executor = shell_executor(&mend)?;
+    use_shell_dialect(&mut mend, &executor);
+    let mut options = run_options(cli);
+    provide_mend_bin(&mut mend, &mut options);
+    executor = provide_secrets(&mend, &mut options, executor)?;
+    fs::create_dir_all(&args.diffs_dir).with_context(|| format!("Could not create `{}`", args.diffs_dir.to_string_lossy()))?;
+    let mut results = vec![];
+    for (sample_i, sample) in mend.corpus.iter().enumerate() {
+        let from = From { repo: sample.repo.clone(), sha: sample.sha.clone(), git_ref: None, fetch: false };
+        let base_repo_dir = base_repo_dir(&from, config_path);
+        if clone_cache::is_remote(&from.repo) {
+            clone_cache::ensure_checkout(&from.repo, &from.sha, &base_repo_dir)?;
+        }
+        let mut sample_mend = mend.clone();
+        sample_mend.steps = sample.steps.iter().cloned().map(Step::Instruction).collect();
+        sample_mend.phases = vec![];
+        fill_verify_command(&mut sample_mend, &base_repo_dir);
+        let step_requests = create_run_status_from_mend(&sample_mend, &StepSelection::default());
+        for (step_i, mut step_request) in step_requests.into_iter().enumerate() {
+            let recipe = corpus::recipe_name(&step_request).to_string();
+            if !args.recipes.is_empty() && !args.recipes.contains(&recipe) {
+                continue;
+            }
+            if args.no_verify {
+                step_request.verify = None;
+            }
+            let run = step_request.run.clone();
+            eprintln!("Trying `{}` on {} {}", run, sample.repo, sample.sha);
+            let (status, diff) = corpus::verify_sample(&base_repo_dir, &sample.sha, step_request, &mut executor, &options)?;
+            if !diff.is_empty() {
+                let diff_path = args.diffs_dir.join(format!("{}-{}-{}.diff", sample_i + 1, step_i + 1, recipe));
+                fs::write(&diff_path, &diff).with_context(|| format!("Could not write `{}`", diff_path.to_string_lossy()))?;
+            }
+            results.push(SampleResult { repo: sample.repo.clone(), recipe, run, status });
+        }
+    }
+    print!("{}", corpus::render_success_rates(&results));
+    let below: Vec<String> = corpus::success_rates(&results)
+        .into_iter()
+        .filter(|(_, (succeeded, total))| corpus::percentage(*succeeded, *total) < args.min_success as usize)
+        .map(|(recipe, _)| recipe)
+        .collect();
+    if !below.is_empty() {
+        bail!("Succeeding on fewer than {}% of their samples: {}", args.min_success, below.join(", "));
     }
     Ok(())
 }
 
+/// Runs one step over and over from the commit a run would start it on, each time its recipe's `run_file` or the
+/// config changes, until interrupted.
+fn run_dev(cli: &Cli, step_id: &str) -> anyhow::Result<()> {
+    let config_path = config_path(cli)?;
+    let mend = config::load_mend(config_path)?;
+    let mut from = mend.from.clone().ok_or_else(|| anyhow!("No from declared in config"))?;
+    configure_git(mend.git.clone().unwrap_or_default());
+    let base_repo_dir = base_repo_dir(&from, config_path);
+    if clone_cache::is_remote(&from.repo) {
+        clone_cache::ensure_checkout(&from.repo, from.rev(), &base_repo_dir)?;
+    }
+    let step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
+    let step_i = dev::step_index(&step_requests, step_id)?;
+    let start_sha = if step_i == 0 {
+        resolve_from(&mut from, &base_repo_dir)?;
+        from.sha.clone()
+    } else {
+        let planned_steps: Vec<(String, String)> =
+            step_requests.into_iter().map(|step_request| (step_request.id, step_request.run)).collect();
+        let state = state::read_state(&base_repo_dir.join(MEND_DIR)).context("mend dev starts a later step on the commits of the last run")?;
+        pin_from_to_state(&mut from, &state);
+        state.start_point(&from.sha, &planned_steps, step_i, None::<&GitRepo>)?
+    };
+    eprintln!("Running step {} on {} whenever its recipe or the config changes, Ctrl-C to stop", step_i + 1, start_sha);
+    loop {
+        let watched = run_dev_once(cli, config_path, &base_repo_dir, &start_sha, step_id).unwrap_or_else(|err| {
+            eprintln!("{:#}", err);
+            vec![config_path.to_path_buf()]
+        });
+        let changed = dev::wait_for_change(&watched, dev::POLL_INTERVAL);
+        eprintln!("\n{} changed", changed.to_string_lossy());
+    }
+}
+
+/// Loads the config again and runs the step once, returning the files whose changes run it again.
+fn run_dev_once(cli: &Cli, config_path: &Path, base_repo_dir: &Path, start_sha: &str, step_id: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let mut mend = config::load_mend(config_path)?;
+    let mut executor = shell_executor(&mend)?;
+    use_shell_dialect(&mut mend, &executor);
+    fill_verify_command(&mut mend, base_repo_dir);
+    let mut options = run_options(cli);
+    provide_mend_bin(&mut mend, &mut options);
+    executor = provide_secrets(&mend, &mut options, executor)?;
+    let mut step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
+    let step_request = step_requests.swap_remove(dev::step_index(&step_requests, step_id)?);
+    let watched = dev::watched_files(&mend, config_path, &step_request);
+    eprintln!("Running `{}`", step_request.run);
+    let dev_run = dev::run_dev_step(base_repo_dir, start_sha, step_request, &mut executor, &options)?;
+    match dev_run.status {
+        run::EStatus::Done if dev_run.diff.is_empty() => eprintln!("The step changed nothing"),
+        run::EStatus::Done => print!("{}", dev_run.diff),
+        run::EStatus::Skipped => eprintln!("The step was skipped, its `when` doesn't hold"),
+        run::EStatus::VerifyFailed => eprintln!("The step failed verification:\n{}", dev_run.output),
+        _ => eprintln!("The step failed:\n{}", dev_run.output),
+    }
+    Ok(watched)
+}
+
+/// The steps as a run would resolve them, for the shell it would use and the project's verify command.
+fn print_plan(mut mend: Mend, config_path: &Path, format: PlanFormat) -> anyhow::Result<()> {
+    if let Ok(shell) = shell_executor(&mend) {
+        use_shell_dialect(&mut mend, &shell);
+    }
+    if let Some(from) = mend.from.clone() {
+        fill_verify_command(&mut mend, &base_repo_dir(&from, config_path));
+    }
+    print!("{}", plan::render_plan(&mend, format)?);
+    Ok(())
+}
+
+/// What the command line asks of a run besides how its steps run.
+struct RunFlags {
+    record: bool,
+    no_commit: bool,
+    /// Index of the step `--from-step` or `--from-id` start at, or the id to look it up by
+    from_step: Option<StartStep>,
+    /// The steps `--only` and `--skip` leave in
+    selection: StepSelection,
+    output: ProgressOutput,
+    verbose: bool,
+    policy: Option<Policy>,
+}
+
+enum StartStep {
+    /// Counting from 1
+    Number(usize),
+    Id(String),
+}
+
+impl StartStep {
+    fn index(&self, planned_steps: &[(String, String)]) -> anyhow::Result<usize> {
+        match self {
+            StartStep::Number(number) => Ok(number - 1),
+            StartStep::Id(id) => planned_steps
+                .iter()
+                .position(|(step_id, _)| step_id == id)
+                .ok_or_else(|| anyhow!("No step with id `{}`", id)),
+        }
+    }
+}
+
+fn run_flags(cli: &Cli) -> anyhow::Result<RunFlags> {
+    Ok(RunFlags {
+        record: cli.record,
+        no_commit: cli.no_commit,
+        from_step: match (cli.from_step, &cli.from_id) {
+            (Some(0), _) => bail!("--from-step counts from 1"),
+            (Some(number), _) => Some(StartStep::Number(number)),
+            (None, Some(id)) => Some(StartStep::Id(id.clone())),
+            (None, None) => None,
+        },
+        selection: step_selection(cli),
+        output: progress_output(cli),
+        verbose: cli.verbose,
+        policy: load_policy(cli.policy.as_deref())?,
+    })
+}
+
+fn step_selection(cli: &Cli) -> StepSelection {
+    StepSelection {
+        only: cli.only.clone(),
+        skip: cli.skip.clone(),
+    }
+}
+
+fn progress_output(cli: &Cli) -> ProgressOutput {
+    let on_github = env::var("GITHUB_ACTIONS").is_ok_and(|value| value == "true");
+    if cli.tui {
+        ProgressOutput::Tui
+    } else if cli.ci == Some(CiSystem::Github) || (cli.output == ProgressOutput::Human && on_github) {
+        ProgressOutput::Github
+    } else {
+        cli.output
+    }
+}
+
+fn run_options(cli: &Cli) -> RunOptions {
+    RunOptions {
+        continue_on_error: cli.continue_on_error,
+        quarantine: cli.quarantine,
+        // One step at a time so each can be asked about
+        max_parallel_steps: match cli.interactive {
+            true => 1,
+            false => cli.jobs.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, usize::from)),
+        },
+        interactive: cli.interactive,
+        on_failure: cli.on_failure,
+        ..Default::default()
+    }
+}
+
 fn extend_mend(merged_mend: &mut Mend, include_mend: Mend) {
     merged_mend.env.extend(include_mend.env);
+    merged_mend.secrets.extend(include_mend.secrets);
+    merged_mend.matrix.extend(include_mend.matrix);
     merged_mend.from = include_mend.from;
     merged_mend.recipes.extend(include_mend.recipes);
     merged_mend.hooks.extend(include_mend.hooks);
+    merged_mend.verify = include_mend.verify.or(merged_mend.verify.take());
+    merged_mend.verify_baseline = include_mend.verify_baseline.or(merged_mend.verify_baseline.take());
+    merged_mend.gates = include_mend.gates.or(merged_mend.gates.take());
+    merged_mend.git = include_mend.git.or(merged_mend.git.take());
+    merged_mend.commit = include_mend.commit.or(merged_mend.commit.take());
+    merged_mend.committers.extend(include_mend.committers);
+    merged_mend.heartbeat = include_mend.heartbeat.or(merged_mend.heartbeat.take());
+    merged_mend.shell = include_mend.shell.or(merged_mend.shell.take());
+    merged_mend.telemetry = include_mend.telemetry.or(merged_mend.telemetry.take());
+    merged_mend.timeout = include_mend.timeout.or(merged_mend.timeout.take());
+    merged_mend.metrics = include_mend.metrics.or(merged_mend.metrics.take());
+    merged_mend.badge = include_mend.badge.or(merged_mend.badge.take());
+    merged_mend.notify = include_mend.notify.or(merged_mend.notify.take());
+    merged_mend.logs = include_mend.logs.or(merged_mend.logs.take());
+    merged_mend.artifacts = include_mend.artifacts.or(merged_mend.artifacts.take());
+    merged_mend.keep_going = include_mend.keep_going.or(merged_mend.keep_going.take());
+    merged_mend.forge = include_mend.forge.or(merged_mend.forge.take());
+    merged_mend.on_failure = include_mend.on_failure.or(merged_mend.on_failure.take());
+    merged_mend.phases.extend(include_mend.phases);
+    merged_mend.aliases.extend(include_mend.aliases);
+    merged_mend.output = include_mend.output.or(merged_mend.output.take());
+    merged_mend.publish = include_mend.publish.or(merged_mend.publish.take());
+    merged_mend.lint_rules.extend(include_mend.lint_rules);
+    merged_mend.corpus.extend(include_mend.corpus);
     for ele in include_mend.steps {
         merged_mend.steps.push(ele)
     }
@@ -202,7 +1692,7 @@ mod tests {
     #[test]
     fn cli_fails_loading_default_file() {
         // Change out of current dir in case we have a mend.toml there.
-        assert!(env::set_current_dir(&path_from_manifest("tests/data")).is_ok());
+        assert!(env::set_current_dir(path_from_manifest("tests/data")).is_ok());
         let result = run(&Cli::parse_from(vec!["mend", "--dry-run"]));
         assert!(result.is_err());
         insta::assert_snapshot!(format!("{:#}", result.err().unwrap()));
@@ -235,4 +1725,26 @@ mod tests {
         assert!(result.is_err());
         insta::assert_snapshot!(strip_manifest_path_from_text(format!("{:#}", result.err().unwrap())));
     }
+
+    #[test]
+    fn cli_fails_running_without_from() {
+        let result = run(&Cli::parse_from(vec![
+            "mend",
+            "-f",
+            path_from_manifest("tests/data/no-from.toml")
+                .to_str()
+                .unwrap(),
+        ]));
+        assert_eq!(format!("{:#}", result.err().unwrap()), "No from declared in config");
+    }
+
+    #[test]
+    fn verify_outside_steps_gets_the_steps_env() {
+        let mend = crate::config::parse_mend(std::path::Path::new("mend.toml"), "[env]\nJAVA_HOME = \"/opt/jdk\"\n").unwrap();
+        let mut options = run::RunOptions::default();
+        options.env.insert("REGISTRY_TOKEN".to_string(), "s3cret".to_string());
+        let env = crate::verify_env(&mend, &options);
+        assert_eq!(env["REGISTRY_TOKEN"], "s3cret");
+        assert_eq!(env["JAVA_HOME"], "/opt/jdk");
+    }
 }
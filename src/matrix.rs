@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+
+/// Step metadata holding how the step's `verify` fared in each `[matrix]` variant, e.g. `java=11: ok; java=17: failed`.
+pub const MATRIX_KEY: &str = "matrix";
+
+/// Every combination of the `[matrix]` values, e.g. `java = ["11", "17"]` and `os = ["linux"]` make
+/// `java=11 os=linux` and `java=17 os=linux`. None without a matrix, a variable without values is left out.
+pub fn variants(matrix: &BTreeMap<String, Vec<String>>) -> Vec<BTreeMap<String, String>> {
+    let mut variants: Vec<BTreeMap<String, String>> = vec![];
+    for (name, values) in matrix.iter().filter(|(_, values)| !values.is_empty()) {
+        let previous = if variants.is_empty() { vec![BTreeMap::new()] } else { variants };
+        variants = previous
+            .iter()
+            .flat_map(|variant| {
+                values.iter().map(move |value| {
+                    let mut variant = variant.clone();
+                    variant.insert(name.clone(), value.clone());
+                    variant
+                })
+            })
+            .collect();
+    }
+    variants
+}
+
+/// `java=11 os=linux`.
+pub fn variant_label(variant: &BTreeMap<String, String>) -> String {
+    variant.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<String>>().join(" ")
+}
+
+/// The `MATRIX_KEY` metadata for whether the step passed in each variant.
+pub fn format_results(results: &[(String, bool)]) -> String {
+    results
+        .iter()
+        .map(|(label, ok)| format!("{}: {}", label, if *ok { "ok" } else { "failed" }))
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
+/// The variant labels and results in `MATRIX_KEY` metadata.
+pub fn parse_results(metadata: &str) -> Vec<(String, String)> {
+    metadata
+        .split("; ")
+        .filter_map(|entry| entry.rsplit_once(": "))
+        .map(|(label, result)| (label.to_string(), result.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::matrix::{format_results, parse_results, variant_label, variants};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn variants_combine_every_value() {
+        let matrix = BTreeMap::from([
+            ("java".to_string(), vec!["11".to_string(), "17".to_string()]),
+            ("os".to_string(), vec!["linux".to_string(), "macos".to_string()]),
+            ("unused".to_string(), vec![]),
+        ]);
+        let labels: Vec<String> = variants(&matrix).iter().map(variant_label).collect();
+        assert_eq!(labels, vec!["java=11 os=linux", "java=11 os=macos", "java=17 os=linux", "java=17 os=macos"]);
+        assert!(variants(&BTreeMap::new()).is_empty());
+
+        let results = format_results(&[("java=11".to_string(), true), ("java=17".to_string(), false)]);
+        assert_eq!(results, "java=11: ok; java=17: failed");
+        assert_eq!(
+            parse_results(&results),
+            vec![("java=11".to_string(), "ok".to_string()), ("java=17".to_string(), "failed".to_string())]
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::gates::DiffStats;
+use crate::run::{run_command_with_output, EStatus};
+use crate::state::RunState;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct MetricsConfig {
+    /// Written when the run ends, e.g. into node_exporter's textfile collector directory
+    pub textfile: Option<String>,
+    /// Pushgateway the metrics are pushed to when the run ends, e.g. `http://localhost:9091`
+    pub pushgateway: Option<String>,
+    /// Job the metrics are grouped under on the pushgateway, `mend` by default
+    pub job: Option<String>,
+}
+
+/// Quotes a label value the way the exposition format expects.
+fn label(value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+fn metric(text: &mut String, name: &str, help: &str, samples: &[(String, String)]) {
+    let _ = writeln!(text, "# HELP {} {}", name, help);
+    let _ = writeln!(text, "# TYPE {} gauge", name);
+    for (labels, value) in samples {
+        let _ = writeln!(text, "{}{{{}}} {}", name, labels, value);
+    }
+}
+
+/// The finished run in Prometheus' text exposition format, every sample labelled with the config.
+pub fn render_metrics(state: &RunState, diff: &DiffStats, duration: Duration, finished_at: u64) -> String {
+    let config = format!("config={}", label(&state.config));
+    let count = |status: EStatus| state.steps.iter().filter(|step| step.status == status).count();
+    let failed = count(EStatus::Failed) + count(EStatus::VerifyFailed);
+    let succeeded = failed == 0 && count(EStatus::Pending) == 0;
+    let mut text = String::new();
+    metric(
+        &mut text,
+        "mend_run_steps",
+        "Steps of the run by status, pending ones didn't run",
+        &[EStatus::Done, EStatus::Failed, EStatus::VerifyFailed, EStatus::Skipped, EStatus::Pending]
+            .map(|status| {
+                let status_label = if status == EStatus::VerifyFailed { "verify_failed".to_string() } else { format!("{:?}", status).to_lowercase() };
+                (format!("{},status={}", config, label(&status_label)), count(status).to_string())
+            }),
+    );
+    metric(
+        &mut text,
+        "mend_run_success",
+        "1 when every step of the run succeeded or was skipped",
+        &[(config.clone(), (succeeded as u8).to_string())],
+    );
+    metric(
+        &mut text,
+        "mend_run_duration_seconds",
+        "How long the run took",
+        &[(config.clone(), format!("{:.3}", duration.as_secs_f64()))],
+    );
+    metric(
+        &mut text,
+        "mend_run_finished_timestamp_seconds",
+        "When the run ended, in seconds since the Unix epoch",
+        &[(config.clone(), finished_at.to_string())],
+    );
+    metric(
+        &mut text,
+        "mend_run_changed_lines",
+        "Added plus removed lines between the run's start and its last commit",
+        &[(config.clone(), diff.changed_lines.to_string())],
+    );
+    metric(
+        &mut text,
+        "mend_run_changed_files",
+        "Files changed between the run's start and its last commit",
+        &[(config.clone(), diff.files.len().to_string())],
+    );
+    let step_durations: Vec<(String, String)> = state
+        .steps
+        .iter()
+        .enumerate()
+        .filter_map(|(step_i, step)| {
+            let labels = format!("{},step=\"{}\",id={}", config, step_i + 1, label(&step.id));
+            Some((labels, format!("{:.3}", step.duration_ms? as f64 / 1000.0)))
+        })
+        .collect();
+    metric(
+        &mut text,
+        "mend_step_duration_seconds",
+        "How long each step that finished took",
+        &step_durations,
+    );
+    text
+}
+
+/// Replaces the file in one go, so the textfile collector never reads half of it.
+fn write_textfile(path: &Path, text: &str) -> anyhow::Result<()> {
+    let temp_path = path.with_extension("prom.tmp");
+    fs::write(&temp_path, text).with_context(|| format!("Could not write `{}`", temp_path.to_string_lossy()))?;
+    fs::rename(&temp_path, path).with_context(|| format!("Could not write `{}`", path.to_string_lossy()))
+}
+
+fn push(url: &str, job: &str, text: &str) -> anyhow::Result<()> {
+    let url = format!("{}/metrics/job/{}", url.trim_end_matches('/'), job);
+    let body_path = env::temp_dir().join(format!("mend-metrics-{}.prom", std::process::id()));
+    fs::write(&body_path, text).with_context(|| format!("Could not write `{}`", body_path.to_string_lossy()))?;
+    let data_arg = format!("@{}", body_path.to_string_lossy());
+    // PUT replaces what the last run pushed, including steps that no longer exist
+    let args = vec!["-fsS", "-X", "PUT", "--data-binary", &data_arg, &url];
+    let output = run_command_with_output(Path::new("."), "curl".to_string(), args).context("Pushing metrics needs curl");
+    let _ = fs::remove_file(&body_path);
+    let output = output?;
+    if !output.status.success() {
+        bail!("{} answered: {}", url, String::from_utf8_lossy(&output.stderr));
+    }
+    Ok(())
+}
+
+/// Writes and pushes the metrics where configured. Failing to doesn't fail the run.
+pub fn publish_metrics(config: &MetricsConfig, text: &str) {
+    if let Some(textfile) = &config.textfile {
+        if let Err(err) = write_textfile(Path::new(textfile), text) {
+            eprintln!("Could not write the run's metrics: {:#}", err);
+        }
+    }
+    if let Some(pushgateway) = &config.pushgateway {
+        if let Err(err) = push(pushgateway, config.job.as_deref().unwrap_or("mend"), text) {
+            eprintln!("Could not push the run's metrics: {:#}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gates::DiffStats;
+    use crate::metrics::{publish_metrics, render_metrics, MetricsConfig};
+    use crate::run::EStatus;
+    use crate::state::RunState;
+    use std::fs;
+    use std::time::Duration;
+
+    #[test]
+    fn metrics_cover_steps_durations_and_diff() {
+        let planned_steps = vec![
+            ("1".to_string(), "rename a b".to_string()),
+            ("lint".to_string(), "lint".to_string()),
+            ("3".to_string(), "rename e f".to_string()),
+        ];
+        let mut state = RunState::new("configs/\"nightly\".toml", "base", &planned_steps);
+        state.steps[0].status = EStatus::Done;
+        state.steps[0].duration_ms = Some(1500);
+        state.steps[1].status = EStatus::Skipped;
+        state.steps[2].status = EStatus::Failed;
+        state.steps[2].duration_ms = Some(20);
+        let diff = DiffStats::from_numstat("3\t1\tsrc/main.c\n-\t-\tlogo.png\n");
+        let text = render_metrics(&state, &diff, Duration::from_millis(2250), 1700000000);
+        insta::assert_snapshot!(text);
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let textfile = temp_dir.path().join("mend.prom");
+        let config = MetricsConfig {
+            textfile: Some(textfile.to_string_lossy().to_string()),
+            pushgateway: None,
+            job: None,
+        };
+        publish_metrics(&config, &text);
+        assert_eq!(fs::read_to_string(&textfile).unwrap(), text);
+        assert!(!temp_dir.path().join("mend.prom.tmp").exists());
+    }
+}
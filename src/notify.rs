@@ -0,0 +1,505 @@
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::progress::Notify;
+use crate::run::{EStatus, RunSummary, StepRequest, StepResponse};
+use crate::secrets::without_identity;
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct NotifyConfig {
+    /// A command told about every step and the end of the run
+    pub exec: Option<ExecNotifyConfig>,
+    /// A URL sent the start of the run, each finished step, failures and the end of the run
+    pub webhook: Option<WebhookNotifyConfig>,
+    /// A Slack channel told when the run is done or a step failed
+    pub slack: Option<SlackNotifyConfig>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct ExecNotifyConfig {
+    /// Program and its leading arguments, e.g. `./scripts/notify.sh`, a relative path is found from the config's directory.
+    /// Started once per event with the event as JSON on stdin
+    pub command: String,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct WebhookNotifyConfig {
+    /// Each event is POSTed here as JSON
+    pub url: String,
+    /// Sent with every request, `$VAR` in a value is taken from the environment, e.g. `Authorization = "Bearer $CHAT_TOKEN"`
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct SlackNotifyConfig {
+    /// The incoming webhook's URL, `$VAR` is taken from the environment so it needn't be committed,
+    /// e.g. `$SLACK_WEBHOOK_URL`
+    pub webhook_url: String,
+    /// Posts somewhere else than the webhook's own channel, where Slack allows it
+    pub channel: Option<String>,
+    /// The message, `$summary` by default. Also knows `$status`, `done` or `failed`, `$step`, the failed step's id,
+    /// and `$sha`, the run's latest commit
+    pub template: Option<String>,
+    /// How many of the failed step's last output lines are attached, 10 by default
+    pub output_lines: Option<usize>,
+}
+
+const DEFAULT_SLACK_OUTPUT_LINES: usize = 10;
+
+/// How long a webhook gets to answer, a slow one shouldn't hold up the run.
+const WEBHOOK_TIMEOUT_SECS: &str = "10";
+
+/// Passes everything on to `inner` and runs a command with each event, for systems mend doesn't know.
+pub struct ExecNotifier<N: Notify> {
+    inner: N,
+    command: Option<Vec<String>>,
+    dir: PathBuf,
+}
+
+impl<N: Notify> ExecNotifier<N> {
+    /// Without a command `inner` is used as it is. `dir` is where the command runs.
+    pub fn new(inner: N, config: Option<&ExecNotifyConfig>, dir: &Path) -> Self {
+        let command = config.map(|config| config.command.split_whitespace().map(str::to_string).collect());
+        ExecNotifier {
+            inner,
+            command,
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    fn send(&self, event: Value) {
+        let Some(command) = &self.command else {
+            return;
+        };
+        if let Err(err) = run_notify_command(&self.dir, command, &event) {
+            eprintln!("Notify command failed: {:#}", err);
+        }
+    }
+}
+
+fn run_notify_command(dir: &Path, command: &[String], event: &Value) -> anyhow::Result<()> {
+    let Some((program, args)) = command.split_first() else {
+        bail!("`[notify.exec] command` is empty");
+    };
+    // Scripts next to the config are found whatever directory mend runs in
+    let program = if program.contains('/') || program.contains('\\') {
+        dir.join(program).to_string_lossy().to_string()
+    } else {
+        program.clone()
+    };
+    let mut child = without_identity(Command::new(&program).current_dir(dir).args(args))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Could not start `{}`", program))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A command that doesn't read its input still ran
+        let _ = stdin.write_all(format!("{}\n", event).as_bytes());
+    }
+    let output = child.wait_with_output().with_context(|| format!("Could not run `{}`", program))?;
+    if !output.status.success() {
+        bail!("`{}` exited with {}: {}", program, output.status, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+impl<N: Notify> Notify for ExecNotifier<N> {
+    fn notify(&mut self, i: usize, run: &str, status: &EStatus, sha: &Option<String>, inc: bool) {
+        self.send(json!({
+            "event": "step",
+            "step": i + 1,
+            "run": run,
+            "status": status,
+            "sha": sha,
+        }));
+        self.inner.notify(i, run, status, sha, inc)
+    }
+
+    fn notify_done(&self, summary: &RunSummary) {
+        self.send(json!({
+            "event": "done",
+            "failed_steps": summary.failed_steps.iter().map(|step_i| step_i + 1).collect::<Vec<usize>>(),
+            "skipped_steps": summary.skipped_steps.iter().map(|step_i| step_i + 1).collect::<Vec<usize>>(),
+            "commits": summary.commits,
+            "totals": summary.totals,
+        }));
+        self.inner.notify_done(summary)
+    }
+
+    fn notify_output(&mut self, i: usize, line: &str) {
+        self.inner.notify_output(i, line)
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        self.inner.suspend(f)
+    }
+
+    fn notify_failure(&self, failed_request: &StepRequest, failed_response: &StepResponse) {
+        self.send(json!({
+            "event": "failure",
+            "id": failed_request.id,
+            "run": failed_request.run,
+            "output": failed_response.output,
+        }));
+        self.inner.notify_failure(failed_request, failed_response)
+    }
+}
+
+/// Passes everything on to `inner` and POSTs the run's milestones to a webhook, e.g. a chat channel or dashboard.
+/// Every event has a `text` line too, which chat webhooks show as the message.
+pub struct WebhookNotifier<N: Notify> {
+    inner: N,
+    config: Option<WebhookNotifyConfig>,
+    total_steps: usize,
+    started: bool,
+    /// How each step finished, so it's posted once however often the run reports it
+    finished: HashMap<usize, EStatus>,
+}
+
+impl<N: Notify> WebhookNotifier<N> {
+    /// Without a config `inner` is used as it is.
+    pub fn new(inner: N, config: Option<&WebhookNotifyConfig>, total_steps: usize) -> Self {
+        WebhookNotifier {
+            inner,
+            config: config.cloned(),
+            total_steps,
+            started: false,
+            finished: HashMap::new(),
+        }
+    }
+
+    fn post(&self, event: Value) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        if let Err(err) = post_json(&config.url, &config.headers, &event) {
+            eprintln!("Webhook {} failed: {:#}", config.url, err);
+        }
+    }
+}
+
+fn post_json(url: &str, extra_headers: &BTreeMap<String, String>, event: &Value) -> anyhow::Result<()> {
+    let mut args = vec!["-fsS", "-o", "/dev/null", "--max-time", WEBHOOK_TIMEOUT_SECS, "-X", "POST"];
+    let mut headers = vec!["Content-Type: application/json".to_string()];
+    for (name, value) in extra_headers {
+        let value = shellexpand::env(value).map(|expanded| expanded.to_string()).unwrap_or_else(|_| value.clone());
+        headers.push(format!("{}: {}", name, value));
+    }
+    for header in &headers {
+        args.extend(["-H", header.as_str()]);
+    }
+    args.extend(["--data-binary", "@-", url]);
+    let mut child = Command::new("curl")
+        .args(&args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Posting to a webhook needs curl")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(event.to_string().as_bytes());
+    }
+    let output = child.wait_with_output().context("Could not run curl")?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+impl<N: Notify> Notify for WebhookNotifier<N> {
+    fn notify(&mut self, i: usize, run: &str, status: &EStatus, sha: &Option<String>, inc: bool) {
+        if !self.started {
+            self.started = true;
+            self.post(json!({
+                "event": "run_started",
+                "steps": self.total_steps,
+                "text": format!("mend started a run of {} steps", self.total_steps),
+            }));
+        }
+        if matches!(status, EStatus::Done | EStatus::Failed | EStatus::VerifyFailed | EStatus::Skipped) && self.finished.get(&i) != Some(status) {
+            self.finished.insert(i, *status);
+            let label = match status {
+                EStatus::Done => "done",
+                EStatus::Failed => "failed",
+                EStatus::VerifyFailed => "failed verification",
+                _ => "skipped",
+            };
+            self.post(json!({
+                "event": "step_finished",
+                "step": i + 1,
+                "run": run,
+                "status": status,
+                "sha": sha,
+                "text": format!("Step {} of {} {}: {}", i + 1, self.total_steps, label, run),
+            }));
+        }
+        self.inner.notify(i, run, status, sha, inc)
+    }
+
+    fn notify_done(&self, summary: &RunSummary) {
+        let text = if summary.failed_steps.is_empty() {
+            format!("mend run done, {} commits", summary.commits.len())
+        } else {
+            format!("mend run done, {} of {} steps failed", summary.failed_steps.len(), self.total_steps)
+        };
+        self.post(json!({
+            "event": "done",
+            "failed_steps": summary.failed_steps.iter().map(|step_i| step_i + 1).collect::<Vec<usize>>(),
+            "skipped_steps": summary.skipped_steps.iter().map(|step_i| step_i + 1).collect::<Vec<usize>>(),
+            "commits": summary.commits,
+            "totals": summary.totals,
+            "text": text,
+        }));
+        self.inner.notify_done(summary)
+    }
+
+    fn notify_output(&mut self, i: usize, line: &str) {
+        self.inner.notify_output(i, line)
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        self.inner.suspend(f)
+    }
+
+    fn notify_failure(&self, failed_request: &StepRequest, failed_response: &StepResponse) {
+        self.post(json!({
+            "event": "failure",
+            "id": failed_request.id,
+            "run": failed_request.run,
+            "output": failed_response.output,
+            "text": format!("mend step {} failed: {}", failed_request.id, failed_request.run),
+        }));
+        self.inner.notify_failure(failed_request, failed_response)
+    }
+}
+
+/// Passes everything on to `inner` and posts to Slack when the run is done or a step failed.
+pub struct SlackNotifier<N: Notify> {
+    inner: N,
+    config: Option<SlackNotifyConfig>,
+    /// The run's latest commit, as the steps reported them
+    last_sha: Option<String>,
+}
+
+impl<N: Notify> SlackNotifier<N> {
+    /// Without a config `inner` is used as it is.
+    pub fn new(inner: N, config: Option<&SlackNotifyConfig>) -> Self {
+        SlackNotifier {
+            inner,
+            config: config.cloned(),
+            last_sha: None,
+        }
+    }
+
+    /// Renders the template with `vars` and sends it, `output` attached below as a code block.
+    fn post(&self, vars: &[(&str, String)], output: Option<&str>) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        let template = config.template.as_deref().unwrap_or("$summary");
+        let mut text = shellexpand::env_with_context_no_errors(template, |name: &str| {
+            vars.iter().find(|(var, _)| *var == name).map(|(_, value)| value.clone())
+        })
+        .to_string();
+        if let Some(output) = output {
+            let lines: Vec<&str> = output.lines().collect();
+            let shown = &lines[lines.len().saturating_sub(config.output_lines.unwrap_or(DEFAULT_SLACK_OUTPUT_LINES))..];
+            if !shown.is_empty() {
+                text.push_str(&format!("\n```\n{}\n```", shown.join("\n")));
+            }
+        }
+        let mut message = json!({"text": text});
+        if let Some(channel) = &config.channel {
+            message["channel"] = json!(channel);
+        }
+        let url = shellexpand::env(&config.webhook_url).map(|url| url.to_string()).unwrap_or_else(|_| config.webhook_url.clone());
+        if let Err(err) = post_json(&url, &BTreeMap::new(), &message) {
+            eprintln!("Posting to Slack failed: {:#}", err);
+        }
+    }
+
+    fn sha(&self) -> String {
+        self.last_sha.clone().unwrap_or_else(|| "-".to_string())
+    }
+}
+
+impl<N: Notify> Notify for SlackNotifier<N> {
+    fn notify(&mut self, i: usize, run: &str, status: &EStatus, sha: &Option<String>, inc: bool) {
+        if let (EStatus::Done, Some(sha)) = (status, sha) {
+            self.last_sha = Some(sha.clone());
+        }
+        self.inner.notify(i, run, status, sha, inc)
+    }
+
+    fn notify_done(&self, summary: &RunSummary) {
+        let sha = summary.commits.last().map_or_else(|| self.sha(), |commit| commit.sha.clone());
+        let (status, result) = if summary.failed_steps.is_empty() {
+            ("done", format!("{} steps committed", summary.commits.len()))
+        } else {
+            ("failed", format!("{} steps failed", summary.failed_steps.len()))
+        };
+        let vars = [
+            ("summary", format!("mend run done, {}, at {}", result, sha)),
+            ("status", status.to_string()),
+            ("step", String::new()),
+            ("sha", sha),
+        ];
+        self.post(&vars, None);
+        self.inner.notify_done(summary)
+    }
+
+    fn notify_output(&mut self, i: usize, line: &str) {
+        self.inner.notify_output(i, line)
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        self.inner.suspend(f)
+    }
+
+    fn notify_failure(&self, failed_request: &StepRequest, failed_response: &StepResponse) {
+        let vars = [
+            (
+                "summary",
+                format!("mend run failed at step [{}] {}, last commit {}", failed_request.id, failed_request.run, self.sha()),
+            ),
+            ("status", "failed".to_string()),
+            ("step", failed_request.id.clone()),
+            ("sha", self.sha()),
+        ];
+        self.post(&vars, failed_response.output.as_deref());
+        self.inner.notify_failure(failed_request, failed_response)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::notify::{ExecNotifier, ExecNotifyConfig, SlackNotifier, SlackNotifyConfig, WebhookNotifier, WebhookNotifyConfig};
+    use crate::progress::Notify;
+    use crate::run::{EStatus, RunSummary, StepCommit, StepRequest, StepResponse};
+    use crate::test_support::SilentNotifier;
+    use std::fs;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::os::unix::fs::PermissionsExt;
+    use std::thread;
+
+    #[test]
+    fn notify_command_gets_each_event_on_stdin() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::create_dir(temp_dir.path().join("scripts")).unwrap();
+        let script_path = temp_dir.path().join("scripts/notify.sh");
+        fs::write(&script_path, "#!/bin/sh\ncat >> events.jsonl\n").unwrap();
+        fs::set_permissions(&script_path, fs::Permissions::from_mode(0o755)).unwrap();
+        let config = ExecNotifyConfig {
+            command: "./scripts/notify.sh".to_string(),
+        };
+        let mut notifier = ExecNotifier::new(SilentNotifier, Some(&config), temp_dir.path());
+        notifier.notify(0, "rename a b", &EStatus::Running, &None, false);
+        notifier.notify(0, "rename a b", &EStatus::Done, &Some("abc1234".to_string()), true);
+        let failed_request = StepRequest {
+            id: "lint".to_string(),
+            run: "lint".to_string(),
+            ..Default::default()
+        };
+        let mut failed_response = StepResponse::pending();
+        failed_response.output = Some("lint: 2 problems".to_string());
+        notifier.notify_failure(&failed_request, &failed_response);
+        notifier.notify_done(&RunSummary {
+            failed_steps: vec![1],
+            commits: vec![StepCommit {
+                id: "1".to_string(),
+                step: 1,
+                sha: "abc1234".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        insta::assert_snapshot!(fs::read_to_string(temp_dir.path().join("events.jsonl")).unwrap());
+
+        let failing = ExecNotifyConfig {
+            command: "false".to_string(),
+        };
+        // Only reported, the run goes on
+        ExecNotifier::new(SilentNotifier, Some(&failing), temp_dir.path()).notify(0, "x", &EStatus::Running, &None, false);
+    }
+
+    /// Answers `requests` POSTs with 200, returning each one's headers and body.
+    fn serve(listener: TcpListener, requests: usize) -> thread::JoinHandle<Vec<(String, String)>> {
+        thread::spawn(move || {
+            let mut received = vec![];
+            for stream in listener.incoming().take(requests) {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut headers = String::new();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    headers.push_str(&line);
+                    line.clear();
+                }
+                let length = headers
+                    .lines()
+                    .find_map(|header| header.to_lowercase().strip_prefix("content-length: ").map(|length| length.parse().unwrap()))
+                    .unwrap_or(0);
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                reader.get_mut().write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").unwrap();
+                received.push((headers, String::from_utf8(body).unwrap()));
+            }
+            received
+        })
+    }
+
+    #[test]
+    fn webhook_gets_the_run_milestones() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = WebhookNotifyConfig {
+            url: format!("http://{}/hooks/mend", listener.local_addr().unwrap()),
+            headers: [("X-Token".to_string(), "t0k3n".to_string())].into(),
+        };
+        let server = serve(listener, 4);
+        let mut notifier = WebhookNotifier::new(SilentNotifier, Some(&config), 2);
+        notifier.notify(0, "rename a b", &EStatus::Running, &None, false);
+        notifier.notify(0, "rename a b", &EStatus::Done, &Some("abc1234".to_string()), true);
+        // Reported again once the steps after it ran, posted once
+        notifier.notify(0, "rename a b", &EStatus::Done, &Some("abc1234".to_string()), false);
+        notifier.notify(1, "lint", &EStatus::Failed, &None, false);
+        notifier.notify_done(&RunSummary {
+            failed_steps: vec![1],
+            ..Default::default()
+        });
+        let received = server.join().unwrap();
+        assert!(received.iter().all(|(headers, _)| headers.starts_with("POST /hooks/mend ") && headers.contains("X-Token: t0k3n")));
+        let bodies: Vec<String> = received.into_iter().map(|(_, body)| body).collect();
+        insta::assert_snapshot!(bodies.join("\n"));
+    }
+
+    #[test]
+    fn slack_is_told_about_failures_with_their_last_lines() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = SlackNotifyConfig {
+            webhook_url: format!("http://{}/services/T0/B0/x", listener.local_addr().unwrap()),
+            channel: Some("#migrations".to_string()),
+            output_lines: Some(2),
+            ..Default::default()
+        };
+        let server = serve(listener, 1);
+        let mut notifier = SlackNotifier::new(SilentNotifier, Some(&config));
+        notifier.notify(0, "rename a b", &EStatus::Done, &Some("abc1234".to_string()), true);
+        let failed_request = StepRequest {
+            id: "lint".to_string(),
+            run: "lint src".to_string(),
+            ..Default::default()
+        };
+        let mut failed_response = StepResponse::pending();
+        failed_response.output = Some("Running\nlint src\nsrc/a.c: unused x\nsrc/b.c: unused y".to_string());
+        notifier.notify_failure(&failed_request, &failed_response);
+        let received = server.join().unwrap();
+        insta::assert_snapshot!(received[0].1);
+    }
+}
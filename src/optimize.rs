@@ -0,0 +1,313 @@
+use anyhow::{anyhow, bail, Context};
+use clap::Args;
+use indicatif::FormattedDuration;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use toml_edit::{Document, Item};
+
+use crate::config::{is_yaml, load_mend, STDIN_CONFIG};
+use crate::repo::MEND_DIR;
+use crate::run::{create_run_status_from_mend, StepRequest};
+use crate::select::StepSelection;
+use crate::state::read_state;
+use crate::Step;
+
+/// How many of the slowest steps without `needs` are named as worth declaring what they need.
+const SLOW_STEPS_SHOWN: usize = 3;
+
+#[derive(Args, Debug)]
+pub struct OptimizeArgs {
+    /// Write the suggested order to the config's `steps`
+    #[arg(long = "apply")]
+    pub apply: bool,
+}
+
+/// A step only waits for the steps before it when it has no `needs`, or is a fixup.
+fn runs_alone(step: &StepRequest) -> bool {
+    step.needs.is_none() || step.fixup.is_some()
+}
+
+/// The batches the steps run in when run in `order`, as `take_parallel_steps` makes them: a step with `needs` joins
+/// the batch before it unless it needs one of its steps or shares a lock with them.
+pub fn batches(steps: &[StepRequest], order: &[usize], max_parallel: usize) -> Vec<Vec<usize>> {
+    let mut batches: Vec<Vec<usize>> = vec![];
+    for &step_i in order {
+        let step = &steps[step_i];
+        let joins = batches.last().is_some_and(|batch| {
+            let in_batch = |id: &String| batch.iter().any(|&other| steps[other].id == *id);
+            let locked = |lock: &String| batch.iter().any(|&other| steps[other].locks.contains(lock));
+            batch.len() < max_parallel
+                && steps[batch[0]].fixup.is_none()
+                && !runs_alone(step)
+                && !step.needs.iter().flatten().any(in_batch)
+                && !step.locks.iter().any(locked)
+        });
+        match batches.last_mut() {
+            Some(batch) if joins => batch.push(step_i),
+            _ => batches.push(vec![step_i]),
+        }
+    }
+    batches
+}
+
+/// How long the batches take one after another, each as long as its slowest step. Steps without a timing count as
+/// taking no time.
+pub fn estimate(batches: &[Vec<usize>], durations: &[Option<Duration>]) -> Duration {
+    batches
+        .iter()
+        .map(|batch| batch.iter().filter_map(|&step_i| durations[step_i]).max().unwrap_or_default())
+        .sum()
+}
+
+/// An order of the steps in which those that don't need each other come together, slowest first, so they share
+/// batches. Steps only move among the steps with `needs` after the same step that runs alone, and stay after their
+/// needs. `phase_starts` are the first steps of the phases, which stay where they are.
+pub fn suggest_order(steps: &[StepRequest], durations: &[Option<Duration>], phase_starts: &[usize]) -> Vec<usize> {
+    let mut segments: Vec<Vec<usize>> = vec![];
+    for (step_i, step) in steps.iter().enumerate() {
+        match segments.last_mut() {
+            Some(segment) if !runs_alone(step) && !phase_starts.contains(&step_i) => segment.push(step_i),
+            _ => segments.push(vec![step_i]),
+        }
+    }
+    let mut order = vec![];
+    for mut segment in segments {
+        // How many steps of the segment have to be done before the step can start, one after another
+        let mut depths: BTreeMap<usize, usize> = BTreeMap::new();
+        for &step_i in &segment {
+            let depth = steps[step_i]
+                .needs
+                .iter()
+                .flatten()
+                .filter_map(|need| segment.iter().find(|&&other| other != step_i && steps[other].id == *need))
+                .filter_map(|other| depths.get(other))
+                .map(|depth| depth + 1)
+                .max()
+                .unwrap_or_default();
+            depths.insert(step_i, depth);
+        }
+        let first = segment[0];
+        segment.sort_by_key(|step_i| (depths[step_i], *step_i != first, Reverse(durations[*step_i])));
+        order.extend(segment);
+    }
+    order
+}
+
+fn describe_batch(steps: &[StepRequest], batch: &[usize], durations: &[Option<Duration>]) -> String {
+    batch
+        .iter()
+        .map(|&step_i| match durations[step_i] {
+            Some(duration) => format!("`{}` {}", steps[step_i].id, FormattedDuration(duration)),
+            None => format!("`{}`", steps[step_i].id),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Writes the steps of `path` in `order`, keeping the formatting of each.
+pub fn reorder_steps_file(path: &Path, order: &[usize]) -> anyhow::Result<()> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Could not read file `{}`", path.to_string_lossy()))?;
+    let mut document: Document = contents
+        .parse()
+        .with_context(|| format!("Unable to parse `{}`", path.to_string_lossy()))?;
+    match document.get_mut("steps") {
+        Some(Item::Value(toml_edit::Value::Array(steps))) if steps.len() == order.len() => {
+            let original: Vec<toml_edit::Value> = steps.iter().cloned().collect();
+            // A step on a line of its own moves with the comments above it, one of a single line array
+            // takes the spacing of its new place
+            let multiline = original
+                .iter()
+                .any(|step| step.decor().prefix().and_then(|prefix| prefix.as_str()).is_some_and(|prefix| prefix.contains('\n')));
+            for (position, &step_i) in order.iter().enumerate() {
+                let mut moved = original[step_i].clone();
+                if !multiline {
+                    *moved.decor_mut() = original[position].decor().clone();
+                }
+                *steps.get_mut(position).unwrap() = moved;
+            }
+        }
+        Some(Item::ArrayOfTables(steps)) if steps.len() == order.len() => {
+            let original: Vec<toml_edit::Table> = steps.iter().cloned().collect();
+            for (position, &step_i) in order.iter().enumerate() {
+                // Tables take the spacing and place in the file of the one they replace
+                let mut moved = original[step_i].clone();
+                *moved.decor_mut() = original[position].decor().clone();
+                if let Some(place) = original[position].position() {
+                    moved.set_position(place);
+                }
+                *steps.get_mut(position).unwrap() = moved;
+            }
+        }
+        _ => bail!("`{}` doesn't list the steps itself, reorder them by hand", path.to_string_lossy()),
+    }
+    fs::write(path, document.to_string()).with_context(|| format!("Could not write `{}`", path.to_string_lossy()))
+}
+
+pub fn run_optimize(config_path: &Path, max_parallel: usize, apply: bool) -> anyhow::Result<()> {
+    let mend = load_mend(config_path)?;
+    let from = mend.from.as_ref().ok_or_else(|| anyhow!("No from declared in config"))?;
+    let steps = create_run_status_from_mend(&mend, &StepSelection::default());
+    // Timings are those of the last run, matched by the steps' ids
+    let timings: BTreeMap<String, u64> = read_state(&crate::base_repo_dir(from, config_path).join(MEND_DIR))
+        .map(|state| state.steps.into_iter().filter_map(|step| Some((step.id, step.duration_ms?))).collect())
+        .unwrap_or_default();
+    let durations: Vec<Option<Duration>> =
+        steps.iter().map(|step| timings.get(&step.id).copied().map(Duration::from_millis)).collect();
+    if timings.is_empty() {
+        println!("No timings of an earlier run, the steps are ordered by their needs only");
+    }
+
+    let phase_starts: Vec<usize> = mend.phases.iter().map(|phase| phase.first_step).collect();
+    let current: Vec<usize> = (0..steps.len()).collect();
+    let suggested = suggest_order(&steps, &durations, &phase_starts);
+    let current_batches = batches(&steps, &current, max_parallel);
+    let suggested_batches = batches(&steps, &suggested, max_parallel);
+    let current_time = estimate(&current_batches, &durations);
+    let suggested_time = estimate(&suggested_batches, &durations);
+    if suggested_batches.len() >= current_batches.len() && suggested_time >= current_time {
+        println!(
+            "The steps are in a good order for `-j {}` already, {} batches taking about {}",
+            max_parallel,
+            current_batches.len(),
+            FormattedDuration(current_time)
+        );
+    } else {
+        println!(
+            "With `-j {}`, {} batches taking about {} instead of {} batches taking about {}:",
+            max_parallel,
+            suggested_batches.len(),
+            FormattedDuration(suggested_time),
+            current_batches.len(),
+            FormattedDuration(current_time)
+        );
+        for (batch_i, batch) in suggested_batches.iter().enumerate() {
+            println!("  {}. {}", batch_i + 1, describe_batch(&steps, batch, &durations));
+        }
+    }
+    let mut slow_alone: Vec<usize> = (0..steps.len())
+        .filter(|&step_i| step_i > 0 && steps[step_i].needs.is_none() && durations[step_i] >= Some(Duration::from_secs(1)))
+        .collect();
+    slow_alone.sort_by_key(|&step_i| Reverse(durations[step_i]));
+    slow_alone.truncate(SLOW_STEPS_SHOWN);
+    if max_parallel > 1 && !slow_alone.is_empty() {
+        println!(
+            "Steps without `needs` wait for every step before them, declaring what these need lets them run alongside others: {}",
+            describe_batch(&steps, &slow_alone, &durations)
+        );
+    }
+
+    if !apply || suggested == current {
+        return Ok(());
+    }
+    if config_path == Path::new(STDIN_CONFIG) || is_yaml(config_path) {
+        bail!("Only a TOML config file is reordered in place, move the steps as shown by hand");
+    }
+    if !mend.phases.is_empty() || mend.steps.len() != steps.len() {
+        bail!("The steps come from phases or `@file` arguments, move them as shown by hand");
+    }
+    // A step without an id is named by its number, which moving it would change
+    if let Some(step_i) = suggested
+        .iter()
+        .enumerate()
+        .find(|(position, step_i)| position != *step_i && !matches!(&mend.steps[**step_i], Step::Structured(step) if step.id.is_some()))
+        .map(|(_, step_i)| *step_i)
+    {
+        bail!("Step {} would move, give it an `id` first so it keeps its name", step_i + 1);
+    }
+    reorder_steps_file(config_path, &suggested)?;
+    println!("Reordered the steps of {}", config_path.to_string_lossy());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::optimize::{batches, estimate, reorder_steps_file, suggest_order};
+    use crate::run::StepRequest;
+    use std::fs;
+    use std::time::Duration;
+
+    fn step(id: &str, needs: Option<&[&str]>) -> StepRequest {
+        StepRequest {
+            id: id.to_string(),
+            needs: needs.map(|needs| needs.iter().map(|need| need.to_string()).collect()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn independent_steps_are_brought_together_slowest_first() {
+        let steps = vec![
+            step("setup", None),
+            step("format", Some(&["setup"])),
+            step("lint", Some(&["format"])),
+            step("rename", Some(&[])),
+            step("docs", Some(&[])),
+            step("release", None),
+        ];
+        let durations = [10, 20, 5, 60, 30, 1].map(|secs| Some(Duration::from_secs(secs)));
+        let current: Vec<usize> = (0..steps.len()).collect();
+        // `lint` needs `format`, which keeps `rename` and `docs` from joining the batch of `setup`
+        assert_eq!(batches(&steps, &current, 4), vec![vec![0], vec![1], vec![2, 3, 4], vec![5]]);
+        assert_eq!(estimate(&batches(&steps, &current, 4), &durations), Duration::from_secs(91));
+
+        let suggested = suggest_order(&steps, &durations, &[]);
+        assert_eq!(suggested, vec![0, 3, 4, 1, 2, 5]);
+        assert_eq!(batches(&steps, &suggested, 4), vec![vec![0, 3, 4], vec![1], vec![2], vec![5]]);
+        assert_eq!(estimate(&batches(&steps, &suggested, 4), &durations), Duration::from_secs(86));
+        // Two jobs take the two slowest together
+        assert_eq!(batches(&steps, &suggested, 2), vec![vec![0, 3], vec![4, 1], vec![2], vec![5]]);
+
+        // Nothing moves into another phase
+        assert_eq!(suggest_order(&steps, &durations, &[3]), current);
+    }
+
+    #[test]
+    fn steps_are_reordered_in_place() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("mend.toml");
+        fs::write(
+            &path,
+            r#"from = { repo = ".", sha = "abc" }
+steps = [
+  { id = "setup", run = "make setup" },
+  { id = "format", run = "cargo fmt", needs = ["setup"] },
+  # Slow
+  { id = "rename", run = "rename Foo Bar", needs = [] },
+]
+
+[[phases]]
+name = "later"
+"#,
+        )
+        .unwrap();
+        reorder_steps_file(&path, &[0, 2, 1]).unwrap();
+        let tables_path = temp_dir.path().join("tables.toml");
+        fs::write(
+            &tables_path,
+            r#"[[steps]]
+id = "lint"
+run = "eslint --fix ."
+
+[[steps]]
+id = "docs"
+run = "make docs"
+needs = []
+"#,
+        )
+        .unwrap();
+        let inline_path = temp_dir.path().join("inline.toml");
+        fs::write(&inline_path, "steps = [\"format\", \"rename Foo Bar\", \"make test\"]\n").unwrap();
+        reorder_steps_file(&tables_path, &[1, 0]).unwrap();
+        reorder_steps_file(&inline_path, &[1, 0, 2]).unwrap();
+        insta::assert_snapshot!(format!(
+            "{}---\n{}---\n{}",
+            fs::read_to_string(&path).unwrap(),
+            fs::read_to_string(&tables_path).unwrap(),
+            fs::read_to_string(&inline_path).unwrap()
+        ));
+    }
+}
@@ -0,0 +1,76 @@
+use std::path::Path;
+
+/// Hands everything in the directory but `.git` back to `uid:gid` and lets the owner read and write it.
+/// Run through the step's shell, which can change files a container created as root.
+pub fn normalize_script(uid: u32, gid: u32) -> String {
+    // Directories are opened up before find descends into them
+    let chown = format!("find . -path ./.git -prune -o -exec chown -h {}:{} {{}} +", uid, gid);
+    let chmod = "find . -path ./.git -prune -o -type d -exec chmod u+rwx {} \\; -o -type f -exec chmod u+rw {} +";
+    format!("{} && {}", chown, chmod)
+}
+
+#[cfg(unix)]
+fn has_foreign_files(dir: &Path, uid: u32) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        // Unreadable, likely because it belongs to someone else
+        return true;
+    };
+    entries.flatten().any(|entry| {
+        if entry.file_name() == ".git" {
+            return false;
+        }
+        let Ok(metadata) = entry.path().symlink_metadata() else {
+            return true;
+        };
+        let wanted_mode = if metadata.is_dir() { 0o700 } else { 0o600 };
+        if metadata.uid() != uid || (!metadata.is_symlink() && metadata.mode() & wanted_mode != wanted_mode) {
+            return true;
+        }
+        metadata.is_dir() && has_foreign_files(&entry.path(), uid)
+    })
+}
+
+/// The owner of `dir` when something in it, `.git` aside, belongs to another user or can't be read
+/// and written by its owner.
+#[cfg(unix)]
+pub fn foreign_files_owner(dir: &Path) -> Option<(u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = dir.metadata().ok()?;
+    has_foreign_files(dir, metadata.uid()).then_some((metadata.uid(), metadata.gid()))
+}
+
+#[cfg(not(unix))]
+pub fn foreign_files_owner(_dir: &Path) -> Option<(u32, u32)> {
+    None
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::ownership::foreign_files_owner;
+    use crate::run::{Executor, ShellExecutor};
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn files_a_step_locked_are_handed_back() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(temp_dir.path().join(".git")).unwrap();
+        fs::write(temp_dir.path().join(".git/index"), "").unwrap();
+        fs::set_permissions(temp_dir.path().join(".git/index"), fs::Permissions::from_mode(0o400)).unwrap();
+        fs::write(temp_dir.path().join("a.txt"), "").unwrap();
+        assert_eq!(foreign_files_owner(temp_dir.path()), None);
+
+        let mut executor = ShellExecutor::default().normalizing_ownership(true);
+        let script = "mkdir -p out && echo generated > out/api.json && chmod 400 out/api.json && chmod 500 out";
+        let output = executor.run_script(temp_dir.path(), script, &BTreeMap::new(), None, &mut |_| {}).unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(foreign_files_owner(temp_dir.path()), None);
+        let mode = fs::metadata(temp_dir.path().join("out/api.json")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o600, 0o600);
+        // Only what the step touched, `.git` is git's business
+        let mode = fs::metadata(temp_dir.path().join(".git/index")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o400);
+    }
+}
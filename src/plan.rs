@@ -0,0 +1,182 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::run::{create_run_status_from_mend, step_hooks, StepRequest};
+use crate::select::StepSelection;
+use crate::Mend;
+
+#[derive(Debug, PartialEq, Clone, Copy, ValueEnum)]
+pub enum PlanFormat {
+    Text,
+    Json,
+}
+
+/// What `--dry-run` shows of a step, everything the run would execute for it.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PlannedStep {
+    pub id: String,
+    /// Counting from 1
+    pub step: usize,
+    pub run: String,
+    pub commit_msg: String,
+    pub before_hooks: Vec<String>,
+    /// The step's own script, its recipe functions included
+    pub script: String,
+    pub after_hooks: Vec<String>,
+    pub verify: Option<String>,
+    /// Scripts run when the step fails, hooks included
+    pub fallback: Vec<String>,
+    pub fixup: Option<String>,
+    pub needs: Option<Vec<String>>,
+    pub when: Option<String>,
+    pub timeout_seconds: Option<u64>,
+    pub locks: Vec<String>,
+    pub env: BTreeMap<String, String>,
+}
+
+fn plan_step(step_i: usize, step_request: StepRequest, mend: &Mend) -> PlannedStep {
+    let (before_hooks, after_hooks) = step_hooks(&step_request.run, mend, step_i);
+    // The hooks are the scripts around the step's own
+    let mut scripts = step_request.run_resolved;
+    scripts.truncate(scripts.len().saturating_sub(after_hooks.len()));
+    let script = scripts.drain(before_hooks.len().min(scripts.len())..).collect::<Vec<String>>().join("");
+    PlannedStep {
+        id: step_request.id,
+        step: step_i + 1,
+        run: step_request.run,
+        commit_msg: step_request.commit_msg,
+        before_hooks,
+        script,
+        after_hooks,
+        verify: step_request.verify,
+        fallback: step_request.fallback_resolved,
+        fixup: step_request.fixup,
+        needs: step_request.needs,
+        when: step_request.when,
+        timeout_seconds: step_request.timeout.map(|timeout| timeout.as_secs()),
+        locks: step_request.locks,
+        env: step_request.env,
+    }
+}
+
+pub fn plan_steps(mend: &Mend) -> Vec<PlannedStep> {
+    create_run_status_from_mend(mend, &StepSelection::default())
+        .into_iter()
+        .enumerate()
+        .map(|(step_i, step_request)| plan_step(step_i, step_request, mend))
+        .collect()
+}
+
+fn write_script(text: &mut String, label: &str, script: &str) {
+    let _ = writeln!(text, "  {}:", label);
+    for line in script.trim_end().lines() {
+        let _ = writeln!(text, "    {}", line);
+    }
+}
+
+/// The resolved steps of a config, for reviewing what a run would execute before starting it.
+pub fn render_plan(mend: &Mend, format: PlanFormat) -> anyhow::Result<String> {
+    let steps = plan_steps(mend);
+    if format == PlanFormat::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(&steps)?));
+    }
+    let mut text = String::new();
+    for step in &steps {
+        let _ = writeln!(text, "Step {} [{}]: {}", step.step, step.id, step.run);
+        let _ = writeln!(text, "  Commit message: {}", step.commit_msg.trim_end().replace('\n', "\n    "));
+        for (label, value) in [("Fixup of", &step.fixup), ("When", &step.when), ("Verify", &step.verify)] {
+            if let Some(value) = value {
+                let _ = writeln!(text, "  {}: {}", label, value);
+            }
+        }
+        if let Some(needs) = &step.needs {
+            let _ = writeln!(text, "  Needs: {}", needs.join(", "));
+        }
+        if !step.locks.is_empty() {
+            let _ = writeln!(text, "  Locks: {}", step.locks.join(", "));
+        }
+        if let Some(timeout_seconds) = step.timeout_seconds {
+            let _ = writeln!(text, "  Timeout: {}s", timeout_seconds);
+        }
+        for hook in &step.before_hooks {
+            write_script(&mut text, "Before hook", hook);
+        }
+        write_script(&mut text, "Script", &step.script);
+        for hook in &step.after_hooks {
+            write_script(&mut text, "After hook", hook);
+        }
+        for fallback in &step.fallback {
+            write_script(&mut text, "Fallback", fallback);
+        }
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::plan::{plan_steps, render_plan, PlanFormat};
+    use crate::{Hook, Mend, Recipe, Step, StepConfig};
+
+    fn create_mend() -> Mend {
+        let toml = r#"
+            steps = ["rename Foo Bar", "cleanup"]
+
+            [recipes.rename]
+            run = "rename-cli $1 $2"
+            tags = ["java"]
+            commit_template = "Rename $1 to $2"
+
+            [recipes.cleanup]
+            run = "rm -f *.orig"
+
+            [verify]
+            run = "make test"
+        "#;
+        let mut mend: Mend = toml::from_str(toml).unwrap();
+        mend.hooks.insert(
+            "before_step".to_string(),
+            vec![Hook {
+                run: Some("git clean -fdq".to_string()),
+                when_tag: Some("java".to_string()),
+                when_not_tag: None,
+            }],
+        );
+        mend.hooks.insert(
+            "after_step".to_string(),
+            vec![Hook {
+                run: Some("./gradlew spotlessApply".to_string()),
+                when_tag: None,
+                when_not_tag: None,
+            }],
+        );
+        mend.steps.push(Step::Structured(Box::new(StepConfig {
+            id: Some("docs".to_string()),
+            run: Some("cleanup".to_string()),
+            fixup: Some("1".to_string()),
+            ..Default::default()
+        })));
+        mend.recipes.insert(
+            "unused".to_string(),
+            Recipe {
+                run: "false".to_string(),
+                ..Default::default()
+            },
+        );
+        mend
+    }
+
+    #[test]
+    fn dry_run_plan_shows_what_each_step_runs() {
+        let mend = create_mend();
+        let steps = plan_steps(&mend);
+        assert_eq!(steps[0].before_hooks, vec!["git clean -fdq"]);
+        assert!(steps[1].before_hooks.is_empty());
+        assert_eq!(steps[1].after_hooks, vec!["./gradlew spotlessApply"]);
+        insta::assert_snapshot!(render_plan(&mend, PlanFormat::Text).unwrap());
+        let json: serde_json::Value = serde_json::from_str(&render_plan(&mend, PlanFormat::Json).unwrap()).unwrap();
+        assert_eq!(json[0]["script"], "rename() {\nrename-cli $1 $2\n}\nrename Foo Bar\n");
+        assert_eq!(json[0]["commit_msg"], "Rename Foo to Bar");
+    }
+}
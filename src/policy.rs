@@ -0,0 +1,221 @@
+use anyhow::{bail, Context};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::gates::{is_protected, DiffStats};
+use crate::plan::PlannedStep;
+use crate::repo::GitRepo;
+use crate::run::StepCommit;
+use crate::secrets::without_identity;
+
+/// Names the policy file when `--policy` isn't given.
+pub const POLICY_ENV: &str = "MEND_POLICY";
+
+/// Organization guardrails every run is held to. They live apart from the project's config, so a config can't relax them.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Policy {
+    /// Paths no step may touch, an entry ending in `/` covers a whole directory, e.g. `.github/`
+    #[serde(default)]
+    pub protected_paths: Vec<String>,
+    /// Regular expression every step's commit message must match, e.g. `[A-Z]+-[0-9]+` for a ticket
+    pub commit_message_pattern: Option<String>,
+    /// Added plus removed lines of any one step
+    pub max_step_changed_lines: Option<usize>,
+    /// Program and its leading arguments, e.g. a script calling `opa eval`, a relative path is found from the policy's
+    /// directory. Given the plan before the run and each step's change after it as JSON on stdin, it blocks the run by
+    /// exiting non-zero, what it printed being the reason
+    pub command: Option<String>,
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+/// What a step's commit changed, as the policy sees it.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct StepChange {
+    pub id: String,
+    /// Counting from 1
+    pub step: usize,
+    pub sha: String,
+    pub message: String,
+    pub files: Vec<String>,
+    pub changed_lines: usize,
+}
+
+/// The policy at `path`, else at the file `MEND_POLICY` names, None when neither is set.
+pub fn load_policy(path: Option<&str>) -> anyhow::Result<Option<Policy>> {
+    let Some(path) = path.map(str::to_string).or_else(|| std::env::var(POLICY_ENV).ok()) else {
+        return Ok(None);
+    };
+    let path = PathBuf::from(shellexpand::tilde(&path).to_string());
+    let text = fs::read_to_string(&path).with_context(|| format!("Could not read the policy `{}`", path.to_string_lossy()))?;
+    let mut policy: Policy = toml::from_str(&text).with_context(|| format!("Invalid policy `{}`", path.to_string_lossy()))?;
+    if let Some(pattern) = &policy.commit_message_pattern {
+        Regex::new(pattern).with_context(|| format!("Invalid commit_message_pattern `{}`", pattern))?;
+    }
+    policy.dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new(".")).to_path_buf();
+    Ok(Some(policy))
+}
+
+/// The change of each step's commit in `repo`.
+pub fn step_changes(repo: &GitRepo, commits: &[StepCommit]) -> anyhow::Result<Vec<StepChange>> {
+    commits
+        .iter()
+        .map(|commit| {
+            let stats = DiffStats::from_numstat(&repo.commit_numstat(&commit.sha)?);
+            Ok(StepChange {
+                id: commit.id.clone(),
+                step: commit.step,
+                sha: commit.sha.clone(),
+                message: repo.commit_message(&commit.sha)?.trim_end().to_string(),
+                files: stats.files,
+                changed_lines: stats.changed_lines,
+            })
+        })
+        .collect()
+}
+
+impl Policy {
+    fn message_violation(&self, step_id: &str, message: &str) -> Option<String> {
+        let pattern = self.commit_message_pattern.as_ref()?;
+        // Checked when the policy was loaded
+        let regex = Regex::new(pattern).ok()?;
+        if regex.is_match(message) {
+            return None;
+        }
+        Some(format!(
+            "commit_message_pattern: step {}'s message `{}` doesn't match `{}`",
+            step_id,
+            message.lines().next().unwrap_or_default(),
+            pattern
+        ))
+    }
+
+    /// Describes each rule the plan breaks, empty when the run may start.
+    pub fn check_plan(&self, steps: &[PlannedStep]) -> anyhow::Result<Vec<String>> {
+        let mut violations: Vec<String> = steps.iter().filter_map(|step| self.message_violation(&step.id, &step.commit_msg)).collect();
+        if let Some(reason) = self.run_command(json!({"kind": "plan", "steps": steps}))? {
+            violations.push(format!("command: the plan was refused: {}", reason));
+        }
+        Ok(violations)
+    }
+
+    /// Describes each rule the step's change breaks, empty when it complies.
+    pub fn check_step(&self, change: &StepChange) -> anyhow::Result<Vec<String>> {
+        let mut violations = vec![];
+        for path in &change.files {
+            if let Some(protected) = self.protected_paths.iter().find(|protected| is_protected(path, protected)) {
+                violations.push(format!("protected_paths: step {} touched {}, matches `{}`", change.id, path, protected));
+            }
+        }
+        if let Some(max_lines) = self.max_step_changed_lines.filter(|max_lines| change.changed_lines > *max_lines) {
+            violations.push(format!(
+                "max_step_changed_lines: step {} changed {} lines, at most {} allowed",
+                change.id, change.changed_lines, max_lines
+            ));
+        }
+        violations.extend(self.message_violation(&change.id, &change.message));
+        let mut input = serde_json::to_value(change)?;
+        input["kind"] = json!("step");
+        if let Some(reason) = self.run_command(input)? {
+            violations.push(format!("command: step {} was refused: {}", change.id, reason));
+        }
+        Ok(violations)
+    }
+
+    /// None when there's no command or it accepts `input`, else what it printed.
+    fn run_command(&self, input: Value) -> anyhow::Result<Option<String>> {
+        let Some(command) = &self.command else {
+            return Ok(None);
+        };
+        let mut words = command.split_whitespace();
+        let Some(program) = words.next() else {
+            bail!("The policy's `command` is empty");
+        };
+        let program = if program.contains('/') || program.contains('\\') {
+            self.dir.join(program).to_string_lossy().to_string()
+        } else {
+            program.to_string()
+        };
+        let mut child = without_identity(Command::new(&program).current_dir(&self.dir).args(words))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Could not start the policy command `{}`", program))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // A command that decides without reading its input still decided
+            let _ = stdin.write_all(format!("{}\n", input).as_bytes());
+        }
+        let output = child.wait_with_output().with_context(|| format!("Could not run the policy command `{}`", program))?;
+        if output.status.success() {
+            return Ok(None);
+        }
+        let mut reason = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        reason.push_str(String::from_utf8_lossy(&output.stderr).trim());
+        if reason.is_empty() {
+            reason = format!("exited with {}", output.status);
+        }
+        Ok(Some(reason))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::policy::{Policy, StepChange};
+
+    #[test]
+    fn step_changes_breaking_rules_are_each_reported() {
+        let policy: Policy = toml::from_str(
+            r#"
+            protected_paths = [".github/", "Jenkinsfile"]
+            commit_message_pattern = "[A-Z]+-[0-9]+"
+            max_step_changed_lines = 10
+            "#,
+        )
+        .unwrap();
+        let mut change = StepChange {
+            id: "rename".to_string(),
+            step: 1,
+            sha: "abc1234".to_string(),
+            message: "PROJ-12 Rename Foo to Bar".to_string(),
+            files: vec!["src/Foo.java".to_string()],
+            changed_lines: 4,
+        };
+        assert!(policy.check_step(&change).unwrap().is_empty());
+        change.message = "Rename Foo to Bar\n\nAll of them".to_string();
+        change.files.push(".github/workflows/ci.yml".to_string());
+        change.changed_lines = 40;
+        insta::assert_yaml_snapshot!(policy.check_step(&change).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn policy_command_decides_from_its_input() {
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let script_path = temp_dir.path().join("policy.sh");
+        fs::write(&script_path, "#!/bin/sh\nif grep -q Jenkinsfile; then echo 'CI config is off limits'; exit 1; fi\n").unwrap();
+        fs::set_permissions(&script_path, fs::Permissions::from_mode(0o755)).unwrap();
+        let policy_path = temp_dir.path().join("policy.toml");
+        fs::write(&policy_path, "command = \"./policy.sh\"\n").unwrap();
+        let policy = crate::policy::load_policy(Some(&policy_path.to_string_lossy())).unwrap().unwrap();
+        let mut change = StepChange {
+            id: "2".to_string(),
+            step: 2,
+            sha: "abc1234".to_string(),
+            message: "Bump".to_string(),
+            files: vec!["pom.xml".to_string()],
+            changed_lines: 2,
+        };
+        assert!(policy.check_step(&change).unwrap().is_empty());
+        change.files.push("Jenkinsfile".to_string());
+        assert_eq!(policy.check_step(&change).unwrap(), vec!["command: step 2 was refused: CI config is off limits"]);
+    }
+}
@@ -1,27 +1,582 @@
-use std::time::Instant;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
+use anyhow::bail;
+use clap::ValueEnum;
 use console::{Emoji, Style};
-use indicatif::{HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
+use indicatif::{HumanDuration, MultiProgress, ProgressBar, ProgressState, ProgressStyle};
+use regex::Regex;
+use serde_json::{json, Value};
 
-use crate::run::{EStatus, StepRequest, StepResponse};
+use crate::run::{EStatus, RunSummary, StepRequest, StepResponse};
+use crate::tui::TuiNotifier;
 
 static SPARKLE: Emoji<'_, '_> = Emoji("✨ ", ":-)");
 static WARN: Emoji<'_, '_> = Emoji("⚠️ ", "(X)");
 
 pub trait Notify {
     fn notify(&mut self, i: usize, run: &str, status: &EStatus, sha: &Option<String>, inc: bool);
-    fn notify_done(&self);
+    fn notify_done(&self, summary: &RunSummary);
     fn notify_failure(&self, failed_request: &StepRequest, failed_response: &StepResponse);
+
+    /// A line the running step's script printed, as soon as it's printed.
+    /// Steps running alongside others don't report theirs.
+    fn notify_output(&mut self, _i: usize, _line: &str) {}
+
+    /// Whether the lines scripts print are shown, changed while the run goes by `mend ctl verbosity`.
+    fn set_verbose(&mut self, _verbose: bool) {}
+
+    /// Runs `f` with the progress display out of its way, e.g. while `--interactive` asks about a step.
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        f()
+    }
+}
+
+impl Notify for Box<dyn Notify> {
+    fn notify(&mut self, i: usize, run: &str, status: &EStatus, sha: &Option<String>, inc: bool) {
+        self.as_mut().notify(i, run, status, sha, inc)
+    }
+
+    fn notify_done(&self, summary: &RunSummary) {
+        self.as_ref().notify_done(summary)
+    }
+
+    fn notify_failure(&self, failed_request: &StepRequest, failed_response: &StepResponse) {
+        self.as_ref().notify_failure(failed_request, failed_response)
+    }
+
+    fn notify_output(&mut self, i: usize, line: &str) {
+        self.as_mut().notify_output(i, line)
+    }
+
+    fn set_verbose(&mut self, verbose: bool) {
+        self.as_mut().set_verbose(verbose)
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        self.as_ref().suspend(f)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, ValueEnum)]
+pub enum ProgressOutput {
+    /// Progress bars for a terminal
+    Human,
+    /// One JSON event per line on stdout
+    Json,
+    /// Full-screen step list with each step's output, progress bars when stdout isn't a terminal
+    Tui,
+    /// A collapsible log group per step, error annotations and a summary table for GitHub Actions
+    Github,
+    /// A plain sentence as each step changes state, for screen readers, without emoji or redrawn lines
+    Simple,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, ValueEnum)]
+pub enum CiSystem {
+    /// GitHub Actions, same as `--output github`
+    Github,
+}
+
+/// The notifier showing progress the way `output` asks for, `verbose` adds the lines the scripts print.
+pub fn create_notifier(output: ProgressOutput, verbose: bool, step_requests: &[StepRequest]) -> Box<dyn Notify> {
+    match output {
+        ProgressOutput::Human => Box::new(create_console_notifier(step_requests, verbose)),
+        ProgressOutput::Json => Box::new(JsonNotifier::new(std::io::stdout(), verbose)),
+        ProgressOutput::Tui if console::Term::stdout().is_term() => Box::new(TuiNotifier::new(step_requests)),
+        ProgressOutput::Tui => Box::new(create_console_notifier(step_requests, verbose)),
+        ProgressOutput::Github => Box::new(GithubNotifier::new(
+            std::io::stdout(),
+            step_requests,
+            std::env::var_os("GITHUB_STEP_SUMMARY").map(PathBuf::from),
+        )),
+        ProgressOutput::Simple => {
+            let colors = std::env::var_os("NO_COLOR").is_none() && console::Term::stdout().is_term();
+            Box::new(SimpleNotifier::new(std::io::stdout(), step_requests, verbose, colors))
+        }
+    }
+}
+
+/// How far a step got, so each event is written once however often the run reports the same status.
+#[derive(Default)]
+struct StepProgress {
+    started: bool,
+    scripts_finished: usize,
+    finished: Option<EStatus>,
+    sha: Option<String>,
+}
+
+/// Writes newline-delimited JSON events for wrappers and CI systems to parse instead of progress bars.
+pub struct JsonNotifier<W: Write> {
+    out: RefCell<W>,
+    steps: HashMap<usize, StepProgress>,
+    /// Whether the lines the scripts print are written too
+    verbose: bool,
+}
+
+impl<W: Write> JsonNotifier<W> {
+    pub fn new(out: W, verbose: bool) -> Self {
+        JsonNotifier {
+            out: RefCell::new(out),
+            steps: HashMap::new(),
+            verbose,
+        }
+    }
+
+    fn write(&self, event: Value) {
+        let mut out = self.out.borrow_mut();
+        // A reader that went away doesn't stop the run
+        let _ = writeln!(out, "{}", event);
+        let _ = out.flush();
+    }
+}
+
+impl<W: Write> Notify for JsonNotifier<W> {
+    fn notify(&mut self, i: usize, run: &str, status: &EStatus, sha: &Option<String>, inc: bool) {
+        let mut events = vec![];
+        let step = self.steps.entry(i).or_default();
+        match status {
+            EStatus::Pending => {}
+            EStatus::Running if !step.started => {
+                step.started = true;
+                events.push(json!({"event": "step_started", "step": i + 1, "run": run}));
+            }
+            // Reported as the next script starts
+            EStatus::Running if inc => {
+                step.scripts_finished += 1;
+                events.push(json!({"event": "script_finished", "step": i + 1, "script": step.scripts_finished, "ok": true}));
+            }
+            EStatus::Running => {}
+            EStatus::Done | EStatus::Failed | EStatus::VerifyFailed | EStatus::Skipped => {
+                if step.started && step.finished.is_none() && *status != EStatus::Skipped {
+                    step.scripts_finished += 1;
+                    events.push(json!({
+                        "event": "script_finished",
+                        "step": i + 1,
+                        "script": step.scripts_finished,
+                        "ok": *status == EStatus::Done,
+                    }));
+                }
+                if let (EStatus::Done, Some(sha)) = (status, sha) {
+                    if step.sha.as_ref() != Some(sha) {
+                        step.sha = Some(sha.clone());
+                        events.push(json!({"event": "commit_created", "step": i + 1, "sha": sha}));
+                    }
+                }
+                if step.finished != Some(*status) {
+                    step.finished = Some(*status);
+                    events.push(json!({"event": "step_finished", "step": i + 1, "run": run, "status": status}));
+                }
+            }
+        }
+        for event in events {
+            self.write(event);
+        }
+    }
+
+    fn notify_done(&self, summary: &RunSummary) {
+        self.write(json!({
+            "event": "done",
+            "failed_steps": summary.failed_steps.iter().map(|step_i| step_i + 1).collect::<Vec<usize>>(),
+            "skipped_steps": summary.skipped_steps.iter().map(|step_i| step_i + 1).collect::<Vec<usize>>(),
+            "commits": summary.commits,
+            "totals": summary.totals,
+        }));
+    }
+
+    fn notify_failure(&self, failed_request: &StepRequest, failed_response: &StepResponse) {
+        self.write(json!({
+            "event": "failed",
+            "id": failed_request.id,
+            "run": failed_request.run,
+            "output": failed_response.output,
+        }));
+    }
+
+    fn set_verbose(&mut self, verbose: bool) {
+        self.verbose = verbose;
+    }
+
+    fn notify_output(&mut self, i: usize, line: &str) {
+        if self.verbose {
+            self.write(json!({"event": "output", "step": i + 1, "line": line}));
+        }
+    }
+}
+
+/// Escapes the message of a GitHub Actions workflow command, which ends at the first newline.
+fn escape_workflow_data(data: &str) -> String {
+    data.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+/// Escapes a property of a workflow command, e.g. its `title`.
+fn escape_workflow_property(value: &str) -> String {
+    escape_workflow_data(value).replace(':', "%3A").replace(',', "%2C")
+}
+
+struct GithubStep {
+    id: String,
+    run: String,
+    status: EStatus,
+    sha: Option<String>,
+}
+
+/// Writes GitHub Actions workflow commands instead of progress bars: each step's output goes in a `::group::`
+/// of the job log, failed steps get an `::error::` annotation, and a table of the steps is appended to
+/// `$GITHUB_STEP_SUMMARY` once the run ends.
+pub struct GithubNotifier<W: Write> {
+    out: RefCell<W>,
+    started: Instant,
+    steps: Vec<GithubStep>,
+    /// The step whose group is open, only one can be at a time
+    open_group: Option<usize>,
+    summary_path: Option<PathBuf>,
+    /// How the run ended, None while it's going
+    outcome: RefCell<Option<String>>,
+}
+
+impl<W: Write> GithubNotifier<W> {
+    pub fn new(out: W, step_requests: &[StepRequest], summary_path: Option<PathBuf>) -> Self {
+        GithubNotifier {
+            out: RefCell::new(out),
+            started: Instant::now(),
+            steps: step_requests
+                .iter()
+                .map(|step_request| GithubStep {
+                    id: step_request.id.clone(),
+                    run: step_request.run.clone(),
+                    status: EStatus::Pending,
+                    sha: None,
+                })
+                .collect(),
+            open_group: None,
+            summary_path,
+            outcome: RefCell::new(None),
+        }
+    }
+
+    fn write(&self, line: &str) {
+        let mut out = self.out.borrow_mut();
+        let _ = writeln!(out, "{}", line);
+        let _ = out.flush();
+    }
+
+    fn end_group(&mut self) {
+        if self.open_group.take().is_some() {
+            self.write("::endgroup::");
+        }
+    }
+
+    fn render_summary(&self) -> String {
+        let outcome = self.outcome.borrow().clone().unwrap_or_else(|| "Stopped".to_string());
+        let mut text = format!("### mend\n\n{}\n\n| Step | Run | Status | Commit |\n| --- | --- | --- | --- |\n", outcome);
+        for (step_i, step) in self.steps.iter().enumerate() {
+            let status = match step.status {
+                EStatus::Done => "✅ done",
+                EStatus::Failed => "❌ failed",
+                EStatus::VerifyFailed => "❌ verify failed",
+                EStatus::Skipped => "⏭️ skipped",
+                EStatus::Pending | EStatus::Running => "not run",
+            };
+            text.push_str(&format!(
+                "| {} [{}] | `{}` | {} | {} |\n",
+                step_i + 1,
+                step.id,
+                step.run.replace('|', "\\|"),
+                status,
+                step.sha.as_deref().unwrap_or_default()
+            ));
+        }
+        text
+    }
+}
+
+impl<W: Write> Notify for GithubNotifier<W> {
+    fn notify(&mut self, i: usize, run: &str, status: &EStatus, sha: &Option<String>, _inc: bool) {
+        let Some(previous) = self.steps.get(i).map(|step| step.status) else {
+            return;
+        };
+        match status {
+            EStatus::Pending => {}
+            EStatus::Running if previous != EStatus::Running => {
+                self.end_group();
+                self.write(&format!("::group::[{}] {}", i + 1, run));
+                self.open_group = Some(i);
+            }
+            EStatus::Running => {}
+            EStatus::Done | EStatus::Failed | EStatus::VerifyFailed | EStatus::Skipped => {
+                if self.open_group == Some(i) {
+                    self.end_group();
+                }
+                if previous != *status {
+                    let label = match status {
+                        EStatus::Done => "Done",
+                        EStatus::Failed => "Failed",
+                        EStatus::VerifyFailed => "Verify failed",
+                        _ => "Skipped",
+                    };
+                    self.write(format!("{} [{}] {} {}", label, i + 1, run, sha.as_deref().unwrap_or_default()).trim_end());
+                }
+            }
+        }
+        let step = &mut self.steps[i];
+        step.status = *status;
+        step.sha.clone_from(sha);
+    }
+
+    fn notify_done(&self, summary: &RunSummary) {
+        let outcome = if summary.failed_steps.is_empty() {
+            format!("Done in {}", HumanDuration(self.started.elapsed()))
+        } else {
+            format!("{} of {} steps failed in {}", summary.failed_steps.len(), self.steps.len(), HumanDuration(self.started.elapsed()))
+        };
+        self.write(&outcome);
+        for commit in &summary.commits {
+            if let Some(warnings) = commit.metadata.get("warning") {
+                for line in warnings.lines() {
+                    let title = escape_workflow_property(&format!("Step {} [{}]", commit.step, commit.id));
+                    self.write(&format!("::warning title={}::{}", title, escape_workflow_data(line)));
+                }
+            }
+        }
+        *self.outcome.borrow_mut() = Some(outcome);
+    }
+
+    fn notify_failure(&self, failed_request: &StepRequest, failed_response: &StepResponse) {
+        let step_number = self.steps.iter().position(|step| step.id == failed_request.id).map_or(0, |step_i| step_i + 1);
+        let title = escape_workflow_property(&format!("Step {} [{}] failed", step_number, failed_request.id));
+        let mut message = failed_request.run.clone();
+        if let Some(output) = &failed_response.output {
+            // The annotation shows the end of the output, the whole of it is in the step's group
+            let lines: Vec<&str> = output.lines().collect();
+            for line in &lines[lines.len().saturating_sub(OUTPUT_LINES * 2)..] {
+                message.push('\n');
+                message.push_str(line);
+            }
+        }
+        self.write(&format!("::error title={}::{}", title, escape_workflow_data(&message)));
+        let mut outcome = self.outcome.borrow_mut();
+        if outcome.is_none() {
+            *outcome = Some(format!("Failed at step {} in {}", step_number, HumanDuration(self.started.elapsed())));
+        }
+    }
+
+    fn notify_output(&mut self, _i: usize, line: &str) {
+        // Folded away in the step's group, so shown whether or not --verbose is given
+        self.write(line);
+    }
+}
+
+impl<W: Write> Drop for GithubNotifier<W> {
+    fn drop(&mut self) {
+        self.end_group();
+        if let Some(summary_path) = &self.summary_path {
+            let appended = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(summary_path)
+                .and_then(|mut file| file.write_all(self.render_summary().as_bytes()));
+            if let Err(err) = appended {
+                eprintln!("Could not write the job summary to `{}`: {}", summary_path.to_string_lossy(), err);
+            }
+        }
+    }
+}
+
+/// Writes a sentence each time a step changes state, e.g. `Step 2 of 5 started: rename Foo Bar.`, for screen readers
+/// and logs. Nothing is redrawn and no emoji is written, colors only when `colors` is set and never with `NO_COLOR`.
+pub struct SimpleNotifier<W: Write> {
+    out: RefCell<W>,
+    started: Instant,
+    ids: Vec<String>,
+    statuses: Vec<EStatus>,
+    /// Steps `--only` or `--skip` leave out, said to be left out rather than skipped
+    excluded: Vec<bool>,
+    verbose: bool,
+    colors: bool,
+}
+
+impl<W: Write> SimpleNotifier<W> {
+    pub fn new(out: W, step_requests: &[StepRequest], verbose: bool, colors: bool) -> Self {
+        SimpleNotifier {
+            out: RefCell::new(out),
+            started: Instant::now(),
+            ids: step_requests.iter().map(|step_request| step_request.id.clone()).collect(),
+            statuses: vec![EStatus::Pending; step_requests.len()],
+            excluded: step_requests.iter().map(|step_request| step_request.excluded).collect(),
+            verbose,
+            colors,
+        }
+    }
+
+    fn write(&self, line: &str) {
+        let mut out = self.out.borrow_mut();
+        let _ = writeln!(out, "{}", line);
+        let _ = out.flush();
+    }
+
+    /// The state word, colored only as a help on top of the sentence saying it.
+    fn styled(&self, word: &str, style: Style) -> String {
+        style.force_styling(self.colors).apply_to(word).to_string()
+    }
+}
+
+impl<W: Write> Notify for SimpleNotifier<W> {
+    fn notify(&mut self, i: usize, run: &str, status: &EStatus, sha: &Option<String>, _inc: bool) {
+        let Some(previous) = self.statuses.get(i).copied() else {
+            return;
+        };
+        if previous == *status || *status == EStatus::Pending {
+            return;
+        }
+        self.statuses[i] = *status;
+        let step = format!("Step {} of {}", i + 1, self.statuses.len());
+        let line = match status {
+            EStatus::Pending => return,
+            EStatus::Running => format!("{} {}: {}.", step, self.styled("started", Style::new().cyan()), run),
+            EStatus::Done => match sha {
+                Some(sha) => format!("{} {}: {}. Committed {}.", step, self.styled("done", Style::new().green()), run, sha),
+                None => format!("{} {}: {}. Nothing to commit.", step, self.styled("done", Style::new().green()), run),
+            },
+            EStatus::Failed => format!("{} {}: {}.", step, self.styled("failed", Style::new().red().bold()), run),
+            EStatus::VerifyFailed => format!("{} {}: {}.", step, self.styled("failed verification", Style::new().red().bold()), run),
+            EStatus::Skipped if self.excluded.get(i).copied().unwrap_or_default() => {
+                format!("{} {}: {}.", step, self.styled("left out", Style::new().dim()), run)
+            }
+            EStatus::Skipped => format!("{} {}: {}.", step, self.styled("skipped", Style::new().yellow()), run),
+        };
+        self.write(&line);
+    }
+
+    fn notify_done(&self, summary: &RunSummary) {
+        let done = self.statuses.iter().filter(|status| **status == EStatus::Done).count();
+        self.write(&format!(
+            "Run finished in {}. {} steps done, {} skipped, {} failed.",
+            HumanDuration(self.started.elapsed()),
+            done,
+            summary.skipped_steps.len(),
+            summary.failed_steps.len()
+        ));
+        if summary.steps_with_stats > 0 {
+            let totals: Vec<String> = summary.totals.iter().map(|(key, total)| format!("{} {}", key, total)).collect();
+            self.write(&format!("Totals across {} steps: {}.", summary.steps_with_stats, totals.join(", ")));
+        }
+        for commit in &summary.commits {
+            if let Some(step_summary) = commit.metadata.get("summary") {
+                for line in step_summary.lines() {
+                    self.write(&format!("Step {} summary: {}", commit.step, line));
+                }
+            }
+            if let Some(warnings) = commit.metadata.get("warning") {
+                for line in warnings.lines() {
+                    self.write(&format!("Step {} {}: {}", commit.step, self.styled("warning", Style::new().yellow()), line));
+                }
+            }
+        }
+    }
+
+    fn notify_failure(&self, failed_request: &StepRequest, failed_response: &StepResponse) {
+        let step_number = self.ids.iter().position(|id| *id == failed_request.id).map_or(0, |step_i| step_i + 1);
+        self.write(&format!(
+            "Run {} after {} at step {}: {}.",
+            self.styled("failed", Style::new().red().bold()),
+            HumanDuration(self.started.elapsed()),
+            step_number,
+            failed_request.run
+        ));
+        if let Some(output) = &failed_response.output {
+            self.write("The step printed:");
+            self.write(output.trim_end());
+            self.write("End of the step's output.");
+        }
+    }
+
+    fn set_verbose(&mut self, verbose: bool) {
+        self.verbose = verbose;
+    }
+
+    fn notify_output(&mut self, i: usize, line: &str) {
+        if self.verbose {
+            self.write(&format!("Step {} output: {}", i + 1, line));
+        }
+    }
 }
 
 pub struct ConsoleNotifier {
     started: Instant,
     multi_progress: MultiProgress,
     progress_bars: Vec<ProgressBar>,
+    timeouts: Vec<Option<Duration>>,
+    /// Steps `--only` or `--skip` leave out, shown dimmed
+    excluded: Vec<bool>,
+    /// When the running steps with a timeout get killed
+    deadlines: Vec<Option<Instant>>,
+    /// Whether the lines the scripts print are shown below their step
+    verbose: bool,
+    /// The latest lines of each step that printed any, until it's finished
+    outputs: HashMap<usize, (ProgressBar, VecDeque<String>)>,
+    /// The recipe's `progress_regex` of each step
+    progress_regexes: Vec<Option<Regex>>,
+    /// How far the tool of each step that reported its progress got, until the step's finished
+    counts: HashMap<usize, ProgressBar>,
+}
+
+/// How many of its latest lines are shown below a running step with `--verbose`.
+const OUTPUT_LINES: usize = 5;
+
+impl ConsoleNotifier {
+    fn clear_output(&mut self, i: usize) {
+        if let Some((output_bar, _)) = self.outputs.remove(&i) {
+            output_bar.finish_and_clear();
+            self.multi_progress.remove(&output_bar);
+        }
+        if let Some(counts_bar) = self.counts.remove(&i) {
+            counts_bar.finish_and_clear();
+            self.multi_progress.remove(&counts_bar);
+        }
+    }
+
+    fn show_counts(&mut self, i: usize, current: u64, total: u64) {
+        let Some(progress) = self.progress_bars.get(i) else {
+            return;
+        };
+        let counts_bar = self.counts.entry(i).or_insert_with(|| {
+            let counts_bar = self.multi_progress.insert_after(progress, ProgressBar::new(total));
+            counts_bar.set_style(ProgressStyle::with_template("      [{bar:30.cyan/dim}] {pos}/{len}").unwrap().progress_chars("=> "));
+            counts_bar
+        });
+        counts_bar.set_length(total);
+        counts_bar.set_position(current.min(total));
+    }
+}
+
+/// A recipe's `progress_regex`, which needs `current` and `total` groups or at least two groups.
+pub fn progress_regex(pattern: &str) -> anyhow::Result<Regex> {
+    let regex = Regex::new(pattern)?;
+    let names: Vec<&str> = regex.capture_names().flatten().collect();
+    let named = names.contains(&"current") && names.contains(&"total");
+    if !named && regex.captures_len() < 3 {
+        bail!("`{}` needs `current` and `total` groups, or two groups", pattern);
+    }
+    Ok(regex)
+}
+
+/// How far the tool got and how far it goes, from a line `regex` matches.
+pub fn parse_progress(regex: &Regex, line: &str) -> Option<(u64, u64)> {
+    let captures = regex.captures(line)?;
+    let group = |name: &str, index: usize| captures.name(name).or_else(|| captures.get(index))?.as_str().parse().ok();
+    Some((group("current", 1)?, group("total", 2)?))
 }
 
 impl Notify for ConsoleNotifier {
     fn notify(&mut self, i: usize, run: &str, status: &EStatus, sha: &Option<String>, inc: bool) {
+        if matches!(status, EStatus::Done | EStatus::Skipped | EStatus::Failed | EStatus::VerifyFailed) {
+            self.clear_output(i);
+        }
         if let Some(progress) = self.progress_bars.get(i) {
             if inc {
                 progress.inc(1);
@@ -45,11 +600,18 @@ impl Notify for ConsoleNotifier {
                     ))
                 }
                 EStatus::Running => {
+                    if let (Some(Some(timeout)), Some(deadline @ None)) = (self.timeouts.get(i), self.deadlines.get_mut(i)) {
+                        let step_deadline = Instant::now() + *timeout;
+                        *deadline = Some(step_deadline);
+                        progress.set_style(create_timeout_style(step_deadline));
+                        progress.enable_steady_tick(Duration::from_secs(1));
+                    }
                     let running_style: Style = Style::new().cyan();
                     let styled_status = running_style.apply_to("Running");
                     progress.set_message(format!("{} {} {}", dim_sha, styled_status, msg))
                 }
                 EStatus::Done => {
+                    progress.set_style(create_spinner_style());
                     let done_style: Style = Style::new().green();
                     let styled_status = done_style.apply_to("Done   ");
                     progress.set_message(format!(
@@ -60,21 +622,64 @@ impl Notify for ConsoleNotifier {
                     ));
                     progress.finish()
                 }
-                EStatus::Failed => {
+                EStatus::Skipped => {
+                    progress.set_style(create_spinner_style());
+                    let excluded = self.excluded.get(i).copied().unwrap_or_default();
+                    let skipped_style: Style = if excluded { Style::new().dim() } else { Style::new().yellow() };
+                    let styled_status = skipped_style.apply_to("Skipped");
+                    progress.set_message(format!(
+                        "{} {} {}",
+                        dim_sha,
+                        styled_status,
+                        dim_style.apply_to(msg)
+                    ));
+                    progress.finish()
+                }
+                EStatus::Failed | EStatus::VerifyFailed => {
+                    progress.set_style(create_spinner_style());
                     let failed_style: Style = Style::new().red().bold();
                     let styled_status = failed_style.apply_to("Failed ");
-                    progress.set_message(format!("{} {} {}", dim_sha, styled_status, msg));
+                    if *status == EStatus::VerifyFailed {
+                        progress.set_message(format!("{} {} {} {}", dim_sha, styled_status, msg, dim_style.apply_to("(verify)")));
+                    } else {
+                        progress.set_message(format!("{} {} {}", dim_sha, styled_status, msg));
+                    }
                     progress.abandon()
                 }
             }
         }
     }
-    fn notify_done(&self) {
+    fn notify_done(&self, summary: &RunSummary) {
         println!(
             "{} Done in {}",
             SPARKLE,
             HumanDuration(self.started.elapsed())
         );
+        if summary.steps_with_stats > 0 {
+            let totals: Vec<String> = summary
+                .totals
+                .iter()
+                .map(|(key, total)| format!("{} {}", key, total))
+                .collect();
+            println!(
+                "{} across {} steps",
+                totals.join(", "),
+                summary.steps_with_stats
+            );
+        }
+        // Reported through $MEND_RESULT_FILE
+        for commit in &summary.commits {
+            if let Some(step_summary) = commit.metadata.get("summary") {
+                for line in step_summary.lines() {
+                    println!("[{}] {}", commit.step, line);
+                }
+            }
+            if let Some(warnings) = commit.metadata.get("warning") {
+                for line in warnings.lines() {
+                    println!("{}[{}] {}", WARN, commit.step, line);
+                }
+            }
+        }
     }
 
     fn notify_failure(&self, failed_request: &StepRequest, failed_response: &StepResponse) {
@@ -89,18 +694,56 @@ impl Notify for ConsoleNotifier {
         }
 
     }
+
+    fn set_verbose(&mut self, verbose: bool) {
+        self.verbose = verbose;
+    }
+
+    fn notify_output(&mut self, i: usize, line: &str) {
+        if let Some((current, total)) = self.progress_regexes.get(i).and_then(Option::as_ref).and_then(|regex| parse_progress(regex, line)) {
+            self.show_counts(i, current, total);
+        }
+        let Some(progress) = self.progress_bars.get(i).filter(|_| self.verbose) else {
+            return;
+        };
+        let (output_bar, lines) = self.outputs.entry(i).or_insert_with(|| {
+            let output_bar = self.multi_progress.insert_after(progress, ProgressBar::new_spinner());
+            output_bar.set_style(ProgressStyle::with_template("{msg:.dim}").unwrap());
+            (output_bar, VecDeque::new())
+        });
+        if lines.len() == OUTPUT_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(format!("      {}", line));
+        output_bar.set_message(lines.iter().map(String::as_str).collect::<Vec<&str>>().join("\n"));
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        self.multi_progress.suspend(f)
+    }
 }
 
-pub fn create_console_notifier(step_requests: &Vec<StepRequest>) -> ConsoleNotifier {
+pub fn create_console_notifier(step_requests: &[StepRequest], verbose: bool) -> ConsoleNotifier {
     let mut notifier = ConsoleNotifier {
         started: Instant::now(),
         multi_progress: MultiProgress::new(),
         progress_bars: vec![],
+        timeouts: step_requests.iter().map(|step_request| step_request.timeout).collect(),
+        excluded: step_requests.iter().map(|step_request| step_request.excluded).collect(),
+        deadlines: vec![None; step_requests.len()],
+        verbose,
+        outputs: HashMap::new(),
+        // Checked when the config was loaded
+        progress_regexes: step_requests
+            .iter()
+            .map(|step_request| step_request.progress_regex.as_deref().and_then(|pattern| progress_regex(pattern).ok()))
+            .collect(),
+        counts: HashMap::new(),
     };
-    let mut i = 0;
     let num_steps = step_requests.len();
-    for step_request in step_requests {
-        let num_step_scripts = step_request.run_resolved.len() + 1;
+    for (i, step_request) in step_requests.iter().enumerate() {
+        let num_step_scripts =
+            step_request.run_resolved.len() + step_request.verify.iter().len() + 1;
         let pb = notifier
             .multi_progress
             .add(ProgressBar::new(num_step_scripts as u64));
@@ -116,7 +759,6 @@ pub fn create_console_notifier(step_requests: &Vec<StepRequest>) -> ConsoleNotif
             &None,
             false,
         );
-        i += 1
     }
     notifier
 }
@@ -124,3 +766,160 @@ pub fn create_console_notifier(step_requests: &Vec<StepRequest>) -> ConsoleNotif
 fn create_spinner_style() -> ProgressStyle {
     ProgressStyle::with_template("{prefix:.bold.dim} {wide_msg}").unwrap()
 }
+
+/// Shows how long the running step has left before it's killed.
+fn create_timeout_style(deadline: Instant) -> ProgressStyle {
+    ProgressStyle::with_template("{prefix:.bold.dim} {wide_msg} {timeout:.dim}")
+        .unwrap()
+        .with_key("timeout", move |_: &ProgressState, text: &mut dyn std::fmt::Write| {
+            let _ = write!(text, "{} left", HumanDuration(deadline.saturating_duration_since(Instant::now())));
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::progress::{parse_progress, progress_regex, GithubNotifier, JsonNotifier, Notify, SimpleNotifier};
+    use crate::run::{EStatus, RunSummary, StepCommit, StepRequest, StepResponse};
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    #[test]
+    fn tools_report_progress_through_their_output() {
+        let named = progress_regex(r"Processed (?<current>\d+) of (?<total>\d+) files").unwrap();
+        assert_eq!(parse_progress(&named, "Processed 137 of 4200 files"), Some((137, 4200)));
+        assert_eq!(parse_progress(&named, "Processing src/App.java"), None);
+        let positional = progress_regex(r"\[(\d+)/(\d+)\]").unwrap();
+        assert_eq!(parse_progress(&positional, "[12/40] src/App.java"), Some((12, 40)));
+        assert!(progress_regex(r"(\d+) files").is_err());
+        assert!(progress_regex(r"(\d+").is_err());
+    }
+
+    #[test]
+    fn json_output_has_one_event_per_line() {
+        let mut notifier = JsonNotifier::new(vec![], true);
+        let sha = Some("abc1234".to_string());
+        notifier.notify(0, "rename a b", &EStatus::Pending, &None, false);
+        notifier.notify(0, "rename a b", &EStatus::Running, &None, true);
+        notifier.notify_output(0, "Renamed a in 3 files");
+        notifier.notify(0, "rename a b", &EStatus::Running, &None, true);
+        notifier.notify(0, "rename a b", &EStatus::Done, &sha, true);
+        notifier.notify(1, "lint", &EStatus::Running, &None, true);
+        notifier.notify(1, "lint", &EStatus::Failed, &None, false);
+        // Reported again once the step's changes are reset
+        notifier.notify(1, "lint", &EStatus::Failed, &None, false);
+        notifier.notify(2, "cleanup", &EStatus::Skipped, &None, true);
+        let failed_request = StepRequest {
+            id: "2".to_string(),
+            run: "lint".to_string(),
+            ..Default::default()
+        };
+        let mut failed_response = StepResponse::pending();
+        failed_response.output = Some("lint: 2 problems".to_string());
+        notifier.notify_failure(&failed_request, &failed_response);
+        notifier.notify_done(&RunSummary {
+            failed_steps: vec![1],
+            skipped_steps: vec![2],
+            commits: vec![StepCommit {
+                id: "1".to_string(),
+                step: 1,
+                sha: "abc1234".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        let output = String::from_utf8(notifier.out.into_inner()).unwrap();
+        for line in output.lines() {
+            serde_json::from_str::<serde_json::Value>(line).unwrap();
+        }
+        insta::assert_snapshot!(output);
+    }
+
+    #[test]
+    fn github_output_groups_steps_and_annotates_failures() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let summary_path = temp_dir.path().join("summary.md");
+        let step_requests: Vec<StepRequest> = ["rename a b", "lint | head", "cleanup"]
+            .iter()
+            .enumerate()
+            .map(|(step_i, run)| StepRequest {
+                id: (step_i + 1).to_string(),
+                run: run.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let mut out = vec![];
+        let mut notifier = GithubNotifier::new(&mut out, &step_requests, Some(summary_path.clone()));
+        let sha = Some("abc1234".to_string());
+        notifier.notify(0, "rename a b", &EStatus::Running, &None, true);
+        notifier.notify_output(0, "Renamed a in 3 files");
+        notifier.notify(0, "rename a b", &EStatus::Done, &sha, true);
+        notifier.notify(1, "lint | head", &EStatus::Running, &None, true);
+        notifier.notify_output(1, "App.java:3: unused import");
+        notifier.notify(1, "lint | head", &EStatus::Failed, &None, false);
+        notifier.notify(1, "lint | head", &EStatus::Failed, &None, false);
+        notifier.notify(2, "cleanup", &EStatus::Skipped, &None, true);
+        let mut failed_response = StepResponse::pending();
+        failed_response.output = Some("App.java:3: unused import\n100% of 1 files: 1 problem".to_string());
+        notifier.notify_failure(&step_requests[1], &failed_response);
+        notifier.notify_done(&RunSummary {
+            failed_steps: vec![1],
+            commits: vec![StepCommit {
+                id: "1".to_string(),
+                step: 1,
+                sha: "abc1234".to_string(),
+                metadata: BTreeMap::from([("warning".to_string(), "grows the worktree by 12 MiB".to_string())]),
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        drop(notifier);
+        let output = String::from_utf8(out).unwrap();
+        assert!(output.contains("::error title=Step 2 [2] failed::lint | head%0AApp.java:3: unused import%0A100%25 of"), "{}", output);
+        insta::assert_snapshot!(output);
+        insta::assert_snapshot!(fs::read_to_string(&summary_path).unwrap());
+    }
+
+    #[test]
+    fn simple_output_says_each_state_change_once() {
+        let step_requests: Vec<StepRequest> = ["rename a b", "lint", "cleanup", "format"]
+            .iter()
+            .enumerate()
+            .map(|(step_i, run)| StepRequest {
+                id: (step_i + 1).to_string(),
+                run: run.to_string(),
+                excluded: step_i == 3,
+                ..Default::default()
+            })
+            .collect();
+        let mut notifier = SimpleNotifier::new(vec![], &step_requests, true, false);
+        let sha = Some("abc1234".to_string());
+        notifier.notify(0, "rename a b", &EStatus::Pending, &None, false);
+        notifier.notify(0, "rename a b", &EStatus::Running, &None, true);
+        notifier.notify_output(0, "Renamed a in 3 files");
+        notifier.notify(0, "rename a b", &EStatus::Running, &None, true);
+        notifier.notify(0, "rename a b", &EStatus::Done, &sha, true);
+        notifier.notify(1, "lint", &EStatus::Running, &None, true);
+        notifier.notify(1, "lint", &EStatus::Failed, &None, false);
+        notifier.notify(1, "lint", &EStatus::Failed, &None, false);
+        notifier.notify(2, "cleanup", &EStatus::Skipped, &None, true);
+        notifier.notify(3, "format", &EStatus::Skipped, &None, false);
+        let mut failed_response = StepResponse::pending();
+        failed_response.output = Some("lint: 2 problems\n".to_string());
+        notifier.notify_failure(&step_requests[1], &failed_response);
+        notifier.notify_done(&RunSummary {
+            failed_steps: vec![1],
+            skipped_steps: vec![2, 3],
+            commits: vec![StepCommit {
+                id: "1".to_string(),
+                step: 1,
+                sha: "abc1234".to_string(),
+                metadata: BTreeMap::from([("warning".to_string(), "grows the worktree by 12 MiB".to_string())]),
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        let output = String::from_utf8(notifier.out.into_inner()).unwrap();
+        assert!(output.is_ascii(), "{}", output);
+        insta::assert_snapshot!(output);
+    }
+}
@@ -0,0 +1,242 @@
+use anyhow::{bail, Context};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{Document, Item, TableLike};
+
+use crate::config::{is_yaml, load_mend, parse_mend, STDIN_CONFIG};
+use crate::include::IncludeSource;
+use crate::{Hook, Mend, Step};
+
+/// Recipes no step can reach and hook rules that can never run.
+#[derive(Debug, Default, PartialEq)]
+pub struct Unused {
+    pub recipes: Vec<String>,
+    /// The hook key, e.g. `before_step`, with the rule
+    pub hooks: Vec<(String, Hook)>,
+}
+
+impl Unused {
+    pub fn is_empty(&self) -> bool {
+        self.recipes.is_empty() && self.hooks.is_empty()
+    }
+}
+
+fn recipe_name(instruction: &str) -> &str {
+    instruction.split_whitespace().next().unwrap_or_default()
+}
+
+pub fn find_unused(mend: &Mend) -> Unused {
+    let mut instructions: Vec<String> = vec![];
+    for step in &mend.steps {
+        match step {
+            Step::Instruction(instruction) => instructions.push(instruction.clone()),
+            Step::Structured(step_config) => {
+                instructions.extend(step_config.run.iter().chain(step_config.fallback.iter()).cloned())
+            }
+        }
+    }
+    let mut used: BTreeSet<&String> = BTreeSet::new();
+    // A used recipe's fallback can use further recipes
+    while let Some(instruction) = instructions.pop() {
+        if let Some((name, recipe)) = mend.recipes.get_key_value(recipe_name(&instruction)) {
+            if used.insert(name) {
+                instructions.extend(recipe.fallback.iter().cloned());
+            }
+        }
+    }
+    let used_tags: BTreeSet<&String> = used
+        .iter()
+        .flat_map(|name| mend.recipes[*name].tags.iter())
+        .collect();
+    let mut unused = Unused {
+        recipes: mend
+            .recipes
+            .keys()
+            .filter(|name| !used.contains(name))
+            .cloned()
+            .collect(),
+        hooks: vec![],
+    };
+    for (key, hooks) in &mend.hooks {
+        for hook in hooks {
+            let never_matches = hook.when_tag.as_ref().is_some_and(|tag| !used_tags.contains(tag));
+            if hook.run.is_none() || never_matches {
+                unused.hooks.push((key.clone(), hook.clone()));
+            }
+        }
+    }
+    unused
+}
+
+fn is_rule(rule: &dyn TableLike, hook: &Hook) -> bool {
+    let field = |name: &str| rule.get(name).and_then(Item::as_str).map(str::to_string);
+    field("run") == hook.run && field("when_tag") == hook.when_tag && field("when_not_tag") == hook.when_not_tag
+}
+
+/// Removes the unused recipes and hook rules defined in one config file, keeping its formatting.
+pub fn prune_file(path: &Path, unused: &Unused) -> anyhow::Result<usize> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Could not read file `{}`", path.to_string_lossy()))?;
+    let mut document: Document = contents
+        .parse()
+        .with_context(|| format!("Unable to parse `{}`", path.to_string_lossy()))?;
+    let mut removed = 0;
+    if let Some(recipes) = document.get_mut("recipes").and_then(Item::as_table_like_mut) {
+        for name in &unused.recipes {
+            if recipes.remove(name).is_some() {
+                removed += 1;
+            }
+        }
+    }
+    if let Some(hooks) = document.get_mut("hooks").and_then(Item::as_table_like_mut) {
+        for (key, hook) in &unused.hooks {
+            match hooks.get_mut(key) {
+                Some(Item::ArrayOfTables(rules)) => {
+                    let before = rules.len();
+                    rules.retain(|rule| !is_rule(rule, hook));
+                    removed += before - rules.len();
+                }
+                Some(Item::Value(toml_edit::Value::Array(rules))) => {
+                    let before = rules.len();
+                    rules.retain(|rule| !rule.as_inline_table().is_some_and(|rule| is_rule(rule, hook)));
+                    removed += before - rules.len();
+                }
+                _ => {}
+            }
+        }
+    }
+    if removed > 0 {
+        fs::write(path, document.to_string())
+            .with_context(|| format!("Could not write `{}`", path.to_string_lossy()))?;
+    }
+    Ok(removed)
+}
+
+/// The config file and the local files it includes at any depth, where recipes and hooks can be defined.
+fn config_files(config_path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    collect_config_files(config_path, &mut files)?;
+    Ok(files)
+}
+
+fn collect_config_files(config_path: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    let contents = fs::read_to_string(config_path)
+        .with_context(|| format!("Could not read file `{}`", config_path.to_string_lossy()))?;
+    let mend = parse_mend(config_path, &contents)?;
+    let parent_dir = config_path.parent().unwrap_or(Path::new(""));
+    // Remote includes are shared recipe packs, only their cached copy would change
+    for include in &mend.include {
+        if IncludeSource::parse(include).is_ok_and(|source| source.is_remote()) {
+            continue;
+        }
+        let include_path = parent_dir.join(include);
+        // Files included twice are edited once, load_mend already rejected cycles
+        if !files.contains(&include_path) {
+            collect_config_files(&include_path, files)?;
+        }
+    }
+    files.push(config_path.to_path_buf());
+    Ok(())
+}
+
+pub fn run_prune(config_path: &Path, dry_run: bool) -> anyhow::Result<()> {
+    let unused = find_unused(&load_mend(config_path)?);
+    if unused.is_empty() {
+        println!("Every recipe and hook rule is used");
+        return Ok(());
+    }
+    for name in &unused.recipes {
+        println!("Unused recipe `{}`", name);
+    }
+    for (key, hook) in &unused.hooks {
+        match (&hook.run, &hook.when_tag) {
+            (None, _) => println!("Hook rule in `{}` without `run`", key),
+            (Some(run), when_tag) => println!(
+                "Hook rule `{}` in `{}` never runs, no used recipe is tagged `{}`",
+                run,
+                key,
+                when_tag.as_deref().unwrap_or_default()
+            ),
+        }
+    }
+    if dry_run {
+        return Ok(());
+    }
+    if config_path == Path::new(STDIN_CONFIG) {
+        bail!("A config read from stdin can't be pruned in place, run with --dry-run to only list what's unused");
+    }
+    for file in config_files(config_path)? {
+        if is_yaml(&file) {
+            // Only TOML is edited in place, keeping comments and layout
+            println!("Not pruning {}, remove the above from it by hand", file.to_string_lossy());
+            continue;
+        }
+        let removed = prune_file(&file, &unused)?;
+        if removed > 0 {
+            println!("Removed {} from {}", removed, file.to_string_lossy());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::load_mend;
+    use crate::prune::{config_files, find_unused, prune_file};
+    use std::fs;
+
+    const RECIPES: &str = r#"# Shared recipes
+[recipes.rename]
+run = "sed -i s/$1/$2/g *.c"
+tag = "binary_identical"
+fallback = "rename_slowly $1 $2"
+
+[recipes.rename_slowly]
+run = "echo slowly"
+
+[recipes.format]
+run = "clang-format -i *.c" # not used any more
+tag = "formatting"
+"#;
+
+    const MAIN: &str = r#"from = { repo = ".", sha = "abc" }
+include = ["recipes.toml"]
+steps = ["rename a b", { run = "echo hi" }]
+
+[[hooks.after_step]]
+when_tag = "binary_identical"
+run = "diff a.out a.out.bak"
+
+[[hooks.after_step]]
+when_tag = "formatting"
+run = "git diff --stat"
+
+[[hooks.after_step]]
+when_not_tag = "formatting"
+run = "make test"
+"#;
+
+    #[test]
+    fn prunes_unreachable_recipes_and_hooks() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let config_path = temp_dir.path().join("mend.toml");
+        fs::write(temp_dir.path().join("recipes.toml"), RECIPES).unwrap();
+        fs::write(&config_path, MAIN).unwrap();
+        let unused = find_unused(&load_mend(&config_path).unwrap());
+        assert_eq!(unused.recipes, vec!["format".to_string()]);
+        assert_eq!(unused.hooks.len(), 1);
+        assert_eq!(unused.hooks[0].1.run.as_deref(), Some("git diff --stat"));
+        let removed: usize = config_files(&config_path)
+            .unwrap()
+            .iter()
+            .map(|file| prune_file(file, &unused).unwrap())
+            .sum();
+        assert_eq!(removed, 2);
+        insta::assert_snapshot!(format!(
+            "{}---\n{}",
+            fs::read_to_string(temp_dir.path().join("recipes.toml")).unwrap(),
+            fs::read_to_string(&config_path).unwrap()
+        ));
+    }
+}
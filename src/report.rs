@@ -0,0 +1,448 @@
+use anyhow::{anyhow, Context};
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::matrix::{self, MATRIX_KEY};
+use crate::run::{EStatus, RunSummary, StepRequest, StepResponse, VERIFIED_KEY};
+use crate::schema::{from_versioned_json, to_versioned_json};
+use crate::Mend;
+
+const RUNS_DIR: &str = "runs";
+
+/// What a finished run leaves in `.mend/runs/<id>.json`, enough to render its reports again later.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct RunRecord {
+    /// Seconds since the Unix epoch when the run started
+    pub id: String,
+    pub config: String,
+    pub from_sha: String,
+    pub steps: Vec<StepRecord>,
+    pub totals: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct StepRecord {
+    pub id: String,
+    /// Counting from 1
+    pub step: usize,
+    pub run: String,
+    /// `Pending` for steps that didn't run because an earlier one failed
+    pub status: EStatus,
+    pub sha: Option<String>,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    /// Kept for failed steps only
+    pub output: Option<String>,
+}
+
+impl RunRecord {
+    /// `steps` are the id and instruction of every planned step.
+    pub fn new(id: String, config: &str, from_sha: &str, steps: &[(String, String)]) -> Self {
+        RunRecord {
+            id,
+            config: config.to_string(),
+            from_sha: from_sha.to_string(),
+            steps: steps
+                .iter()
+                .enumerate()
+                .map(|(step_i, (id, run))| StepRecord {
+                    id: id.clone(),
+                    step: step_i + 1,
+                    run: run.clone(),
+                    status: EStatus::Pending,
+                    sha: None,
+                    metadata: BTreeMap::new(),
+                    output: None,
+                })
+                .collect(),
+            totals: BTreeMap::new(),
+        }
+    }
+
+    /// Fills in a run that went through all steps.
+    pub fn record_summary(&mut self, summary: &RunSummary) {
+        self.totals = summary.totals.clone();
+        for step in self.steps.iter_mut() {
+            let failed = summary.failed_steps.iter().position(|step_i| *step_i == step.step - 1);
+            step.status = if let Some(failed) = failed {
+                summary.failures.get(failed).map(|(_, response)| response.status).unwrap_or(EStatus::Failed)
+            } else if summary.skipped_steps.contains(&(step.step - 1)) {
+                EStatus::Skipped
+            } else {
+                EStatus::Done
+            };
+            if let Some(commit) = summary.commits.iter().find(|commit| commit.step == step.step) {
+                step.sha = Some(commit.sha.clone());
+                step.metadata = commit.metadata.clone();
+            }
+        }
+        for (request, response) in &summary.failures {
+            self.record_failure(request, response);
+        }
+    }
+
+    /// Fills in a run that stopped at a failed step, the steps before it are done.
+    pub fn record_stop(&mut self, failed_request: &StepRequest, failed_response: &StepResponse) {
+        let failed_step = self.steps.iter().position(|step| step.id == failed_request.id);
+        for step in self.steps.iter_mut().take(failed_step.unwrap_or_default()) {
+            step.status = EStatus::Done;
+        }
+        self.record_failure(failed_request, failed_response);
+    }
+
+    /// Notes something learned about the step after the run, e.g. the issue opened about it.
+    pub fn add_step_metadata(&mut self, id: &str, key: &str, value: &str) {
+        if let Some(step) = self.steps.iter_mut().find(|step| step.id == id) {
+            step.metadata.insert(key.to_string(), value.to_string());
+        }
+    }
+
+    fn record_failure(&mut self, request: &StepRequest, response: &StepResponse) {
+        if let Some(step) = self.steps.iter_mut().find(|step| step.id == request.id) {
+            step.status = EStatus::Failed;
+            step.metadata = response.metadata.clone();
+            step.output = response.output.clone();
+        }
+    }
+
+    fn count(&self, status: EStatus) -> usize {
+        self.steps.iter().filter(|step| step.status == status).count()
+    }
+}
+
+/// Where `--record` puts the asciinema cast of a run, next to its record.
+pub fn cast_path(mend_dir: &Path, run_id: &str) -> PathBuf {
+    mend_dir.join(RUNS_DIR).join(format!("{}.cast", run_id))
+}
+
+/// Where a `--no-commit` run keeps each step's patch.
+pub fn patch_dir(mend_dir: &Path, run_id: &str) -> PathBuf {
+    mend_dir.join(RUNS_DIR).join(run_id).join("patches")
+}
+
+/// Where the merged config a run used is kept, next to its record.
+pub fn run_config_path(mend_dir: &Path, run_id: &str) -> PathBuf {
+    mend_dir.join(RUNS_DIR).join(format!("{}.toml", run_id))
+}
+
+pub fn write_run_config(mend_dir: &Path, run_id: &str, mend: &Mend) -> anyhow::Result<()> {
+    let path = run_config_path(mend_dir, run_id);
+    if let Some(runs_dir) = path.parent() {
+        fs::create_dir_all(runs_dir)
+            .with_context(|| format!("Could not create `{}`", runs_dir.to_string_lossy()))?;
+    }
+    fs::write(&path, toml::to_string(mend)?).with_context(|| format!("Could not write `{}`", path.to_string_lossy()))
+}
+
+pub fn write_run(mend_dir: &Path, record: &RunRecord) -> anyhow::Result<()> {
+    let runs_dir = mend_dir.join(RUNS_DIR);
+    fs::create_dir_all(&runs_dir)
+        .with_context(|| format!("Could not create `{}`", runs_dir.to_string_lossy()))?;
+    let path = runs_dir.join(format!("{}.json", record.id));
+    fs::write(&path, to_versioned_json(record)?)
+        .with_context(|| format!("Could not write `{}`", path.to_string_lossy()))
+}
+
+/// Reads the run with `run_id`, or the latest run.
+pub fn read_run(mend_dir: &Path, run_id: Option<&str>) -> anyhow::Result<RunRecord> {
+    let runs_dir = mend_dir.join(RUNS_DIR);
+    let run_id = match run_id {
+        Some(run_id) => run_id.to_string(),
+        None => fs::read_dir(&runs_dir)
+            .ok()
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.strip_suffix(".json").map(str::to_string))
+            .max_by_key(|run_id| run_id.parse::<u64>().unwrap_or_default())
+            .ok_or_else(|| anyhow!("No runs recorded in `{}`, run mend first", runs_dir.to_string_lossy()))?,
+    };
+    let path = runs_dir.join(format!("{}.json", run_id));
+    let contents =
+        fs::read_to_string(&path).with_context(|| format!("No run `{}` recorded in `{}`", run_id, runs_dir.to_string_lossy()))?;
+    from_versioned_json(&path, &contents, &[])
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, ValueEnum)]
+pub enum ReportFormat {
+    Console,
+    Markdown,
+    Html,
+    Junit,
+}
+
+#[derive(Args, Debug)]
+pub struct ReportArgs {
+    /// Id of a run in `.mend/runs`, the latest run when not given
+    #[arg(long = "run")]
+    pub run: Option<String>,
+
+    #[arg(long = "format", value_enum, default_value = "console")]
+    pub format: ReportFormat,
+}
+
+fn status_label(status: &EStatus) -> &'static str {
+    match status {
+        EStatus::Pending => "Not run",
+        EStatus::Running => "Running",
+        EStatus::Done => "Done",
+        EStatus::Failed => "Failed",
+        EStatus::VerifyFailed => "Verify failed",
+        EStatus::Skipped => "Skipped",
+    }
+}
+
+fn escape_markup(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn render_report(record: &RunRecord, format: ReportFormat) -> String {
+    let mut text = String::new();
+    let (done, failed, not_run, skipped) = (
+        record.count(EStatus::Done),
+        record.count(EStatus::Failed) + record.count(EStatus::VerifyFailed),
+        record.count(EStatus::Pending),
+        record.count(EStatus::Skipped),
+    );
+    let mut heading = format!(
+        "Run {} of {} from {}: {} done, {} failed, {} not run",
+        record.id, record.config, record.from_sha, done, failed, not_run
+    );
+    if skipped > 0 {
+        let _ = write!(heading, ", {} skipped", skipped);
+    }
+    // Which tier of checks each step passed, shown when the run verified its steps
+    let verified = |step: &StepRecord| step.metadata.get(VERIFIED_KEY).cloned().unwrap_or_default();
+    let any_verified = record.steps.iter().any(|step| step.metadata.contains_key(VERIFIED_KEY));
+    match format {
+        ReportFormat::Console => {
+            let _ = writeln!(text, "{}", heading);
+            for step in &record.steps {
+                let sha = step.sha.as_deref().unwrap_or("-------");
+                let mut line = format!("  [{}] {:<7} {}", step.step, status_label(&step.status), sha);
+                if any_verified {
+                    let _ = write!(line, " {:<4}", verified(step));
+                }
+                let _ = writeln!(text, "{} {}", line, step.run);
+            }
+            for (key, total) in &record.totals {
+                let _ = writeln!(text, "{} {}", key, total);
+            }
+        }
+        ReportFormat::Markdown => {
+            let _ = writeln!(text, "## {}\n", heading);
+            if any_verified {
+                let _ = writeln!(text, "| Step | Status | Commit | Verified | Run |");
+                let _ = writeln!(text, "| --- | --- | --- | --- | --- |");
+            } else {
+                let _ = writeln!(text, "| Step | Status | Commit | Run |");
+                let _ = writeln!(text, "| --- | --- | --- | --- |");
+            }
+            for step in &record.steps {
+                let verified_cell = if any_verified { format!(" {} |", verified(step)) } else { String::new() };
+                let _ = writeln!(
+                    text,
+                    "| {} | {} | {} |{} `{}` |",
+                    step.id,
+                    status_label(&step.status),
+                    step.sha.as_deref().unwrap_or(""),
+                    verified_cell,
+                    step.run.replace('|', "\\|")
+                );
+            }
+        }
+        ReportFormat::Html => {
+            let _ = writeln!(text, "<h2>{}</h2>\n<table>", escape_markup(&heading));
+            let verified_heading = if any_verified { "<th>Verified</th>" } else { "" };
+            let _ = writeln!(text, "<tr><th>Step</th><th>Status</th><th>Commit</th>{}<th>Run</th></tr>", verified_heading);
+            for step in &record.steps {
+                let verified_cell = if any_verified { format!("<td>{}</td>", verified(step)) } else { String::new() };
+                let _ = writeln!(
+                    text,
+                    "<tr><td>{}</td><td>{}</td><td>{}</td>{}<td><code>{}</code></td></tr>",
+                    escape_markup(&step.id),
+                    status_label(&step.status),
+                    step.sha.as_deref().unwrap_or(""),
+                    verified_cell,
+                    escape_markup(&step.run)
+                );
+            }
+            let _ = writeln!(text, "</table>");
+        }
+        ReportFormat::Junit => {
+            let _ = writeln!(text, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+            let _ = writeln!(
+                text,
+                "<testsuite name=\"mend {}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\">",
+                escape_markup(&record.id),
+                record.steps.len(),
+                failed,
+                not_run + skipped
+            );
+            for step in &record.steps {
+                let name = escape_markup(&format!("[{}] {}", step.id, step.run));
+                match step.status {
+                    EStatus::Failed | EStatus::VerifyFailed => {
+                        let message = if step.status == EStatus::VerifyFailed { "Step failed verification" } else { "Step failed" };
+                        let _ = writeln!(text, "  <testcase name=\"{}\" classname=\"mend\">", name);
+                        let _ = writeln!(
+                            text,
+                            "    <failure message=\"{}\">{}</failure>",
+                            message,
+                            escape_markup(step.output.as_deref().unwrap_or_default())
+                        );
+                        let _ = writeln!(text, "  </testcase>");
+                    }
+                    EStatus::Done => {
+                        let _ = writeln!(text, "  <testcase name=\"{}\" classname=\"mend\"/>", name);
+                    }
+                    _ => {
+                        let _ = writeln!(text, "  <testcase name=\"{}\" classname=\"mend\"><skipped/></testcase>", name);
+                    }
+                }
+            }
+            let _ = writeln!(text, "</testsuite>");
+        }
+    }
+    render_matrix(record, format, &mut text);
+    text
+}
+
+/// Adds a grid of how each step's `verify` fared in each `[matrix]` variant, when the run had a matrix.
+fn render_matrix(record: &RunRecord, format: ReportFormat, text: &mut String) {
+    let rows: Vec<(&StepRecord, Vec<(String, String)>)> = record
+        .steps
+        .iter()
+        .filter_map(|step| Some((step, matrix::parse_results(step.metadata.get(MATRIX_KEY)?))))
+        .collect();
+    let mut variants: Vec<&String> = vec![];
+    for (label, _) in rows.iter().flat_map(|(_, results)| results) {
+        if !variants.contains(&label) {
+            variants.push(label);
+        }
+    }
+    if variants.is_empty() {
+        return;
+    }
+    let result = |results: &Vec<(String, String)>, variant: &String| {
+        results.iter().find(|(label, _)| label == variant).map_or("-".to_string(), |(_, result)| result.clone())
+    };
+    match format {
+        ReportFormat::Console => {
+            let widths: Vec<usize> = variants.iter().map(|variant| variant.len().max(6)).collect();
+            let mut heading = "Matrix".to_string();
+            for (variant, width) in variants.iter().zip(&widths) {
+                let _ = write!(heading, "  {:<width$}", variant, width = width);
+            }
+            let _ = writeln!(text, "{}", heading.trim_end());
+            for (step, results) in &rows {
+                let mut line = format!("  [{}]{}", step.step, " ".repeat(4usize.saturating_sub(step.step.to_string().len() + 2)));
+                for (variant, width) in variants.iter().zip(&widths) {
+                    let _ = write!(line, "  {:<width$}", result(results, variant), width = width);
+                }
+                let _ = writeln!(text, "{}", line.trim_end());
+            }
+        }
+        ReportFormat::Markdown => {
+            let names: Vec<String> = variants.iter().map(|variant| format!(" {} |", variant)).collect();
+            let _ = writeln!(text, "\n| Step |{}", names.concat());
+            let _ = writeln!(text, "| --- |{}", " --- |".repeat(variants.len()));
+            for (step, results) in &rows {
+                let cells: Vec<String> = variants.iter().map(|variant| format!(" {} |", result(results, variant))).collect();
+                let _ = writeln!(text, "| {} |{}", step.id, cells.concat());
+            }
+        }
+        ReportFormat::Html => {
+            let names: Vec<String> = variants.iter().map(|variant| format!("<th>{}</th>", escape_markup(variant))).collect();
+            let _ = writeln!(text, "<table>\n<tr><th>Step</th>{}</tr>", names.concat());
+            for (step, results) in &rows {
+                let cells: Vec<String> = variants.iter().map(|variant| format!("<td>{}</td>", result(results, variant))).collect();
+                let _ = writeln!(text, "<tr><td>{}</td>{}</tr>", escape_markup(&step.id), cells.concat());
+            }
+            let _ = writeln!(text, "</table>");
+        }
+        // The failed variant is in the failed step's output
+        ReportFormat::Junit => {}
+    }
+}
+
+/// Renders a recorded run without looking at the repository.
+pub fn run_report(mend_dir: &Path, args: &ReportArgs) -> anyhow::Result<()> {
+    let record = read_run(mend_dir, args.run.as_deref())?;
+    print!("{}", render_report(&record, args.format));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::report::{read_run, render_report, write_run, ReportFormat, RunRecord};
+    use crate::run::{EStatus, RunSummary, StepCommit, StepRequest, StepResponse};
+
+    fn create_record() -> RunRecord {
+        let steps = vec![
+            ("1".to_string(), "rename a b".to_string()),
+            ("format".to_string(), "clang-format -i <main.c>".to_string()),
+            ("3".to_string(), "rename c d".to_string()),
+        ];
+        let mut record = RunRecord::new("1700000000".to_string(), "mend.toml", "43a3a253", &steps);
+        let failed_request = StepRequest {
+            id: "format".to_string(),
+            ..Default::default()
+        };
+        let mut failed_response = StepResponse::pending();
+        failed_response.status = EStatus::Failed;
+        failed_response.output = Some("main.c: error & more".to_string());
+        failed_response.metadata = [("matrix".to_string(), "java=11: ok; java=17: failed".to_string())].into();
+        let summary = RunSummary {
+            failed_steps: vec![1],
+            failures: vec![(failed_request, failed_response)],
+            commits: vec![StepCommit {
+                id: "1".to_string(),
+                step: 1,
+                sha: "abc1234".to_string(),
+                metadata: [
+                    ("verified".to_string(), "full".to_string()),
+                    ("matrix".to_string(), "java=11: ok; java=17: ok".to_string()),
+                ]
+                .into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        record.record_summary(&summary);
+        record
+    }
+
+    #[test]
+    fn render_recorded_run_in_every_format() {
+        let record = create_record();
+        let reports: Vec<String> = [
+            ReportFormat::Console,
+            ReportFormat::Markdown,
+            ReportFormat::Html,
+            ReportFormat::Junit,
+        ]
+        .iter()
+        .map(|format| render_report(&record, *format))
+        .collect();
+        insta::assert_snapshot!(reports.join("\n"));
+    }
+
+    #[test]
+    fn latest_run_is_read_back() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut older = create_record();
+        older.id = "999".to_string();
+        write_run(temp_dir.path(), &older).unwrap();
+        write_run(temp_dir.path(), &create_record()).unwrap();
+        assert_eq!(read_run(temp_dir.path(), None).unwrap(), create_record());
+        assert_eq!(read_run(temp_dir.path(), Some("999")).unwrap().id, "999");
+        assert!(read_run(temp_dir.path(), Some("1")).is_err());
+    }
+}
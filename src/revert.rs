@@ -0,0 +1,140 @@
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::repo::{GitRepo, Repo, MEND_DIR, WORKTREE_DIR};
+use crate::run::{Executor, StepCommit};
+use crate::schema::{from_versioned_json, to_versioned_json, Migration};
+
+const COMMITS_FILE: &str = "commits.json";
+
+#[derive(Serialize, Deserialize)]
+struct CommitsFile {
+    commits: Vec<StepCommit>,
+}
+
+/// Before versioning `commits.json` held the bare list.
+const COMMITS_MIGRATIONS: &[Migration] = &[|value| match value {
+    Value::Array(commits) => json!({ "commits": commits }),
+    other => other,
+}];
+
+/// Records which commit each step of the last run ended up in, for `mend revert`.
+pub fn write_commits(mend_dir: &Path, commits: &[StepCommit]) -> anyhow::Result<()> {
+    let path = mend_dir.join(COMMITS_FILE);
+    let file = CommitsFile {
+        commits: commits.to_vec(),
+    };
+    fs::write(&path, to_versioned_json(&file)?)
+        .with_context(|| format!("Could not write `{}`", path.to_string_lossy()))
+}
+
+pub fn read_commits(mend_dir: &Path) -> anyhow::Result<Vec<StepCommit>> {
+    let path = mend_dir.join(COMMITS_FILE);
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("No step commits recorded in `{}`, run mend first", path.to_string_lossy()))?;
+    let file: CommitsFile = from_versioned_json(&path, &contents, COMMITS_MIGRATIONS)?;
+    Ok(file.commits)
+}
+
+/// Finds a step by its id, or by its number for steps without one.
+pub fn find_commit<'a>(commits: &'a [StepCommit], step_id: &str) -> anyhow::Result<&'a StepCommit> {
+    commits
+        .iter()
+        .find(|commit| commit.id == step_id)
+        .or_else(|| commits.iter().find(|commit| commit.step.to_string() == step_id))
+        .ok_or_else(|| anyhow!("No committed step `{}` in the last run", step_id))
+}
+
+/// Reverts one step's commit in mend's worktree, then verifies. A revert that fails verification is dropped.
+pub fn revert_step<E: Executor>(base_repo_dir: &Path, step_id: &str, verify: Option<&str>, executor: &mut E) -> anyhow::Result<()> {
+    let commits = read_commits(&base_repo_dir.join(MEND_DIR))?;
+    let commit = find_commit(&commits, step_id)?;
+    let worktree_dir = base_repo_dir.join(WORKTREE_DIR);
+    if !worktree_dir.exists() {
+        bail!("No mend worktree at `{}`", worktree_dir.to_string_lossy());
+    }
+    let worktree_repo = GitRepo {
+        repo_dir: worktree_dir,
+    };
+    if commits.iter().filter(|other| other.sha == commit.sha).count() > 1 {
+        eprintln!("Step {} shares commit {} with other steps, reverting all of them", step_id, commit.sha);
+    }
+    worktree_repo.revert_commit(&commit.sha)?;
+    if let Some(verify) = verify {
+        let output = executor.run_script(&worktree_repo.repo_dir, verify, &BTreeMap::new(), None, &mut |_| {})?;
+        if !output.status.success() {
+            worktree_repo.drop_head_commit()?;
+            bail!(
+                "Verify failed after reverting step {}, the revert was dropped. Output:\n{}{}",
+                step_id,
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    }
+    println!(
+        "Reverted step {} ({}) as {}",
+        step_id,
+        commit.sha,
+        worktree_repo.current_short_sha()?
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repo::{GitRepo, Repo, MEND_DIR, WORKTREE_DIR};
+    use crate::revert::{find_commit, read_commits, revert_step, write_commits};
+    use crate::run::{ShellExecutor, StepCommit};
+    use std::fs;
+    use std::process::Command;
+
+    fn step_commit(id: &str, step: usize, sha: &str) -> StepCommit {
+        StepCommit {
+            id: id.to_string(),
+            step,
+            sha: sha.to_string(),
+            revert: format!("git revert --no-edit {}", sha),
+            metadata: Default::default(),
+        }
+    }
+
+    #[test]
+    fn finds_commit_by_id_or_number() {
+        let commits = vec![step_commit("1", 1, "aaa"), step_commit("rename", 2, "bbb")];
+        assert_eq!(find_commit(&commits, "rename").unwrap().sha, "bbb");
+        assert_eq!(find_commit(&commits, "2").unwrap().sha, "bbb");
+        assert!(find_commit(&commits, "3").is_err());
+    }
+
+    #[test]
+    fn revert_is_dropped_when_verify_fails() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let worktree_dir = temp_dir.path().join(WORKTREE_DIR);
+        fs::create_dir_all(&worktree_dir).unwrap();
+        let git = |args: &[&str]| {
+            assert!(Command::new("git").current_dir(&worktree_dir).args(args).output().unwrap().status.success());
+        };
+        git(&["init"]);
+        fs::write(worktree_dir.join("file"), "one").unwrap();
+        git(&["add", "file"]);
+        git(&["commit", "-m", "one"]);
+        let mut worktree_repo = GitRepo { repo_dir: worktree_dir.clone() };
+        fs::write(worktree_dir.join("file"), "two").unwrap();
+        worktree_repo.commit_all("two").unwrap();
+        let step_sha = worktree_repo.current_short_sha().unwrap();
+        write_commits(&temp_dir.path().join(MEND_DIR), &[step_commit("1", 1, &step_sha)]).unwrap();
+        assert_eq!(read_commits(&temp_dir.path().join(MEND_DIR)).unwrap().len(), 1);
+
+        assert!(revert_step(temp_dir.path(), "1", Some("grep two file"), &mut ShellExecutor::default()).is_err());
+        assert_eq!(worktree_repo.current_short_sha().unwrap(), step_sha);
+
+        revert_step(temp_dir.path(), "1", Some("grep one file"), &mut ShellExecutor::default()).unwrap();
+        assert_eq!(fs::read_to_string(worktree_dir.join("file")).unwrap(), "one");
+        assert_eq!(worktree_repo.count_commits_since(&step_sha).unwrap(), 1);
+    }
+}
@@ -1,31 +1,406 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use crate::artifacts::{check_step_artifacts, format_growth, ArtifactsConfig, GROWTH_KEY};
+use crate::batch::Chunk;
+use crate::error::{MendError, Result};
+use crate::incremental::{current_state, StepCache};
+use crate::interactive::{self, Approval};
+use crate::logs::write_step_log;
+use crate::matrix::{self, MATRIX_KEY};
 use crate::progress::Notify;
-use crate::repo::Repo;
-use crate::run::EStatus::{Done, Failed, Running};
-use crate::{Mend, Recipe};
-use anyhow::Context;
+use crate::ownership::{foreign_files_owner, normalize_script};
+use crate::repo::{add_worker_worktree, remove_worktree, GitRepo, Identity, Repo};
+use crate::run::EStatus::{Done, Failed, Running, Skipped, VerifyFailed};
+use crate::secrets::without_identity;
+use crate::select::StepSelection;
+use crate::shell::ShellDialect;
+use crate::{CommitMode, Mend, Phase, Recipe, Step, StepConfig};
+use crate::when::Condition;
+use anyhow::{anyhow, Context};
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
 use std::fmt::Debug;
-use std::path::Path;
-use std::process::{Command, Output};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::io::{BufRead, BufReader, Read};
+use std::iter::{self, Peekable};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use indicatif::HumanDuration;
 use which::which;
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct StepRequest {
+    pub id: String,
     pub run: String,
     pub run_resolved: Vec<String>,
-    pub commit_msg: String
+    pub commit_msg: String,
+    pub verify: Option<String>,
+    /// Whether `verify` is the per-step check or the full suite run at milestones
+    #[serde(default)]
+    pub verify_tier: VerifyTier,
+    /// `[verify] full` when the step is checked by `[verify]`, run in place of `verify` when a milestone before it was skipped
+    #[serde(default)]
+    pub full_verify: Option<String>,
+    /// Left out by `--only` or `--skip`, reported as skipped without running
+    #[serde(default)]
+    pub excluded: bool,
+    pub fallback_resolved: Vec<String>,
+    /// Id of the step whose commit this one is a fixup of
+    pub fixup: Option<String>,
+    /// Ids of the earlier steps this one builds on, none when it builds on all of them
+    pub needs: Option<Vec<String>>,
+    /// Skips the step unless this condition holds
+    pub when: Option<String>,
+    /// `[env]` and the step's own `env`, expanded, for the step's scripts
+    pub env: BTreeMap<String, String>,
+    /// Globs of the files the step's recipe reads and writes, for skipping it when they're unchanged
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+    /// Scripts still running this long after the step started are killed
+    pub timeout: Option<Duration>,
+    /// Steps sharing any of these don't run alongside each other
+    pub locks: Vec<String>,
+    /// `Key: value` lines added to the end of the step's commit message
+    #[serde(default)]
+    pub trailers: Vec<String>,
+    /// Who the step's commit is made as, from its `committer_role`, git's configured user when none
+    pub committer: Option<Identity>,
+    /// The recipe's `progress_regex`, for the progress of the step's scripts
+    #[serde(default)]
+    pub progress_regex: Option<String>,
+}
+
+/// Step metadata naming the tier of checks a step passed, `fast` or `full`.
+pub const VERIFIED_KEY: &str = "verified";
+
+#[derive(Debug, Default, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VerifyTier {
+    /// `[verify] run`, after every step
+    #[default]
+    Fast,
+    /// `[verify] full`, at the end of each phase, every `full_every` steps and at the end of the run
+    Full,
+}
+
+impl VerifyTier {
+    pub fn label(self) -> &'static str {
+        match self {
+            VerifyTier::Fast => "fast",
+            VerifyTier::Full => "full",
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct StepResponse {
     pub sha: Option<String>,
     pub status: EStatus,
-    pub output: Option<String>
+    pub output: Option<String>,
+    pub metadata: BTreeMap<String, String>,
+    /// Written by the step's scripts to `$MEND_COMMIT_MSG_FILE`, replaces the rendered message
+    pub commit_msg: Option<String>,
+    /// The step failed but the run goes on although it doesn't keep going, as chosen on leaving the debug shell
+    #[serde(default)]
+    pub continue_run: bool,
+}
+
+/// Built-in step types print lines like `mend:stats matched=3 changed=2` to report what they did.
+pub const STATS_PREFIX: &str = "mend:stats ";
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RunSummary {
+    pub totals: BTreeMap<String, u64>,
+    pub steps_with_stats: usize,
+    /// Indexes of steps that failed when continuing on error
+    pub failed_steps: Vec<usize>,
+    /// The failed steps with their output, in the same order
+    pub failures: Vec<(StepRequest, StepResponse)>,
+    /// Indexes of steps skipped because their `when` condition didn't hold
+    #[serde(default)]
+    pub skipped_steps: Vec<usize>,
+    /// The commit each step ended up in, fixups are part of their target's commit
+    pub commits: Vec<StepCommit>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct StepCommit {
+    pub id: String,
+    /// Counting from 1
+    pub step: usize,
+    pub sha: String,
+    /// Undoes just this step, run in the worktree
+    pub revert: String,
+    /// Stats and results the step reported
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl RunSummary {
+    fn commit_sha(&self, id: &str) -> Option<&String> {
+        self.commits.iter().find(|commit| commit.id == id).map(|commit| &commit.sha)
+    }
+
+    /// Points the commits at new shas after history was rewritten, `new_shas` oldest first.
+    fn remap_commits(&mut self, new_shas: &[String]) {
+        let mut old_shas: Vec<String> = self.commits.iter().map(|commit| commit.sha.clone()).collect();
+        old_shas.dedup();
+        if old_shas.len() != new_shas.len() {
+            return;
+        }
+        for commit in self.commits.iter_mut() {
+            if let Some(position) = old_shas.iter().position(|sha| *sha == commit.sha) {
+                commit.sha = new_shas[position].clone();
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RunOptions {
+    /// Keep running later steps after one fails instead of stopping
+    pub continue_on_error: bool,
+    /// Save what a failed step changed to a `mend/failed-<n>` branch before resetting
+    pub quarantine: bool,
+    /// Step commits replaced by one commit each, from `[commit] mode`
+    pub squash_groups: Vec<SquashGroup>,
+    /// Steps before this one are already done, when resuming a run
+    pub first_step: usize,
+    /// The commits of those steps
+    pub resumed_commits: Vec<StepCommit>,
+    /// Variables every step's scripts get, like `MEND_BIN`
+    pub env: BTreeMap<String, String>,
+    /// Where steps with `inputs` remember their last successful run, none to always run them
+    pub step_cache: Option<PathBuf>,
+    /// How many steps with `needs` may run at once, each in a worktree of its own. Below 2 steps run one after another
+    pub max_parallel_steps: usize,
+    /// Where each step that ran gets a log of its scripts and output, none to keep no logs
+    pub log_dir: Option<PathBuf>,
+    /// Build artifacts to remove and warn about before each commit, none to commit whatever the steps left
+    pub artifacts: Option<ArtifactsConfig>,
+    /// Ask on the terminal before each step whether to run, skip or edit it, or stop the run
+    pub interactive: bool,
+    /// What happens to a failed step before it's reset, none to reset it right away
+    pub on_failure: Option<OnFailure>,
+    /// The `[matrix]` variants each step's `verify` runs in, none to run it once
+    pub matrix: Vec<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone, Copy, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum OnFailure {
+    /// Reset the failed step's changes
+    #[default]
+    Reset,
+    /// Open a shell in the worktree with the step's environment, `exit 0` goes on with the run and `exit 1` stops it
+    Shell,
+}
+
+/// Consecutive steps that are committed one by one, then squashed once the last of them ran.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SquashGroup {
+    pub first_step: usize,
+    pub last_step: usize,
+    pub commit_msg: String,
+}
+
+pub fn plan_squash_groups(mend: &Mend, step_requests: &[StepRequest]) -> Vec<SquashGroup> {
+    let mode = mend.commit.as_ref().map(|commit| commit.mode).unwrap_or_default();
+    match mode {
+        CommitMode::Step => chunk_groups(mend, step_requests),
+        CommitMode::Phase => mend
+            .phases
+            .iter()
+            .filter(|phase| phase.step_count > 0)
+            .map(|phase| {
+                let template = phase.commit_template.as_deref().unwrap_or(&phase.name);
+                let title = shellexpand::env_with_context_no_errors(template, |var: &str| {
+                    if var == "phase" { Some(phase.name.clone()) } else { commit_env_var(mend, var) }
+                });
+                squash_group(step_requests, phase.first_step, phase.first_step + phase.step_count - 1, &title, &commit_trailers(mend))
+            })
+            .collect(),
+        CommitMode::Squash if step_requests.is_empty() => vec![],
+        CommitMode::Squash => {
+            let default_title = format!("Apply {} mend steps", step_requests.len());
+            let title = match mend.commit.as_ref().and_then(|commit| commit.squash_template.as_deref()) {
+                Some(template) => shellexpand::env_with_context_no_errors(template, |var: &str| commit_env_var(mend, var)).to_string(),
+                None => default_title,
+            };
+            vec![squash_group(step_requests, 0, step_requests.len() - 1, &title, &commit_trailers(mend))]
+        }
+    }
+}
+
+/// A group for each chunk of the steps expanded from a batch file with a `chunk_size`, its commit numbered when there
+/// are several, e.g. `rename @renames.csv (2/3)`.
+fn chunk_groups(mend: &Mend, step_requests: &[StepRequest]) -> Vec<SquashGroup> {
+    let chunks: Vec<(usize, Option<&Chunk>)> = mend.steps.iter().map(Step::chunk).enumerate().collect();
+    chunks
+        .chunk_by(|(_, chunk), (_, next_chunk)| chunk == next_chunk)
+        .filter_map(|steps| {
+            let (first_step, chunk) = steps.first()?;
+            let (last_step, _) = steps.last()?;
+            let chunk = (*chunk)?;
+            let title = match chunk.count {
+                1 => chunk.title.clone(),
+                count => format!("{} ({}/{})", chunk.title, chunk.number, count),
+            };
+            Some(squash_group(step_requests, *first_step, *last_step, &title, &commit_trailers(mend)))
+        })
+        .collect()
 }
 
+/// The squashed commit keeps each step's message in its body.
+fn squash_group(step_requests: &[StepRequest], first_step: usize, last_step: usize, title: &str, trailers: &[String]) -> SquashGroup {
+    let body: Vec<String> = step_requests[first_step..=last_step]
+        .iter()
+        .map(|step_request| format!("- {}", step_request.commit_msg))
+        .collect();
+    SquashGroup {
+        first_step,
+        last_step,
+        commit_msg: with_trailers(&format!("{}\n\n{}", title, body.join("\n")), trailers),
+    }
+}
+
+/// The `[committers]` identity for `role` with its variables expanded, e.g. `email = "$BOT_EMAIL"`.
+fn committer(mend: &Mend, role: &str) -> Option<Identity> {
+    let expand = |value: &str| shellexpand::env_with_context_no_errors(value, |var: &str| commit_env_var(mend, var)).to_string();
+    mend.committers.get(role).map(|identity| Identity {
+        name: expand(&identity.name),
+        email: expand(&identity.email),
+    })
+}
+
+/// Fails unless the identity of each step's `committer_role` has a name and an email once its variables are expanded,
+/// checked before the run starts so no step is committed as someone other than its role.
+pub fn check_committers(mend: &Mend) -> Result<()> {
+    for (step_i, step) in mend.steps.iter().enumerate() {
+        let Some(role) = step.committer_role() else {
+            continue;
+        };
+        let identity = mend
+            .committers
+            .get(role)
+            .ok_or_else(|| MendError::Validation(format!("Step {} commits as `{}`, which is not in [committers]", step_i + 1, role)))?;
+        for (field, value) in [("name", &identity.name), ("email", &identity.email)] {
+            let expanded = shellexpand::env_with_context(value, |var: &str| commit_env_var(mend, var).map(Some).ok_or("not set"))
+                .map_err(|err| {
+                    MendError::Validation(format!(
+                        "The {} of committer `{}` needs `${}`, which is not set or not in [commit] allow_env",
+                        field,
+                        role,
+                        err.var_name
+                    ))
+                })?;
+            if expanded.trim().is_empty() {
+                return Err(MendError::Validation(format!("Committer `{}` has no {}", role, field)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `[commit] trailers` with their variables expanded.
+fn commit_trailers(mend: &Mend) -> Vec<String> {
+    let Some(commit) = &mend.commit else {
+        return vec![];
+    };
+    commit
+        .trailers
+        .iter()
+        .map(|trailer| shellexpand::env_with_context_no_errors(trailer, |var: &str| commit_env_var(mend, var)).to_string())
+        .collect()
+}
+
+/// The step's commit trailers, with `[commit] step_trailers` its number and recipe too.
+fn step_trailers(mend: &Mend, step_i: usize, step_request: &StepRequest) -> Vec<String> {
+    let mut trailers = commit_trailers(mend);
+    if mend.commit.as_ref().is_some_and(|commit| commit.step_trailers) {
+        trailers.push(format!("Mend-Step: {}", step_i + 1));
+        if let Some(recipe_name) = find_matching_recipes(step_request.run.trim(), mend).into_keys().next() {
+            trailers.push(format!("Mend-Recipe: {}", recipe_name));
+        }
+    }
+    trailers
+}
+
+/// `message` with a paragraph of `trailers` at the end, as `git interpret-trailers` expects them.
+fn with_trailers(message: &str, trailers: &[String]) -> String {
+    if trailers.is_empty() {
+        message.to_string()
+    } else {
+        format!("{}\n\n{}", message.trim_end(), trailers.join("\n"))
+    }
+}
+
+/// Branch holding the changes of failed step `step_i`, numbered from 1 like the progress output.
+pub fn quarantine_branch(step_i: usize) -> String {
+    format!("mend/failed-{}", step_i + 1)
+}
+
+impl RunSummary {
+    fn add_step(&mut self, step_response: &StepResponse) {
+        let mut has_stats = false;
+        for (key, value) in &step_response.metadata {
+            if let Ok(count) = value.parse::<u64>() {
+                *self.totals.entry(key.to_string()).or_default() += count;
+                has_stats = true;
+            }
+        }
+        if has_stats {
+            self.steps_with_stats += 1;
+        }
+    }
+}
 
 impl StepResponse {
+    pub fn pending() -> Self {
+        StepResponse { sha: None, status: EStatus::Pending, output: None, metadata: BTreeMap::new(), commit_msg: None, continue_run: false }
+    }
+
+    pub fn record_stats(&mut self, stdout: &str) {
+        for line in stdout.lines() {
+            if let Some(stats) = line.strip_prefix(STATS_PREFIX) {
+                for pair in stats.split_whitespace() {
+                    if let Some((key, value)) = pair.split_once('=') {
+                        let count = value.parse::<u64>().unwrap_or_default();
+                        self.add_metadata(key, &count.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads the `key=value` lines a step wrote to `$MEND_RESULT_FILE`.
+    pub fn record_results(&mut self, results: &str) {
+        for line in results.lines() {
+            if let Some((key, value)) = line.split_once('=') {
+                if !key.trim().is_empty() {
+                    self.add_metadata(key.trim(), value.trim());
+                }
+            }
+        }
+    }
+
+    /// Numbers add up, other values given more than once are kept one per line.
+    fn add_metadata(&mut self, key: &str, value: &str) {
+        let combined = match (self.metadata.get(key), value.parse::<u64>()) {
+            (None, _) => value.to_string(),
+            (Some(previous), Ok(count)) if previous.parse::<u64>().is_ok() => {
+                (previous.parse::<u64>().unwrap_or_default() + count).to_string()
+            }
+            (Some(previous), _) => format!("{}\n{}", previous, value),
+        };
+        self.metadata.insert(key.to_string(), combined);
+    }
+
     pub fn push_output_str(&mut self, text: &str) {
         match &self.output {
             None => self.output = Some(text.to_string()),
@@ -34,49 +409,422 @@ impl StepResponse {
     }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
 pub enum EStatus {
     Pending,
     Running,
     Done,
     Failed,
+    /// Its scripts ran but `verify` failed on the change, the step broke the build rather than failing to apply
+    VerifyFailed,
+    /// Its `when` condition didn't hold
+    Skipped,
 }
 
-fn resolve_step_scripts(instruction: &String, mend: &Mend, matching_recipes: BTreeMap<&String, &Recipe>) -> Vec<String> {
-    let mut resolved_instruction = "".to_owned();
-    let mut scripts = vec![];
+impl EStatus {
+    /// Failed either way, in its scripts or its verification.
+    pub fn is_failed(&self) -> bool {
+        matches!(self, Failed | VerifyFailed)
+    }
+}
+
+/// The step's scripts wrapped in the hooks matching its recipes' tags, those of `phase` first.
+fn resolve_step_scripts(instruction: &str, mend: &Mend, phase: Option<&Phase>, matching_recipes: BTreeMap<&String, &Recipe>, step_exit_codes: Option<&Vec<i32>>) -> Vec<String> {
+    let dialect = shell_dialect(mend);
+    let mut functions = "".to_owned();
     let mut recipe_tags: Vec<String> = vec![];
+    let exit_codes = step_exit_codes.cloned().or_else(|| matching_recipes.values().find_map(|recipe| recipe.expected_exit_codes.clone()));
 
+    let mut call = instruction.to_string();
     for (recipe_name, recipe) in matching_recipes {
-        let recipe_fn = format!("function {}() {{\n{}\n}}\n", recipe_name, recipe.run);
-        resolved_instruction.push_str(&recipe_fn);
         for tag in &recipe.tags {
             recipe_tags.push(tag.to_string())
         }
+        if let Some(interpreter) = &recipe.interpreter {
+            // Not a shell function, the body goes to the interpreter as it is
+            let step_args = StepArgs::parse(instruction, Some(recipe));
+            let values: Vec<String> = step_args.positional.iter().map(|value| dialect.quote(value)).collect();
+            call = dialect.interpreter_call(interpreter, &recipe.run, &values, &step_args.named);
+            continue;
+        }
+        functions.push_str(&dialect.recipe_function(recipe_name, &recipe.run, &recipe.params));
+        let function_name = dialect.function_name(recipe_name);
+        if !recipe.params.is_empty() {
+            // Passed in the order of `params`, however the step named them
+            let step_args = StepArgs::parse(instruction, Some(recipe));
+            let values: Vec<String> = step_args.positional.iter().map(|value| dialect.quote(value)).collect();
+            call = format!("{} {}", function_name, values.join(" "));
+        } else if let Some(args) = instruction.trim_start().strip_prefix(recipe_name.as_str()) {
+            call = format!("{}{}", function_name, args);
+        }
+    }
+    // Hooks only check membership, sorted so the same tags always give the same request
+    recipe_tags.sort();
+    recipe_tags.dedup();
+    let mut resolved_instruction = dialect.step_script(&functions, &call);
+    if let Some(exit_codes) = exit_codes {
+        resolved_instruction = dialect.accept_exit_codes(resolved_instruction, &exit_codes);
+    }
+
+    wrap_with_hooks(resolved_instruction, mend, phase, &recipe_tags)
+}
+
+/// The dialect of the configured shell, POSIX unless set. `drive` sets it from the shell it found.
+pub fn shell_dialect(mend: &Mend) -> ShellDialect {
+    mend.shell.as_ref().and_then(|shell| shell.dialect).unwrap_or_default()
+}
+
+/// Values of a recipe's `params` for the words after the recipe name, in the order the params are declared.
+/// A word like `name=value` sets a param by name, the other words fill the remaining params in order.
+pub fn bind_params(recipe_name: &str, params: &[String], words: &[&str]) -> Result<Vec<(String, String)>> {
+    let declared: Vec<(&str, Option<&str>)> = params
+        .iter()
+        .map(|param| match param.split_once('=') {
+            Some((name, default)) => (name, Some(default)),
+            None => (param.as_str(), None),
+        })
+        .collect();
+    let mut named = BTreeMap::new();
+    let mut positional = vec![];
+    for word in words {
+        match word.split_once('=') {
+            Some((name, value)) if declared.iter().any(|(declared_name, _)| *declared_name == name) => {
+                named.insert(name, value);
+            }
+            _ => positional.push(*word),
+        }
+    }
+    let mut positional = positional.into_iter();
+    let mut bound = vec![];
+    for (name, default) in declared {
+        let value = named
+            .get(name)
+            .copied()
+            .or_else(|| positional.next())
+            .or(default)
+            .ok_or_else(|| {
+                MendError::Validation(format!("Recipe `{}` needs a value for its param `{}`, e.g. `{}=...`", recipe_name, name, name))
+            })?;
+        bound.push((name.to_string(), value.to_string()));
+    }
+    let extra: Vec<&str> = positional.collect();
+    if !extra.is_empty() {
+        return Err(MendError::Validation(format!("Recipe `{}` has no param for `{}`", recipe_name, extra.join(" "))));
+    }
+    Ok(bound)
+}
+
+/// The arguments of an instruction, as `$1`.. and for recipes with `params` as `$name`.
+struct StepArgs {
+    positional: Vec<String>,
+    named: Vec<(String, String)>,
+}
+
+impl StepArgs {
+    /// Params that can't be bound count as plain words, `load_mend` already rejected such steps.
+    fn parse(instruction: &str, recipe: Option<&Recipe>) -> Self {
+        let words: Vec<&str> = instruction.split_whitespace().collect();
+        let (recipe_name, args) = words.split_first().map_or(("", &[][..]), |(name, args)| (*name, args));
+        let bound = recipe
+            .filter(|recipe| !recipe.params.is_empty())
+            .and_then(|recipe| bind_params(recipe_name, &recipe.params, args).ok());
+        match bound {
+            Some(named) => StepArgs {
+                positional: named.iter().map(|(_, value)| value.clone()).collect(),
+                named,
+            },
+            None => StepArgs {
+                positional: args.iter().map(|arg| arg.to_string()).collect(),
+                named: vec![],
+            },
+        }
     }
-    resolved_instruction.push_str(&instruction);
-    resolved_instruction.push('\n');
 
-    add_matching_hooks(&mut scripts, mend, "before_step", &recipe_tags);
-    scripts.push(resolved_instruction);
-    add_matching_hooks(&mut scripts, mend, "after_step", &recipe_tags);
+    /// Looks up `$1`.. or `$name`.
+    fn get(&self, name: &str) -> Option<String> {
+        match str::parse::<usize>(name) {
+            Ok(arg_num) if arg_num >= 1 => self.positional.get(arg_num - 1).cloned(),
+            Ok(_) => None,
+            Err(_) => self
+                .named
+                .iter()
+                .find(|(param, _)| param == name)
+                .map(|(_, value)| value.clone()),
+        }
+    }
+}
+
+fn wrap_with_hooks(script: String, mend: &Mend, phase: Option<&Phase>, tags: &[String]) -> Vec<String> {
+    let mut scripts = vec![];
+    add_matching_hooks(&mut scripts, mend, phase, "before_step", tags);
+    scripts.push(script);
+    add_matching_hooks(&mut scripts, mend, phase, "after_step", tags);
     scripts
 }
 
+/// A step type implemented by mend rather than by a user recipe.
+pub trait BuiltinStep {
+    /// Shown in progress output and used as the commit message.
+    fn describe(&self) -> String;
+    fn to_script(&self) -> Result<String>;
+}
+
 pub trait Executor {
-    fn run_script(&mut self, cwd: &Path, script: &str) -> anyhow::Result<Output>;
+    /// Runs `script` with `env` added to mend's own environment, killing it once `timeout` has passed.
+    /// Each line the script prints is passed to `on_line` as it comes, and is part of the returned output too.
+    fn run_script(
+        &mut self,
+        cwd: &Path,
+        script: &str,
+        env: &BTreeMap<String, String>,
+        timeout: Option<Duration>,
+        on_line: &mut dyn FnMut(&str),
+    ) -> Result<Output>;
+
+    /// An executor for steps running on other threads, None when steps have to run one after another.
+    fn worker(&self) -> Option<Box<dyn Executor + Send>> {
+        None
+    }
+}
+
+impl<E: Executor + ?Sized> Executor for Box<E> {
+    fn run_script(
+        &mut self,
+        cwd: &Path,
+        script: &str,
+        env: &BTreeMap<String, String>,
+        timeout: Option<Duration>,
+        on_line: &mut dyn FnMut(&str),
+    ) -> Result<Output> {
+        (**self).run_script(cwd, script, env, timeout, on_line)
+    }
+
+    fn worker(&self) -> Option<Box<dyn Executor + Send>> {
+        (**self).worker()
+    }
 }
 
-pub struct ShellExecutor {}
+/// Runs scripts with `<shell> -c <script>`, the shell being a program and its leading arguments.
+/// PowerShell and cmd get their own way of taking a script, see `ShellDialect`.
+#[derive(Clone)]
+pub struct ShellExecutor {
+    shell: Vec<String>,
+    dialect: ShellDialect,
+    normalize_ownership: bool,
+    masked: Vec<String>,
+}
+
+/// Tried in order when the config doesn't list its own shells.
+#[cfg(not(windows))]
+pub const DEFAULT_SHELLS: &[&str] = &["sh", "bash", "dash", "busybox sh"];
+/// Tried in order when the config doesn't list its own shells, Git for Windows brings bash.
+#[cfg(windows)]
+pub const DEFAULT_SHELLS: &[&str] = &["bash", "sh", "pwsh", "powershell", "cmd"];
+
+static SCRIPT_FILES_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+impl Default for ShellExecutor {
+    fn default() -> Self {
+        ShellExecutor { shell: vec!["sh".to_string()], dialect: ShellDialect::Posix, normalize_ownership: false, masked: vec![] }
+    }
+}
+
+impl ShellExecutor {
+    /// Uses the first of `candidates` that is installed, so a missing shell is one setup error rather than a failure per step.
+    /// Its dialect is guessed from its name.
+    pub fn find(candidates: &[String]) -> Result<Self> {
+        candidates
+            .iter()
+            .map(|candidate| candidate.split_whitespace().map(str::to_string).collect::<Vec<String>>())
+            .find(|shell| shell.first().is_some_and(|program| which(program).is_ok()))
+            .map(|shell| ShellExecutor { dialect: ShellDialect::of_program(&shell[0]), shell, normalize_ownership: false, masked: vec![] })
+            .ok_or_else(|| MendError::Validation(format!(
+                "No shell to run steps with, tried {}. Install one of them or list an installed shell in `[shell] candidates`",
+                candidates.join(", ")
+            )))
+    }
+
+    /// For shells whose name doesn't give away their dialect, e.g. a wrapper script.
+    pub fn with_dialect(self, dialect: ShellDialect) -> Self {
+        ShellExecutor { dialect, ..self }
+    }
+
+    pub fn dialect(&self) -> ShellDialect {
+        self.dialect
+    }
+
+    /// After each script, hands files the shell left to another user, or unwritable, back to the worktree's owner.
+    pub fn normalizing_ownership(self, normalize_ownership: bool) -> Self {
+        ShellExecutor { normalize_ownership, ..self }
+    }
+
+    /// Prints `***` for the values of `secrets` in what the scripts print, which ends up in logs, bundles and on the forge.
+    /// Each line of a multi-line value is masked too, as the output is streamed a line at a time.
+    pub fn masking(self, secrets: &BTreeMap<String, String>) -> Self {
+        let mut masked: Vec<String> = secrets
+            .values()
+            .flat_map(|value| iter::once(value.trim()).chain(value.lines().map(str::trim)))
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+            .collect();
+        // The longest first, so a value containing another isn't left half masked
+        masked.sort_by_key(|value| Reverse(value.len()));
+        masked.dedup();
+        ShellExecutor { masked, ..self }
+    }
+
+    fn mask(&self, text: &str) -> String {
+        self.masked.iter().fold(text.to_string(), |text, value| text.replace(value.as_str(), "***"))
+    }
+
+    fn run_shell(
+        &self,
+        cwd: &Path,
+        script: &str,
+        env: &BTreeMap<String, String>,
+        timeout: Option<Duration>,
+        on_line: &mut dyn FnMut(&str),
+    ) -> Result<Output> {
+        // cmd only runs multi-line scripts from a batch file, which needs CRLF for labels to be found reliably
+        let script_file = match self.dialect {
+            ShellDialect::Cmd => {
+                let path = env::temp_dir().join(format!(
+                    "mend-{}-{}.cmd",
+                    std::process::id(),
+                    SCRIPT_FILES_COUNT.fetch_add(1, Ordering::Relaxed)
+                ));
+                let contents = format!("@echo off\n{}", script).replace("\r\n", "\n").replace('\n', "\r\n");
+                fs::write(&path, contents).with_context(|| format!("Could not write `{}`", path.to_string_lossy()))?;
+                Some(path)
+            }
+            _ => None,
+        };
+        let script_file_str = script_file.as_ref().map(|path| path.to_string_lossy().to_string());
+        let mut args: Vec<&str> = self.shell.iter().skip(1).map(String::as_str).collect();
+        args.extend(self.dialect.script_args());
+        args.push(script_file_str.as_deref().unwrap_or(script));
+        let output = run_command_streaming(cwd, self.shell[0].clone(), args, env, timeout, on_line);
+        if let Some(script_file) = script_file {
+            let _ = fs::remove_file(script_file);
+        }
+        output
+    }
+
+    fn hand_back_files(&self, cwd: &Path, env: &BTreeMap<String, String>) -> Result<()> {
+        let Some((uid, gid)) = foreign_files_owner(cwd) else {
+            return Ok(());
+        };
+        let output = self.run_shell(cwd, &normalize_script(uid, gid), env, None, &mut |_| {})?;
+        if foreign_files_owner(cwd).is_some() {
+            return Err(anyhow!(
+                "Could not hand the files the step created back to {}:{}, does `{}` run as root? {}",
+                uid,
+                gid,
+                self.shell.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
 
 impl Executor for ShellExecutor {
-    fn run_script(&mut self, cwd: &Path, script: &str) -> anyhow::Result<Output> {
-        run_command_with_output(cwd, "sh".to_string(), vec!["-c", script])
+    fn run_script(
+        &mut self,
+        cwd: &Path,
+        script: &str,
+        env: &BTreeMap<String, String>,
+        timeout: Option<Duration>,
+        on_line: &mut dyn FnMut(&str),
+    ) -> Result<Output> {
+        let output = if self.masked.is_empty() {
+            self.run_shell(cwd, script, env, timeout, on_line)?
+        } else {
+            let mut output = self.run_shell(cwd, script, env, timeout, &mut |line| on_line(&self.mask(line)))?;
+            output.stdout = self.mask(&String::from_utf8_lossy(&output.stdout)).into_bytes();
+            output.stderr = self.mask(&String::from_utf8_lossy(&output.stderr)).into_bytes();
+            output
+        };
+        if self.normalize_ownership {
+            self.hand_back_files(cwd, env)?;
+        }
+        Ok(output)
+    }
+
+    fn worker(&self) -> Option<Box<dyn Executor + Send>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+static STEP_FILES_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Files a step's scripts can write to pass things back to mend, named in their environment.
+pub struct StepFiles {
+    dir: PathBuf,
+}
+
+impl StepFiles {
+    pub fn create() -> Result<Self> {
+        let dir = env::temp_dir().join(format!(
+            "mend-{}-{}",
+            std::process::id(),
+            STEP_FILES_COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Could not create `{}`", dir.to_string_lossy()))?;
+        Ok(StepFiles { dir })
+    }
+
+    fn commit_msg_path(&self) -> PathBuf {
+        self.dir.join("commit-msg")
+    }
+
+    fn result_path(&self) -> PathBuf {
+        self.dir.join("result")
+    }
+
+    pub fn env(&self) -> BTreeMap<String, String> {
+        BTreeMap::from([
+            (
+                "MEND_COMMIT_MSG_FILE".to_string(),
+                self.commit_msg_path().to_string_lossy().to_string(),
+            ),
+            (
+                "MEND_RESULT_FILE".to_string(),
+                self.result_path().to_string_lossy().to_string(),
+            ),
+        ])
+    }
+
+    /// Forgets what an attempt wrote, before a fallback runs.
+    fn clear(&self) {
+        let _ = fs::remove_file(self.commit_msg_path());
+        let _ = fs::remove_file(self.result_path());
+    }
+
+    fn results(&self) -> Option<String> {
+        fs::read_to_string(self.result_path()).ok()
+    }
+
+    fn commit_msg(&self) -> Option<String> {
+        fs::read_to_string(self.commit_msg_path())
+            .ok()
+            .map(|message| message.trim().to_string())
+            .filter(|message| !message.is_empty())
     }
 }
 
-fn add_matching_hooks(scripts: &mut Vec<String>, mend: &Mend, key: &str, tags: &Vec<String>) {
-    if let Some(hooks) = mend.hooks.get(key) {
+impl Drop for StepFiles {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Hooks of `phase` come before the top level ones.
+fn add_matching_hooks(scripts: &mut Vec<String>, mend: &Mend, phase: Option<&Phase>, key: &str, tags: &[String]) {
+    let phase_hooks = phase.and_then(|phase| phase.hooks.get(key));
+    for hooks in phase_hooks.into_iter().chain(mend.hooks.get(key)) {
         for hook in hooks {
             if let Some(hook_run) = &hook.run {
                 if let Some(when_tag) = &hook.when_tag {
@@ -95,90 +843,833 @@ fn add_matching_hooks(scripts: &mut Vec<String>, mend: &Mend, key: &str, tags: &
     }
 }
 
-pub fn create_run_status_from_mend(mend: &Mend) -> Vec<StepRequest> {
-    mend
-            .steps
-            .iter()
-            .map({
-                |step_text| {
-                    let instruction = step_text.to_string();
-
-                    let instruction_trimmed = instruction.trim();
-                    let instruction_recipe_name = instruction_trimmed.split_whitespace().next().unwrap_or_default().to_string();
-                    let matching_recipes : BTreeMap<&String, &Recipe> = mend.recipes.iter()
-                        .filter(|&(recipe_name, _)| (recipe_name.eq(&instruction_recipe_name))).collect();
-                    let commit_msg = render_commit_message(instruction_trimmed, &matching_recipes);
-                    StepRequest {
-                        run: step_text.to_string(),
-                        run_resolved: resolve_step_scripts(&instruction, mend, matching_recipes),
-                        commit_msg
-                    }
-                }
-            }).collect()
+/// The `before_step` and `after_step` hooks that run around step `step_i` with `instruction`, matched by its recipe's tags.
+pub fn step_hooks(instruction: &str, mend: &Mend, step_i: usize) -> (Vec<String>, Vec<String>) {
+    let phase = step_phase(mend, step_i);
+    let mut tags: Vec<String> = find_matching_recipes(instruction.trim(), mend)
+        .values()
+        .flat_map(|recipe| recipe.tags.clone())
+        .collect();
+    tags.sort();
+    tags.dedup();
+    let (mut before, mut after) = (vec![], vec![]);
+    add_matching_hooks(&mut before, mend, phase, "before_step", &tags);
+    add_matching_hooks(&mut after, mend, phase, "after_step", &tags);
+    (before, after)
+}
+
+/// The phase step `step_i` is in, None for the top level steps.
+fn step_phase(mend: &Mend, step_i: usize) -> Option<&Phase> {
+    mend.phases
+        .iter()
+        .find(|phase| (phase.first_step..phase.first_step + phase.step_count).contains(&step_i))
+}
+
+/// The steps of `mend` resolved for running, those `selection` leaves out marked as excluded.
+pub fn create_run_status_from_mend(mend: &Mend, selection: &StepSelection) -> Vec<StepRequest> {
+    let mut step_requests: Vec<StepRequest> = mend
+            .steps
+            .iter()
+            .enumerate()
+            .map(|(step_i, step)| {
+                let phase = step_phase(mend, step_i);
+                let mut step_request = match step {
+                    Step::Instruction(instruction) => create_instruction_request(instruction, mend, phase, &StepConfig::default()),
+                    Step::Structured(step_config) => create_structured_request(step_config, mend, phase),
+                };
+                step_request.id = step.id(step_i);
+                step_request.fixup = step.fixup().cloned();
+                step_request.needs = step.needs().cloned();
+                step_request.when = step.when().cloned();
+                step_request.env = step_env(mend, step.env());
+                step_request.excluded = !selection.includes(step_i, &step_request);
+                step_request.trailers = step_trailers(mend, step_i, &step_request);
+                step_request.committer = step.committer_role().and_then(|role| committer(mend, role));
+                step_request
+            }).collect();
+    use_full_verify(mend, &mut step_requests);
+    step_requests
+}
+
+/// Steps checked by `[verify]` run `[verify] full` in its place at milestones: the last of them in each phase, every
+/// `full_every`th of them and the last of them, counting the steps the selection runs. The slow suite then runs a few
+/// times per run instead of after every step. A recipe's own `verify`, `verify = ""` included, is kept.
+fn use_full_verify(mend: &Mend, step_requests: &mut [StepRequest]) {
+    let Some(verify) = &mend.verify else {
+        return;
+    };
+    let Some(full) = verify.full.as_ref().filter(|full| !full.trim().is_empty()) else {
+        return;
+    };
+    for (step_i, step_request) in step_requests.iter_mut().enumerate() {
+        if !has_recipe_verify(mend, step_i) {
+            step_request.full_verify = Some(full.clone());
+        }
+    }
+    let to_run: Vec<usize> = (0..step_requests.len())
+        .filter(|step_i| step_requests[*step_i].full_verify.is_some() && !step_requests[*step_i].excluded)
+        .collect();
+    let mut milestones: Vec<usize> = mend
+        .phases
+        .iter()
+        .filter_map(|phase| {
+            let phase_steps = phase.first_step..phase.first_step + phase.step_count;
+            to_run.iter().rev().find(|step_i| phase_steps.contains(step_i)).copied()
+        })
+        .collect();
+    if let Some(every) = verify.full_every.filter(|every| *every > 0) {
+        milestones.extend(to_run.iter().skip(every - 1).step_by(every));
+    }
+    milestones.extend(to_run.last());
+    for step_i in milestones {
+        take_full_verify(&mut step_requests[step_i]);
+    }
+}
+
+/// Whether step `step_i` runs a recipe with a `verify` of its own, which `[verify]` doesn't replace.
+fn has_recipe_verify(mend: &Mend, step_i: usize) -> bool {
+    let instruction = match &mend.steps[step_i] {
+        Step::Instruction(instruction) => Some(instruction.as_str()),
+        Step::Structured(step_config) if step_config.builtin().is_none() => step_config.run.as_deref(),
+        Step::Structured(_) => None,
+    };
+    instruction.is_some_and(|instruction| find_matching_recipes(instruction.trim(), mend).values().any(|recipe| recipe.verify.is_some()))
+}
+
+/// Has the step run `[verify] full` in place of its check, false when it's checked by its recipe instead.
+fn take_full_verify(step_request: &mut StepRequest) -> bool {
+    if step_request.full_verify.is_none() {
+        return false;
+    }
+    step_request.verify = step_request.full_verify.clone();
+    step_request.verify_tier = VerifyTier::Full;
+    true
+}
+
+/// `[env]` with its values expanded against mend's own environment.
+pub fn resolve_env(mend: &Mend) -> BTreeMap<String, String> {
+    mend.env
+        .iter()
+        .map(|(key, value)| {
+            let expanded = shellexpand::env(value).map(|expanded| expanded.to_string()).unwrap_or_else(|_| value.clone());
+            (key.clone(), expanded)
+        })
+        .collect()
+}
+
+/// `[env]` with a step's own variables on top, those can refer to `[env]` values, e.g. `PATH = "$PATH:bin"`.
+fn step_env(mend: &Mend, overrides: Option<&BTreeMap<String, String>>) -> BTreeMap<String, String> {
+    let mut merged = resolve_env(mend);
+    for (key, value) in overrides.into_iter().flatten() {
+        let expanded = shellexpand::env_with_context_no_errors(value, |name: &str| {
+            merged.get(name).cloned().or_else(|| env::var(name).ok())
+        })
+        .to_string();
+        merged.insert(key.clone(), expanded);
+    }
+    merged
+}
+
+/// The step's own timeout, else its recipe's, else the config's. They were checked when the config was loaded.
+fn step_timeout(mend: &Mend, step_config: &StepConfig, recipe_timeout: Option<&String>) -> Option<Duration> {
+    step_config
+        .timeout
+        .as_ref()
+        .or(recipe_timeout)
+        .or(mend.timeout.as_ref())
+        .and_then(|timeout| parse_timeout(timeout).ok())
+}
+
+fn find_matching_recipes<'a>(instruction: &str, mend: &'a Mend) -> BTreeMap<&'a String, &'a Recipe> {
+    let instruction_recipe_name = instruction.split_whitespace().next().unwrap_or_default().to_string();
+    mend.recipes.iter()
+        .filter(|&(recipe_name, _)| recipe_name.eq(&instruction_recipe_name)).collect()
+}
+
+/// `step_config` holds the step level settings when the instruction came from a step table.
+fn create_instruction_request(step_text: &str, mend: &Mend, phase: Option<&Phase>, step_config: &StepConfig) -> StepRequest {
+    let instruction_trimmed = step_text.trim();
+    let matching_recipes = find_matching_recipes(instruction_trimmed, mend);
+    let commit_msg = render_commit_message(instruction_trimmed, &matching_recipes, mend);
+    let recipe_verify = matching_recipes.values().find_map(|recipe| recipe.verify.clone());
+    let fallback = step_config.fallback.clone().or_else(|| matching_recipes.values().find_map(|recipe| recipe.fallback.clone()));
+    let inputs = matching_recipes.values().flat_map(|recipe| recipe.inputs.clone()).collect();
+    let outputs = matching_recipes.values().flat_map(|recipe| recipe.outputs.clone()).collect();
+    let timeout = step_timeout(mend, step_config, matching_recipes.values().find_map(|recipe| recipe.timeout.as_ref()));
+    let progress_regex = matching_recipes.values().find_map(|recipe| recipe.progress_regex.clone());
+    let mut locks = step_config.locks.clone();
+    locks.extend(matching_recipes.values().flat_map(|recipe| recipe.locks.clone()));
+    locks.sort();
+    locks.dedup();
+    StepRequest {
+        run: step_text.to_string(),
+        run_resolved: resolve_step_scripts(step_text, mend, phase, matching_recipes, step_config.expected_exit_codes.as_ref()),
+        commit_msg,
+        verify: recipe_verify.or_else(|| default_verify(mend)).filter(|verify| !verify.trim().is_empty()),
+        fallback_resolved: resolve_fallback(fallback.as_deref(), instruction_trimmed, mend, phase),
+        inputs,
+        outputs,
+        timeout,
+        locks,
+        progress_regex,
+        ..Default::default()
+    }
+}
+
+fn create_structured_request(step_config: &StepConfig, mend: &Mend, phase: Option<&Phase>) -> StepRequest {
+    match (&step_config.run, step_config.builtin()) {
+        (_, Some(builtin)) => {
+            let description = builtin.describe();
+            // A value that can't be rendered only fails this step
+            let mut script = builtin.to_script().unwrap_or_else(|err| format!("echo {}; false\n", shell_quote(&format!("{:#}", err))));
+            if let Some(exit_codes) = &step_config.expected_exit_codes {
+                script = ShellDialect::Posix.accept_exit_codes(script, exit_codes);
+            }
+            StepRequest {
+                run: description.clone(),
+                run_resolved: wrap_with_hooks(script, mend, phase, &[]),
+                commit_msg: description.clone(),
+                verify: default_verify(mend),
+                fallback_resolved: resolve_fallback(step_config.fallback.as_deref(), &description, mend, phase),
+                timeout: step_timeout(mend, step_config, None),
+                locks: step_config.locks.clone(),
+                ..Default::default()
+            }
+        }
+        (Some(run), None) => create_instruction_request(run, mend, phase, step_config),
+        (None, None) => create_instruction_request("", mend, phase, step_config),
+    }
+}
+
+/// Resolves a fallback instruction with `$1`.. taken from the arguments of the failed step.
+fn resolve_fallback(fallback: Option<&str>, instruction: &str, mend: &Mend, phase: Option<&Phase>) -> Vec<String> {
+    match fallback {
+        None => vec![],
+        Some(fallback) => {
+            let step_args = StepArgs::parse(instruction, find_matching_recipes(instruction, mend).into_values().next());
+            let fallback_instruction = shellexpand::env_with_context_no_errors(fallback, |s: &str| step_args.get(s)).to_string();
+            let matching_recipes = find_matching_recipes(&fallback_instruction, mend);
+            resolve_step_scripts(&fallback_instruction, mend, phase, matching_recipes, None)
+        }
+    }
+}
+
+fn default_verify(mend: &Mend) -> Option<String> {
+    mend.verify.as_ref().and_then(|verify| verify.run.clone())
+}
+
+/// Quotes text for use as a single `sh` word.
+pub fn shell_quote(text: &str) -> String {
+    ShellDialect::Posix.quote(text)
+}
+
+/// The variables mend sets for the steps' scripts itself, the only `MEND_*` ones commit messages can use.
+pub const MEND_ENV_VARS: &[&str] = &["MEND_BIN", "MEND_COMMIT_MSG_FILE", "MEND_RESULT_FILE"];
+
+/// Commit messages end up in history, so they only see `[env]`, the variables mend sets and `[commit] allow_env` ones.
+/// Mend's own are taken from the run rather than the environment, where e.g. `MEND_AGE_IDENTITY` is a private key.
+pub fn commit_env_var(mend: &Mend, name: &str) -> Option<String> {
+    if let Some(value) = mend.env.get(name) {
+        return Some(shellexpand::env(value).map(|expanded| expanded.to_string()).unwrap_or_else(|_| value.clone()));
+    }
+    if MEND_ENV_VARS.contains(&name) {
+        return mend.run_env.get(name).cloned();
+    }
+    let allowed = mend.commit.as_ref().is_some_and(|commit| commit.allow_env.iter().any(|allowed| allowed == name));
+    if allowed {
+        std::env::var(name).ok()
+    } else {
+        None
+    }
+}
+
+fn render_commit_message(instruction: &str, matching_recipes: &BTreeMap<&String, &Recipe>, mend: &Mend) -> String {
+    let commit_template = match matching_recipes.values().next() {
+        None => { instruction }
+        Some(recipe) => {
+            match &recipe.commit_template {
+                None => { instruction }
+                Some(template) => { template }
+            }
+        }
+    };
+    // For now splitting on whitespace, perhaps shlex parse later?
+    let step_args = StepArgs::parse(instruction, matching_recipes.values().next().copied());
+    let context = {
+        |s: &_| {
+            if let Some(found_arg) = step_args.get(s) {
+                return Some(found_arg)
+            }
+            commit_env_var(mend, s)
+        }
+    };
+    let commit_msg = shellexpand::env_with_context_no_errors(&commit_template, context);
+    commit_msg.to_string()
+}
+
+pub fn run_all_steps<R: Repo, E: Executor, N: Notify>(step_requests: Vec<StepRequest>, notifier: &mut N, worktree_repo: &mut R, executor: &mut E, options: &RunOptions)
+    -> Result<RunSummary, Box<(StepRequest, StepResponse)>>{
+    let mut summary = RunSummary {
+        commits: options.resumed_commits.clone(),
+        ..Default::default()
+    };
+    let mut group_start_sha = None;
+    let has_fixups = step_requests.iter().any(|step_request| step_request.fixup.is_some());
+    let run_start_sha = if has_fixups { worktree_repo.current_short_sha().ok() } else { None };
+    let mut step_cache = options.step_cache.as_deref().map(StepCache::read);
+    let step_count = step_requests.len();
+    let mut step_requests = step_requests.into_iter().enumerate().peekable();
+    // `[verify] full` of a skipped milestone, run by the next step checked by `[verify]` instead
+    let mut owed_full_verify: Option<String> = None;
+    while let Some((step_i, mut step_request)) = step_requests.next() {
+        if step_i < options.first_step {
+            let sha = summary.commits.iter().find(|commit| commit.step == step_i + 1).map(|commit| commit.sha.clone());
+            notifier.notify(step_i, &step_request.run, &Done, &sha, false);
+            continue;
+        }
+        if owed_full_verify.is_some() && step_request.verify_tier == VerifyTier::Fast && take_full_verify(&mut step_request) {
+            owed_full_verify = None;
+        }
+        if options.squash_groups.iter().any(|group| group.first_step == step_i) {
+            group_start_sha = worktree_repo.current_short_sha().ok();
+        }
+        let parallel_steps = take_parallel_steps(&step_request, &mut step_requests, executor, options);
+        if !parallel_steps.is_empty() {
+            let batch: Vec<(usize, StepRequest)> = std::iter::once((step_i, step_request)).chain(parallel_steps).collect();
+            let milestones: Vec<(usize, Option<String>)> = batch
+                .iter()
+                .filter(|(_, step_request)| step_request.verify_tier == VerifyTier::Full)
+                .map(|(step_i, step_request)| (*step_i, step_request.verify.clone()))
+                .collect();
+            run_parallel_steps(batch, notifier, worktree_repo, executor, options, &mut summary, &mut step_cache)?;
+            if let Some((_, full)) = milestones.into_iter().find(|(step_i, _)| summary.skipped_steps.contains(step_i)) {
+                owed_full_verify = full;
+            }
+            continue;
+        }
+        if step_request.verify_tier == VerifyTier::Full {
+            owed_full_verify = step_request.verify.clone();
+        }
+        if is_skipped(&step_cache, worktree_repo, &step_request) {
+            notifier.notify(step_i, &step_request.run, &Skipped, &None, true);
+            summary.skipped_steps.push(step_i);
+        } else if let Some(reason) = blocked_by_failure(&summary, &step_request) {
+            fail_without_running(notifier, &mut summary, step_i, step_request, &reason);
+        } else {
+            let mut step_request = step_request;
+            match ask_before_step(notifier, options, step_i, step_count, &mut step_request) {
+                Approval::Run => {
+                    if step_request.verify_tier == VerifyTier::Full {
+                        owed_full_verify = None;
+                    }
+                    let mut step_response = StepResponse::pending();
+                    let fixup_sha = step_request.fixup.as_ref().and_then(|target| summary.commit_sha(target)).cloned();
+                    run_step(
+                        worktree_repo,
+                        executor,
+                        notifier,
+                        step_i,
+                        &step_request,
+                        &mut step_response,
+                        options,
+                        fixup_sha.as_deref(),
+                    );
+                    record_result(worktree_repo, options, &mut summary, &mut step_cache, step_i, step_request, step_response, fixup_sha.is_some())?;
+                }
+                Approval::Skip => {
+                    notifier.notify(step_i, &step_request.run, &Skipped, &None, true);
+                    summary.skipped_steps.push(step_i);
+                }
+                Approval::Quit => {
+                    let mut step_response = StepResponse::pending();
+                    step_response.push_output_str("Run stopped before the step, `mend resume` starts it here");
+                    return Err(Box::new((step_request, step_response)));
+                }
+            }
+        }
+        // A run that stops early keeps the group's step commits as they are
+        if let Some(group) = options.squash_groups.iter().find(|group| group.last_step == step_i) {
+            if let Some(start_sha) = group_start_sha.take() {
+                match worktree_repo.squash_since(&start_sha, &group.commit_msg) {
+                    Ok(true) => {
+                        if let Ok(squashed_sha) = worktree_repo.current_short_sha() {
+                            for commit in summary.commits.iter_mut().filter(|commit| commit.step > group.first_step) {
+                                commit.sha = squashed_sha.clone();
+                            }
+                        }
+                    }
+                    Ok(false) => {}
+                    Err(err) => eprintln!("Could not squash steps {} to {}: {:#}", group.first_step + 1, group.last_step + 1, err),
+                }
+            }
+        }
+    }
+    if let Some(full) = owed_full_verify {
+        run_owed_full_verify(worktree_repo, executor, options, &full)?;
+    }
+    if let Some(run_start_sha) = run_start_sha {
+        match worktree_repo.autosquash_since(&run_start_sha) {
+            Ok(_) => {
+                if let Ok(new_shas) = worktree_repo.commits_since(&run_start_sha) {
+                    summary.remap_commits(&new_shas);
+                }
+            }
+            Err(err) => eprintln!("Could not fold fixup commits into their steps: {:#}", err),
+        }
+    }
+    for commit in summary.commits.iter_mut() {
+        commit.revert = format!("git revert --no-edit {}", commit.sha);
+    }
+    // Reported once the progress output is finished so it isn't interleaved
+    for (step_request, step_response) in &summary.failures {
+        notifier.notify_failure(step_request, step_response);
+    }
+    Ok(summary)
+}
+
+/// Runs `[verify] full` on what the steps left when the last milestone to run it was skipped, failing the run with it.
+fn run_owed_full_verify<R: Repo, E: Executor>(repo: &R, executor: &mut E, options: &RunOptions, full: &str) -> Result<(), Box<(StepRequest, StepResponse)>> {
+    let mut step_response = StepResponse::pending();
+    match executor.run_script(repo.dir(), full, &options.env, None, &mut |_| {}) {
+        Ok(output) if output.status.success() => return Ok(()),
+        Ok(output) => {
+            step_response.push_output_str(&String::from_utf8_lossy(&output.stdout));
+            step_response.push_output_str(&String::from_utf8_lossy(&output.stderr));
+        }
+        Err(err) => step_response.push_output_str(&format!("{:#}", err)),
+    }
+    step_response.status = VerifyFailed;
+    let step_request = StepRequest {
+        id: "verify".to_string(),
+        run: full.to_string(),
+        verify: Some(full.to_string()),
+        verify_tier: VerifyTier::Full,
+        ..Default::default()
+    };
+    Err(Box::new((step_request, step_response)))
+}
+
+/// What `--interactive` was told to do with the step, always to run it without.
+fn ask_before_step<N: Notify>(notifier: &mut N, options: &RunOptions, step_i: usize, step_count: usize, step_request: &mut StepRequest) -> Approval {
+    if !options.interactive {
+        return Approval::Run;
+    }
+    let mut approval = Approval::Quit;
+    notifier.suspend(&mut || approval = interactive::ask_on_terminal(step_i, step_count, step_request));
+    approval
+}
+
+/// Whether the step's `when` doesn't hold or its inputs and outputs are as its last run left them.
+fn is_skipped<R: Repo>(step_cache: &Option<StepCache>, repo: &R, step_request: &StepRequest) -> bool {
+    if step_request.excluded {
+        return true;
+    }
+    // Conditions were checked when the config was loaded, one that doesn't parse never holds
+    let skipped = step_request.when.as_deref().is_some_and(|when| {
+        !Condition::parse(when).is_ok_and(|condition| condition.holds(repo.dir(), &step_request.env))
+    });
+    skipped || is_cached(step_cache, repo, step_request)
+}
+
+/// Why the step can't run when an earlier step failed and the run kept going.
+fn blocked_by_failure(summary: &RunSummary, step_request: &StepRequest) -> Option<String> {
+    let failed = |id: &String| summary.failures.iter().any(|(failed_request, _)| failed_request.id == *id);
+    if let Some(target) = step_request.fixup.as_ref().filter(|target| failed(target)) {
+        // There is no commit to fix up, it is retried along with its target
+        return Some(format!("Not run, step `{}` it fixes up failed", target));
+    }
+    let need = step_request.needs.iter().flatten().find(|need| failed(need))?;
+    Some(format!("Not run, step `{}` it needs failed", need))
+}
+
+fn fail_without_running<N: Notify>(notifier: &mut N, summary: &mut RunSummary, step_i: usize, step_request: StepRequest, reason: &str) {
+    let mut step_response = StepResponse::pending();
+    step_response.status = Failed;
+    step_response.push_output_str(reason);
+    notifier.notify(step_i, &step_request.run, &Failed, &None, true);
+    summary.failed_steps.push(step_i);
+    summary.failures.push((step_request, step_response));
+}
+
+/// Adds a step that ran to the summary, stopping the run at a failure unless it keeps going.
+#[allow(clippy::too_many_arguments)]
+fn record_result<R: Repo>(
+    repo: &R,
+    options: &RunOptions,
+    summary: &mut RunSummary,
+    step_cache: &mut Option<StepCache>,
+    step_i: usize,
+    step_request: StepRequest,
+    step_response: StepResponse,
+    is_fixup: bool,
+) -> Result<(), Box<(StepRequest, StepResponse)>> {
+    if let Some(log_dir) = &options.log_dir {
+        if let Err(err) = write_step_log(log_dir, step_i, &step_request, &step_response) {
+            eprintln!("{:#}", err);
+        }
+    }
+    if let (Some(sha), false) = (&step_response.sha, is_fixup) {
+        summary.commits.push(StepCommit {
+            id: step_request.id.clone(),
+            step: step_i + 1,
+            sha: sha.clone(),
+            metadata: step_response.metadata.clone(),
+            ..Default::default()
+        });
+    }
+    if step_response.status.is_failed() {
+        if !options.continue_on_error && !step_response.continue_run {
+            return Err(Box::new((step_request, step_response)));
+        }
+        summary.failed_steps.push(step_i);
+        summary.failures.push((step_request, step_response));
+    } else {
+        summary.add_step(&step_response);
+        record_cached(step_cache, options, repo, &step_request);
+    }
+    Ok(())
+}
+
+/// The steps right after `first` that may run alongside it: they have `needs`, none of which is
+/// in the batch, and hold none of the batch's locks. Empty when steps run one after another.
+fn take_parallel_steps<I: Iterator<Item = (usize, StepRequest)>, E: Executor>(
+    first: &StepRequest,
+    step_requests: &mut Peekable<I>,
+    executor: &E,
+    options: &RunOptions,
+) -> Vec<(usize, StepRequest)> {
+    let mut batch: Vec<(usize, StepRequest)> = vec![];
+    // Fixups and squashed groups rely on the commits being made in order
+    if options.max_parallel_steps < 2 || !options.squash_groups.is_empty() || first.fixup.is_some() || executor.worker().is_none() {
+        return batch;
+    }
+    while batch.len() + 1 < options.max_parallel_steps {
+        let next = step_requests.next_if(|(_, next)| {
+            let in_batch = |need: &String| *need == first.id || batch.iter().any(|(_, step_request)| step_request.id == *need);
+            let locked = |lock: &String| first.locks.contains(lock) || batch.iter().any(|(_, step_request)| step_request.locks.contains(lock));
+            next.fixup.is_none() && next.needs.as_ref().is_some_and(|needs| !needs.iter().any(in_batch)) && !next.locks.iter().any(locked)
+        });
+        match next {
+            Some(next) => batch.push(next),
+            None => break,
+        }
+    }
+    batch
+}
+
+/// The files `globs` match in `repo` now, with the globs themselves for files that don't exist yet.
+fn declared_files<R: Repo>(repo: &R, globs: &[String]) -> Result<BTreeSet<String>> {
+    let mut files: BTreeSet<String> = globs.iter().cloned().collect();
+    // `<object id> <path>` lines
+    files.extend(repo.file_hashes(globs)?.lines().filter_map(|line| line.split_once(' ')).map(|(_, path)| path.to_string()));
+    Ok(files)
+}
+
+/// Finds steps that could run alongside each other although one writes files the other reads or writes, by their
+/// `inputs` and `outputs`, and gives both a lock named after each of those files, so they run one after another
+/// instead of conflicting when the later commit is applied. Returns a note for each pair that wasn't locked already.
+pub fn lock_overlapping_steps<R: Repo>(repo: &R, step_requests: &mut [StepRequest]) -> Result<Vec<String>> {
+    let mut declared = vec![];
+    for step_request in step_requests.iter() {
+        let written = declared_files(repo, &step_request.outputs)?;
+        let mut touched = declared_files(repo, &step_request.inputs)?;
+        touched.extend(written.iter().cloned());
+        declared.push((written, touched));
+    }
+    let mut notes = vec![];
+    for later in 1..step_requests.len() {
+        // Only steps with `needs` join a batch after its first step
+        let Some(needs) = step_requests[later].needs.clone() else {
+            continue;
+        };
+        for earlier in 0..later {
+            if needs.contains(&step_requests[earlier].id) {
+                continue;
+            }
+            let shared: BTreeSet<&String> = declared[earlier]
+                .0
+                .intersection(&declared[later].1)
+                .chain(declared[later].0.intersection(&declared[earlier].1))
+                .collect();
+            if shared.is_empty() {
+                continue;
+            }
+            if !step_requests[earlier].locks.iter().any(|lock| step_requests[later].locks.contains(lock)) {
+                notes.push(format!(
+                    "Steps {} ({}) and {} ({}) both touch {}, they'll run one after another",
+                    earlier + 1,
+                    step_requests[earlier].id,
+                    later + 1,
+                    step_requests[later].id,
+                    shared.iter().map(|file| file.as_str()).collect::<Vec<&str>>().join(", ")
+                ));
+            }
+            for step_i in [earlier, later] {
+                let locks = &mut step_requests[step_i].locks;
+                locks.extend(shared.iter().map(|file| file.to_string()));
+                locks.sort();
+                locks.dedup();
+            }
+        }
+    }
+    Ok(notes)
+}
+
+/// Passes on what a step running on another thread reports, until the thread owning the real notifier
+/// has applied its commit and reports it done or failed itself.
+struct ChannelNotifier {
+    sender: Sender<(usize, String, EStatus, bool)>,
+}
+
+impl Notify for ChannelNotifier {
+    fn notify(&mut self, i: usize, run: &str, status: &EStatus, _sha: &Option<String>, inc: bool) {
+        if !matches!(status, Done | Failed | VerifyFailed) {
+            let _ = self.sender.send((i, run.to_string(), *status, inc));
+        }
+    }
+    fn notify_done(&self, _summary: &RunSummary) {}
+    fn notify_failure(&self, _failed_request: &StepRequest, _failed_response: &StepResponse) {}
+}
+
+fn run_worker_step(
+    step_i: usize,
+    step_request: &StepRequest,
+    executor: Option<Box<dyn Executor + Send>>,
+    worker_dir: &Result<PathBuf>,
+    sender: Sender<(usize, String, EStatus, bool)>,
+    options: &RunOptions,
+) -> StepResponse {
+    let mut step_response = StepResponse::pending();
+    match (executor, worker_dir) {
+        (Some(mut executor), Ok(worker_dir)) => {
+            let mut repo = GitRepo { repo_dir: worker_dir.clone() };
+            let mut notifier = ChannelNotifier { sender };
+            run_step(&mut repo, &mut executor, &mut notifier, step_i, step_request, &mut step_response, options, None);
+        }
+        (_, Err(err)) => {
+            step_response.status = Failed;
+            step_response.push_output_str(format!("Could not create a worktree for the step: {:#}", err).as_str());
+        }
+        (None, _) => {
+            step_response.status = Failed;
+            step_response.push_output_str("Steps can't run alongside each other with this executor");
+        }
+    }
+    step_response
+}
+
+/// Runs the steps of `batch` at once, each in a worktree of its own made from HEAD, then applies
+/// their commits on top of `worktree_repo` in the order the steps were declared.
+fn run_parallel_steps<R: Repo, E: Executor, N: Notify>(
+    batch: Vec<(usize, StepRequest)>,
+    notifier: &mut N,
+    worktree_repo: &mut R,
+    executor: &E,
+    options: &RunOptions,
+    summary: &mut RunSummary,
+    step_cache: &mut Option<StepCache>,
+) -> Result<(), Box<(StepRequest, StepResponse)>> {
+    let mut to_run = vec![];
+    for (step_i, step_request) in batch {
+        if is_skipped(step_cache, worktree_repo, &step_request) {
+            notifier.notify(step_i, &step_request.run, &Skipped, &None, true);
+            summary.skipped_steps.push(step_i);
+        } else if let Some(reason) = blocked_by_failure(summary, &step_request) {
+            fail_without_running(notifier, summary, step_i, step_request, &reason);
+        } else {
+            to_run.push((step_i, step_request));
+        }
+    }
+    let worker_dirs: Vec<Result<PathBuf>> = to_run
+        .iter()
+        .map(|(step_i, _)| add_worker_worktree(worktree_repo.dir(), *step_i))
+        .collect();
+    let (sender, receiver) = mpsc::channel();
+    let responses: Vec<StepResponse> = thread::scope(|scope| {
+        let workers: Vec<_> = to_run
+            .iter()
+            .zip(&worker_dirs)
+            .map(|((step_i, step_request), worker_dir)| {
+                let (executor, sender) = (executor.worker(), sender.clone());
+                scope.spawn(move || run_worker_step(*step_i, step_request, executor, worker_dir, sender, options))
+            })
+            .collect();
+        drop(sender);
+        for (step_i, run, status, inc) in receiver {
+            notifier.notify(step_i, &run, &status, &None, inc);
+        }
+        workers
+            .into_iter()
+            .map(|worker| {
+                worker.join().unwrap_or_else(|_| {
+                    let mut step_response = StepResponse::pending();
+                    step_response.status = Failed;
+                    step_response.push_output_str("The step's thread panicked");
+                    step_response
+                })
+            })
+            .collect()
+    });
+    // Their commits stay in the repository once the worktrees are gone
+    for worker_dir in worker_dirs.iter().flatten() {
+        if let Err(err) = remove_worktree(worker_dir) {
+            eprintln!("Could not remove the worktree `{}`: {:#}", worker_dir.to_string_lossy(), err);
+        }
+    }
+    for ((step_i, step_request), mut step_response) in to_run.into_iter().zip(responses) {
+        if let (Done, Some(sha)) = (step_response.status, step_response.sha.take()) {
+            match worktree_repo.cherry_pick(&sha, step_request.committer.as_ref()) {
+                Ok(_) => step_response.sha = worktree_repo.current_short_sha().ok(),
+                Err(err) => {
+                    step_response.status = Failed;
+                    step_response.push_output_str(format!("Could not apply its commit after the steps before it:\n{:#}", err).as_str());
+                }
+            }
+        }
+        notifier.notify(step_i, &step_request.run, &step_response.status, &step_response.sha, true);
+        record_result(worktree_repo, options, summary, step_cache, step_i, step_request, step_response, false)?;
+    }
+    Ok(())
+}
+
+fn is_cached<R: Repo>(step_cache: &Option<StepCache>, repo: &R, step_request: &StepRequest) -> bool {
+    match step_cache {
+        Some(step_cache) if !step_request.inputs.is_empty() => current_state(repo, step_request)
+            .is_ok_and(|state| step_cache.is_fresh(step_request, &state)),
+        _ => false,
+    }
 }
 
-fn render_commit_message(instruction: &str, matching_recipes: &BTreeMap<&String, &Recipe>) -> String {
-    let commit_template = match matching_recipes.values().next() {
-        None => { instruction }
-        Some(recipe) => {
-            match &recipe.commit_template {
-                None => { instruction }
-                Some(template) => { template }
-            }
+/// Remembers what a successful step left its inputs and outputs like, so a run from there skips it.
+fn record_cached<R: Repo>(step_cache: &mut Option<StepCache>, options: &RunOptions, repo: &R, step_request: &StepRequest) {
+    if let (Some(step_cache), Some(path)) = (step_cache, &options.step_cache) {
+        if step_request.inputs.is_empty() {
+            return;
         }
-    };
-    // For now splitting on whitespace, perhaps shlex parse later?
-    let args : Vec<&str> = instruction.split_whitespace().collect();
-    let context = {
-        |s: &_| {
-            eprintln!("resolving {}", s);
-            if let Ok(arg_num) =  str::parse::<i16>(s) {
-                eprintln!("parsed arg_num {}", arg_num);
-                if arg_num >= 1 && arg_num < args.len() as i16 {
-                    if let Some(found_arg) = args.get(arg_num as usize) {
-                        return Some(found_arg.to_string())
-                    }
-                }
-            }
-            std::env::var(s).ok()
+        let recorded = current_state(repo, step_request).and_then(|state| {
+            step_cache.record(step_request, state);
+            step_cache.write(path)
+        });
+        if let Err(err) = recorded {
+            eprintln!("Could not remember step {} for later runs: {:#}", step_request.id, err);
         }
-    };
-    let commit_msg = shellexpand::env_with_context_no_errors(&commit_template, context);
-    let string = commit_msg.to_string();
-    string
+    }
 }
 
-pub fn run_all_steps<R: Repo, E: Executor, N: Notify>(step_requests: Vec<StepRequest>, notifier: &mut N, worktree_repo: &mut R, executor: &mut E)
-    -> Result<(), (StepRequest, StepResponse)>{
-    let mut step_i: usize = 0;
-    for step_request in step_requests {
-        let mut step_response = StepResponse { sha: None, status: EStatus::Pending, output: None };
-        run_step(
-            worktree_repo,
-            executor,
-            notifier,
-            step_i,
-            &step_request,
-            &mut step_response,
-        );
-        step_i += 1;
-        if step_response.status == Failed {
-            return Err((step_request, step_response))
+#[allow(clippy::too_many_arguments)]
+pub fn run_step<R: Repo, E: Executor, N: Notify>(
+    repo: &mut R,
+    executor: &mut E,
+    notifier: &mut N,
+    step_i: usize,
+    step_request: &StepRequest,
+    step_response: &mut StepResponse,
+    options: &RunOptions,
+    fixup_sha: Option<&str>,
+) {
+    step_response.status = Running;
+    let step_files = StepFiles::create();
+    if let Err(err) = &step_files {
+        step_response.push_output_str(format!("Scripts can't pass results back: {:#}", err).as_str());
+    }
+    let mut step_env = options.env.clone();
+    step_env.extend(step_request.env.clone());
+    step_env.extend(step_files.as_ref().map(|files| files.env()).unwrap_or_default());
+    // Verification runs last so a failure resets the step like any other script, with a matrix once per variant
+    let matrix_verify = step_request.verify.as_ref().filter(|_| !options.matrix.is_empty());
+    let verify = step_request.verify.as_ref().filter(|_| matrix_verify.is_none());
+    run_scripts(repo, executor, notifier, step_i, step_request, step_request.run_resolved.iter(), verify, &step_env, step_response);
+    if let Some(matrix_verify) = matrix_verify {
+        run_verify_matrix(repo, executor, notifier, step_i, step_request, matrix_verify, &options.matrix, &step_env, step_response);
+    }
+    if step_response.status.is_failed() && !step_request.fallback_resolved.is_empty() {
+        let _ = repo.reset_hard();
+        if let Ok(files) = &step_files {
+            files.clear();
+        }
+        step_response.push_output_str("Step failed, reset and running fallback");
+        step_response.status = Running;
+        let fallback_scripts = step_request.fallback_resolved.iter();
+        run_scripts(repo, executor, notifier, step_i, step_request, fallback_scripts, verify, &step_env, step_response);
+        if let Some(matrix_verify) = matrix_verify {
+            run_verify_matrix(repo, executor, notifier, step_i, step_request, matrix_verify, &options.matrix, &step_env, step_response);
+        }
+    }
+    if let Ok(files) = &step_files {
+        step_response.commit_msg = files.commit_msg();
+        if let Some(results) = files.results() {
+            step_response.record_results(&results);
+        }
+    }
+    if let (Some(_), true) = (&step_request.verify, !step_response.status.is_failed()) {
+        step_response.metadata.insert(VERIFIED_KEY.to_string(), step_request.verify_tier.label().to_string());
+    }
+    if let (Some(config), true) = (&options.artifacts, !step_response.status.is_failed()) {
+        record_artifacts(repo.dir(), config, step_response);
+    }
+    if step_response.status.is_failed() && options.on_failure == Some(OnFailure::Shell) {
+        let shell = env::var("SHELL").unwrap_or_else(|_| if cfg!(windows) { "cmd" } else { "sh" }.to_string());
+        let mut go_on = Ok(false);
+        notifier.suspend(&mut || go_on = open_debug_shell(&shell, repo.dir(), step_i, step_request, &step_env));
+        match go_on {
+            Ok(go_on) => step_response.continue_run = go_on,
+            Err(err) => step_response.push_output_str(format!("{:#}", err).as_str()),
         }
     }
-    return Ok(())
+    let quarantine = if options.quarantine { Some(quarantine_branch(step_i)) } else { None };
+    finish_step(repo, notifier, step_i, step_request, step_response, quarantine.as_deref(), fixup_sha);
 }
 
-pub fn run_step<R: Repo, E: Executor, N: Notify>(
+/// Runs `verify` on the worktree before any step changed it, so a baseline that's broken already
+/// fails the run up front instead of the first step being blamed for it.
+pub fn verify_baseline<E: Executor>(executor: &mut E, dir: &Path, from_sha: &str, verify: &str, env: &BTreeMap<String, String>) -> Result<()> {
+    let output = executor.run_script(dir, verify, env, None, &mut |_| {})?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "The baseline is already broken, `{}` fails on {} before any step ran. Fix it or start from another `from.sha`:\n{}{}",
+            verify,
+            from_sha,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Opens `shell` in the worktree of the failed step with the step's environment, so its state can be looked at
+/// before it's reset. Whether the run goes on is told by the shell's exit status, `exit 0` to go on.
+fn open_debug_shell(shell: &str, dir: &Path, step_i: usize, step_request: &StepRequest, step_env: &BTreeMap<String, String>) -> Result<bool> {
+    eprintln!(
+        "Step {} failed: {}\nOpening {} in {} as the step left it, `exit 0` to reset the step and go on with the run, `exit 1` to stop here",
+        step_i + 1,
+        step_request.run,
+        shell,
+        dir.to_string_lossy()
+    );
+    let status = without_identity(Command::new(shell).current_dir(dir).envs(step_env))
+        .status()
+        .with_context(|| format!("Could not open the shell `{}`", shell))?;
+    Ok(status.success())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_scripts<'a, R: Repo, E: Executor, N: Notify>(
     repo: &mut R,
     executor: &mut E,
     notifier: &mut N,
     step_i: usize,
     step_request: &StepRequest,
+    scripts: impl Iterator<Item = &'a String>,
+    verify: Option<&'a String>,
+    step_env: &BTreeMap<String, String>,
     step_response: &mut StepResponse,
 ) {
-    step_response.status = Running;
-    for script in &step_request.run_resolved {
+    // The timeout covers all of the step's scripts, a fallback gets one of its own
+    let started = Instant::now();
+    // Told apart so a change that breaks the build isn't mistaken for a recipe that couldn't apply
+    let scripts = scripts.map(|script| (script, Failed)).chain(verify.map(|verify| (verify, VerifyFailed)));
+    for (script, failed_status) in scripts {
         notifier.notify(
             step_i,
             &step_request.run,
@@ -187,15 +1678,24 @@ pub fn run_step<R: Repo, E: Executor, N: Notify>(
             true,
         );
         step_response.push_output_str(format!("Running\n{}\n", script).as_str());
-        let output_result = executor.run_script(repo.dir(), script);
+        let remaining = step_request.timeout.map(|timeout| timeout.saturating_sub(started.elapsed()));
+        let output_result = match remaining {
+            Some(remaining) if remaining.is_zero() => Err(anyhow!("No time left to start it").into()),
+            _ => executor.run_script(repo.dir(), script, step_env, remaining, &mut |line| notifier.notify_output(step_i, line)),
+        };
+        let timed_out = step_request.timeout.filter(|timeout| started.elapsed() >= *timeout);
         match output_result {
             Ok(output) => {
                 let stdout = String::from_utf8_lossy(&output.stdout);
                 let stderr = String::from_utf8_lossy(&output.stderr);
                 step_response.push_output_str(stdout.as_ref());
                 step_response.push_output_str(stderr.as_ref());
-                if !output.status.success() {
-                    step_response.status = Failed;
+                step_response.record_stats(stdout.as_ref());
+                if let Some(timeout) = timed_out {
+                    step_response.push_output_str(format!("Step timed out after {}", HumanDuration(timeout)).as_str());
+                }
+                if !output.status.success() || timed_out.is_some() {
+                    step_response.status = failed_status;
                     notifier.notify(
                         step_i,
                         &step_request.run,
@@ -208,7 +1708,10 @@ pub fn run_step<R: Repo, E: Executor, N: Notify>(
             }
             Err(e) => {
                 step_response.push_output_str(format!("Failed to run\n{:?}", e).as_str());
-                step_response.status = Failed;
+                if let Some(timeout) = timed_out {
+                    step_response.push_output_str(format!("Step timed out after {}", HumanDuration(timeout)).as_str());
+                }
+                step_response.status = failed_status;
                 notifier.notify(
                     step_i,
                     &step_request.run,
@@ -216,14 +1719,101 @@ pub fn run_step<R: Repo, E: Executor, N: Notify>(
                     &step_response.sha,
                     false,
                 );
+                break;
+            }
+        }
+    }
+}
+
+/// Runs the step's `verify` on its change once in each `[matrix]` variant, with the variant's variables set.
+/// All variants run even after one fails, so the metadata holds the whole row of the grid, the step fails with any of them.
+#[allow(clippy::too_many_arguments)]
+fn run_verify_matrix<R: Repo, E: Executor, N: Notify>(
+    repo: &R,
+    executor: &mut E,
+    notifier: &mut N,
+    step_i: usize,
+    step_request: &StepRequest,
+    verify: &str,
+    variants: &[BTreeMap<String, String>],
+    step_env: &BTreeMap<String, String>,
+    step_response: &mut StepResponse,
+) {
+    if step_response.status.is_failed() {
+        return;
+    }
+    let mut results = vec![];
+    for variant in variants {
+        notifier.notify(step_i, &step_request.run, &step_response.status, &step_response.sha, true);
+        let label = matrix::variant_label(variant);
+        step_response.push_output_str(format!("Running with {}\n{}\n", label, verify).as_str());
+        let mut variant_env = step_env.clone();
+        variant_env.extend(variant.clone());
+        // Each variant gets the step's whole timeout
+        let ok = match executor.run_script(repo.dir(), verify, &variant_env, step_request.timeout, &mut |line| notifier.notify_output(step_i, line)) {
+            Ok(output) => {
+                step_response.push_output_str(String::from_utf8_lossy(&output.stdout).as_ref());
+                step_response.push_output_str(String::from_utf8_lossy(&output.stderr).as_ref());
+                output.status.success()
+            }
+            Err(err) => {
+                step_response.push_output_str(format!("Failed to run\n{:?}", err).as_str());
+                false
+            }
+        };
+        results.push((label, ok));
+    }
+    step_response.metadata.insert(MATRIX_KEY.to_string(), matrix::format_results(&results));
+    if let Some((label, _)) = results.iter().find(|(_, ok)| !ok) {
+        step_response.push_output_str(format!("Verification failed with {}", label).as_str());
+        step_response.status = VerifyFailed;
+        notifier.notify(step_i, &step_request.run, &step_response.status, &step_response.sha, false);
+    }
+}
+
+/// Cleans up what the step left that `config` lists, noting the worktree's growth and warnings in its metadata.
+fn record_artifacts(repo_dir: &Path, config: &ArtifactsConfig, step_response: &mut StepResponse) {
+    match check_step_artifacts(repo_dir, config) {
+        Ok(artifacts) => {
+            if !artifacts.removed.is_empty() {
+                step_response.push_output_str(format!("Removed per [artifacts] clean:\n{}", artifacts.removed.join("\n")).as_str());
+            }
+            step_response.metadata.insert(GROWTH_KEY.to_string(), format_growth(artifacts.growth));
+            for warning in &artifacts.warnings {
+                step_response.add_metadata("warning", warning);
             }
         }
+        Err(err) => step_response.push_output_str(format!("Could not check for build artifacts: {:#}", err).as_str()),
     }
+}
 
-    if step_response.status != Failed {
+fn finish_step<R: Repo, N: Notify>(
+    repo: &mut R,
+    notifier: &mut N,
+    step_i: usize,
+    step_request: &StepRequest,
+    step_response: &mut StepResponse,
+    quarantine_branch: Option<&str>,
+    fixup_sha: Option<&str>,
+) {
+    if !step_response.status.is_failed() {
         step_response.status = Done;
-        step_response.push_output_str(format!("Committing with message '{}'", step_request.commit_msg).as_str());
-        match repo.commit_all(step_request.commit_msg.as_str()) {
+        let commit_result = match fixup_sha {
+            Some(sha) => {
+                step_response.push_output_str(format!("Committing as fixup of {}", sha).as_str());
+                repo.commit_fixup(sha)
+            }
+            None => {
+                let commit_msg = step_response.commit_msg.clone().unwrap_or_else(|| step_request.commit_msg.clone());
+                let commit_msg = with_trailers(&commit_msg, &step_request.trailers);
+                step_response.push_output_str(format!("Committing with message '{}'", commit_msg).as_str());
+                match &step_request.committer {
+                    Some(identity) => repo.commit_all_as(commit_msg.as_str(), identity),
+                    None => repo.commit_all(commit_msg.as_str()),
+                }
+            }
+        };
+        match commit_result {
             Ok(_) => {
                 if let Ok(sha) = repo.current_short_sha() {
                     step_response.sha = Some(sha)
@@ -242,6 +1832,14 @@ pub fn run_step<R: Repo, E: Executor, N: Notify>(
             true,
         );
     } else {
+        if let Some(branch) = quarantine_branch {
+            let message = format!("Failed: {}", step_request.commit_msg);
+            match repo.save_changes_to_branch(branch, &message) {
+                Ok(true) => step_response.push_output_str(format!("Saved changes to branch {}", branch).as_str()),
+                Ok(false) => {}
+                Err(err) => step_response.push_output_str(format!("Could not save changes to {}\n{:?}", branch, err).as_str()),
+            }
+        }
         let _ = repo.reset_hard();
         notifier.notify(
             step_i,
@@ -257,38 +1855,195 @@ pub fn run_command_with_output(
     repo_dir: &Path,
     cmd: String,
     args: Vec<&str>,
-) -> anyhow::Result<Output> {
-    let cmd_path = which(&cmd).with_context(|| "could not resolve")?;
-    Command::new(&cmd_path)
-        .current_dir(repo_dir)
-        .args(args)
+) -> Result<Output> {
+    run_command_with_env(repo_dir, cmd, args, &BTreeMap::new())
+}
+
+pub fn run_command_with_env(
+    repo_dir: &Path,
+    cmd: String,
+    args: Vec<&str>,
+    env: &BTreeMap<String, String>,
+) -> Result<Output> {
+    let exec_error = |cause| MendError::Exec { program: cmd.clone(), cause };
+    let cmd_path = which(&cmd).map_err(|err| exec_error(err.into()))?;
+    without_identity(Command::new(&cmd_path).current_dir(repo_dir).args(args).envs(env))
         .output()
-        .with_context(|| format!("Could not run command {}, resolved {:?}", cmd, cmd_path))
+        .map_err(|err| exec_error(err.into()))
+}
+
+/// Like `run_command_with_env`, but passes each line the command prints to `on_line` as it comes, and kills the
+/// command and everything it started once `timeout` has passed. The output so far is kept, with the reason added to stderr.
+pub fn run_command_streaming(
+    repo_dir: &Path,
+    cmd: String,
+    args: Vec<&str>,
+    env: &BTreeMap<String, String>,
+    timeout: Option<Duration>,
+    on_line: &mut dyn FnMut(&str),
+) -> Result<Output> {
+    let exec_error = |cause| MendError::Exec { program: cmd.clone(), cause };
+    let cmd_path = which(&cmd).map_err(|err| exec_error(err.into()))?;
+    let mut child = without_identity(Command::new(&cmd_path).current_dir(repo_dir).args(args).envs(env))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| exec_error(err.into()))?;
+    // Read while waiting, a script filling a pipe would otherwise block until it's killed
+    let (sender, receiver) = mpsc::channel();
+    let stdout = read_in_background(child.stdout.take(), sender.clone());
+    let stderr = read_in_background(child.stderr.take(), sender);
+    let started = Instant::now();
+    let (status, timed_out) = loop {
+        // Also how long to wait before checking on the command again
+        if let Ok(line) = receiver.recv_timeout(Duration::from_millis(50)) {
+            on_line(&line);
+        }
+        if let Some(status) = child.try_wait()? {
+            break (status, None);
+        }
+        if let Some(timeout) = timeout.filter(|timeout| started.elapsed() >= *timeout) {
+            kill_tree(child.id());
+            let _ = child.kill();
+            break (child.wait()?, Some(timeout));
+        }
+    };
+    let mut output = Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    };
+    for line in receiver.try_iter() {
+        on_line(&line);
+    }
+    if let Some(timeout) = timed_out {
+        output.stderr.extend(format!("\nKilled after running for {}\n", HumanDuration(timeout)).bytes());
+    }
+    Ok(output)
+}
+
+/// Reads all of `pipe`, sending each line on as it's read.
+fn read_in_background<P: Read + Send + 'static>(pipe: Option<P>, lines: Sender<String>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = vec![];
+        if let Some(pipe) = pipe {
+            let mut reader = BufReader::new(pipe);
+            let mut line = vec![];
+            while reader.read_until(b'\n', &mut line).is_ok_and(|read| read > 0) {
+                let _ = lines.send(String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string());
+                bytes.append(&mut line);
+            }
+        }
+        bytes
+    })
+}
+
+/// Kills `pid` and every process it started, tools started by a script would keep running otherwise.
+fn kill_tree(pid: u32) {
+    kill_pids(&process_tree(pid));
+}
+
+/// Kills every process this one started, e.g. the running steps' scripts when the run is aborted.
+pub fn kill_children() {
+    kill_pids(&process_tree(std::process::id())[1..]);
+}
+
+/// `pid` followed by every process it started, directly or not.
+fn process_tree(pid: u32) -> Vec<u32> {
+    let mut pids = vec![pid];
+    if let Ok(output) = Command::new("ps").args(["-A", "-o", "pid=,ppid="]).output() {
+        let parents: Vec<(u32, u32)> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                Some((fields.next()?.parse().ok()?, fields.next()?.parse().ok()?))
+            })
+            .collect();
+        let mut i = 0;
+        while i < pids.len() {
+            let parent = pids[i];
+            pids.extend(parents.iter().filter(|(_, ppid)| *ppid == parent).map(|(pid, _)| *pid));
+            i += 1;
+        }
+    }
+    pids
+}
+
+fn kill_pids(pids: &[u32]) {
+    if pids.is_empty() {
+        return;
+    }
+    let pid_args: Vec<String> = pids.iter().map(u32::to_string).collect();
+    let _ = Command::new("kill").arg("-KILL").args(&pid_args).output();
+}
+
+/// Parses a timeout like `90s`, `10m`, `1h30m` or plain seconds.
+pub fn parse_timeout(text: &str) -> Result<Duration> {
+    let mut seconds = 0;
+    let mut number = String::new();
+    for c in text.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            _ => {
+                return Err(MendError::Validation(format!(
+                    "Timeout `{}` needs a number of seconds or a duration like `90s`, `10m` or `1h30m`",
+                    text
+                )))
+            }
+        };
+        if number.is_empty() {
+            return Err(MendError::Validation(format!("Timeout `{}` has a unit without a number", text)));
+        }
+        seconds += number.parse::<u64>().with_context(|| format!("Timeout `{}` is too long", text))? * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        seconds += number.parse::<u64>().with_context(|| format!("Timeout `{}` is too long", text))?;
+    }
+    if seconds == 0 {
+        return Err(MendError::Validation(format!("Timeout `{}` needs to be longer than 0 seconds", text)));
+    }
+    Ok(Duration::from_secs(seconds))
 }
 
 #[cfg(test)]
 mod tests {
     use crate::progress::Notify;
-    use crate::repo::Repo;
-    use crate::run::{create_run_status_from_mend, EStatus, Executor, run_all_steps, run_command_with_output, run_step, StepRequest, StepResponse};
-    use crate::{Hook, Mend, Recipe};
+    use crate::error::Result;
+    use crate::config::{load_mend_contents, parse_mend};
+    use crate::repo::{GitRepo, Identity, Repo};
+    use crate::select::StepSelection;
+    use crate::run::{bind_params, create_run_status_from_mend, parse_timeout, EStatus, Executor, open_debug_shell, run_all_steps, run_command_with_output, run_step, step_hooks, RunOptions, RunSummary, ShellExecutor, SquashGroup, StepCommit, StepRequest, StepResponse, take_parallel_steps, lock_overlapping_steps, verify_baseline, finish_step, check_committers, VerifyTier};
+    use crate::edit::{Edit, EditOp};
+    use crate::shell::ShellDialect;
+    use crate::{CommitConfig, Hook, Mend, Recipe, ShellConfig, Step, StepConfig, Verify};
     use std::borrow::Borrow;
     use std::cell::RefCell;
+    use std::collections::BTreeMap;
     use std::env;
+    use std::fs;
     use std::path::Path;
-    use std::process::Output;
+    use std::process::{Command, Output};
     use std::rc::Rc;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn test_create_run_status_empty() {
         let mend = create_mend_with_steps(vec![]);
-        insta::assert_yaml_snapshot!(create_run_status_from_mend(&mend));
+        insta::assert_yaml_snapshot!(create_run_status_from_mend(&mend, &StepSelection::default()));
     }
 
     #[test]
     fn test_create_run_status_one_step() {
         let mend = create_mend_with_steps(vec!["cmd arg1 arg2".to_string()]);
-        let step_requests = create_run_status_from_mend(&mend);
+        let step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
         assert_eq!(step_requests.len(), 1);
         insta::assert_yaml_snapshot!(step_requests);
     }
@@ -302,8 +2057,7 @@ mod tests {
             Recipe {
                 run: "resolved $1 $2".to_string(),
                 commit_template: None,
-                tag: None,
-                tags: vec![],
+                ..Default::default()
             },
         );
         mend.recipes.insert(
@@ -311,31 +2065,465 @@ mod tests {
             Recipe {
                 run: "should not appear!".to_string(),
                 commit_template: None,
-                tag: None,
-                tags: vec![],
+                ..Default::default()
             },
         );
-        let step_requests = create_run_status_from_mend(&mend);
+        let step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
         assert_eq!(step_requests.len(), 1);
         insta::assert_yaml_snapshot!(step_requests);
     }
 
     #[test]
-    fn create_run_request_with_recipe_commit_template() {
-        let mut mend = create_mend_with_steps(vec!["rename arg1 arg2".to_string()]);
+    fn recipe_functions_are_normalized() {
+        let resolve = |run: &str| {
+            let mut mend = create_mend_with_steps(vec!["cmd arg1".to_string()]);
+            mend.recipes.insert(
+                "cmd".to_string(),
+                Recipe {
+                    run: run.to_string(),
+                    tags: vec!["b".to_string(), "a".to_string(), "b".to_string()],
+                    ..Default::default()
+                },
+            );
+            create_run_status_from_mend(&mend, &StepSelection::default()).remove(0).run_resolved
+        };
+        let clean = resolve("first $1\nsecond");
+        assert_eq!(resolve("first $1\r\nsecond\r\n\r\n"), clean);
+        assert_eq!(clean, vec!["cmd() {\nfirst $1\nsecond\n}\ncmd arg1\n".to_string()]);
+    }
+
+    #[test]
+    fn recipes_are_wrapped_for_each_shell_dialect() {
+        let scripts: Vec<String> = [ShellDialect::Posix, ShellDialect::Powershell, ShellDialect::Cmd]
+            .into_iter()
+            .map(|dialect| {
+                let mut mend = create_mend_with_steps(vec![]);
+                mend.steps = vec![Step::Structured(Box::new(StepConfig {
+                    run: Some("rename foo bar".to_string()),
+                    expected_exit_codes: Some(vec![0, 3]),
+                    ..Default::default()
+                }))];
+                mend.recipes.insert(
+                    "rename".to_string(),
+                    Recipe {
+                        run: match dialect {
+                            ShellDialect::Cmd => "rename-cli %old% %new%".to_string(),
+                            _ => "rename-cli $old $new".to_string(),
+                        },
+                        params: vec!["old".to_string(), "new".to_string()],
+                        ..Default::default()
+                    },
+                );
+                mend.shell = Some(ShellConfig {
+                    dialect: Some(dialect),
+                    ..Default::default()
+                });
+                format!("# {:?}\n{}", dialect, create_run_status_from_mend(&mend, &StepSelection::default()).remove(0).run_resolved.join(""))
+            })
+            .collect();
+        insta::assert_snapshot!(scripts.join("\n"));
+    }
+
+    #[test]
+    fn interpreter_recipes_get_their_body_on_stdin() {
+        let mut mend = create_mend_with_steps(vec!["greet who=world twice".to_string()]);
+        mend.recipes.insert(
+            "greet".to_string(),
+            Recipe {
+                run: "import os, sys\r\nprint('hello', os.environ['who'], sys.argv[2])\n".to_string(),
+                params: vec!["who".to_string(), "times=once".to_string()],
+                interpreter: Some("python3".to_string()),
+                ..Default::default()
+            },
+        );
+        let script = create_run_status_from_mend(&mend, &StepSelection::default()).remove(0).run_resolved.join("");
+        assert_eq!(
+            script,
+            "who='world' times='twice' python3 - 'world' 'twice' <<'MEND_RECIPE'\nimport os, sys\nprint('hello', os.environ['who'], sys.argv[2])\nMEND_RECIPE\n"
+        );
+        let temp_dir = tempfile::tempdir().unwrap();
+        let output = ShellExecutor::default().run_script(temp_dir.path(), &script, &BTreeMap::new(), None, &mut |_| {}).unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "hello world twice\n");
+    }
+
+    #[test]
+    fn named_params_are_bound_with_defaults() {
+        let mut mend = create_mend_with_steps(vec!["rename new=bar foo".to_string()]);
+        mend.recipes.insert(
+            "rename".to_string(),
+            Recipe {
+                run: "rename-cli \"$old\" \"$new\" --scope $scope".to_string(),
+                commit_template: Some("Rename $old to $2 in $scope".to_string()),
+                params: vec!["old".to_string(), "new".to_string(), "scope=all".to_string()],
+                ..Default::default()
+            },
+        );
+        let step_request = create_run_status_from_mend(&mend, &StepSelection::default()).remove(0);
+        assert_eq!(step_request.commit_msg, "Rename foo to bar in all");
+        assert_eq!(
+            step_request.run_resolved,
+            vec!["rename() {\nold=\"$1\"\nnew=\"$2\"\nscope=\"$3\"\nrename-cli \"$old\" \"$new\" --scope $scope\n}\nrename 'foo' 'bar' 'all'\n".to_string()]
+        );
+        let params = &mend.recipes["rename"].params;
+        assert!(bind_params("rename", params, &["new=bar"]).is_err());
+        assert!(bind_params("rename", params, &["a", "b", "c", "d"]).is_err());
+    }
+
+    #[test]
+    fn create_run_request_with_recipe_commit_template() {
+        let mut mend = create_mend_with_steps(vec!["rename arg1 arg2".to_string()]);
+
+        mend.recipes.insert(
+            "rename".to_string(),
+            Recipe {
+                run: "rename-cli $1 $2".to_string(),
+                commit_template: Some("r - Rename $1 to $2".to_string()),
+                ..Default::default()
+            },
+        );
+        let step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
+        assert_eq!(step_requests.len(), 1);
+        assert_eq!(step_requests.first().unwrap().commit_msg, "r - Rename arg1 to arg2");
+    }
+
+    #[test]
+    fn commit_message_only_resolves_allowed_env() {
+        env::set_var("LEAKY_TOKEN_FOR_TEST", "secret");
+        env::set_var("CI_JOB_FOR_TEST", "42");
+        let mut mend = create_mend_with_steps(vec!["rename a b".to_string()]);
+        mend.env.insert("TICKET".to_string(), "JIRA-1".to_string());
+        mend.commit = Some(CommitConfig {
+            allow_env: vec!["CI_JOB_FOR_TEST".to_string()],
+            ..Default::default()
+        });
+        mend.recipes.insert(
+            "rename".to_string(),
+            Recipe {
+                run: "rename-cli $1 $2".to_string(),
+                commit_template: Some("$TICKET Rename $1 in job $CI_JOB_FOR_TEST ${LEAKY_TOKEN_FOR_TEST}".to_string()),
+                ..Default::default()
+            },
+        );
+        let step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
+        assert_eq!(step_requests[0].commit_msg, "JIRA-1 Rename a in job 42 ${LEAKY_TOKEN_FOR_TEST}");
+    }
+
+    #[test]
+    fn commit_message_never_resolves_mend_variables_from_the_environment() {
+        env::set_var("MEND_AGE_IDENTITY", "AGE-SECRET-KEY-1FORTEST");
+        env::set_var("MEND_RESULT_FILE", "/tmp/elsewhere");
+        let mut mend = create_mend_with_steps(vec!["rename a b".to_string()]);
+        mend.run_env.insert("MEND_BIN".to_string(), "/usr/bin/mend".to_string());
+        mend.recipes.insert(
+            "rename".to_string(),
+            Recipe {
+                run: "rename-cli $1 $2".to_string(),
+                commit_template: Some("Rename $1 with $MEND_BIN $MEND_AGE_IDENTITY $MEND_RESULT_FILE".to_string()),
+                ..Default::default()
+            },
+        );
+        let step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
+        assert_eq!(step_requests[0].commit_msg, "Rename a with /usr/bin/mend $MEND_AGE_IDENTITY $MEND_RESULT_FILE");
+    }
+
+    #[test]
+    fn commit_trailers_record_the_step_and_recipe() {
+        let mut mend = create_mend_with_steps(vec!["rename a b".to_string(), "echo done".to_string()]);
+        mend.env.insert("TICKET".to_string(), "JIRA-1".to_string());
+        mend.commit = Some(CommitConfig {
+            trailers: vec!["Refactor-Tool: mend".to_string(), "Refs: $TICKET".to_string()],
+            step_trailers: true,
+            ..Default::default()
+        });
+        mend.recipes.insert("rename".to_string(), Recipe { run: "rename-cli $1 $2".to_string(), ..Default::default() });
+        let step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
+        assert_eq!(step_requests[0].trailers, vec!["Refactor-Tool: mend", "Refs: JIRA-1", "Mend-Step: 1", "Mend-Recipe: rename"]);
+        assert_eq!(step_requests[1].trailers, vec!["Refactor-Tool: mend", "Refs: JIRA-1", "Mend-Step: 2"]);
+
+        let logger_rc = Rc::new(RefCell::new(TestLogger { messages: vec![] }));
+        finish_step(
+            &mut FakeRepo { logger: logger_rc.clone() },
+            &mut FakeNotifier { logger: logger_rc.clone() },
+            0,
+            &step_requests[0],
+            &mut StepResponse::pending(),
+            None,
+            None,
+        );
+        let logger_ref_cell: &RefCell<TestLogger> = logger_rc.borrow();
+        assert!(logger_ref_cell
+            .borrow()
+            .messages
+            .contains(&"Repo commit all with msg 'rename a b\n\nRefactor-Tool: mend\nRefs: JIRA-1\nMend-Step: 1\nMend-Recipe: rename'".to_string()));
+    }
+
+    #[test]
+    fn steps_commit_as_their_committer_role() {
+        let toml = "[env]\nBOT_EMAIL = \"bot@example.com\"\n[committers.bot]\nname = \"mend-bot\"\nemail = \"$BOT_EMAIL\"\n\
+            [[steps]]\nrun = \"cargo fmt\"\ncommitter_role = \"bot\"\n[[steps]]\nrun = \"cargo fix\"\n";
+        let mut mend = parse_mend(Path::new("mend.toml"), toml).unwrap();
+        check_committers(&mend).unwrap();
+        let step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
+        let bot = Identity { name: "mend-bot".to_string(), email: "bot@example.com".to_string() };
+        assert_eq!(step_requests[0].committer, Some(bot));
+        assert_eq!(step_requests[1].committer, None);
+
+        let logger_rc = Rc::new(RefCell::new(TestLogger { messages: vec![] }));
+        finish_step(
+            &mut FakeRepo { logger: logger_rc.clone() },
+            &mut FakeNotifier { logger: logger_rc.clone() },
+            0,
+            &step_requests[0],
+            &mut StepResponse::pending(),
+            None,
+            None,
+        );
+        let logger_ref_cell: &RefCell<TestLogger> = logger_rc.borrow();
+        assert!(logger_ref_cell
+            .borrow()
+            .messages
+            .contains(&"Repo commit all as mend-bot <bot@example.com> with msg 'cargo fmt'".to_string()));
+
+        // An identity that doesn't resolve stops the run before it starts
+        mend.env.clear();
+        let message = format!("{:#}", check_committers(&mend).unwrap_err());
+        assert_eq!(message, "The email of committer `bot` needs `$BOT_EMAIL`, which is not set or not in [commit] allow_env");
+        mend.committers.get_mut("bot").unwrap().email = " ".to_string();
+        assert_eq!(format!("{:#}", check_committers(&mend).unwrap_err()), "Committer `bot` has no email");
+    }
+
+    #[test]
+    fn create_run_request_with_verify_and_recipe_override() {
+        let mut mend = create_mend_with_steps(vec!["cmd".to_string(), "quick".to_string(), "other".to_string()]);
+        mend.verify = Some(Verify { run: Some("make test".to_string()), ..Default::default() });
+        mend.recipes.insert(
+            "cmd".to_string(),
+            Recipe {
+                run: "echo cmd".to_string(),
+                ..Default::default()
+            },
+        );
+        mend.recipes.insert(
+            "quick".to_string(),
+            Recipe {
+                run: "echo quick".to_string(),
+                verify: Some("".to_string()),
+                ..Default::default()
+            },
+        );
+        mend.recipes.insert(
+            "other".to_string(),
+            Recipe {
+                run: "echo other".to_string(),
+                verify: Some("make check".to_string()),
+                ..Default::default()
+            },
+        );
+        let verifies: Vec<Option<String>> = create_run_status_from_mend(&mend, &StepSelection::default())
+            .into_iter()
+            .map(|step_request| step_request.verify)
+            .collect();
+        assert_eq!(verifies, vec![Some("make test".to_string()), None, Some("make check".to_string())]);
+    }
+
+    #[test]
+    fn full_verify_runs_every_n_steps_and_last() {
+        let toml = r#"
+            steps = ["a", "b", "c", "d", "e"]
+
+            [verify]
+            fast = "make check"
+            full = "make test"
+            full_every = 2
+        "#;
+        let mend: Mend = toml::from_str(toml).unwrap();
+        let tiers: Vec<(Option<String>, VerifyTier)> = create_run_status_from_mend(&mend, &StepSelection::default())
+            .into_iter()
+            .map(|step_request| (step_request.verify, step_request.verify_tier))
+            .collect();
+        let fast = (Some("make check".to_string()), VerifyTier::Fast);
+        let full = (Some("make test".to_string()), VerifyTier::Full);
+        assert_eq!(tiers, vec![fast.clone(), full.clone(), fast, full.clone(), full]);
+    }
+
+    #[test]
+    fn full_verify_keeps_recipe_verify_and_counts_only_the_steps_that_run() {
+        let toml = r#"
+            steps = ["a", "own", "opt_out", "b", "c"]
+
+            [recipes.own]
+            run = "echo own"
+            verify = "make own"
+
+            [recipes.opt_out]
+            run = "echo opt_out"
+            verify = ""
+
+            [verify]
+            fast = "make check"
+            full = "make test"
+            full_every = 2
+        "#;
+        let mend: Mend = toml::from_str(toml).unwrap();
+        let selection = StepSelection { skip: vec!["c".to_string()], ..Default::default() };
+        let tiers: Vec<(Option<String>, VerifyTier)> = create_run_status_from_mend(&mend, &selection)
+            .into_iter()
+            .map(|step_request| (step_request.verify, step_request.verify_tier))
+            .collect();
+        let fast = (Some("make check".to_string()), VerifyTier::Fast);
+        assert_eq!(
+            tiers,
+            vec![
+                fast.clone(),
+                (Some("make own".to_string()), VerifyTier::Fast),
+                (None, VerifyTier::Fast),
+                (Some("make test".to_string()), VerifyTier::Full),
+                fast,
+            ]
+        );
+    }
+
+    #[test]
+    fn full_verify_of_a_skipped_milestone_runs_later() {
+        let skipped_milestone = || StepRequest {
+            id: "milestone".to_string(),
+            run: "milestone".to_string(),
+            run_resolved: vec!["..milestone..".to_string()],
+            when: Some("env.MEND_RUN_TEST_UNSET".to_string()),
+            verify: Some("make test".to_string()),
+            verify_tier: VerifyTier::Full,
+            full_verify: Some("make test".to_string()),
+            ..Default::default()
+        };
+        let next = || StepRequest {
+            id: "next".to_string(),
+            run: "next".to_string(),
+            run_resolved: vec!["..next..".to_string()],
+            verify: Some("make check".to_string()),
+            full_verify: Some("make test".to_string()),
+            ..Default::default()
+        };
+        let logger_rc = Rc::new(RefCell::new(TestLogger { messages: vec![] }));
+        let summary = run_all_steps(
+            vec![skipped_milestone(), next()],
+            &mut FakeNotifier { logger: logger_rc.clone() },
+            &mut FakeRepo { logger: logger_rc.clone() },
+            &mut FakeExecutor { logger: logger_rc.clone(), succeed: true },
+            &RunOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(summary.skipped_steps, vec![0]);
+        let logger_ref_cell: &RefCell<TestLogger> = logger_rc.borrow();
+        let scripts: Vec<String> = logger_ref_cell.borrow().messages.iter().filter(|message| message.starts_with("Executor")).cloned().collect();
+        assert_eq!(scripts, vec!["Executor run script:\n..next..\n", "Executor run script:\nmake test\n"]);
+
+        // With no step after it, the run ends with it
+        let result = run_all_steps(
+            vec![next(), skipped_milestone()],
+            &mut FakeNotifier { logger: logger_rc.clone() },
+            &mut FakeRepo { logger: logger_rc.clone() },
+            &mut ScriptedExecutor { logger: logger_rc.clone(), failing: vec!["make test".to_string()] },
+            &RunOptions::default(),
+        );
+        let (step_request, step_response) = *result.unwrap_err();
+        assert_eq!(step_request.id, "verify");
+        assert_eq!(step_response.status, EStatus::VerifyFailed);
+    }
+
+    #[test]
+    fn run_step_fails_when_verify_fails() {
+        let step_request = StepRequest {
+            run: "cmd".to_string(),
+            run_resolved: vec!["..cmd..".to_string()],
+            commit_msg: "..msg..".to_string(),
+            verify: Some("..verify..".to_string()),
+            ..Default::default()
+        };
+        let mut step_response = StepResponse::pending();
+        let logger_rc = Rc::new(RefCell::new(TestLogger { messages: vec![] }));
+        run_step(
+            &mut FakeRepo { logger: logger_rc.clone() },
+            &mut FakeExecutor { logger: logger_rc.clone(), succeed: false },
+            &mut FakeNotifier { logger: logger_rc.clone() },
+            0,
+            &step_request,
+            &mut step_response,
+            &RunOptions::default(),
+            None,
+        );
+        assert_eq!(step_response.status, EStatus::Failed);
+    }
+
+    #[test]
+    fn verify_failures_are_told_apart_from_script_failures() {
+        let status_of = |script: &str, verify: &str| {
+            let step_request = StepRequest {
+                run: "cmd".to_string(),
+                run_resolved: vec![script.to_string()],
+                commit_msg: "..msg..".to_string(),
+                verify: Some(verify.to_string()),
+                ..Default::default()
+            };
+            let mut step_response = StepResponse::pending();
+            let logger_rc = Rc::new(RefCell::new(TestLogger { messages: vec![] }));
+            run_step(
+                &mut FakeRepo { logger: logger_rc.clone() },
+                &mut ShellExecutor::default(),
+                &mut FakeNotifier { logger: logger_rc.clone() },
+                0,
+                &step_request,
+                &mut step_response,
+                &RunOptions::default(),
+                None,
+            );
+            step_response.status
+        };
+        assert_eq!(status_of("true", "false"), EStatus::VerifyFailed);
+        assert_eq!(status_of("false", "true"), EStatus::Failed);
+        assert_eq!(status_of("true", "true"), EStatus::Done);
+        assert!(EStatus::VerifyFailed.is_failed());
+    }
 
-        mend.recipes.insert(
-            "rename".to_string(),
-            Recipe {
-                run: "rename-cli $1 $2".to_string(),
-                commit_template: Some("r - Rename $1 to $2".to_string()),
-                tag: None,
-                tags: vec![],
-            },
+    #[test]
+    fn broken_baselines_fail_before_any_step() {
+        let dir = env::temp_dir();
+        let env = BTreeMap::new();
+        assert!(verify_baseline(&mut ShellExecutor::default(), &dir, "1a2b3c4", "true", &env).is_ok());
+        let err = verify_baseline(&mut ShellExecutor::default(), &dir, "1a2b3c4", "echo 2 tests failed; false", &env).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The baseline is already broken, `echo 2 tests failed; false` fails on 1a2b3c4 before any step ran. Fix it or start from another `from.sha`:\n2 tests failed\n"
         );
-        let step_requests = create_run_status_from_mend(&mend);
-        assert_eq!(step_requests.len(), 1);
-        assert_eq!(step_requests.get(0).unwrap().commit_msg, "r - Rename arg1 to arg2");
+    }
+
+    #[test]
+    fn matrix_verifies_the_step_in_every_variant() {
+        let step_request = StepRequest {
+            run: "cmd".to_string(),
+            run_resolved: vec!["true".to_string()],
+            commit_msg: "..msg..".to_string(),
+            verify: Some("test \"$JAVA\" != 17".to_string()),
+            ..Default::default()
+        };
+        let options = RunOptions {
+            matrix: ["11", "17", "21"].iter().map(|java| BTreeMap::from([("JAVA".to_string(), java.to_string())])).collect(),
+            ..Default::default()
+        };
+        let mut step_response = StepResponse::pending();
+        let logger_rc = Rc::new(RefCell::new(TestLogger { messages: vec![] }));
+        run_step(
+            &mut FakeRepo { logger: logger_rc.clone() },
+            &mut ShellExecutor::default(),
+            &mut FakeNotifier { logger: logger_rc.clone() },
+            0,
+            &step_request,
+            &mut step_response,
+            &options,
+            None,
+        );
+        assert_eq!(step_response.status, EStatus::VerifyFailed);
+        assert_eq!(step_response.metadata["matrix"], "JAVA=11: ok; JAVA=17: failed; JAVA=21: ok");
+        assert!(step_response.output.unwrap().contains("Verification failed with JAVA=17"));
     }
 
     #[test]
@@ -356,7 +2544,7 @@ mod tests {
             .insert("before_step".to_string(), vec![before_step_hook]);
         mend.hooks
             .insert("after_step".to_string(), vec![after_step_hook]);
-        let step_requests = create_run_status_from_mend(&mend);
+        let step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
         assert_eq!(step_requests.len(), 1);
         insta::assert_yaml_snapshot!(step_requests);
     }
@@ -384,11 +2572,68 @@ mod tests {
             Recipe {
                 run: "resolved $1 $2".to_string(),
                 commit_template: None,
-                tag: None,
                 tags: vec!["some_tag".to_string()],
+                ..Default::default()
             },
         );
-        let step_requests = create_run_status_from_mend(&mend);
+        let step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
+        assert_eq!(step_requests.len(), 1);
+        insta::assert_yaml_snapshot!(step_requests);
+    }
+
+    #[test]
+    fn phase_hooks_run_only_around_the_phase_steps_before_the_top_level_ones() {
+        let toml = r#"
+steps = ["format"]
+[hooks]
+before_step = [{ run = "git clean -fdq" }]
+after_step = [{ run = "cargo fmt" }]
+
+[[phases]]
+name = "cleanup"
+steps = ["remove_comments"]
+[phases.hooks]
+before_step = [{ run = "cargo fix" }]
+after_step = [{ run = "cargo clippy", when_tag = "lint" }, { run = "cargo check" }]
+
+[recipes.remove_comments]
+run = "strip_comments"
+fallback = "format"
+"#;
+        let mend = load_mend_contents(Path::new("mend.toml"), toml).unwrap();
+        let step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
+        assert_eq!(step_requests[0].run_resolved, vec!["git clean -fdq", "format\n", "cargo fmt"]);
+        let phase_scripts = &step_requests[1].run_resolved;
+        assert_eq!(phase_scripts[..2], ["cargo fix", "git clean -fdq"]);
+        assert_eq!(phase_scripts[3..], ["cargo check", "cargo fmt"]);
+        // A fallback runs in the failed step's phase too
+        assert_eq!(step_requests[1].fallback_resolved.first().map(String::as_str), Some("cargo fix"));
+        let (before, after) = step_hooks("remove_comments", &mend, 1);
+        assert_eq!(before, vec!["cargo fix", "git clean -fdq"]);
+        assert_eq!(after, vec!["cargo check", "cargo fmt"]);
+    }
+
+    #[test]
+    fn create_run_status_edit_step() {
+        let mut mend = create_mend_with_steps(vec![]);
+        mend.steps.push(Step::Structured(Box::new(StepConfig {
+            edit: Some(Edit {
+                file: "package.json".to_string(),
+                op: EditOp::Set,
+                path: ".scripts.test".to_string(),
+                value: Some(toml::Value::String("it's jest".to_string())),
+            }),
+            ..Default::default()
+        })));
+        mend.hooks.insert(
+            "after_step".to_string(),
+            vec![Hook {
+                run: Some("npm test".to_string()),
+                when_tag: None,
+                when_not_tag: None,
+            }],
+        );
+        let step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
         assert_eq!(step_requests.len(), 1);
         insta::assert_yaml_snapshot!(step_requests);
     }
@@ -398,9 +2643,36 @@ mod tests {
             from: None,
             include: vec![],
             env: Default::default(),
+            secrets: Default::default(),
             recipes: Default::default(),
             hooks: Default::default(),
-            steps,
+            steps: steps.iter().map(|step| Step::from(step.as_str())).collect(),
+            verify: None,
+            matrix: Default::default(),
+            verify_baseline: None,
+            gates: None,
+            git: None,
+            commit: None,
+            committers: Default::default(),
+            phases: vec![],
+            heartbeat: None,
+            shell: None,
+            telemetry: None,
+            metrics: None,
+            keep_going: None,
+            on_failure: None,
+            badge: None,
+            notify: None,
+            forge: None,
+            logs: None,
+            artifacts: None,
+            output: None,
+            publish: None,
+            lint_rules: vec![],
+            corpus: vec![],
+            aliases: Default::default(),
+            timeout: None,
+            run_env: Default::default(),
         }
     }
 
@@ -408,7 +2680,7 @@ mod tests {
         logger: Rc<RefCell<TestLogger>>,
     }
     impl Repo for FakeRepo {
-        fn commit_all(&mut self, message: &str) -> anyhow::Result<()> {
+        fn commit_all(&mut self, message: &str) -> Result<()> {
             let logger_ref_cell: &RefCell<TestLogger> = self.logger.borrow();
             logger_ref_cell
                 .borrow_mut()
@@ -416,7 +2688,7 @@ mod tests {
             Ok(())
         }
 
-        fn reset_hard(&mut self) -> anyhow::Result<()> {
+        fn reset_hard(&mut self) -> Result<()> {
             let logger_ref_cell: &RefCell<TestLogger> = self.logger.borrow();
             logger_ref_cell
                 .borrow_mut()
@@ -424,12 +2696,69 @@ mod tests {
             Ok(())
         }
 
-        fn current_short_sha(&self) -> anyhow::Result<String> {
+        fn current_short_sha(&self) -> Result<String> {
             Ok("..SHA..".to_string())
         }
 
+        fn commits_since(&self, _sha: &str) -> Result<Vec<String>> {
+            Ok(vec!["..NEW_SHA..".to_string()])
+        }
+
+        fn commit_fixup(&mut self, sha: &str) -> Result<()> {
+            let logger_ref_cell: &RefCell<TestLogger> = self.logger.borrow();
+            logger_ref_cell
+                .borrow_mut()
+                .log(format!("Repo commit fixup of {}", sha));
+            Ok(())
+        }
+
+        fn autosquash_since(&mut self, sha: &str) -> Result<()> {
+            let logger_ref_cell: &RefCell<TestLogger> = self.logger.borrow();
+            logger_ref_cell
+                .borrow_mut()
+                .log(format!("Repo autosquash since {}", sha));
+            Ok(())
+        }
+
+        fn squash_since(&mut self, sha: &str, message: &str) -> Result<bool> {
+            let logger_ref_cell: &RefCell<TestLogger> = self.logger.borrow();
+            logger_ref_cell
+                .borrow_mut()
+                .log(format!("Repo squash since {} with msg '{}'", sha, message));
+            Ok(true)
+        }
+
+        fn save_changes_to_branch(&mut self, branch: &str, message: &str) -> Result<bool> {
+            let logger_ref_cell: &RefCell<TestLogger> = self.logger.borrow();
+            logger_ref_cell
+                .borrow_mut()
+                .log(format!("Repo save changes to {} with msg '{}'", branch, message));
+            Ok(true)
+        }
+
         fn dir(&self) -> &Path {
-            Path::new("some_path")
+            // Real executors run scripts here
+            Path::new(".")
+        }
+
+        fn file_hashes(&self, globs: &[String]) -> Result<String> {
+            Ok(format!("..HASHES {}..", globs.join(" ")))
+        }
+
+        fn cherry_pick(&mut self, sha: &str, _identity: Option<&Identity>) -> Result<()> {
+            let logger_ref_cell: &RefCell<TestLogger> = self.logger.borrow();
+            logger_ref_cell
+                .borrow_mut()
+                .log(format!("Repo cherry-pick {}", sha));
+            Ok(())
+        }
+
+        fn commit_all_as(&mut self, message: &str, identity: &Identity) -> Result<()> {
+            let logger_ref_cell: &RefCell<TestLogger> = self.logger.borrow();
+            logger_ref_cell
+                .borrow_mut()
+                .log(format!("Repo commit all as {} <{}> with msg '{}'", identity.name, identity.email, message));
+            Ok(())
         }
     }
     struct FakeExecutor {
@@ -438,7 +2767,14 @@ mod tests {
     }
 
     impl Executor for FakeExecutor {
-        fn run_script(&mut self, _cwd: &Path, script: &str) -> anyhow::Result<Output> {
+        fn run_script(
+            &mut self,
+            _cwd: &Path,
+            script: &str,
+            _env: &BTreeMap<String, String>,
+            _timeout: Option<Duration>,
+            _on_line: &mut dyn FnMut(&str),
+        ) -> Result<Output> {
             let cmd = if self.succeed {
                 "echo".to_string()
             } else {
@@ -448,9 +2784,33 @@ mod tests {
             logger_ref_cell
                 .borrow_mut()
                 .log(format!("Executor run script:\n{}\n", script));
-            return run_command_with_output(env::current_dir().unwrap().as_path(), cmd, vec![]);
+            run_command_with_output(env::current_dir().unwrap().as_path(), cmd, vec![])
+        }
+    }
+    /// Fails only the listed scripts, for exercising recovery paths.
+    struct ScriptedExecutor {
+        logger: Rc<RefCell<TestLogger>>,
+        failing: Vec<String>,
+    }
+
+    impl Executor for ScriptedExecutor {
+        fn run_script(
+            &mut self,
+            _cwd: &Path,
+            script: &str,
+            _env: &BTreeMap<String, String>,
+            _timeout: Option<Duration>,
+            _on_line: &mut dyn FnMut(&str),
+        ) -> Result<Output> {
+            let logger_ref_cell: &RefCell<TestLogger> = self.logger.borrow();
+            logger_ref_cell
+                .borrow_mut()
+                .log(format!("Executor run script:\n{}\n", script));
+            let cmd = if self.failing.contains(&script.to_string()) { "false" } else { "echo" };
+            run_command_with_output(env::current_dir().unwrap().as_path(), cmd.to_string(), vec![])
         }
     }
+
     struct FakeNotifier {
         logger: Rc<RefCell<TestLogger>>,
     }
@@ -469,7 +2829,7 @@ mod tests {
                 .log(format!("Notify step {} status {:?} inc {}", i, status, inc))
         }
 
-        fn notify_done(&self) {
+        fn notify_done(&self, _summary: &RunSummary) {
             let logger_ref_cell: &RefCell<TestLogger> = self.logger.borrow();
             logger_ref_cell.borrow_mut().log("Notify done".to_string())
         }
@@ -494,8 +2854,8 @@ mod tests {
             "..cmd..".to_string(),
             "..after..".to_string(),
         ];
-        let mut step_response = StepResponse { sha: None, status: EStatus::Pending, output: None };
-        let step_request = StepRequest { run: "cmd".to_string(), run_resolved: scripts.clone(), commit_msg: "..msg..".to_string() };
+        let mut step_response = StepResponse::pending();
+        let step_request = StepRequest { run: "cmd".to_string(), run_resolved: scripts.clone(), commit_msg: "..msg..".to_string(), ..Default::default() };
 
         // The intent here is is to log is to log all interactions with the  fake objects in one vec.
         // I may have done something silly here to get the compiler to accept it. Better ideas?
@@ -517,6 +2877,8 @@ mod tests {
             1,
             &step_request,
             &mut step_response,
+            &RunOptions::default(),
+            None,
         );
         assert_eq!(step_response.status, EStatus::Done);
         let logger_ref_cell: &RefCell<TestLogger> = logger_rc.borrow();
@@ -531,8 +2893,8 @@ mod tests {
             "..cmd..".to_string(),
             "..after..".to_string(),
         ];
-        let step_request = StepRequest { run: "cmd".to_string(), run_resolved: scripts.clone(), commit_msg: "..msg..".to_string() };
-        let mut step_response = StepResponse { sha: None, status: EStatus::Pending, output: None };
+        let step_request = StepRequest { run: "cmd".to_string(), run_resolved: scripts.clone(), commit_msg: "..msg..".to_string(), ..Default::default() };
+        let mut step_response = StepResponse::pending();
 
         // The intent here is is to log is to log all interactions with the  fake objects in one vec.
         // I may have done something silly here to get the compiler to accept it. Better ideas?
@@ -551,6 +2913,8 @@ mod tests {
             1,
             &step_request,
             &mut step_response,
+            &RunOptions::default(),
+            None,
         );
         assert_eq!(step_response.status, EStatus::Failed);
         let logger_ref_cell: &RefCell<TestLogger> = logger_rc.borrow();
@@ -558,6 +2922,164 @@ mod tests {
         assert_eq!(step_response.sha, None);
     }
 
+    #[test]
+    fn step_response_sums_stats_lines() {
+        let mut step_response = StepResponse::pending();
+        step_response.record_stats("mend:stats matched=2 changed=1\nother output\nmend:stats changed=3\n");
+        assert_eq!(step_response.metadata.get("matched"), Some(&"2".to_string()));
+        assert_eq!(step_response.metadata.get("changed"), Some(&"4".to_string()));
+
+        let mut summary = RunSummary::default();
+        summary.add_step(&step_response);
+        summary.add_step(&StepResponse::pending());
+        summary.add_step(&step_response);
+        assert_eq!(summary.totals.get("changed"), Some(&8));
+        assert_eq!(summary.steps_with_stats, 2);
+    }
+
+    #[test]
+    fn step_response_reads_result_lines() {
+        let mut step_response = StepResponse::pending();
+        step_response.record_stats("mend:stats changed=2\n");
+        step_response.record_results("summary=Renamed foo to bar\nchanged=3\nwarning=one\nnot a result\nwarning=two\n");
+        insta::assert_yaml_snapshot!(step_response.metadata);
+    }
+
+    #[test]
+    fn create_run_request_resolves_fallback_with_step_args() {
+        let mut mend = create_mend_with_steps(vec!["rename foo bar".to_string()]);
+        mend.recipes.insert(
+            "rename".to_string(),
+            Recipe {
+                run: "fast-rename $1 $2".to_string(),
+                fallback: Some("manual-rename $1 $2 $HOME".to_string()),
+                ..Default::default()
+            },
+        );
+        mend.recipes.insert(
+            "manual-rename".to_string(),
+            Recipe {
+                run: "sed -i s/$1/$2/g *.c".to_string(),
+                ..Default::default()
+            },
+        );
+        let step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
+        assert_eq!(
+            step_requests[0].fallback_resolved,
+            vec!["mend_recipe_manual_2d_rename() {\nsed -i s/$1/$2/g *.c\n}\nmend_recipe_manual_2d_rename foo bar $HOME\n".to_string()]
+        );
+    }
+
+    #[test]
+    fn shell_executor_falls_back_to_installed_shell() {
+        let candidates = vec!["no-such-shell-for-mend".to_string(), "sh -e".to_string()];
+        let mut executor = ShellExecutor::find(&candidates).unwrap();
+        let output = executor.run_script(Path::new("."), "false; echo not reached", &BTreeMap::new(), None, &mut |_| {}).unwrap();
+        assert!(!output.status.success());
+        assert!(output.stdout.is_empty());
+        let err = ShellExecutor::find(&candidates[..1]).err().unwrap();
+        assert!(format!("{:#}", err).contains("tried no-such-shell-for-mend"));
+    }
+
+    #[test]
+    fn shell_executor_passes_on_lines_as_they_are_printed() {
+        let mut lines = vec![];
+        let script = "echo one; echo two >&2; printf 'no newline'";
+        let output = ShellExecutor::default()
+            .run_script(Path::new("."), script, &BTreeMap::new(), Some(Duration::from_secs(10)), &mut |line| lines.push(line.to_string()))
+            .unwrap();
+        lines.sort();
+        assert_eq!(lines, vec!["no newline", "one", "two"]);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "one\nno newline");
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "two\n");
+    }
+
+    #[test]
+    fn shell_executor_masks_secrets_and_hides_the_identity() {
+        env::set_var("MEND_AGE_IDENTITY", "AGE-SECRET-KEY-1TEST");
+        let secrets = BTreeMap::from([
+            ("TOKEN".to_string(), "s3cret\n".to_string()),
+            ("KEY".to_string(), "line one\nline two\n".to_string()),
+        ]);
+        let mut lines = vec![];
+        let script = "printf 'token=%s' \"$TOKEN\"; printf '%s' \"$KEY\" >&2; echo \"identity=${MEND_AGE_IDENTITY:-unset}\"";
+        let output = ShellExecutor::default()
+            .masking(&secrets)
+            .run_script(Path::new("."), script, &secrets, None, &mut |line| lines.push(line.to_string()))
+            .unwrap();
+        lines.sort();
+        assert_eq!(lines, vec!["***", "***", "identity=unset", "token=***"]);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "token=***\nidentity=unset\n");
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "***\n");
+    }
+
+    #[test]
+    fn timeouts_are_parsed() {
+        assert_eq!(parse_timeout("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_timeout("10m").unwrap(), Duration::from_secs(600));
+        assert_eq!(parse_timeout("1h30m").unwrap(), Duration::from_secs(5400));
+        for invalid in ["", "0s", "10 minutes", "m"] {
+            assert!(parse_timeout(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn run_step_kills_scripts_that_time_out() {
+        let step_request = StepRequest {
+            run: "hang".to_string(),
+            // The child keeps the pipes open, so this also checks that what the script started is killed
+            run_resolved: vec!["echo started; sleep 30 & sleep 30".to_string(), "echo never".to_string()],
+            commit_msg: "hang".to_string(),
+            timeout: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        let mut step_response = StepResponse::pending();
+        let logger_rc = Rc::new(RefCell::new(TestLogger { messages: vec![] }));
+        let started = Instant::now();
+        run_step(
+            &mut FakeRepo { logger: logger_rc.clone() },
+            &mut ShellExecutor::default(),
+            &mut FakeNotifier { logger: logger_rc.clone() },
+            0,
+            &step_request,
+            &mut step_response,
+            &RunOptions::default(),
+            None,
+        );
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(step_response.status, EStatus::Failed);
+        let output = step_response.output.unwrap();
+        assert!(output.contains("started"), "{}", output);
+        assert!(output.contains("Step timed out after 1 second"), "{}", output);
+        assert!(!output.contains("never\n"), "{}", output);
+    }
+
+    #[test]
+    fn run_step_recovers_with_fallback() {
+        let step_request = StepRequest {
+            run: "cmd".to_string(),
+            run_resolved: vec!["..cmd..".to_string()],
+            commit_msg: "..msg..".to_string(),
+            fallback_resolved: vec!["..fallback..".to_string()],
+            ..Default::default()
+        };
+        let mut step_response = StepResponse::pending();
+        let logger_rc = Rc::new(RefCell::new(TestLogger { messages: vec![] }));
+        run_step(
+            &mut FakeRepo { logger: logger_rc.clone() },
+            &mut ScriptedExecutor { logger: logger_rc.clone(), failing: vec!["..cmd..".to_string()] },
+            &mut FakeNotifier { logger: logger_rc.clone() },
+            0,
+            &step_request,
+            &mut step_response,
+            &RunOptions::default(),
+            None,
+        );
+        assert_eq!(step_response.status, EStatus::Done);
+        let logger_ref_cell: &RefCell<TestLogger> = logger_rc.borrow();
+        insta::assert_yaml_snapshot!(logger_ref_cell.borrow().messages);
+    }
+
     #[test]
     fn run_all_steps_reports_ok_when_steps_pass() {
         let scripts = vec![
@@ -565,7 +3087,7 @@ mod tests {
             "..cmd..".to_string(),
             "..after..".to_string(),
         ];
-        let step_request = StepRequest { run: "cmd".to_string(), run_resolved: scripts.clone(), commit_msg: "..msg..".to_string() };
+        let step_request = StepRequest { run: "cmd".to_string(), run_resolved: scripts.clone(), commit_msg: "..msg..".to_string(), ..Default::default() };
         let logger_rc = Rc::new(RefCell::new(TestLogger { messages: vec![] }));
         let step_requests = vec![step_request];
         let result = run_all_steps(
@@ -579,7 +3101,8 @@ mod tests {
             &mut FakeExecutor {
                 logger: logger_rc.clone(),
                 succeed: true,
-            }
+            },
+            &RunOptions::default(),
         );
         assert!(result.is_ok());
     }
@@ -591,7 +3114,7 @@ mod tests {
             "..cmd..".to_string(),
             "..after..".to_string(),
         ];
-        let step_request = StepRequest { run: "cmd".to_string(), run_resolved: scripts.clone(), commit_msg: "..msg..".to_string() };
+        let step_request = StepRequest { run: "cmd".to_string(), run_resolved: scripts.clone(), commit_msg: "..msg..".to_string(), ..Default::default() };
         let logger_rc = Rc::new(RefCell::new(TestLogger { messages: vec![] }));
         let mut repo: FakeRepo = FakeRepo {
             logger: logger_rc.clone(),
@@ -608,14 +3131,394 @@ mod tests {
             step_requests,
             &mut notifier,
             &mut repo,
-            &mut executor
+            &mut executor,
+            &RunOptions::default(),
         );
         assert!(result.is_err());
-        let (failed_step_request, failed_step_response) = result.err().unwrap();
+        let (failed_step_request, failed_step_response) = *result.err().unwrap();
         assert_eq!(failed_step_request.run, "cmd".to_string());
         assert_eq!(failed_step_response.status, EStatus::Failed);
         let logger_ref_cell: &RefCell<TestLogger> = logger_rc.borrow();
         insta::assert_yaml_snapshot!(logger_ref_cell.borrow().messages);
         assert_eq!(failed_step_response.sha, None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn run_all_steps_continues_and_quarantines_failed_steps() {
+        let step_requests = vec![
+            StepRequest { run: "first".to_string(), run_resolved: vec!["..first..".to_string()], commit_msg: "first".to_string(), ..Default::default() },
+            StepRequest { run: "second".to_string(), run_resolved: vec!["..second..".to_string()], commit_msg: "second".to_string(), ..Default::default() },
+            StepRequest { run: "third".to_string(), run_resolved: vec!["..third..".to_string()], commit_msg: "third".to_string(), ..Default::default() },
+        ];
+        let logger_rc = Rc::new(RefCell::new(TestLogger { messages: vec![] }));
+        let result = run_all_steps(
+            step_requests,
+            &mut FakeNotifier { logger: logger_rc.clone() },
+            &mut FakeRepo { logger: logger_rc.clone() },
+            &mut ScriptedExecutor { logger: logger_rc.clone(), failing: vec!["..second..".to_string()] },
+            &RunOptions { continue_on_error: true, quarantine: true, ..Default::default() },
+        );
+        let summary = result.unwrap();
+        assert_eq!(summary.failed_steps, vec![1]);
+        let logger_ref_cell: &RefCell<TestLogger> = logger_rc.borrow();
+        insta::assert_yaml_snapshot!(logger_ref_cell.borrow().messages);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn debug_shell_opens_in_the_worktree_and_its_exit_status_tells_whether_to_go_on() {
+        use std::os::unix::fs::PermissionsExt;
+        let temp_dir = tempfile::tempdir().unwrap();
+        let shell_path = temp_dir.path().join("shell");
+        fs::write(&shell_path, "#!/bin/sh\necho \"$REGISTRY_TOKEN in $(pwd)\" > seen\nexit \"$EXIT\"\n").unwrap();
+        fs::set_permissions(&shell_path, fs::Permissions::from_mode(0o755)).unwrap();
+        let shell = shell_path.to_string_lossy().to_string();
+        let step_request = StepRequest { run: "rename Foo Bar".to_string(), ..Default::default() };
+        let step_env = |exit: &str| BTreeMap::from([("REGISTRY_TOKEN".to_string(), "secret".to_string()), ("EXIT".to_string(), exit.to_string())]);
+
+        assert!(open_debug_shell(&shell, temp_dir.path(), 0, &step_request, &step_env("0")).unwrap());
+        let seen = fs::read_to_string(temp_dir.path().join("seen")).unwrap();
+        assert_eq!(seen.trim(), format!("secret in {}", fs::canonicalize(temp_dir.path()).unwrap().to_string_lossy()));
+        assert!(!open_debug_shell(&shell, temp_dir.path(), 0, &step_request, &step_env("1")).unwrap());
+        assert!(open_debug_shell("no-such-shell", temp_dir.path(), 0, &step_request, &step_env("0")).is_err());
+    }
+
+    #[test]
+    fn run_all_steps_runs_independent_steps_at_once_and_applies_them_in_order() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let repo_dir = temp_dir.path().join("repo");
+        let markers = temp_dir.path().join("markers");
+        std::fs::create_dir_all(&markers).unwrap();
+        std::fs::create_dir_all(&repo_dir).unwrap();
+        for file in ["a.txt", "b.txt", "c.txt"] {
+            std::fs::write(repo_dir.join(file), "").unwrap();
+        }
+        Command::new("git").args(["init", "-q"]).current_dir(&repo_dir).output().unwrap();
+        Command::new("git").args(["add", "-A"]).current_dir(&repo_dir).output().unwrap();
+        let mut repo = GitRepo { repo_dir: repo_dir.clone() };
+        repo.commit_all("Initial").unwrap();
+        // Each of the first two steps waits for the other to start, so they only succeed when run at once
+        let meet = |me: &str, other: &str| {
+            format!(
+                "touch {markers}/{me}; i=0; while [ ! -e {markers}/{other} ] && [ $i -lt 100 ]; do sleep 0.1; i=$((i+1)); done; [ -e {markers}/{other} ] && echo {me} >> {me}.txt",
+                markers = markers.to_string_lossy(),
+            )
+        };
+        let step = |id: &str, script: String, needs: Option<Vec<&str>>| StepRequest {
+            id: id.to_string(),
+            run: id.to_string(),
+            run_resolved: vec![script],
+            commit_msg: id.to_string(),
+            needs: needs.map(|needs| needs.iter().map(|need| need.to_string()).collect()),
+            ..Default::default()
+        };
+        let step_requests = vec![
+            step("a", meet("a", "b"), None),
+            step("b", meet("b", "a"), Some(vec![])),
+            step("c", "echo c > c.txt".to_string(), Some(vec!["a"])),
+            // Runs alongside c, so its change to the same line conflicts
+            step("d", "echo d > c.txt".to_string(), Some(vec!["a"])),
+        ];
+        let logger_rc = Rc::new(RefCell::new(TestLogger { messages: vec![] }));
+        let summary = run_all_steps(
+            step_requests,
+            &mut FakeNotifier { logger: logger_rc.clone() },
+            &mut repo,
+            &mut ShellExecutor::default(),
+            &RunOptions { continue_on_error: true, max_parallel_steps: 4, ..Default::default() },
+        )
+        .unwrap();
+        assert_eq!(summary.failed_steps, vec![3]);
+        assert!(summary.failures[0].1.output.as_deref().unwrap().contains("Could not apply its commit"));
+        let log = run_command_with_output(&repo_dir, "git".to_string(), vec!["log", "--format=%s"]).unwrap();
+        assert_eq!(String::from_utf8_lossy(&log.stdout), "c\nb\na\nInitial\n");
+        assert_eq!(std::fs::read_to_string(repo_dir.join("a.txt")).unwrap(), "a\n");
+        assert_eq!(std::fs::read_to_string(repo_dir.join("c.txt")).unwrap(), "c\n");
+        assert_eq!(summary.commits.iter().map(|commit| commit.step).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert!(!temp_dir.path().join("repo-step-2").exists());
+    }
+
+    #[test]
+    fn steps_holding_the_same_lock_are_not_batched() {
+        let step = |id: &str, locks: &[&str]| {
+            (
+                0,
+                StepRequest {
+                    id: id.to_string(),
+                    needs: Some(vec![]),
+                    locks: locks.iter().map(|lock| lock.to_string()).collect(),
+                    ..Default::default()
+                },
+            )
+        };
+        let (_, first) = step("a", &["schema-registry"]);
+        let mut step_requests = vec![step("b", &["artifacts"]), step("c", &["schema-registry"]), step("d", &[])]
+            .into_iter()
+            .peekable();
+        let options = RunOptions { max_parallel_steps: 4, ..Default::default() };
+        let batch = take_parallel_steps(&first, &mut step_requests, &ShellExecutor::default(), &options);
+        assert_eq!(batch.iter().map(|(_, step_request)| step_request.id.as_str()).collect::<Vec<_>>(), vec!["b"]);
+        // Left for a later batch, keeping the declared order
+        assert_eq!(step_requests.next().unwrap().1.id, "c");
+    }
+
+    #[test]
+    fn steps_touching_the_same_files_get_a_shared_lock() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let repo_dir = temp_dir.path();
+        std::fs::create_dir_all(repo_dir.join("spec")).unwrap();
+        for file in ["api.json", "spec/pets.yaml"] {
+            std::fs::write(repo_dir.join(file), "").unwrap();
+        }
+        Command::new("git").args(["init", "-q"]).current_dir(repo_dir).output().unwrap();
+        Command::new("git").args(["add", "-A"]).current_dir(repo_dir).output().unwrap();
+        let globs = |globs: &[&str]| globs.iter().map(|glob| glob.to_string()).collect::<Vec<String>>();
+        let step = |id: &str, needs: &[&str], inputs: &[&str], outputs: &[&str]| StepRequest {
+            id: id.to_string(),
+            needs: Some(globs(needs)),
+            inputs: globs(inputs),
+            outputs: globs(outputs),
+            ..Default::default()
+        };
+        let mut step_requests = vec![
+            step("spec", &[], &[], &["*.json"]),
+            step("client", &[], &["spec/*.yaml"], &["client/*.ts"]),
+            step("docs", &[], &["api.json"], &[]),
+            step("server", &["spec"], &["api.json"], &[]),
+            step("gen-a", &[], &[], &["gen/*.rs"]),
+            step("gen-b", &[], &[], &["gen/*.rs"]),
+        ];
+        let notes = lock_overlapping_steps(&GitRepo { repo_dir: repo_dir.to_path_buf() }, &mut step_requests).unwrap();
+        assert_eq!(
+            notes,
+            vec![
+                "Steps 1 (spec) and 3 (docs) both touch api.json, they'll run one after another",
+                "Steps 5 (gen-a) and 6 (gen-b) both touch gen/*.rs, they'll run one after another",
+            ]
+        );
+        let locks: Vec<&Vec<String>> = step_requests.iter().map(|step_request| &step_request.locks).collect();
+        assert_eq!(locks, vec![&globs(&["api.json"]), &vec![], &globs(&["api.json"]), &vec![], &globs(&["gen/*.rs"]), &globs(&["gen/*.rs"])]);
+    }
+
+    #[test]
+    fn run_all_steps_keeps_going_without_the_fixups_of_failed_steps() {
+        let step_requests = vec![
+            StepRequest { id: "rename".to_string(), run: "rename".to_string(), run_resolved: vec!["..rename..".to_string()], commit_msg: "rename".to_string(), ..Default::default() },
+            StepRequest { id: "2".to_string(), run: "format".to_string(), run_resolved: vec!["..format..".to_string()], commit_msg: "format".to_string(), fixup: Some("rename".to_string()), ..Default::default() },
+            StepRequest { id: "3".to_string(), run: "third".to_string(), run_resolved: vec!["..third..".to_string()], commit_msg: "third".to_string(), ..Default::default() },
+        ];
+        let logger_rc = Rc::new(RefCell::new(TestLogger { messages: vec![] }));
+        let summary = run_all_steps(
+            step_requests,
+            &mut FakeNotifier { logger: logger_rc.clone() },
+            &mut FakeRepo { logger: logger_rc.clone() },
+            &mut ScriptedExecutor { logger: logger_rc.clone(), failing: vec!["..rename..".to_string()] },
+            &RunOptions { continue_on_error: true, ..Default::default() },
+        )
+        .unwrap();
+        assert_eq!(summary.failed_steps, vec![0, 1]);
+        assert_eq!(summary.failures[1].1.output.as_deref(), Some("Not run, step `rename` it fixes up failed"));
+        let logger_ref_cell: &RefCell<TestLogger> = logger_rc.borrow();
+        insta::assert_yaml_snapshot!(logger_ref_cell.borrow().messages);
+    }
+
+    #[test]
+    fn run_all_steps_skips_steps_whose_condition_does_not_hold() {
+        let step_requests = vec![
+            StepRequest { run: "first".to_string(), run_resolved: vec!["..first..".to_string()], commit_msg: "first".to_string(), when: Some("file_exists('Cargo.toml')".to_string()), ..Default::default() },
+            StepRequest { run: "second".to_string(), run_resolved: vec!["..second..".to_string()], commit_msg: "second".to_string(), when: Some("env.MEND_RUN_TEST_UNSET".to_string()), ..Default::default() },
+        ];
+        let logger_rc = Rc::new(RefCell::new(TestLogger { messages: vec![] }));
+        let summary = run_all_steps(
+            step_requests,
+            &mut FakeNotifier { logger: logger_rc.clone() },
+            &mut FakeRepo { logger: logger_rc.clone() },
+            &mut FakeExecutor { logger: logger_rc.clone(), succeed: true },
+            &RunOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(summary.skipped_steps, vec![1]);
+        let logger_ref_cell: &RefCell<TestLogger> = logger_rc.borrow();
+        insta::assert_yaml_snapshot!(logger_ref_cell.borrow().messages);
+    }
+
+    #[test]
+    fn run_all_steps_skips_steps_with_unchanged_inputs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let options = RunOptions { step_cache: Some(temp_dir.path().join("steps.json")), ..Default::default() };
+        let run = || {
+            let step_requests = vec![
+                StepRequest { run: "generate".to_string(), run_resolved: vec!["..generate..".to_string()], commit_msg: "generate".to_string(), inputs: vec!["spec/*.yaml".to_string()], ..Default::default() },
+                StepRequest { run: "format".to_string(), run_resolved: vec!["..format..".to_string()], commit_msg: "format".to_string(), ..Default::default() },
+            ];
+            let logger_rc = Rc::new(RefCell::new(TestLogger { messages: vec![] }));
+            run_all_steps(
+                step_requests,
+                &mut FakeNotifier { logger: logger_rc.clone() },
+                &mut FakeRepo { logger: logger_rc.clone() },
+                &mut FakeExecutor { logger: logger_rc.clone(), succeed: true },
+                &options,
+            )
+            .unwrap()
+        };
+        assert!(run().skipped_steps.is_empty());
+        assert_eq!(run().skipped_steps, vec![0]);
+    }
+
+    #[test]
+    fn run_all_steps_resumes_after_done_steps() {
+        let step_requests = vec![
+            StepRequest { id: "1".to_string(), run: "first".to_string(), run_resolved: vec!["..first..".to_string()], commit_msg: "first".to_string(), ..Default::default() },
+            StepRequest { id: "2".to_string(), run: "second".to_string(), run_resolved: vec!["..second..".to_string()], commit_msg: "second".to_string(), ..Default::default() },
+        ];
+        let logger_rc = Rc::new(RefCell::new(TestLogger { messages: vec![] }));
+        let options = RunOptions {
+            first_step: 1,
+            resumed_commits: vec![StepCommit { id: "1".to_string(), step: 1, sha: "aaa1111".to_string(), ..Default::default() }],
+            ..Default::default()
+        };
+        let summary = run_all_steps(
+            step_requests,
+            &mut FakeNotifier { logger: logger_rc.clone() },
+            &mut FakeRepo { logger: logger_rc.clone() },
+            &mut FakeExecutor { logger: logger_rc.clone(), succeed: true },
+            &options,
+        )
+        .unwrap();
+        let commit_ids: Vec<&str> = summary.commits.iter().map(|commit| commit.id.as_str()).collect();
+        assert_eq!(commit_ids, vec!["1", "2"]);
+        let logger_ref_cell: &RefCell<TestLogger> = logger_rc.borrow();
+        insta::assert_yaml_snapshot!(logger_ref_cell.borrow().messages);
+    }
+
+    #[test]
+    fn run_all_steps_squashes_group_after_its_last_step() {
+        let step_requests = vec![
+            StepRequest { run: "first".to_string(), run_resolved: vec!["..first..".to_string()], commit_msg: "first".to_string(), ..Default::default() },
+            StepRequest { run: "second".to_string(), run_resolved: vec!["..second..".to_string()], commit_msg: "second".to_string(), ..Default::default() },
+            StepRequest { run: "third".to_string(), run_resolved: vec!["..third..".to_string()], commit_msg: "third".to_string(), ..Default::default() },
+        ];
+        let logger_rc = Rc::new(RefCell::new(TestLogger { messages: vec![] }));
+        let options = RunOptions {
+            squash_groups: vec![SquashGroup { first_step: 0, last_step: 1, commit_msg: "phase".to_string() }],
+            ..Default::default()
+        };
+        let result = run_all_steps(
+            step_requests,
+            &mut FakeNotifier { logger: logger_rc.clone() },
+            &mut FakeRepo { logger: logger_rc.clone() },
+            &mut FakeExecutor { logger: logger_rc.clone(), succeed: true },
+            &options,
+        );
+        assert!(result.is_ok());
+        let logger_ref_cell: &RefCell<TestLogger> = logger_rc.borrow();
+        insta::assert_yaml_snapshot!(logger_ref_cell.borrow().messages);
+    }
+
+    #[test]
+    fn run_all_steps_commits_fixups_and_autosquashes() {
+        let mut mend = create_mend_with_steps(vec![]);
+        mend.steps = vec![
+            Step::Structured(Box::new(StepConfig { id: Some("rename".to_string()), run: Some("rename a b".to_string()), ..Default::default() })),
+            Step::from("other"),
+            Step::Structured(Box::new(StepConfig { fixup: Some("rename".to_string()), run: Some("format".to_string()), ..Default::default() })),
+        ];
+        let step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
+        assert_eq!(step_requests[1].id, "2");
+        assert_eq!(step_requests[2].fixup, Some("rename".to_string()));
+        let logger_rc = Rc::new(RefCell::new(TestLogger { messages: vec![] }));
+        let result = run_all_steps(
+            step_requests,
+            &mut FakeNotifier { logger: logger_rc.clone() },
+            &mut FakeRepo { logger: logger_rc.clone() },
+            &mut FakeExecutor { logger: logger_rc.clone(), succeed: true },
+            &RunOptions::default(),
+        );
+        let summary = result.unwrap();
+        // The fixup isn't a commit of its own and the shas are updated after the rebase
+        assert_eq!(summary.commits.iter().map(|commit| commit.id.as_str()).collect::<Vec<_>>(), vec!["rename", "2"]);
+        assert_eq!(summary.commits[0].revert, "git revert --no-edit ..NEW_SHA..");
+        let logger_ref_cell: &RefCell<TestLogger> = logger_rc.borrow();
+        let messages = &logger_ref_cell.borrow().messages;
+        assert!(messages.contains(&"Repo commit fixup of ..SHA..".to_string()));
+        assert_eq!(messages.last(), Some(&"Repo autosquash since ..SHA..".to_string()));
+    }
+
+    #[test]
+    fn expected_exit_codes_count_as_success() {
+        let mut mend = create_mend_with_steps(vec!["codemod src".to_string()]);
+        mend.steps.push(Step::Structured(Box::new(StepConfig {
+            run: Some("codemod lib".to_string()),
+            expected_exit_codes: Some(vec![3]),
+            ..Default::default()
+        })));
+        mend.recipes.insert(
+            "codemod".to_string(),
+            Recipe {
+                run: "exit 1".to_string(),
+                expected_exit_codes: Some(vec![0, 1]),
+                ..Default::default()
+            },
+        );
+        let step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
+        insta::assert_snapshot!(step_requests[0].run_resolved[0]);
+        let status = |script: &str| Command::new("bash").args(["-c", script]).output().unwrap().status.code();
+        assert_eq!(status(&step_requests[0].run_resolved[0]), Some(0));
+        assert_eq!(status(&step_requests[1].run_resolved[0]), Some(1));
+    }
+
+    #[test]
+    fn step_env_is_passed_to_that_step_only() {
+        let mut mend = create_mend_with_steps(vec!["format".to_string()]);
+        mend.env.insert("TOOLS".to_string(), "/opt/tools".to_string());
+        mend.steps.push(Step::Structured(Box::new(StepConfig {
+            run: Some("test \"$STEP_PATH\" = /opt/tools/bin".to_string()),
+            env: BTreeMap::from([("STEP_PATH".to_string(), "$TOOLS/bin".to_string())]),
+            ..Default::default()
+        })));
+        let step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
+        assert_eq!(step_requests[0].env, BTreeMap::from([("TOOLS".to_string(), "/opt/tools".to_string())]));
+        assert_eq!(step_requests[1].env.get("STEP_PATH"), Some(&"/opt/tools/bin".to_string()));
+
+        let mut step_response = StepResponse::pending();
+        let logger_rc = Rc::new(RefCell::new(TestLogger { messages: vec![] }));
+        run_step(
+            &mut FakeRepo { logger: logger_rc.clone() },
+            &mut ShellExecutor::default(),
+            &mut FakeNotifier { logger: logger_rc.clone() },
+            1,
+            &step_requests[1],
+            &mut step_response,
+            &RunOptions::default(),
+            None,
+        );
+        assert_eq!(step_response.status, EStatus::Done, "{:?}", step_response.output);
+        assert!(env::var("STEP_PATH").is_err());
+    }
+
+    #[test]
+    fn run_step_commits_with_message_from_script() {
+        let step_request = StepRequest {
+            run: "rename".to_string(),
+            run_resolved: vec!["echo 'Renamed 12 occurrences in 5 files' > \"$MEND_COMMIT_MSG_FILE\"".to_string()],
+            commit_msg: "rename".to_string(),
+            ..Default::default()
+        };
+        let mut step_response = StepResponse::pending();
+        let logger_rc = Rc::new(RefCell::new(TestLogger { messages: vec![] }));
+        run_step(
+            &mut FakeRepo { logger: logger_rc.clone() },
+            &mut ShellExecutor::default(),
+            &mut FakeNotifier { logger: logger_rc.clone() },
+            0,
+            &step_request,
+            &mut step_response,
+            &RunOptions::default(),
+            None,
+        );
+        assert_eq!(step_response.status, EStatus::Done);
+        assert_eq!(step_response.commit_msg, Some("Renamed 12 occurrences in 5 files".to_string()));
+        let logger_ref_cell: &RefCell<TestLogger> = logger_rc.borrow();
+        assert!(logger_ref_cell.borrow().messages.contains(&"Repo commit all with msg 'Renamed 12 occurrences in 5 files'".to_string()));
+    }
+}
@@ -0,0 +1,75 @@
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::path::Path;
+
+/// Version of the files mend keeps in `.mend` between runs, raised whenever one of them changes shape.
+/// Files from before versioning count as version 0.
+pub const SCHEMA_VERSION: u64 = 1;
+
+/// Upgrades a file's JSON by one version.
+pub type Migration = fn(Value) -> Value;
+
+/// The JSON of `data` with `schema_version` as its first field, `data` must serialize to an object.
+pub fn to_versioned_json<T: Serialize>(data: &T) -> anyhow::Result<String> {
+    let mut versioned = Map::new();
+    versioned.insert("schema_version".to_string(), Value::from(SCHEMA_VERSION));
+    if let Value::Object(fields) = serde_json::to_value(data)? {
+        versioned.extend(fields);
+    }
+    Ok(serde_json::to_string_pretty(&versioned)?)
+}
+
+/// Reads a file written by any mend version. `migrations[i]` upgrades version `i` to `i + 1`,
+/// versions without one kept the shape of the version before. Files from a newer mend are read
+/// as far as this version understands them.
+pub fn from_versioned_json<T: DeserializeOwned>(path: &Path, contents: &str, migrations: &[Migration]) -> anyhow::Result<T> {
+    let parse_context = || format!("Could not parse `{}`", path.to_string_lossy());
+    let mut value: Value = serde_json::from_str(contents).with_context(parse_context)?;
+    let version = value.get("schema_version").and_then(Value::as_u64).unwrap_or_default();
+    if version > SCHEMA_VERSION {
+        eprintln!(
+            "`{}` was written by a newer mend (schema version {}, this mend knows up to {}), fields it added are ignored",
+            path.to_string_lossy(),
+            version,
+            SCHEMA_VERSION
+        );
+    }
+    for migration in migrations.iter().skip(version as usize) {
+        value = migration(value);
+    }
+    serde_json::from_value(value).with_context(parse_context)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schema::{from_versioned_json, to_versioned_json, Migration, SCHEMA_VERSION};
+    use serde::{Deserialize, Serialize};
+    use serde_json::{json, Value};
+    use std::path::Path;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Steps {
+        steps: Vec<String>,
+    }
+
+    const MIGRATIONS: &[Migration] = &[|value| match value {
+        Value::Array(steps) => json!({ "steps": steps }),
+        other => other,
+    }];
+
+    #[test]
+    fn files_of_every_version_are_read() {
+        let steps = Steps {
+            steps: vec!["rename a b".to_string()],
+        };
+        let current = to_versioned_json(&steps).unwrap();
+        assert!(current.starts_with(&format!("{{\n  \"schema_version\": {},", SCHEMA_VERSION)));
+        let path = Path::new(".mend/steps.json");
+        assert_eq!(from_versioned_json::<Steps>(path, &current, MIGRATIONS).unwrap(), steps);
+        assert_eq!(from_versioned_json::<Steps>(path, r#"["rename a b"]"#, MIGRATIONS).unwrap(), steps);
+        let newer = r#"{"schema_version": 99, "steps": ["rename a b"], "added_later": true}"#;
+        assert_eq!(from_versioned_json::<Steps>(path, newer, MIGRATIONS).unwrap(), steps);
+    }
+}
@@ -0,0 +1,127 @@
+use age::armor::ArmoredReader;
+use age::{x25519, Decryptor, Encryptor, Identity, IdentityFile, Recipient};
+use anyhow::{anyhow, bail, Context};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::process::Command;
+
+/// An age identity, `AGE-SECRET-KEY-1...`, to decrypt `[secrets]` with.
+pub const IDENTITY_ENV: &str = "MEND_AGE_IDENTITY";
+/// A file of age identities, used when `MEND_AGE_IDENTITY` isn't set.
+pub const IDENTITY_FILE_ENV: &str = "MEND_AGE_IDENTITY_FILE";
+
+/// The start of an age file, binary or ASCII armored.
+const AGE_HEADERS: &[&[u8]] = &[b"age-encryption.org/", b"-----BEGIN AGE ENCRYPTED FILE-----"];
+
+fn identities_from_file(path: &str) -> anyhow::Result<Vec<Box<dyn Identity>>> {
+    let path = shellexpand::tilde(path).to_string();
+    let identity_file = IdentityFile::from_file(path.clone()).with_context(|| format!("Could not read the age identity file `{}`", path))?;
+    into_identities(identity_file)
+}
+
+/// The identities from `MEND_AGE_IDENTITY`, else from the file `MEND_AGE_IDENTITY_FILE` names.
+fn identities_from_env() -> anyhow::Result<Vec<Box<dyn Identity>>> {
+    match (env::var(IDENTITY_ENV), env::var(IDENTITY_FILE_ENV)) {
+        (Ok(identity), _) => into_identities(
+            IdentityFile::from_buffer(BufReader::new(identity.as_bytes()))
+                .with_context(|| format!("{} doesn't hold an age identity", IDENTITY_ENV))?,
+        ),
+        (Err(_), Ok(path)) => identities_from_file(&path),
+        (Err(_), Err(_)) => bail!("No age identity to decrypt with, set {} or {}", IDENTITY_ENV, IDENTITY_FILE_ENV),
+    }
+}
+
+fn into_identities(identity_file: IdentityFile<age::NoCallbacks>) -> anyhow::Result<Vec<Box<dyn Identity>>> {
+    let identities = identity_file.into_identities().map_err(|err| anyhow!("Unusable age identity: {}", err))?;
+    if identities.is_empty() {
+        bail!("No age identity found in {} or {}", IDENTITY_ENV, IDENTITY_FILE_ENV);
+    }
+    Ok(identities)
+}
+
+/// Commands mend starts get its environment, but not the identity the secrets are decrypted with.
+pub fn without_identity(command: &mut Command) -> &mut Command {
+    command.env_remove(IDENTITY_ENV).env_remove(IDENTITY_FILE_ENV)
+}
+
+fn decrypt_value(identities: &[Box<dyn Identity>], ciphertext: &str) -> anyhow::Result<String> {
+    let decryptor = Decryptor::new_buffered(ArmoredReader::new(ciphertext.trim().as_bytes()))?;
+    let mut reader = decryptor.decrypt(identities.iter().map(|identity| identity.as_ref()))?;
+    let mut plaintext = String::new();
+    reader.read_to_string(&mut plaintext)?;
+    Ok(plaintext)
+}
+
+/// Decrypts each of `secrets`, armored age ciphertext keyed by the variable it's passed to the steps in.
+/// Nothing is needed from the environment when there are no secrets.
+pub fn decrypt_secrets(secrets: &BTreeMap<String, String>) -> anyhow::Result<BTreeMap<String, String>> {
+    if secrets.is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    let identities = identities_from_env().context("The config has [secrets]")?;
+    secrets
+        .iter()
+        .map(|(name, ciphertext)| {
+            let value = decrypt_value(&identities, ciphertext).with_context(|| format!("Could not decrypt secret `{}`", name))?;
+            Ok((name.clone(), value))
+        })
+        .collect()
+}
+
+/// Whether `path` is an age file rather than what it encrypts.
+pub fn is_encrypted(path: &Path) -> anyhow::Result<bool> {
+    let mut start = vec![];
+    File::open(path)
+        .and_then(|file| file.take(64).read_to_end(&mut start))
+        .with_context(|| format!("Could not read `{}`", path.to_string_lossy()))?;
+    Ok(AGE_HEADERS.iter().any(|header| start.starts_with(header)))
+}
+
+/// Encrypts `input` into `output` for each of `recipients`, age public keys `age1...`.
+pub fn encrypt_file(recipients: &[String], input: &Path, output: &Path) -> anyhow::Result<()> {
+    let recipients = recipients
+        .iter()
+        .map(|recipient| recipient.parse::<x25519::Recipient>().map_err(|err| anyhow!("`{}` is not an age recipient: {}", recipient, err)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let encryptor = Encryptor::with_recipients(recipients.iter().map(|recipient| recipient as &dyn Recipient))?;
+    let file = File::create(output).with_context(|| format!("Could not write `{}`", output.to_string_lossy()))?;
+    let mut writer = encryptor.wrap_output(file)?;
+    io::copy(&mut File::open(input)?, &mut writer)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Decrypts `input` into `output` with the identities in `identity_file`, else those in the environment.
+pub fn decrypt_file(identity_file: Option<&str>, input: &Path, output: &Path) -> anyhow::Result<()> {
+    let identities = match identity_file {
+        Some(identity_file) => identities_from_file(identity_file)?,
+        None => identities_from_env()?,
+    };
+    let file = File::open(input).with_context(|| format!("Could not read `{}`", input.to_string_lossy()))?;
+    let decryptor = Decryptor::new_buffered(ArmoredReader::new(BufReader::new(file)))?;
+    let mut reader = decryptor.decrypt(identities.iter().map(|identity| identity.as_ref()))?;
+    io::copy(&mut reader, &mut File::create(output)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::secrets::decrypt_value;
+    use age::secrecy::ExposeSecret;
+    use age::x25519;
+    use age::Identity;
+
+    #[test]
+    fn secrets_are_decrypted_with_a_matching_identity() {
+        let identity = x25519::Identity::generate();
+        let ciphertext = age::encrypt_and_armor(&identity.to_public(), b"s3cr3t").unwrap();
+        let identities: Vec<Box<dyn Identity>> = vec![Box::new(identity.to_string().expose_secret().parse::<x25519::Identity>().unwrap())];
+        assert_eq!(decrypt_value(&identities, &ciphertext).unwrap(), "s3cr3t");
+
+        let other: Vec<Box<dyn Identity>> = vec![Box::new(x25519::Identity::generate())];
+        assert!(decrypt_value(&other, &ciphertext).is_err());
+    }
+}
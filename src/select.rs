@@ -0,0 +1,82 @@
+use anyhow::bail;
+
+use crate::run::StepRequest;
+
+/// Which steps `--only` and `--skip` leave in a run. Each entry is a step number counting from 1,
+/// a range of them like `5-7`, a step id or a recipe name.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct StepSelection {
+    /// When not empty, the steps left in, before `skip` takes its steps out
+    pub only: Vec<String>,
+    pub skip: Vec<String>,
+}
+
+fn recipe_name(step_request: &StepRequest) -> &str {
+    step_request.run.split_whitespace().next().unwrap_or_default()
+}
+
+fn matches(entry: &str, step_i: usize, step_request: &StepRequest) -> bool {
+    let number = step_i + 1;
+    if let Ok(entry_number) = entry.parse::<usize>() {
+        return entry_number == number;
+    }
+    if let Some((first, last)) = entry.split_once('-') {
+        if let (Ok(first), Ok(last)) = (first.parse::<usize>(), last.parse::<usize>()) {
+            return (first..=last).contains(&number);
+        }
+    }
+    entry == step_request.id || entry == recipe_name(step_request)
+}
+
+impl StepSelection {
+    pub fn includes(&self, step_i: usize, step_request: &StepRequest) -> bool {
+        let included = self.only.is_empty() || self.only.iter().any(|entry| matches(entry, step_i, step_request));
+        included && !self.skip.iter().any(|entry| matches(entry, step_i, step_request))
+    }
+
+    /// Fails on an entry that matches no step, most likely a typo that would leave the wrong steps in.
+    pub fn check(&self, step_requests: &[StepRequest]) -> anyhow::Result<()> {
+        for (flag, entries) in [("--only", &self.only), ("--skip", &self.skip)] {
+            for entry in entries {
+                if !step_requests.iter().enumerate().any(|(step_i, step_request)| matches(entry, step_i, step_request)) {
+                    bail!("`{} {}` matches no step number, id or recipe", flag, entry);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::run::StepRequest;
+    use crate::select::StepSelection;
+
+    #[test]
+    fn steps_are_selected_by_number_range_id_or_recipe() {
+        let step_requests: Vec<StepRequest> = [("1", "rename Foo Bar"), ("lint", "eslint --fix"), ("3", "rename Baz Qux"), ("4", "format")]
+            .iter()
+            .map(|(id, run)| StepRequest {
+                id: id.to_string(),
+                run: run.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let included = |selection: &StepSelection| -> Vec<usize> {
+            (0..step_requests.len()).filter(|step_i| selection.includes(*step_i, &step_requests[*step_i])).map(|step_i| step_i + 1).collect()
+        };
+        let selection = |only: &[&str], skip: &[&str]| StepSelection {
+            only: only.iter().map(|entry| entry.to_string()).collect(),
+            skip: skip.iter().map(|entry| entry.to_string()).collect(),
+        };
+        assert_eq!(included(&StepSelection::default()), vec![1, 2, 3, 4]);
+        assert_eq!(included(&selection(&["1", "3-4"], &[])), vec![1, 3, 4]);
+        assert_eq!(included(&selection(&["rename"], &[])), vec![1, 3]);
+        assert_eq!(included(&selection(&[], &["lint", "format"])), vec![1, 3]);
+        assert_eq!(included(&selection(&["2-4"], &["3"])), vec![2, 4]);
+
+        assert!(selection(&["1-2"], &["lint"]).check(&step_requests).is_ok());
+        let err = selection(&[], &["lnit"]).check(&step_requests).unwrap_err();
+        assert_eq!(err.to_string(), "`--skip lnit` matches no step number, id or recipe");
+    }
+}
@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+
+/// The language a shell takes scripts in, which decides how recipes are wrapped into functions and called.
+/// Hooks, `verify` and recipe bodies are run as written, so they have to be in the shell's dialect.
+/// Built-in step types write POSIX scripts.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ShellDialect {
+    /// `sh`, `bash`, `dash`, `zsh`, `busybox sh` and the like, recipes being defined as `name() { ... }` functions
+    /// named like POSIX variables, which all of them take
+    #[default]
+    Posix,
+    /// Windows PowerShell or PowerShell 7 (`pwsh`)
+    Powershell,
+    /// `cmd.exe`, scripts run as a batch file
+    Cmd,
+}
+
+impl ShellDialect {
+    /// Guessed from the name of the shell's program, POSIX unless it is PowerShell or cmd.
+    pub fn of_program(program: &str) -> Self {
+        // Windows paths are split on either separator whatever mend runs on
+        let file_name = program.rsplit(['/', '\\']).next().unwrap_or_default().to_lowercase();
+        match file_name.strip_suffix(".exe").unwrap_or(&file_name) {
+            "powershell" | "pwsh" => ShellDialect::Powershell,
+            "cmd" => ShellDialect::Cmd,
+            _ => ShellDialect::Posix,
+        }
+    }
+
+    /// Arguments before the script, or for cmd before the batch file holding it.
+    pub fn script_args(self) -> &'static [&'static str] {
+        match self {
+            ShellDialect::Posix => &["-c"],
+            ShellDialect::Powershell => &["-NoProfile", "-NonInteractive", "-Command"],
+            ShellDialect::Cmd => &["/D", "/C"],
+        }
+    }
+
+    /// Quotes text for use as a single word.
+    pub fn quote(self, text: &str) -> String {
+        match self {
+            ShellDialect::Posix => format!("'{}'", text.replace('\'', "'\\''")),
+            ShellDialect::Powershell => format!("'{}'", text.replace('\'', "''")),
+            ShellDialect::Cmd => format!("\"{}\"", text.replace('"', "\"\"")),
+        }
+    }
+
+    /// The name of the function a recipe becomes. POSIX shells like dash only take names of ASCII letters, digits and
+    /// `_` not starting with a digit, other recipe names are spelled out in those.
+    pub fn function_name(self, recipe_name: &str) -> String {
+        let is_posix_name = !recipe_name.is_empty()
+            && !recipe_name.starts_with(|c: char| c.is_ascii_digit())
+            && recipe_name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if self != ShellDialect::Posix || is_posix_name {
+            return recipe_name.to_string();
+        }
+        let mut name = "mend_recipe_".to_string();
+        for c in recipe_name.chars() {
+            if c.is_ascii_alphanumeric() {
+                name.push(c);
+            } else {
+                name.push_str(&format!("_{:x}_", c as u32));
+            }
+        }
+        name
+    }
+
+    /// Recipes are emitted ordered by the bytes of their names (`BTreeMap` order), never by locale.
+    /// The body is normalized so a config saved with CRLF or trailing blank lines gives the same script.
+    /// Named `params` are set from the function's arguments before the body runs.
+    pub fn recipe_function(self, recipe_name: &str, run: &str, params: &[String]) -> String {
+        let body = run.replace("\r\n", "\n");
+        let mut prologue = String::new();
+        for (param_i, param) in params.iter().enumerate() {
+            let name = param.split_once('=').map_or(param.as_str(), |(name, _)| name);
+            prologue.push_str(&match self {
+                ShellDialect::Posix => format!("{}=\"${}\"\n", name, param_i + 1),
+                ShellDialect::Powershell => format!("${} = $args[{}]\n", name, param_i),
+                ShellDialect::Cmd => format!("set \"{}=%~{}\"\n", name, param_i + 1),
+            });
+        }
+        match self {
+            ShellDialect::Posix => format!("{}() {{\n{}{}\n}}\n", self.function_name(recipe_name), prologue, body.trim_end()),
+            ShellDialect::Powershell => format!("function {} {{\n{}{}\n}}\n", recipe_name, prologue, body.trim_end()),
+            // A label called like a subroutine, returning its last command's status
+            ShellDialect::Cmd => format!(":{}\n{}{}\nexit /b %errorlevel%\n", recipe_name, prologue, body.trim_end()),
+        }
+    }
+
+    /// Runs `body` with `interpreter`, which reads its program from stdin when given `-`, as python, node,
+    /// ruby and perl do. `args` are quoted already, `env` values are set for the interpreter only.
+    pub fn interpreter_call(self, interpreter: &str, body: &str, args: &[String], env: &[(String, String)]) -> String {
+        let body = body.replace("\r\n", "\n");
+        let body = body.trim_end();
+        let mut command = format!("{} -", interpreter.trim());
+        for arg in args {
+            command.push(' ');
+            command.push_str(arg);
+        }
+        match self {
+            ShellDialect::Posix => {
+                let mut delimiter = "MEND_RECIPE".to_string();
+                while body.lines().any(|line| line == delimiter) {
+                    delimiter.push('_');
+                }
+                let assignments: String = env.iter().map(|(name, value)| format!("{}={} ", name, self.quote(value))).collect();
+                format!("{}{} <<'{}'\n{}\n{}", assignments, command, delimiter, body, delimiter)
+            }
+            ShellDialect::Powershell => {
+                let assignments: String = env.iter().map(|(name, value)| format!("$env:{} = {}\n", name, self.quote(value))).collect();
+                format!("{}@'\n{}\n'@ | & {}", assignments, body, command)
+            }
+            ShellDialect::Cmd => "echo Recipes with an interpreter need a POSIX shell or PowerShell, not cmd 1>&2\nexit /b 1".to_string(),
+        }
+    }
+
+    /// The script for a step: its recipe functions and the call of the instruction.
+    pub fn step_script(self, functions: &str, call: &str) -> String {
+        match self {
+            ShellDialect::Posix | ShellDialect::Powershell => format!("{}{}\n", functions, call),
+            // Labels are only reached through `call`, so the call goes first
+            ShellDialect::Cmd if functions.is_empty() => format!("{}\n", call),
+            ShellDialect::Cmd => format!("call :{}\nexit /b %errorlevel%\n{}", call, functions),
+        }
+    }
+
+    /// Makes the script exit 0 for any of `exit_codes` and 1 for any other status, including 0 if not listed.
+    pub fn accept_exit_codes(self, script: String, exit_codes: &[i32]) -> String {
+        let patterns: Vec<String> = exit_codes.iter().map(|code| code.to_string()).collect();
+        match self {
+            ShellDialect::Posix => format!(
+                "(\n{})\nmend_status=$?\ncase $mend_status in {}) exit 0 ;; esac\necho \"Exit status $mend_status is not one of the expected {}\" >&2\nexit 1\n",
+                script,
+                patterns.join("|"),
+                patterns.join(", ")
+            ),
+            ShellDialect::Powershell => format!(
+                "& {{\n{}}}\n$mend_status = if ($null -eq $LASTEXITCODE) {{ 0 }} else {{ $LASTEXITCODE }}\nif (@({}) -contains $mend_status) {{ exit 0 }}\n[Console]::Error.WriteLine(\"Exit status $mend_status is not one of the expected {}\")\nexit 1\n",
+                script,
+                patterns.join(", "),
+                patterns.join(", ")
+            ),
+            ShellDialect::Cmd => format!(
+                "call :mend_step\nset mend_status=%errorlevel%\nfor %%c in ({}) do if \"%mend_status%\"==\"%%c\" exit /b 0\necho Exit status %mend_status% is not one of the expected {} 1>&2\nexit /b 1\n:mend_step\n{}exit /b %errorlevel%\n",
+                patterns.join(" "),
+                patterns.join(", "),
+                script
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shell::ShellDialect;
+
+    #[test]
+    fn dialect_is_guessed_from_the_program() {
+        assert_eq!(ShellDialect::of_program("bash"), ShellDialect::Posix);
+        assert_eq!(ShellDialect::of_program("busybox"), ShellDialect::Posix);
+        assert_eq!(ShellDialect::of_program("pwsh"), ShellDialect::Powershell);
+        assert_eq!(ShellDialect::of_program("C:\\Windows\\System32\\WindowsPowerShell\\v1.0\\PowerShell.exe"), ShellDialect::Powershell);
+        assert_eq!(ShellDialect::of_program("cmd.exe"), ShellDialect::Cmd);
+        assert_eq!(ShellDialect::Powershell.quote("it's"), "'it''s'");
+        assert_eq!(ShellDialect::Cmd.quote("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn recipes_run_with_every_posix_shell() {
+        let dialect = ShellDialect::Posix;
+        let functions = [
+            dialect.recipe_function("clang-format", "echo formatted $1", &[]),
+            dialect.recipe_function("rename", "echo $old to $new", &["old".to_string(), "new".to_string()]),
+            dialect.recipe_function("2to3.renommé", "echo ported", &[]),
+        ]
+        .concat();
+        let call = format!("{} a.c && rename Foo Bar && {}", dialect.function_name("clang-format"), dialect.function_name("2to3.renommé"));
+        let script = dialect.step_script(&functions, &call);
+        for shell in [&["sh"][..], &["dash"], &["bash"], &["zsh"], &["busybox", "sh"]] {
+            if which::which(shell[0]).is_err() {
+                continue;
+            }
+            let output = std::process::Command::new(shell[0]).args(&shell[1..]).arg("-c").arg(&script).output().unwrap();
+            assert!(output.status.success(), "{:?}: {}", shell, String::from_utf8_lossy(&output.stderr));
+            assert_eq!(String::from_utf8_lossy(&output.stdout), "formatted a.c\nFoo to Bar\nported\n", "{:?}", shell);
+        }
+        assert_eq!(dialect.function_name("clang-format"), "mend_recipe_clang_2d_format");
+        assert_eq!(ShellDialect::Powershell.function_name("clang-format"), "clang-format");
+    }
+}
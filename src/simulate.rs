@@ -0,0 +1,156 @@
+use anyhow::Context;
+use clap::Args;
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::progress::Notify;
+use crate::report::RunRecord;
+use crate::repo::{ensure_worktree, remove_worktree, GitRepo, Repo, MEND_DIR};
+use crate::run::{run_all_steps, EStatus, Executor, RunOptions, StepRequest};
+
+#[derive(Args, Debug)]
+pub struct SimulateArgs {
+    /// Refs or shas to run the steps on, comma separated, e.g. `main,release/2.x`
+    #[arg(long = "bases", value_delimiter = ',', required = true)]
+    pub bases: Vec<String>,
+
+    /// Skip the verify commands, for a quicker look at which steps apply
+    #[arg(long = "no-verify")]
+    pub no_verify: bool,
+}
+
+/// Runs the steps on `base` in a throwaway worktree, going on after failures so every step gets its chance.
+pub fn simulate_base<E: Executor, N: Notify>(
+    base_repo_dir: &Path,
+    base_i: usize,
+    base: &str,
+    step_requests: Vec<StepRequest>,
+    notifier: &mut N,
+    executor: &mut E,
+    options: &RunOptions,
+) -> anyhow::Result<RunRecord> {
+    let work_dir = ensure_worktree(base_repo_dir, &format!("{}/simulate-{}", MEND_DIR, base_i + 1), base)
+        .with_context(|| format!("Could not check out `{}`", base))?;
+    let mut repo = GitRepo { repo_dir: work_dir.clone() };
+    let from_sha = repo.current_short_sha();
+    let planned_steps: Vec<(String, String)> = step_requests
+        .iter()
+        .map(|step_request| (step_request.id.clone(), step_request.run.clone()))
+        .collect();
+    let options = RunOptions {
+        continue_on_error: true,
+        quarantine: false,
+        step_cache: None,
+        first_step: 0,
+        resumed_commits: vec![],
+        squash_groups: vec![],
+        env: options.env.clone(),
+        max_parallel_steps: options.max_parallel_steps,
+        log_dir: None,
+        artifacts: None,
+        interactive: false,
+        on_failure: None,
+        matrix: vec![],
+    };
+    let outcome = run_all_steps(step_requests, notifier, &mut repo, executor, &options);
+    // The commits stay behind unreferenced, git collects them eventually
+    let removed = remove_worktree(&work_dir);
+    let mut record = RunRecord::new(base.to_string(), "", &from_sha?, &planned_steps);
+    match outcome {
+        Ok(summary) => record.record_summary(&summary),
+        Err(failure) => record.record_stop(&failure.0, &failure.1),
+    }
+    removed?;
+    Ok(record)
+}
+
+fn status_label(status: EStatus) -> &'static str {
+    match status {
+        EStatus::Done => "ok",
+        EStatus::Failed => "FAILED",
+        EStatus::VerifyFailed => "VERIFY FAILED",
+        EStatus::Skipped => "skipped",
+        EStatus::Pending | EStatus::Running => "not run",
+    }
+}
+
+/// A table of each step's outcome on each base, `records` in the order of the bases.
+pub fn render_simulation(records: &[RunRecord]) -> String {
+    let Some(first) = records.first() else {
+        return String::new();
+    };
+    let step_labels: Vec<String> = first.steps.iter().map(|step| format!("[{}] {}", step.id, step.run)).collect();
+    let step_width = step_labels.iter().map(|label| label.chars().count()).max().unwrap_or_default();
+    let column_widths: Vec<usize> = records.iter().map(|record| record.id.chars().count().max(7)).collect();
+    let mut text = format!("{:<width$}", "Step", width = step_width);
+    for (record, column_width) in records.iter().zip(&column_widths) {
+        let _ = write!(text, "  {:<width$}", record.id, width = column_width);
+    }
+    text = format!("{}\n", text.trim_end());
+    for (step_i, step_label) in step_labels.iter().enumerate() {
+        let mut line = format!("{:<width$}", step_label, width = step_width);
+        for (record, column_width) in records.iter().zip(&column_widths) {
+            let status = record.steps.get(step_i).map_or(EStatus::Pending, |step| step.status);
+            let _ = write!(line, "  {:<width$}", status_label(status), width = column_width);
+        }
+        let _ = writeln!(text, "{}", line.trim_end());
+    }
+    let clean: Vec<&str> = records
+        .iter()
+        .filter(|record| record.steps.iter().all(|step| matches!(step.status, EStatus::Done | EStatus::Skipped)))
+        .map(|record| record.id.as_str())
+        .collect();
+    if clean.is_empty() {
+        let _ = writeln!(text, "\nSteps fail on every base");
+    } else {
+        let _ = writeln!(text, "\nEvery step succeeds on {}", clean.join(", "));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::run::{create_run_status_from_mend, RunOptions, ShellExecutor};
+    use crate::select::StepSelection;
+    use crate::simulate::{render_simulation, simulate_base};
+    use crate::Mend;
+    use crate::test_support::{git, SilentNotifier};
+    use std::fs;
+
+    #[test]
+    fn steps_are_run_on_each_base() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let repo_dir = temp_dir.path();
+        git(repo_dir, &["init", "-q", "-b", "main"]);
+        fs::write(repo_dir.join("App.java"), "class Foo {}\n").unwrap();
+        git(repo_dir, &["add", "App.java"]);
+        git(repo_dir, &["commit", "-q", "-m", "Foo"]);
+        git(repo_dir, &["checkout", "-q", "-b", "release/2.x"]);
+        fs::write(repo_dir.join("App.java"), "class Baz {}\n").unwrap();
+        git(repo_dir, &["commit", "-q", "-am", "Baz"]);
+        git(repo_dir, &["checkout", "-q", "main"]);
+
+        let toml = r#"
+            steps = ["rename Foo Bar", "echo // Notes >> App.java"]
+
+            [recipes.rename]
+            run = "grep -q $1 App.java && sed -i.bak s/$1/$2/ App.java && rm App.java.bak"
+        "#;
+        let mend: Mend = toml::from_str(toml).unwrap();
+        let records: Vec<_> = ["main", "release/2.x"]
+            .iter()
+            .enumerate()
+            .map(|(base_i, base)| {
+                let step_requests = create_run_status_from_mend(&mend, &StepSelection::default());
+                // Recipes become functions, which dash doesn't know
+                let mut executor = ShellExecutor::find(&["bash".to_string()]).unwrap();
+                simulate_base(repo_dir, base_i, base, step_requests, &mut SilentNotifier, &mut executor, &RunOptions::default())
+                    .unwrap()
+            })
+            .collect();
+        insta::assert_snapshot!(render_simulation(&records));
+        // Nothing is left behind
+        assert!(!repo_dir.join(".mend/simulate-1").exists());
+        assert_eq!(fs::read_to_string(repo_dir.join("App.java")).unwrap(), "class Foo {}\n");
+    }
+}
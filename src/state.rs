@@ -0,0 +1,318 @@
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::progress::Notify;
+use crate::repo::Repo;
+use crate::schema::{from_versioned_json, to_versioned_json};
+use crate::run::{EStatus, RunSummary, StepCommit, StepRequest, StepResponse};
+
+const STATE_FILE: &str = "state.json";
+const OUTPUT_DIR: &str = "output";
+
+/// Kept up to date in `.mend/state.json` while a run goes on, so `mend resume` can pick it up.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct RunState {
+    pub config: String,
+    pub from_sha: String,
+    pub steps: Vec<StepState>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct StepState {
+    pub id: String,
+    pub run: String,
+    pub status: EStatus,
+    pub sha: Option<String>,
+    /// File with the output of a failed step
+    pub output: Option<String>,
+    /// From the step starting until it was done or failed, fallback included
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+}
+
+impl RunState {
+    /// `steps` are the id and instruction of every planned step.
+    pub fn new(config: &str, from_sha: &str, steps: &[(String, String)]) -> Self {
+        RunState {
+            config: config.to_string(),
+            from_sha: from_sha.to_string(),
+            steps: steps
+                .iter()
+                .map(|(id, run)| StepState {
+                    id: id.clone(),
+                    run: run.clone(),
+                    status: EStatus::Pending,
+                    sha: None,
+                    output: None,
+                    duration_ms: None,
+                })
+                .collect(),
+        }
+    }
+
+    /// Index of the first step that isn't done, as long as the config still plans the same steps up to it.
+    pub fn resume_point(&self, from_sha: &str, steps: &[(String, String)]) -> anyhow::Result<usize> {
+        self.check_same_start(from_sha)?;
+        let first_step = self
+            .steps
+            .iter()
+            .position(|step| !matches!(step.status, EStatus::Done | EStatus::Skipped))
+            .unwrap_or(self.steps.len());
+        if first_step == steps.len() && first_step == self.steps.len() {
+            bail!("All {} steps of the last run are done, nothing to resume", first_step);
+        }
+        self.check_unchanged_before(first_step, steps)?;
+        Ok(first_step)
+    }
+
+    /// The sha a run skipping the steps before `first_step` starts from: the last commit this run made before it,
+    /// or HEAD of `worktree` when it has commits on top of that which no later step made, e.g. a fix made by hand.
+    pub fn start_point<R: Repo>(&self, from_sha: &str, steps: &[(String, String)], first_step: usize, worktree: Option<&R>) -> anyhow::Result<String> {
+        self.check_same_start(from_sha)?;
+        if first_step >= steps.len() {
+            bail!("There are only {} steps to start from", steps.len());
+        }
+        self.check_unchanged_before(first_step, steps)?;
+        let last_sha = self.last_sha_before(first_step).to_string();
+        let Some(worktree) = worktree else {
+            return Ok(last_sha);
+        };
+        let later_shas: Vec<&str> = self.steps.iter().skip(first_step).filter_map(|step| step.sha.as_deref()).collect();
+        let same_sha = |a: &str, b: &str| a.starts_with(b) || b.starts_with(a);
+        match worktree.commits_since(&last_sha) {
+            Ok(commits)
+                if !commits.is_empty()
+                    && !commits.iter().any(|commit| later_shas.iter().any(|later_sha| same_sha(commit, later_sha))) =>
+            {
+                Ok(worktree.current_short_sha()?)
+            }
+            _ => Ok(last_sha),
+        }
+    }
+
+    fn check_same_start(&self, from_sha: &str) -> anyhow::Result<()> {
+        if self.from_sha != from_sha {
+            bail!(
+                "The last run started from {}, but the config now starts from {}",
+                self.from_sha,
+                from_sha
+            );
+        }
+        Ok(())
+    }
+
+    /// The steps that ran before `first_step` are still the ones planned, so their commits can be built on.
+    fn check_unchanged_before(&self, first_step: usize, steps: &[(String, String)]) -> anyhow::Result<()> {
+        for (step_i, step) in self.steps.iter().take(first_step).enumerate() {
+            match steps.get(step_i) {
+                Some((id, run)) if *id == step.id && *run == step.run => {}
+                _ => bail!(
+                    "Step {} changed since it ran as `{}`, start a new run instead",
+                    step_i + 1,
+                    step.run
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// Commits of the steps before `first_step`, as a resumed run doesn't make them again.
+    pub fn commits_before(&self, first_step: usize) -> Vec<StepCommit> {
+        self.steps
+            .iter()
+            .take(first_step)
+            .enumerate()
+            .filter_map(|(step_i, step)| {
+                Some(StepCommit {
+                    id: step.id.clone(),
+                    step: step_i + 1,
+                    sha: step.sha.clone()?,
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    /// The sha HEAD should be at to continue with `first_step`.
+    pub fn last_sha_before(&self, first_step: usize) -> &str {
+        self.steps
+            .iter()
+            .take(first_step)
+            .rev()
+            .find_map(|step| step.sha.as_deref())
+            .unwrap_or(&self.from_sha)
+    }
+}
+
+fn write_state(mend_dir: &Path, state: &RunState) -> anyhow::Result<()> {
+    let path = mend_dir.join(STATE_FILE);
+    fs::write(&path, to_versioned_json(state)?)
+        .with_context(|| format!("Could not write `{}`", path.to_string_lossy()))
+}
+
+pub fn read_state(mend_dir: &Path) -> anyhow::Result<RunState> {
+    let path = mend_dir.join(STATE_FILE);
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("No run state in `{}`, nothing to resume", path.to_string_lossy()))?;
+    from_versioned_json(&path, &contents, &[])
+}
+
+/// Passes everything on to `inner` and writes the state of each step as it changes.
+pub struct StateNotifier<N: Notify> {
+    inner: N,
+    mend_dir: PathBuf,
+    state: RefCell<RunState>,
+    step_started: BTreeMap<usize, Instant>,
+}
+
+impl<N: Notify> StateNotifier<N> {
+    pub fn new(inner: N, mend_dir: &Path, state: RunState) -> Self {
+        StateNotifier {
+            inner,
+            mend_dir: mend_dir.to_path_buf(),
+            state: RefCell::new(state),
+            step_started: BTreeMap::new(),
+        }
+    }
+
+    fn save(&self) {
+        if let Err(err) = write_state(&self.mend_dir, &self.state.borrow()) {
+            eprintln!("{:#}", err);
+        }
+    }
+}
+
+impl<N: Notify> Notify for StateNotifier<N> {
+    fn notify(&mut self, i: usize, run: &str, status: &EStatus, sha: &Option<String>, inc: bool) {
+        let started = *self.step_started.entry(i).or_insert_with(Instant::now);
+        if let Some(step) = self.state.get_mut().steps.get_mut(i) {
+            // Steps done before a resume keep the duration they took then
+            let finished = matches!(status, EStatus::Done | EStatus::Failed | EStatus::VerifyFailed) && step.status != EStatus::Done;
+            if finished {
+                step.duration_ms = Some(started.elapsed().as_millis() as u64);
+            }
+            step.status = *status;
+            step.sha = sha.clone();
+        }
+        self.save();
+        self.inner.notify(i, run, status, sha, inc)
+    }
+
+    fn notify_done(&self, summary: &RunSummary) {
+        self.inner.notify_done(summary)
+    }
+
+    fn notify_output(&mut self, i: usize, line: &str) {
+        self.inner.notify_output(i, line)
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        self.inner.suspend(f)
+    }
+
+    fn notify_failure(&self, failed_request: &StepRequest, failed_response: &StepResponse) {
+        let output_dir = self.mend_dir.join(OUTPUT_DIR);
+        let output_path = output_dir.join(format!("{}.log", failed_request.id));
+        let written = fs::create_dir_all(&output_dir).and_then(|_| {
+            fs::write(&output_path, failed_response.output.as_deref().unwrap_or_default())
+        });
+        if written.is_ok() {
+            if let Some(step) = self
+                .state
+                .borrow_mut()
+                .steps
+                .iter_mut()
+                .find(|step| step.id == failed_request.id)
+            {
+                step.output = Some(output_path.to_string_lossy().to_string());
+            }
+            self.save();
+        }
+        self.inner.notify_failure(failed_request, failed_response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::progress::Notify;
+    use crate::repo::{GitRepo, Repo};
+    use crate::run::{EStatus, StepRequest, StepResponse};
+    use crate::state::{read_state, RunState, StateNotifier};
+    use crate::test_support::SilentNotifier;
+    use std::fs;
+    use std::process::Command;
+
+    fn planned_steps() -> Vec<(String, String)> {
+        vec![
+            ("1".to_string(), "rename a b".to_string()),
+            ("2".to_string(), "rename c d".to_string()),
+            ("3".to_string(), "rename e f".to_string()),
+        ]
+    }
+
+    #[test]
+    fn state_follows_the_run_and_resumes_after_done_steps() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut notifier = StateNotifier::new(SilentNotifier, temp_dir.path(), RunState::new("mend.toml", "base", &planned_steps()));
+        notifier.notify(0, "rename a b", &EStatus::Done, &Some("aaa1111".to_string()), true);
+        notifier.notify(1, "rename c d", &EStatus::Failed, &None, false);
+        let failed_request = StepRequest {
+            id: "2".to_string(),
+            ..Default::default()
+        };
+        let mut failed_response = StepResponse::pending();
+        failed_response.output = Some("no such symbol c".to_string());
+        notifier.notify_failure(&failed_request, &failed_response);
+
+        let state = read_state(temp_dir.path()).unwrap();
+        let output = state.steps[1].output.clone().unwrap();
+        assert_eq!(std::fs::read_to_string(output).unwrap(), "no such symbol c");
+        assert!(state.steps[0].duration_ms.is_some());
+        assert_eq!(state.steps[2].duration_ms, None);
+        assert_eq!(state.resume_point("base", &planned_steps()).unwrap(), 1);
+        assert_eq!(state.last_sha_before(1), "aaa1111");
+        assert_eq!(state.commits_before(1)[0].sha, "aaa1111");
+        assert!(state.resume_point("other", &planned_steps()).is_err());
+        let mut changed = planned_steps();
+        changed[0].1 = "rename a z".to_string();
+        assert!(state.resume_point("base", &changed).is_err());
+    }
+
+    #[test]
+    fn restarts_after_the_last_commit_or_a_fix_made_by_hand() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let repo_dir = temp_dir.path();
+        let output = Command::new("git").current_dir(repo_dir).args(["init", "-q"]).output().unwrap();
+        assert!(output.status.success());
+        let mut repo = GitRepo { repo_dir: repo_dir.to_path_buf() };
+        let mut commit = |text: &str| {
+            fs::write(repo_dir.join("App.java"), text).unwrap();
+            Command::new("git").current_dir(repo_dir).args(["add", "App.java"]).output().unwrap();
+            repo.commit_all(text).unwrap();
+            repo.current_short_sha().unwrap()
+        };
+        let base = commit("class A {}");
+        let step_1 = commit("class B {}");
+        let step_2 = commit("class D {}");
+        let mut state = RunState::new("mend.toml", &base, &planned_steps());
+        state.steps[0].status = EStatus::Done;
+        state.steps[0].sha = Some(step_1.clone());
+        state.steps[1].status = EStatus::Done;
+        state.steps[1].sha = Some(step_2.clone());
+        let repo = GitRepo { repo_dir: repo_dir.to_path_buf() };
+
+        // HEAD is a later step's commit, which is made again
+        assert_eq!(state.start_point(&base, &planned_steps(), 1, Some(&repo)).unwrap(), step_1);
+        assert_eq!(state.start_point(&base, &planned_steps(), 0, None::<&GitRepo>).unwrap(), base);
+        // Step 2 failed and was fixed by hand
+        state.steps[1].status = EStatus::Failed;
+        state.steps[1].sha = None;
+        assert_eq!(state.start_point(&base, &planned_steps(), 2, Some(&repo)).unwrap(), step_2);
+        assert!(state.start_point(&base, &planned_steps(), 3, Some(&repo)).is_err());
+    }
+}
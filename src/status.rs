@@ -0,0 +1,285 @@
+use indicatif::HumanDuration;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::lock::{read_lock, RunLock};
+use crate::run::EStatus;
+use crate::state::{read_state, RunState};
+use crate::repo::{list_branches, list_worktrees, worker_worktrees, GitRepo, Repo, WorktreeInfo, MEND_DIR, WORKTREE_DIR};
+
+pub struct WorktreeProgress {
+    pub path: String,
+    pub short_sha: String,
+    pub completed_steps: usize,
+}
+
+pub struct StatusReport {
+    pub lock: Option<(RunLock, bool)>,
+    pub from_sha: String,
+    pub total_steps: usize,
+    pub progress: Option<WorktreeProgress>,
+    pub worktrees: Vec<WorktreeInfo>,
+    /// Worktrees of parallel steps left by a killed run, while no run is going
+    pub stale_workers: Vec<String>,
+    pub run_branches: Vec<String>,
+    /// From `.mend/state.json`, if a run got that far
+    pub last_run: Option<RunState>,
+}
+
+pub fn collect_status(
+    base_repo_dir: &Path,
+    from_sha: &str,
+    total_steps: usize,
+) -> anyhow::Result<StatusReport> {
+    let lock = read_lock(&base_repo_dir.join(MEND_DIR)).map(|lock| {
+        let alive = lock.is_alive();
+        (lock, alive)
+    });
+    let stale_workers = match &lock {
+        Some((_, true)) => vec![],
+        _ => worker_worktrees(base_repo_dir)
+            .iter()
+            .map(|worker_dir| worker_dir.to_string_lossy().to_string())
+            .collect(),
+    };
+    let worktree_dir = base_repo_dir.join(WORKTREE_DIR);
+    let progress = if worktree_dir.exists() {
+        let worktree_repo = GitRepo {
+            repo_dir: worktree_dir.clone(),
+        };
+        Some(WorktreeProgress {
+            path: worktree_dir.to_string_lossy().to_string(),
+            short_sha: worktree_repo.current_short_sha()?,
+            completed_steps: worktree_repo.count_commits_since(from_sha)?,
+        })
+    } else {
+        None
+    };
+    Ok(StatusReport {
+        lock,
+        from_sha: from_sha.to_string(),
+        total_steps,
+        progress,
+        worktrees: list_worktrees(base_repo_dir)?,
+        stale_workers,
+        run_branches: list_branches(base_repo_dir, "mend/*")?,
+        last_run: read_state(&base_repo_dir.join(MEND_DIR)).ok(),
+    })
+}
+
+pub fn format_status(report: &StatusReport, now: u64) -> String {
+    let mut text = String::new();
+    match &report.lock {
+        Some((lock, true)) => {
+            let elapsed = Duration::from_secs(now.saturating_sub(lock.started));
+            let _ = writeln!(
+                text,
+                "Run in progress: pid {} started {} ago from {}",
+                lock.pid,
+                HumanDuration(elapsed),
+                lock.config
+            );
+        }
+        Some((lock, false)) => {
+            let _ = writeln!(
+                text,
+                "Run interrupted: pid {} from {} is gone, the worktree is resumable",
+                lock.pid, lock.config
+            );
+        }
+        None => {
+            let _ = writeln!(text, "No run in progress");
+        }
+    }
+    match &report.progress {
+        Some(progress) => {
+            let _ = writeln!(
+                text,
+                "Latest result: {} in {}, {} of {} steps committed since {}",
+                progress.short_sha,
+                progress.path,
+                progress.completed_steps,
+                report.total_steps,
+                report.from_sha
+            );
+        }
+        None => {
+            let _ = writeln!(text, "No mend worktree yet");
+        }
+    }
+    let _ = writeln!(text, "Worktrees:");
+    for worktree in &report.worktrees {
+        let head: String = worktree
+            .head
+            .as_deref()
+            .unwrap_or("-------")
+            .chars()
+            .take(7)
+            .collect();
+        let branch = match &worktree.branch {
+            _ if worktree.bare => "(bare)".to_string(),
+            Some(branch) => format!("[{}]", branch),
+            None => "(detached)".to_string(),
+        };
+        let _ = writeln!(text, "  {} {} {}", head, worktree.path, branch);
+    }
+    if !report.stale_workers.is_empty() {
+        let _ = writeln!(text, "Left by a killed run, `mend gc` removes them:");
+        for worker_dir in &report.stale_workers {
+            let _ = writeln!(text, "  {}", worker_dir);
+        }
+    }
+    if report.run_branches.is_empty() {
+        let _ = writeln!(text, "Run branches: none");
+    } else {
+        let _ = writeln!(text, "Run branches:");
+        for branch in &report.run_branches {
+            let _ = writeln!(text, "  {}", branch);
+        }
+    }
+    if let Some(last_run) = &report.last_run {
+        let _ = writeln!(text, "Last run of {} from {}:", last_run.config, last_run.from_sha);
+        let _ = writeln!(text, "  {:>3}  {:<7}  {:<7}  {:>10}  Step", "#", "Sha", "Status", "Duration");
+        for (step_i, step) in last_run.steps.iter().enumerate() {
+            let status = match step.status {
+                EStatus::Pending => "Pending",
+                EStatus::Running => "Running",
+                EStatus::Done => "Done",
+                EStatus::Failed => "Failed",
+                EStatus::VerifyFailed => "Verify failed",
+                EStatus::Skipped => "Skipped",
+            };
+            let duration = step
+                .duration_ms
+                .map(|duration_ms| HumanDuration(Duration::from_millis(duration_ms)).to_string())
+                .unwrap_or_default();
+            let _ = writeln!(
+                text,
+                "  {:>3}  {:<7}  {:<7}  {:>10}  {}",
+                step_i + 1,
+                step.sha.as_deref().unwrap_or("-------"),
+                status,
+                duration,
+                step.run
+            );
+        }
+    }
+    text
+}
+
+pub fn print_status(base_repo_dir: &Path, from_sha: &str, total_steps: usize) -> anyhow::Result<()> {
+    let report = collect_status(base_repo_dir, from_sha, total_steps)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    print!("{}", format_status(&report, now));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lock::RunLock;
+    use crate::repo::WorktreeInfo;
+    use crate::run::EStatus;
+    use crate::state::{RunState, StepState};
+    use crate::status::{format_status, StatusReport, WorktreeProgress};
+
+    #[test]
+    fn format_status_of_running_run() {
+        let report = StatusReport {
+            lock: Some((
+                RunLock {
+                    pid: 42,
+                    started: 1000,
+                    config: "mend.toml".to_string(),
+                    total_steps: 13,
+                },
+                true,
+            )),
+            from_sha: "43a3a253".to_string(),
+            total_steps: 13,
+            progress: Some(WorktreeProgress {
+                path: "/repo/.mend/worktree2".to_string(),
+                short_sha: "abc1234".to_string(),
+                completed_steps: 5,
+            }),
+            worktrees: vec![
+                WorktreeInfo {
+                    path: "/repo".to_string(),
+                    head: Some("0123456789abcdef".to_string()),
+                    branch: Some("main".to_string()),
+                    bare: false,
+                },
+                WorktreeInfo {
+                    path: "/repo/.mend/worktree2".to_string(),
+                    head: Some("abc1234ffff".to_string()),
+                    branch: None,
+                    bare: false,
+                },
+            ],
+            stale_workers: vec![],
+            run_branches: vec![],
+            last_run: None,
+        };
+        insta::assert_snapshot!(format_status(&report, 1000 + 300));
+    }
+
+    #[test]
+    fn format_status_of_interrupted_run() {
+        let report = StatusReport {
+            lock: Some((
+                RunLock {
+                    pid: 42,
+                    started: 1000,
+                    config: "mend.toml".to_string(),
+                    total_steps: 13,
+                },
+                false,
+            )),
+            from_sha: "43a3a253".to_string(),
+            total_steps: 13,
+            progress: None,
+            worktrees: vec![WorktreeInfo {
+                path: "/mirrors/repo.git".to_string(),
+                head: None,
+                branch: None,
+                bare: true,
+            }],
+            stale_workers: vec!["/repo/.mend/worktree2-step-3".to_string()],
+            run_branches: vec!["mend/rename-all".to_string()],
+            last_run: Some(RunState {
+                config: "mend.toml".to_string(),
+                from_sha: "43a3a253".to_string(),
+                steps: vec![
+                    StepState {
+                        id: "1".to_string(),
+                        run: "rename a b".to_string(),
+                        status: EStatus::Done,
+                        sha: Some("abc1234".to_string()),
+                        output: None,
+                        duration_ms: Some(95_000),
+                    },
+                    StepState {
+                        id: "2".to_string(),
+                        run: "rename c d".to_string(),
+                        status: EStatus::Failed,
+                        sha: None,
+                        output: Some("/repo/.mend/output/2.log".to_string()),
+                        duration_ms: Some(1_200),
+                    },
+                    StepState {
+                        id: "3".to_string(),
+                        run: "rename e f".to_string(),
+                        status: EStatus::Pending,
+                        sha: None,
+                        output: None,
+                        duration_ms: None,
+                    },
+                ],
+            }),
+        };
+        insta::assert_snapshot!(format_status(&report, 2000));
+    }
+}
@@ -0,0 +1,26 @@
+use std::path::Path;
+use std::process::Command;
+
+use crate::progress::Notify;
+use crate::run::{EStatus, RunSummary, StepRequest, StepResponse};
+
+/// Runs git in `dir` as a made-up user, failing the test when it fails. Returns what it printed, trimmed.
+pub fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args(["-c", "user.name=mend", "-c", "user.email=mend@example.com"])
+        .args(args)
+        .output()
+        .expect("Could not run git");
+    assert!(output.status.success(), "git {:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+/// For tests that only look at how a run ended.
+pub struct SilentNotifier;
+
+impl Notify for SilentNotifier {
+    fn notify(&mut self, _i: usize, _run: &str, _status: &EStatus, _sha: &Option<String>, _inc: bool) {}
+    fn notify_done(&self, _summary: &RunSummary) {}
+    fn notify_failure(&self, _failed_request: &StepRequest, _failed_response: &StepResponse) {}
+}
@@ -0,0 +1,358 @@
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::progress::Notify;
+use crate::run::{run_command_with_output, EStatus, Executor, RunSummary, StepRequest, StepResponse};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct TelemetryConfig {
+    /// OTLP/HTTP collector, e.g. `http://localhost:4318`, spans are posted to `<endpoint>/v1/traces` when the run ends
+    pub endpoint: String,
+    /// `service.name` of the spans, `mend` by default
+    pub service_name: Option<String>,
+    /// Sent along with the spans, e.g. the collector's API key
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+static ID_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A random looking id of `len` hex digits, unique within the process.
+fn new_id(len: usize) -> String {
+    let seed = format!("{} {} {}", std::process::id(), now_nanos(), ID_COUNT.fetch_add(1, Ordering::Relaxed));
+    format!("{:x}", Sha256::digest(seed.as_bytes()))[..len].to_string()
+}
+
+fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+#[derive(Debug)]
+struct Span {
+    id: String,
+    parent_id: Option<String>,
+    name: String,
+    start: u64,
+    end: Option<u64>,
+    attributes: BTreeMap<String, Value>,
+    ok: Option<bool>,
+}
+
+impl Span {
+    fn start(name: &str, parent_id: Option<String>) -> Self {
+        Span {
+            id: new_id(16),
+            parent_id,
+            name: name.to_string(),
+            start: now_nanos(),
+            end: None,
+            attributes: BTreeMap::new(),
+            ok: None,
+        }
+    }
+
+    fn finish(&mut self, ok: bool) {
+        self.end = Some(now_nanos());
+        self.ok = Some(ok);
+    }
+
+    fn to_otlp(&self, trace_id: &str) -> Value {
+        let attributes: Vec<Value> = self.attributes.iter().map(|(key, value)| otlp_attribute(key, value)).collect();
+        json!({
+            "traceId": trace_id,
+            "spanId": self.id,
+            "parentSpanId": self.parent_id.clone().unwrap_or_default(),
+            "name": self.name,
+            // Internal
+            "kind": 1,
+            "startTimeUnixNano": self.start.to_string(),
+            "endTimeUnixNano": self.end.unwrap_or(self.start).to_string(),
+            "attributes": attributes,
+            // Unset, ok or error
+            "status": { "code": match self.ok { None => 0, Some(true) => 1, Some(false) => 2 } },
+        })
+    }
+}
+
+fn otlp_attribute(key: &str, value: &Value) -> Value {
+    let value = match value {
+        Value::Number(number) if number.is_i64() => json!({ "intValue": number.to_string() }),
+        Value::Bool(flag) => json!({ "boolValue": flag }),
+        Value::String(text) => json!({ "stringValue": text }),
+        other => json!({ "stringValue": other.to_string() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+/// Spans of one run: the run itself, its steps and the scripts each step ran.
+#[derive(Debug)]
+pub struct Trace {
+    trace_id: String,
+    spans: Vec<Span>,
+    /// Index in `spans` of each step's span
+    step_spans: BTreeMap<usize, usize>,
+    /// The step whose scripts are running
+    current_step: Option<usize>,
+}
+
+impl Trace {
+    pub fn start(config: &str, from_sha: &str, run_id: &str) -> Self {
+        let mut run_span = Span::start("mend run", None);
+        run_span.attributes.insert("mend.config".to_string(), json!(config));
+        run_span.attributes.insert("mend.from_sha".to_string(), json!(from_sha));
+        run_span.attributes.insert("mend.run_id".to_string(), json!(run_id));
+        Trace {
+            trace_id: new_id(32),
+            spans: vec![run_span],
+            step_spans: BTreeMap::new(),
+            current_step: None,
+        }
+    }
+
+    fn step_status(&mut self, i: usize, run: &str, status: &EStatus, sha: &Option<String>, inc: bool) {
+        let span_index = match (self.step_spans.get(&i), status) {
+            (Some(span_index), _) => *span_index,
+            // Steps done before a resume are reported without running again
+            (None, EStatus::Done | EStatus::Failed | EStatus::VerifyFailed) if !inc => return,
+            (None, EStatus::Pending) => return,
+            (None, _) => {
+                let mut span = Span::start(run, Some(self.spans[0].id.clone()));
+                span.attributes.insert("mend.step".to_string(), json!(i + 1));
+                self.spans.push(span);
+                self.step_spans.insert(i, self.spans.len() - 1);
+                self.spans.len() - 1
+            }
+        };
+        let span = &mut self.spans[span_index];
+        span.attributes.insert("mend.status".to_string(), json!(format!("{:?}", status)));
+        if let Some(sha) = sha {
+            span.attributes.insert("mend.sha".to_string(), json!(sha));
+        }
+        match status {
+            EStatus::Pending => {}
+            EStatus::Running => {
+                // A fallback runs after the step was reported as failed, the span goes on
+                span.end = None;
+                span.ok = None;
+                self.current_step = Some(i);
+            }
+            EStatus::Done | EStatus::Skipped | EStatus::Failed | EStatus::VerifyFailed => {
+                span.finish(!status.is_failed());
+                self.current_step = None;
+            }
+        }
+    }
+
+    fn start_script(&mut self, script: &str) -> usize {
+        let parent_index = self.current_step.and_then(|i| self.step_spans.get(&i)).copied().unwrap_or(0);
+        let mut span = Span::start("script", Some(self.spans[parent_index].id.clone()));
+        let first_line = script.trim().lines().next().unwrap_or_default();
+        span.attributes.insert("mend.script".to_string(), json!(first_line.chars().take(200).collect::<String>()));
+        self.spans.push(span);
+        self.spans.len() - 1
+    }
+
+    fn finish_script(&mut self, span_index: usize, output: &crate::error::Result<Output>) {
+        let span = &mut self.spans[span_index];
+        match output {
+            Ok(output) => {
+                if let Some(code) = output.status.code() {
+                    span.attributes.insert("mend.exit_code".to_string(), json!(code));
+                }
+                span.finish(output.status.success());
+            }
+            Err(err) => {
+                span.attributes.insert("mend.error".to_string(), json!(format!("{:#}", err)));
+                span.finish(false);
+            }
+        }
+    }
+
+    /// Ends the run span with `ok` and returns the OTLP/JSON export request of all spans.
+    pub fn export_request(&mut self, service_name: &str, ok: bool) -> Value {
+        self.spans[0].finish(ok);
+        let spans: Vec<Value> = self.spans.iter().map(|span| span.to_otlp(&self.trace_id)).collect();
+        json!({
+            "resourceSpans": [{
+                "resource": { "attributes": [otlp_attribute("service.name", &json!(service_name))] },
+                "scopeSpans": [{
+                    "scope": { "name": "mend", "version": env!("CARGO_PKG_VERSION") },
+                    "spans": spans,
+                }],
+            }],
+        })
+    }
+}
+
+/// Posts the run's spans to the collector, a collector that can't be reached doesn't fail the run.
+pub fn export_trace(config: &TelemetryConfig, trace: &mut Trace, ok: bool) {
+    let request = trace.export_request(config.service_name.as_deref().unwrap_or("mend"), ok);
+    if let Err(err) = post_json(config, &request) {
+        eprintln!("Could not export the run's trace: {:#}", err);
+    }
+}
+
+fn post_json(config: &TelemetryConfig, request: &Value) -> anyhow::Result<()> {
+    let url = format!("{}/v1/traces", config.endpoint.trim_end_matches('/'));
+    let body_path = env::temp_dir().join(format!("mend-trace-{}.json", std::process::id()));
+    fs::write(&body_path, request.to_string())
+        .with_context(|| format!("Could not write `{}`", body_path.to_string_lossy()))?;
+    let data_arg = format!("@{}", body_path.to_string_lossy());
+    let headers: Vec<String> = std::iter::once("Content-Type: application/json".to_string())
+        .chain(config.headers.iter().map(|(name, value)| format!("{}: {}", name, value)))
+        .collect();
+    let mut args = vec!["-fsS", "-X", "POST", "--data-binary", &data_arg];
+    for header in &headers {
+        args.extend(["-H", header]);
+    }
+    args.push(&url);
+    let output = run_command_with_output(Path::new("."), "curl".to_string(), args).context("Exporting traces needs curl");
+    let _ = fs::remove_file(&body_path);
+    let output = output?;
+    if !output.status.success() {
+        bail!("{} answered: {}", url, String::from_utf8_lossy(&output.stderr));
+    }
+    Ok(())
+}
+
+/// Passes everything on to `inner`, adding a span for each step when there is a trace.
+pub struct TraceNotifier<N: Notify> {
+    inner: N,
+    trace: Option<Rc<RefCell<Trace>>>,
+}
+
+impl<N: Notify> TraceNotifier<N> {
+    pub fn new(inner: N, trace: Option<Rc<RefCell<Trace>>>) -> Self {
+        TraceNotifier { inner, trace }
+    }
+}
+
+impl<N: Notify> Notify for TraceNotifier<N> {
+    fn notify(&mut self, i: usize, run: &str, status: &EStatus, sha: &Option<String>, inc: bool) {
+        if let Some(trace) = &self.trace {
+            trace.borrow_mut().step_status(i, run, status, sha, inc);
+        }
+        self.inner.notify(i, run, status, sha, inc)
+    }
+
+    fn notify_done(&self, summary: &RunSummary) {
+        self.inner.notify_done(summary)
+    }
+
+    fn notify_output(&mut self, i: usize, line: &str) {
+        self.inner.notify_output(i, line)
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        self.inner.suspend(f)
+    }
+
+    fn notify_failure(&self, failed_request: &StepRequest, failed_response: &StepResponse) {
+        self.inner.notify_failure(failed_request, failed_response)
+    }
+}
+
+/// Passes scripts on to `inner`, adding a span for each one under its step when there is a trace.
+pub struct TraceExecutor<E: Executor> {
+    inner: E,
+    trace: Option<Rc<RefCell<Trace>>>,
+}
+
+impl<E: Executor> TraceExecutor<E> {
+    pub fn new(inner: E, trace: Option<Rc<RefCell<Trace>>>) -> Self {
+        TraceExecutor { inner, trace }
+    }
+}
+
+impl<E: Executor> Executor for TraceExecutor<E> {
+    fn run_script(
+        &mut self,
+        cwd: &Path,
+        script: &str,
+        env: &BTreeMap<String, String>,
+        timeout: Option<Duration>,
+        on_line: &mut dyn FnMut(&str),
+    ) -> crate::error::Result<Output> {
+        let span_index = self.trace.as_ref().map(|trace| trace.borrow_mut().start_script(script));
+        let output = self.inner.run_script(cwd, script, env, timeout, on_line);
+        if let (Some(trace), Some(span_index)) = (&self.trace, span_index) {
+            trace.borrow_mut().finish_script(span_index, &output);
+        }
+        output
+    }
+
+    /// Steps running alongside others still get their spans, their scripts don't
+    fn worker(&self) -> Option<Box<dyn Executor + Send>> {
+        self.inner.worker()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repo::{GitRepo, Repo};
+    use crate::run::{run_all_steps, RunOptions, ShellExecutor, StepRequest};
+    use crate::test_support::SilentNotifier;
+    use crate::trace::{Trace, TraceExecutor, TraceNotifier};
+    use serde_json::Value;
+    use std::cell::RefCell;
+    use std::fs;
+    use std::process::Command;
+    use std::rc::Rc;
+
+    #[test]
+    fn runs_are_traced_with_a_span_per_step_and_script() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let repo_dir = temp_dir.path().to_path_buf();
+        Command::new("git").arg("init").arg("-q").current_dir(&repo_dir).output().unwrap();
+        fs::write(repo_dir.join("a.txt"), "a\n").unwrap();
+        Command::new("git").args(["add", "a.txt"]).current_dir(&repo_dir).output().unwrap();
+        let mut repo = GitRepo { repo_dir };
+        repo.commit_all("Start").unwrap();
+
+        let trace = Rc::new(RefCell::new(Trace::start("mend.toml", "abc1234", "1")));
+        let step_requests = vec![
+            StepRequest { id: "1".to_string(), run: "append".to_string(), run_resolved: vec!["echo b >> a.txt".to_string()], commit_msg: "Append".to_string(), ..Default::default() },
+            StepRequest { id: "2".to_string(), run: "fail".to_string(), run_resolved: vec!["exit 3".to_string()], commit_msg: "Fail".to_string(), ..Default::default() },
+        ];
+        let result = run_all_steps(
+            step_requests,
+            &mut TraceNotifier::new(SilentNotifier, Some(trace.clone())),
+            &mut repo,
+            &mut TraceExecutor::new(ShellExecutor::default(), Some(trace.clone())),
+            &RunOptions::default(),
+        );
+        assert!(result.is_err());
+
+        let request = trace.borrow_mut().export_request("mend", false);
+        let spans = request["resourceSpans"][0]["scopeSpans"][0]["spans"].as_array().unwrap();
+        let names: Vec<&str> = spans.iter().map(|span| span["name"].as_str().unwrap()).collect();
+        assert_eq!(names, vec!["mend run", "append", "script", "fail", "script"]);
+        let status = |span: &Value| span["status"]["code"].as_i64().unwrap();
+        assert_eq!(spans.iter().map(status).collect::<Vec<i64>>(), vec![2, 1, 1, 2, 2]);
+        // Scripts belong to their step, steps to the run
+        assert_eq!(spans[2]["parentSpanId"], spans[1]["spanId"]);
+        assert_eq!(spans[3]["parentSpanId"], spans[0]["spanId"]);
+        assert_eq!(spans[0]["traceId"].as_str().unwrap().len(), 32);
+        let exit_code = spans[4]["attributes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|attribute| attribute["key"] == "mend.exit_code")
+            .map(|attribute| attribute["value"]["intValue"].clone());
+        assert_eq!(exit_code, Some(Value::from("3")));
+    }
+}
@@ -0,0 +1,308 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use indicatif::HumanDuration;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::Frame;
+
+use crate::progress::Notify;
+use crate::run::{kill_children, EStatus, RunSummary, StepRequest, StepResponse};
+
+/// Lines a page key scrolls the output by.
+const PAGE_LINES: usize = 20;
+
+struct TuiStep {
+    id: String,
+    run: String,
+    status: EStatus,
+    sha: Option<String>,
+    output: Vec<String>,
+}
+
+/// What the screen shows, shared between the run reporting into it and the thread drawing it.
+struct TuiState {
+    steps: Vec<TuiStep>,
+    selected: usize,
+    /// The selection moves to each step as it starts until a step is picked by hand
+    follow: bool,
+    /// How many lines above the latest the output is scrolled, 0 keeps up with new lines
+    scroll_back: usize,
+    /// How the run ended, None while it's going
+    outcome: Option<String>,
+}
+
+enum KeyAction {
+    None,
+    Close,
+    Abort,
+}
+
+impl TuiState {
+    fn new(step_requests: &[StepRequest]) -> Self {
+        TuiState {
+            steps: step_requests
+                .iter()
+                .map(|step_request| TuiStep {
+                    id: step_request.id.clone(),
+                    run: step_request.run.clone(),
+                    status: EStatus::Pending,
+                    sha: None,
+                    output: vec![],
+                })
+                .collect(),
+            selected: 0,
+            follow: true,
+            scroll_back: 0,
+            outcome: None,
+        }
+    }
+
+    fn select(&mut self, step_i: usize) {
+        if step_i != self.selected {
+            self.selected = step_i;
+            self.scroll_back = 0;
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> KeyAction {
+        if key.kind != KeyEventKind::Press {
+            return KeyAction::None;
+        }
+        let last_step = self.steps.len().saturating_sub(1);
+        let output_lines = self.steps.get(self.selected).map_or(0, |step| step.output.len());
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.follow = false;
+                self.select(self.selected.saturating_sub(1));
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.follow = false;
+                self.select((self.selected + 1).min(last_step));
+            }
+            KeyCode::PageUp => self.scroll_back = (self.scroll_back + PAGE_LINES).min(output_lines.saturating_sub(1)),
+            KeyCode::PageDown => self.scroll_back = self.scroll_back.saturating_sub(PAGE_LINES),
+            KeyCode::Home => self.scroll_back = output_lines.saturating_sub(1),
+            KeyCode::End => self.scroll_back = 0,
+            KeyCode::Char('f') => {
+                if let Some(failed_i) = self.steps.iter().position(|step| step.status.is_failed()) {
+                    self.follow = false;
+                    self.select(failed_i);
+                }
+            }
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return self.quit_action(),
+            KeyCode::Char('q') | KeyCode::Esc => return self.quit_action(),
+            _ => {}
+        }
+        KeyAction::None
+    }
+
+    fn quit_action(&self) -> KeyAction {
+        if self.outcome.is_some() {
+            KeyAction::Close
+        } else {
+            KeyAction::Abort
+        }
+    }
+}
+
+fn status_style(status: EStatus) -> (&'static str, Style) {
+    match status {
+        EStatus::Pending => ("·", Style::default().fg(Color::DarkGray)),
+        EStatus::Running => ("▶", Style::default().fg(Color::Yellow)),
+        EStatus::Done => ("✔", Style::default().fg(Color::Green)),
+        EStatus::Failed | EStatus::VerifyFailed => ("✘", Style::default().fg(Color::Red)),
+        EStatus::Skipped => ("↷", Style::default().fg(Color::Blue)),
+    }
+}
+
+fn render(frame: &mut Frame, state: &TuiState) {
+    let [main_area, footer_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+    let [steps_area, output_area] = Layout::horizontal([Constraint::Percentage(35), Constraint::Min(0)]).areas(main_area);
+
+    let items: Vec<ListItem> = state
+        .steps
+        .iter()
+        .enumerate()
+        .map(|(step_i, step)| {
+            let (symbol, style) = status_style(step.status);
+            ListItem::new(Line::styled(format!("{} {}. {}", symbol, step_i + 1, step.run), style))
+        })
+        .collect();
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(" Steps "))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    let mut list_state = ListState::default().with_selected(Some(state.selected));
+    frame.render_stateful_widget(list, steps_area, &mut list_state);
+
+    if let Some(step) = state.steps.get(state.selected) {
+        let height = usize::from(output_area.height.saturating_sub(2));
+        let end = step.output.len().saturating_sub(state.scroll_back);
+        let lines: Vec<Line> = step.output[end.saturating_sub(height)..end].iter().map(|line| Line::raw(line.as_str())).collect();
+        let mut title = format!(" [{}] {} ", step.id, step.run);
+        if let Some(sha) = &step.sha {
+            title.push_str(&format!("{} ", sha));
+        }
+        frame.render_widget(Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title)), output_area);
+    }
+
+    let footer = match &state.outcome {
+        Some(outcome) => format!("{}  f failed step  q quit", outcome),
+        None => "↑↓ step  PgUp/PgDn scroll  f failed step  q abort".to_string(),
+    };
+    frame.render_widget(Paragraph::new(footer).style(Style::default().add_modifier(Modifier::DIM)), footer_area);
+}
+
+/// Draws the screen and handles keys until it's closed, once the run ended, or the run is aborted.
+fn draw_until_closed(state: Arc<Mutex<TuiState>>) {
+    let Ok(mut terminal) = ratatui::try_init() else {
+        return;
+    };
+    loop {
+        if terminal.draw(|frame| render(frame, &state.lock().unwrap())).is_err() {
+            break;
+        }
+        let mut action = KeyAction::None;
+        if event::poll(Duration::from_millis(100)).unwrap_or(false) {
+            if let Ok(Event::Key(key)) = event::read() {
+                action = state.lock().unwrap().handle_key(key);
+            }
+        }
+        match action {
+            KeyAction::None => {}
+            KeyAction::Close => break,
+            KeyAction::Abort => {
+                ratatui::restore();
+                kill_children();
+                eprintln!("Aborted, the worktree is left as the running step had it");
+                std::process::exit(130);
+            }
+        }
+    }
+    ratatui::restore();
+}
+
+/// Full-screen view of the run, the steps on the left and the selected one's output on the right.
+/// Once the run ends it stays up to look around in until it's closed.
+pub struct TuiNotifier {
+    started: Instant,
+    state: Arc<Mutex<TuiState>>,
+    screen: Option<thread::JoinHandle<()>>,
+}
+
+impl TuiNotifier {
+    pub fn new(step_requests: &[StepRequest]) -> Self {
+        let state = Arc::new(Mutex::new(TuiState::new(step_requests)));
+        let screen_state = state.clone();
+        TuiNotifier {
+            started: Instant::now(),
+            state,
+            screen: Some(thread::spawn(move || draw_until_closed(screen_state))),
+        }
+    }
+}
+
+impl Notify for TuiNotifier {
+    fn notify(&mut self, i: usize, _run: &str, status: &EStatus, sha: &Option<String>, _inc: bool) {
+        let mut state = self.state.lock().unwrap();
+        if *status == EStatus::Running && state.follow {
+            state.select(i);
+        }
+        if let Some(step) = state.steps.get_mut(i) {
+            step.status = *status;
+            step.sha = sha.clone();
+        }
+    }
+
+    fn notify_done(&self, summary: &RunSummary) {
+        let mut state = self.state.lock().unwrap();
+        state.outcome = Some(if summary.failed_steps.is_empty() {
+            format!("Done in {}", HumanDuration(self.started.elapsed()))
+        } else {
+            format!("{} steps failed in {}", summary.failed_steps.len(), HumanDuration(self.started.elapsed()))
+        });
+    }
+
+    fn notify_failure(&self, failed_request: &StepRequest, failed_response: &StepResponse) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(step) = state.steps.iter_mut().find(|step| step.id == failed_request.id) {
+            // Steps running alongside others didn't stream theirs
+            if step.output.is_empty() {
+                step.output = failed_response.output.as_deref().unwrap_or_default().lines().map(str::to_string).collect();
+            }
+        }
+        if state.outcome.is_none() {
+            state.outcome = Some(format!("Failed in {}", HumanDuration(self.started.elapsed())));
+        }
+    }
+
+    fn notify_output(&mut self, i: usize, line: &str) {
+        if let Some(step) = self.state.lock().unwrap().steps.get_mut(i) {
+            step.output.push(line.to_string());
+        }
+    }
+}
+
+impl Drop for TuiNotifier {
+    /// Waits for the screen to be closed, so what's printed after the run lands on the normal screen.
+    fn drop(&mut self) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.outcome.is_none() {
+                state.outcome = Some("Stopped".to_string());
+            }
+        }
+        if let Some(screen) = self.screen.take() {
+            let _ = screen.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::run::{EStatus, StepRequest};
+    use crate::tui::{render, KeyAction, TuiState};
+    use ratatui::backend::TestBackend;
+    use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use ratatui::Terminal;
+
+    fn press(state: &mut TuiState, code: KeyCode) -> KeyAction {
+        state.handle_key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn selected_step_output_is_shown_and_scrolled() {
+        let step_requests: Vec<StepRequest> = ["rename Foo Bar", "extract-method App.java 10 20 helper", "format"]
+            .iter()
+            .enumerate()
+            .map(|(step_i, run)| StepRequest {
+                id: (step_i + 1).to_string(),
+                run: run.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let mut state = TuiState::new(&step_requests);
+        state.steps[0].status = EStatus::Done;
+        state.steps[0].sha = Some("abc1234".to_string());
+        state.steps[1].status = EStatus::Failed;
+        state.steps[1].output = (1..=30).map(|line| format!("line {}", line)).collect();
+        state.outcome = Some("Failed in 3 seconds".to_string());
+
+        assert!(matches!(press(&mut state, KeyCode::Char('f')), KeyAction::None));
+        assert_eq!(state.selected, 1);
+        press(&mut state, KeyCode::PageUp);
+        assert_eq!(state.scroll_back, 20);
+        assert!(matches!(press(&mut state, KeyCode::Char('q')), KeyAction::Close));
+
+        let mut terminal = Terminal::new(TestBackend::new(80, 12)).unwrap();
+        terminal.draw(|frame| render(frame, &state)).unwrap();
+        insta::assert_snapshot!(terminal.backend());
+
+        state.outcome = None;
+        assert!(matches!(press(&mut state, KeyCode::Char('q')), KeyAction::Abort));
+    }
+}
@@ -0,0 +1,191 @@
+use anyhow::{anyhow, bail, Context};
+use clap::Args;
+use minisign_verify::{PublicKey, Signature};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use crate::run::run_command_with_output;
+
+const RELEASES_URL: &str = "https://api.github.com/repos/craftvscruft/mend/releases";
+
+/// The minisign key release binaries are signed with, set when building a release.
+const RELEASE_PUBLIC_KEY: Option<&str> = option_env!("MEND_RELEASE_PUBLIC_KEY");
+
+#[derive(Args, Debug)]
+pub struct SelfUpdateArgs {
+    /// Only tell whether a newer release is out
+    #[arg(long = "check")]
+    pub check: bool,
+
+    /// Release tag to install instead of the latest, e.g. `v0.2.0`
+    #[arg(long = "version")]
+    pub version: Option<String>,
+
+    /// Minisign public key the release must be signed with, replaces the one built into mend
+    #[arg(long = "public-key")]
+    pub public_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    #[serde(default)]
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    fn asset_url(&self, name: &str) -> anyhow::Result<&str> {
+        self.assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .map(|asset| asset.browser_download_url.as_str())
+            .ok_or_else(|| anyhow!("Release {} has no `{}`", self.tag_name, name))
+    }
+}
+
+/// Name of the release binary for the platform mend was built for, e.g. `mend-x86_64-linux`.
+fn binary_name() -> String {
+    format!("mend-{}-{}{}", env::consts::ARCH, env::consts::OS, env::consts::EXE_SUFFIX)
+}
+
+/// Versions as numbers, so `v0.10.0` is newer than `0.9.1`. Pre-release suffixes are ignored.
+fn is_newer(tag: &str, current: &str) -> bool {
+    let numbers = |version: &str| -> Vec<u64> {
+        version
+            .trim_start_matches('v')
+            .split(['.', '-', '+'])
+            .map_while(|part| part.parse().ok())
+            .collect()
+    };
+    numbers(tag) > numbers(current)
+}
+
+/// Checks the downloaded binary against the release's `.sha256` file and its minisign signature.
+fn verify_download(binary: &[u8], checksum_file: &str, signature_file: &str, public_key: &str) -> anyhow::Result<()> {
+    let expected = checksum_file
+        .split_whitespace()
+        .next()
+        .ok_or_else(|| anyhow!("The release's checksum file is empty"))?;
+    let actual = format!("{:x}", Sha256::digest(binary));
+    if !expected.eq_ignore_ascii_case(&actual) {
+        bail!("Checksum mismatch, expected {} but downloaded {}", expected, actual);
+    }
+    let public_key = PublicKey::from_base64(public_key.trim())
+        .or_else(|_| PublicKey::decode(public_key.trim()))
+        .map_err(|err| anyhow!("Could not read the public key: {}", err))?;
+    let signature = Signature::decode(signature_file).map_err(|err| anyhow!("Could not read the signature: {}", err))?;
+    public_key
+        .verify(binary, &signature, false)
+        .map_err(|err| anyhow!("Signature doesn't match the release key: {}", err))
+}
+
+fn fetch(dir: &Path, url: &str) -> anyhow::Result<Vec<u8>> {
+    let output = run_command_with_output(dir, "curl".to_string(), vec!["-fsSL", url])
+        .context("Updating mend needs curl")?;
+    if !output.status.success() {
+        bail!("Could not fetch `{}`:\n{}", url, String::from_utf8_lossy(&output.stderr));
+    }
+    Ok(output.stdout)
+}
+
+fn fetch_text(dir: &Path, url: &str) -> anyhow::Result<String> {
+    String::from_utf8(fetch(dir, url)?).with_context(|| format!("`{}` is not text", url))
+}
+
+pub fn run_self_update(args: &SelfUpdateArgs) -> anyhow::Result<()> {
+    let current_exe = env::current_exe().context("Could not find the running mend binary")?;
+    let exe_dir = current_exe.parent().unwrap_or(Path::new("."));
+    let release_url = match &args.version {
+        Some(tag) => format!("{}/tags/{}", RELEASES_URL, tag),
+        None => format!("{}/latest", RELEASES_URL),
+    };
+    let release: Release = serde_json::from_str(&fetch_text(exe_dir, &release_url)?)
+        .with_context(|| format!("Could not parse the release from `{}`", release_url))?;
+    let current = env!("CARGO_PKG_VERSION");
+    if args.version.is_none() && !is_newer(&release.tag_name, current) {
+        eprintln!("mend {} is up to date, the latest release is {}", current, release.tag_name);
+        return Ok(());
+    }
+    if args.check {
+        eprintln!("mend {} is out, this is {}", release.tag_name, current);
+        return Ok(());
+    }
+    let public_key = args.public_key.as_deref().or(RELEASE_PUBLIC_KEY).ok_or_else(|| {
+        anyhow!("This mend has no release key built in, pass the key releases are signed with as --public-key")
+    })?;
+    let binary_name = binary_name();
+    let binary = fetch(exe_dir, release.asset_url(&binary_name)?)?;
+    let checksum_file = fetch_text(exe_dir, release.asset_url(&format!("{}.sha256", binary_name))?)?;
+    let signature_file = fetch_text(exe_dir, release.asset_url(&format!("{}.minisig", binary_name))?)?;
+    verify_download(&binary, &checksum_file, &signature_file, public_key)
+        .with_context(|| format!("Not installing {} {}", binary_name, release.tag_name))?;
+
+    // Written next to the binary, so the rename that replaces it can't end up half done
+    let new_exe = current_exe.with_extension("new");
+    fs::write(&new_exe, &binary).with_context(|| format!("Could not write `{}`", new_exe.to_string_lossy()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&new_exe, fs::Permissions::from_mode(0o755))?;
+    }
+    fs::rename(&new_exe, &current_exe).with_context(|| {
+        let _ = fs::remove_file(&new_exe);
+        format!("Could not replace `{}`", current_exe.to_string_lossy())
+    })?;
+    eprintln!("Updated mend {} to {}", current, release.tag_name);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::update::{is_newer, verify_download, Release};
+
+    // The test vector of minisign-verify, signing the bytes `test`
+    const PUBLIC_KEY: &str = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+    const SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=
+trusted comment: timestamp:1556193335\tfile:test
+y/rUw2y8/hOUYjZU71eHp/Wo1KZ40fGy2VJEDl34XMJM+TX48Ss/17u3IvIfbVR1FkZZSNCisQbuQY+bHwhEBg==";
+    const CHECKSUM: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08  mend-x86_64-linux\n";
+
+    #[test]
+    fn releases_are_compared_by_version_numbers() {
+        assert!(is_newer("v0.10.0", "0.9.1"));
+        assert!(is_newer("v0.0.2", "0.0.1"));
+        assert!(!is_newer("v0.0.1", "0.0.1"));
+        assert!(!is_newer("v0.0.1-rc.1", "0.0.1"));
+        assert!(!is_newer("nightly", "0.0.1"));
+    }
+
+    #[test]
+    fn downloads_must_match_checksum_and_signature() {
+        assert!(verify_download(b"test", CHECKSUM, SIGNATURE, PUBLIC_KEY).is_ok());
+        let tampered = verify_download(b"Test", CHECKSUM, SIGNATURE, PUBLIC_KEY).unwrap_err();
+        assert!(tampered.to_string().starts_with("Checksum mismatch"));
+        // A matching checksum alone isn't enough, it comes from the same release
+        let other_key = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO4";
+        assert!(verify_download(b"test", CHECKSUM, SIGNATURE, other_key).is_err());
+    }
+
+    #[test]
+    fn release_assets_are_found_by_name() {
+        let release: Release = serde_json::from_str(
+            r#"{"tag_name": "v0.1.0", "assets": [{"name": "mend-x86_64-linux", "browser_download_url": "https://example.com/mend"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(release.asset_url("mend-x86_64-linux").unwrap(), "https://example.com/mend");
+        assert_eq!(
+            release.asset_url("mend-x86_64-linux.minisig").unwrap_err().to_string(),
+            "Release v0.1.0 has no `mend-x86_64-linux.minisig`"
+        );
+    }
+}
@@ -0,0 +1,271 @@
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use std::env;
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::config::load_mend;
+use crate::{Mend, Step};
+
+/// Words that are part of the shell itself rather than programs on PATH.
+const SHELL_WORDS: &[&str] = &[
+    ".", ":", "[", "case", "cd", "command", "echo", "eval", "exec", "exit", "export", "false", "for",
+    "function", "if", "local", "printf", "read", "return", "set", "shift", "source", "test", "true",
+    "unset", "while", "{", "(",
+];
+
+/// The first word of a script that names a program, skipping variable assignments.
+fn first_command(script: &str) -> Option<&str> {
+    script
+        .split_whitespace()
+        .find(|word| !word.contains('='))
+        .filter(|word| !word.starts_with('$') && !word.starts_with('"') && !word.starts_with('\''))
+}
+
+/// The PATH steps will get, taking `[env] PATH` into account.
+fn search_path(mend: &Mend) -> Option<OsString> {
+    match mend.env.get("PATH") {
+        Some(path) => shellexpand::env(path).ok().map(|path| OsString::from(path.as_ref())),
+        None => env::var_os("PATH"),
+    }
+}
+
+/// Problems the config would only run into once steps execute, without running anything.
+pub fn find_problems(mend: &Mend) -> Vec<String> {
+    let path = search_path(mend);
+    let cwd = env::current_dir().unwrap_or_default();
+    let is_known_command = |command: &str| {
+        SHELL_WORDS.contains(&command)
+            || mend.recipes.contains_key(command)
+            || which::which_in(command, path.as_ref(), &cwd).is_ok()
+    };
+    let mut problems = vec![];
+    if mend.from.is_none() {
+        problems.push("No `from` declared, mend doesn't know which repository and sha to start from".to_string());
+    }
+    for (key, value) in &mend.env {
+        if let Err(err) = shellexpand::env(value) {
+            problems.push(format!("[env] {} can't be expanded: {}", key, err));
+        }
+    }
+    for (step_i, step) in mend.steps.iter().enumerate() {
+        let (instruction, fallback) = match step {
+            Step::Instruction(instruction) => (Some(instruction), None),
+            Step::Structured(step_config) => (step_config.run.as_ref(), step_config.fallback.as_ref()),
+        };
+        for (kind, script) in [("Step", instruction), ("Fallback of step", fallback)] {
+            if let Some(command) = script.and_then(|script| first_command(script)) {
+                if !is_known_command(command) {
+                    problems.push(format!(
+                        "{} {} uses `{}`, which is neither a recipe nor a program on PATH",
+                        kind,
+                        step_i + 1,
+                        command
+                    ));
+                }
+            }
+        }
+    }
+    for (name, recipe) in &mend.recipes {
+        if let Some(command) = first_command(&recipe.run) {
+            if !is_known_command(command) {
+                problems.push(format!("Recipe `{}` runs `{}`, which is not on PATH", name, command));
+            }
+        }
+    }
+    for (key, hooks) in &mend.hooks {
+        for hook in hooks {
+            if let (Some(when_tag), Some(when_not_tag)) = (&hook.when_tag, &hook.when_not_tag) {
+                problems.push(format!(
+                    "Hook rule in `{}` has both when_tag `{}` and when_not_tag `{}`, only when_tag is used",
+                    key, when_tag, when_not_tag
+                ));
+            }
+        }
+    }
+    problems
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Fails `mend validate`
+    #[default]
+    Error,
+    /// Shown without failing it
+    Warning,
+}
+
+/// One finding of a lint rule, which prints a JSON array of them, e.g.
+/// `[{"severity": "warning", "step": 2, "message": "Commit messages need a ticket"}]`.
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct Diagnostic {
+    #[serde(default)]
+    pub severity: Severity,
+    pub message: String,
+    /// Counting from 1, for a finding about one step
+    pub step: Option<usize>,
+}
+
+impl Diagnostic {
+    fn describe(&self, rule: &str) -> String {
+        match self.step {
+            Some(step) => format!("Step {}: {} ({})", step, self.message, rule),
+            None => format!("{} ({})", self.message, rule),
+        }
+    }
+}
+
+/// Runs the lint rule `rule` in `dir` with the merged config as JSON on stdin and returns what it found. Nothing printed
+/// means nothing found, a rule that fails without printing diagnostics is an error.
+pub fn run_lint_rule(rule: &str, dir: &Path, mend: &Mend) -> anyhow::Result<Vec<Diagnostic>> {
+    let mut words = rule.split_whitespace();
+    let Some(program) = words.next() else {
+        bail!("A lint rule is empty");
+    };
+    let program = if program.contains('/') || program.contains('\\') {
+        dir.join(program).to_string_lossy().to_string()
+    } else {
+        program.to_string()
+    };
+    let mut child = Command::new(&program)
+        .current_dir(dir)
+        .args(words)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Could not start the lint rule `{}`", rule))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A rule that doesn't need the config may not read it
+        let _ = stdin.write_all(format!("{}\n", serde_json::to_string(mend)?).as_bytes());
+    }
+    let output = child.wait_with_output().with_context(|| format!("Could not run the lint rule `{}`", rule))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if stdout.trim().is_empty() {
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if stderr.trim().is_empty() {
+                bail!("The lint rule `{}` exited with {}", rule, output.status);
+            }
+            bail!("The lint rule `{}` exited with {}: {}", rule, output.status, stderr.trim());
+        }
+        return Ok(vec![]);
+    }
+    serde_json::from_str(&stdout).with_context(|| format!("The lint rule `{}` did not print a JSON array of diagnostics", rule))
+}
+
+/// The errors and the warnings of every lint rule, a rule that can't be run is an error.
+pub fn lint(mend: &Mend, dir: &Path) -> (Vec<String>, Vec<String>) {
+    let mut errors = vec![];
+    let mut warnings = vec![];
+    for rule in &mend.lint_rules {
+        match run_lint_rule(rule, dir, mend) {
+            Ok(diagnostics) => {
+                for diagnostic in diagnostics {
+                    match diagnostic.severity {
+                        Severity::Error => errors.push(diagnostic.describe(rule)),
+                        Severity::Warning => warnings.push(diagnostic.describe(rule)),
+                    }
+                }
+            }
+            Err(err) => errors.push(format!("{:#}", err)),
+        }
+    }
+    (errors, warnings)
+}
+
+pub fn run_validate(config_path: &Path) -> anyhow::Result<()> {
+    let mend = load_mend(config_path)?;
+    let mut problems = find_problems(&mend);
+    let config_dir = config_path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let (errors, warnings) = lint(&mend, config_dir);
+    problems.extend(errors);
+    for warning in &warnings {
+        println!("Warning: {}", warning);
+    }
+    if problems.is_empty() {
+        println!("No problems found in {}", config_path.to_string_lossy());
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    bail!("Found {} problem(s) in {}", problems.len(), config_path.to_string_lossy())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::validate::{find_problems, first_command, lint};
+    use crate::Mend;
+
+    #[test]
+    fn first_command_skips_assignments() {
+        assert_eq!(first_command("LC_ALL=C sort -u file"), Some("sort"));
+        assert_eq!(first_command("\"$MEND_BIN\" edit set"), None);
+        assert_eq!(first_command("   "), None);
+    }
+
+    #[test]
+    fn validate_reports_problems() {
+        let mend: Mend = toml::from_str(
+            r#"
+steps = [
+    "rename a b",
+    "not-a-mend-command-anywhere x",
+    { run = "echo hi", fallback = "also-not-a-mend-command" },
+]
+
+[env]
+TOKEN = "${MEND_UNSET_FOR_VALIDATE_TEST}"
+
+[recipes.rename]
+run = "rename-symbol-tool-mend-never-has $1 $2"
+
+[[hooks.after_step]]
+when_tag = "fast"
+when_not_tag = "slow"
+run = "make test"
+"#,
+        )
+        .unwrap();
+        insta::assert_yaml_snapshot!(find_problems(&mend));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn lint_rules_report_from_the_config_they_are_given() {
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let script_path = temp_dir.path().join("house-rules.sh");
+        fs::write(
+            &script_path,
+            r#"#!/bin/sh
+if grep -q '"after_step"'; then
+  echo '[]'
+else
+  echo '[{"message": "Every config needs an after_step hook"}, {"severity": "warning", "step": 1, "message": "Prefer recipes"}]'
+fi
+"#,
+        )
+        .unwrap();
+        fs::set_permissions(&script_path, fs::Permissions::from_mode(0o755)).unwrap();
+        let mut mend: Mend = toml::from_str(
+            r#"
+steps = ["echo hi"]
+lint_rules = ["./house-rules.sh", "false", "echo not-json"]
+"#,
+        )
+        .unwrap();
+        let (errors, warnings) = lint(&mend, temp_dir.path());
+        insta::assert_yaml_snapshot!((errors, warnings));
+
+        mend.lint_rules.truncate(1);
+        mend.hooks.insert("after_step".to_string(), vec![]);
+        assert_eq!(lint(&mend, temp_dir.path()), (vec![], vec![]));
+    }
+}
@@ -0,0 +1,216 @@
+use anyhow::{anyhow, bail};
+use std::collections::BTreeMap;
+use std::env;
+use std::path::Path;
+
+/// A step's `when` expression, e.g. `env.CI == 'true' && !file_exists('Cargo.lock')`.
+#[derive(Debug, PartialEq)]
+pub enum Condition {
+    Text(String),
+    Bool(bool),
+    /// `env.NAME`, the step's variables first, empty when the variable isn't set
+    Env(String),
+    /// `file_exists('path')`, relative to the worktree
+    FileExists(String),
+    Equal(Box<Condition>, Box<Condition>),
+    NotEqual(Box<Condition>, Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Not(Box<Condition>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Word(String),
+    Text(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: &[&str] = &["==", "!=", "&&", "||", "!", "(", ")"];
+
+fn tokenize(text: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else if rest.starts_with('\'') || rest.starts_with('"') {
+            let quote = &rest[..1];
+            let end = rest[1..]
+                .find(quote)
+                .ok_or_else(|| anyhow!("Missing closing {} in `{}`", quote, text))?;
+            tokens.push(Token::Text(rest[1..end + 1].to_string()));
+            rest = &rest[end + 2..];
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                bail!("Unexpected `{}` in `{}`", &rest[..rest.chars().next().map_or(1, char::len_utf8)], text);
+            }
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn accept(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.tokens.get(self.position), Some(Token::Symbol(found)) if *found == symbol);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, symbol: &str) -> anyhow::Result<()> {
+        if !self.accept(symbol) {
+            bail!("Expected `{}` in `{}`", symbol, self.text);
+        }
+        Ok(())
+    }
+
+    fn or(&mut self) -> anyhow::Result<Condition> {
+        let mut condition = self.and()?;
+        while self.accept("||") {
+            condition = Condition::Or(Box::new(condition), Box::new(self.and()?));
+        }
+        Ok(condition)
+    }
+
+    fn and(&mut self) -> anyhow::Result<Condition> {
+        let mut condition = self.unary()?;
+        while self.accept("&&") {
+            condition = Condition::And(Box::new(condition), Box::new(self.unary()?));
+        }
+        Ok(condition)
+    }
+
+    fn unary(&mut self) -> anyhow::Result<Condition> {
+        if self.accept("!") {
+            return Ok(Condition::Not(Box::new(self.unary()?)));
+        }
+        let left = self.primary()?;
+        if self.accept("==") {
+            Ok(Condition::Equal(Box::new(left), Box::new(self.primary()?)))
+        } else if self.accept("!=") {
+            Ok(Condition::NotEqual(Box::new(left), Box::new(self.primary()?)))
+        } else {
+            Ok(left)
+        }
+    }
+
+    fn primary(&mut self) -> anyhow::Result<Condition> {
+        match self.next() {
+            Some(Token::Symbol("(")) => {
+                let condition = self.or()?;
+                self.expect(")")?;
+                Ok(condition)
+            }
+            Some(Token::Text(text)) => Ok(Condition::Text(text)),
+            Some(Token::Word(word)) if word == "true" || word == "false" => Ok(Condition::Bool(word == "true")),
+            Some(Token::Word(word)) if word == "file_exists" => {
+                self.expect("(")?;
+                let path = match self.next() {
+                    Some(Token::Text(path)) => path,
+                    _ => bail!("file_exists takes a quoted path in `{}`", self.text),
+                };
+                self.expect(")")?;
+                Ok(Condition::FileExists(path))
+            }
+            Some(Token::Word(word)) => match word.strip_prefix("env.") {
+                Some(name) if !name.is_empty() => Ok(Condition::Env(name.to_string())),
+                _ => bail!(
+                    "Unknown `{}` in `{}`, use env.NAME, file_exists('path'), quoted text, true or false",
+                    word,
+                    self.text
+                ),
+            },
+            _ => bail!("Incomplete condition `{}`", self.text),
+        }
+    }
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut parser = Parser {
+            text,
+            tokens: tokenize(text)?,
+            position: 0,
+        };
+        let condition = parser.or()?;
+        if parser.position < parser.tokens.len() {
+            bail!("Unexpected {:?} in `{}`", parser.tokens[parser.position], text);
+        }
+        Ok(condition)
+    }
+
+    /// Text is true when it isn't empty, so `env.CI` alone checks that CI is set.
+    fn value(&self, dir: &Path, step_env: &BTreeMap<String, String>) -> String {
+        match self {
+            Condition::Text(text) => text.clone(),
+            Condition::Env(name) => step_env.get(name).cloned().or_else(|| env::var(name).ok()).unwrap_or_default(),
+            other => other.holds(dir, step_env).to_string(),
+        }
+    }
+
+    /// `step_env` holds the variables the step's scripts get besides mend's own environment.
+    pub fn holds(&self, dir: &Path, step_env: &BTreeMap<String, String>) -> bool {
+        match self {
+            Condition::Text(_) | Condition::Env(_) => !self.value(dir, step_env).is_empty(),
+            Condition::Bool(value) => *value,
+            Condition::FileExists(path) => dir.join(path).exists(),
+            Condition::Equal(left, right) => left.value(dir, step_env) == right.value(dir, step_env),
+            Condition::NotEqual(left, right) => left.value(dir, step_env) != right.value(dir, step_env),
+            Condition::And(left, right) => left.holds(dir, step_env) && right.holds(dir, step_env),
+            Condition::Or(left, right) => left.holds(dir, step_env) || right.holds(dir, step_env),
+            Condition::Not(condition) => !condition.holds(dir, step_env),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::when::Condition;
+    use std::collections::BTreeMap;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn conditions_check_env_and_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::write(temp_dir.path().join("Cargo.toml"), "").unwrap();
+        env::set_var("MEND_WHEN_TEST_CI", "true");
+        let step_env = BTreeMap::from([("MEND_WHEN_TEST_STEP".to_string(), "yes".to_string())]);
+        let holds = |text: &str| Condition::parse(text).unwrap().holds(temp_dir.path(), &step_env);
+        assert!(holds("env.MEND_WHEN_TEST_CI == 'true'"));
+        assert!(holds("env.MEND_WHEN_TEST_CI"));
+        assert!(!holds("env.MEND_WHEN_TEST_UNSET"));
+        assert!(holds("env.MEND_WHEN_TEST_STEP == 'yes'"));
+        assert!(holds("file_exists('Cargo.toml') && !file_exists(\"Cargo.lock\")"));
+        assert!(holds("(false || env.MEND_WHEN_TEST_UNSET != 'x') && true"));
+        assert!(!holds("file_exists('Cargo.lock') || env.MEND_WHEN_TEST_CI == 'false'"));
+    }
+
+    #[test]
+    fn invalid_conditions_are_rejected() {
+        for text in ["", "env.", "CI == 'true'", "file_exists(Cargo.toml)", "(true", "true false", "'open"] {
+            assert!(Condition::parse(text).is_err(), "{}", text);
+        }
+    }
+}